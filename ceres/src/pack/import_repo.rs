@@ -27,7 +27,7 @@ use mercury::{hash::SHA1, internal::pack::encode::PackEncoder};
 
 use crate::{
     api_service::{mono_api_service::MonoApiService, ApiHandler},
-    pack::PackHandler,
+    pack::{PackHandler, UploadOptions},
     protocol::{
         import_refs::{CommandType, RefCommand, Refs},
         repo::Repo,
@@ -166,44 +166,16 @@ impl PackHandler for ImportRepo {
 
     async fn incremental_pack(
         &self,
-        want: Vec<String>,
+        want_commits: Vec<Commit>,
         have: Vec<String>,
+        options: &UploadOptions,
     ) -> Result<ReceiverStream<Vec<u8>>, GitError> {
-        let mut want_clone = want.clone();
         let pack_config = &self.context.config.pack;
         let storage = self.context.services.git_db_storage.clone();
         let obj_num = AtomicUsize::new(0);
 
         let mut exist_objs = HashSet::new();
 
-        let mut want_commits: Vec<Commit> = storage
-            .get_commits_by_hashes(self.repo.repo_id, &want_clone)
-            .await
-            .unwrap()
-            .into_iter()
-            .map(|x| x.into())
-            .collect();
-        let mut traversal_list: Vec<Commit> = want_commits.clone();
-
-        // traverse commit's all parents to find the commit that client does not have
-        while let Some(temp) = traversal_list.pop() {
-            for p_commit_id in temp.parent_commit_ids {
-                let p_commit_id = p_commit_id.to_string();
-
-                if !have.contains(&p_commit_id) && !want_clone.contains(&p_commit_id) {
-                    let parent: Commit = storage
-                        .get_commit_by_hash(self.repo.repo_id, &p_commit_id)
-                        .await
-                        .unwrap()
-                        .unwrap()
-                        .into();
-                    want_commits.push(parent.clone());
-                    want_clone.push(p_commit_id);
-                    traversal_list.push(parent);
-                }
-            }
-        }
-
         let want_tree_ids = want_commits.iter().map(|c| c.tree_id.to_string()).collect();
         let want_trees: HashMap<SHA1, Tree> = storage
             .get_trees_by_hashes(self.repo.repo_id, want_tree_ids)
@@ -228,7 +200,8 @@ impl PackHandler for ImportRepo {
             .unwrap();
         // traverse to get exist_objs
         for have_tree in have_trees {
-            self.traverse(have_tree.into(), &mut exist_objs, None).await;
+            self.traverse(have_tree.into(), &mut exist_objs, None, options.omit_blobs)
+                .await;
         }
 
         let mut counted_obj = HashSet::new();
@@ -239,6 +212,7 @@ impl PackHandler for ImportRepo {
                 &exist_objs,
                 &mut counted_obj,
                 &obj_num,
+                options.omit_blobs,
             )
             .await;
         }
@@ -252,6 +226,7 @@ impl PackHandler for ImportRepo {
                 want_trees.get(&c.tree_id).unwrap().clone(),
                 &mut exist_objs,
                 Some(&entry_tx),
+                options.omit_blobs,
             )
             .await;
             entry_tx.send(c.into()).await.unwrap();
@@ -261,6 +236,18 @@ impl PackHandler for ImportRepo {
         Ok(ReceiverStream::new(stream_rx))
     }
 
+    async fn get_commits_by_hashes(&self, hashes: Vec<String>) -> Result<Vec<Commit>, MegaError> {
+        Ok(self
+            .context
+            .services
+            .git_db_storage
+            .get_commits_by_hashes(self.repo.repo_id, &hashes)
+            .await?
+            .into_iter()
+            .map(|x| x.into())
+            .collect())
+    }
+
     async fn get_trees_by_hashes(&self, hashes: Vec<String>) -> Result<Vec<Tree>, MegaError> {
         Ok(self
             .context
@@ -34,6 +34,36 @@ use mercury::{
 pub mod import_repo;
 pub mod monorepo;
 
+/// The shallow & partial clone arguments of the upload-pack request
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct UploadOptions {
+    /// `deepen <depth>`: send at most `depth` commits from each wanted commit
+    pub depth: Option<usize>,
+    /// `deepen-since <timestamp>`: don't send the commits committed before the time
+    pub deepen_since: Option<usize>,
+    /// `filter blob:none`: don't send any blob
+    pub omit_blobs: bool,
+    /// `shallow <hash>`: the commits whose parents the client doesn't have
+    pub client_shallow: Vec<String>,
+}
+
+impl UploadOptions {
+    /// `shallow`/`unshallow` lines are sent before the pack only if the client is deepening
+    pub fn is_deepening(&self) -> bool {
+        self.depth.is_some() || self.deepen_since.is_some()
+    }
+}
+
+/// The commits to send by upload-pack, and the changes of the client's shallow commits
+#[derive(Debug, Default)]
+pub struct CommitWalk {
+    pub commits: Vec<Commit>,
+    /// The sent commits whose parents are not sent, reported as `shallow <hash>`
+    pub shallow: Vec<String>,
+    /// The client's shallow commits whose parents are sent now, reported as `unshallow <hash>`
+    pub unshallow: Vec<String>,
+}
+
 #[async_trait]
 pub trait PackHandler: Send + Sync {
     async fn head_hash(&self) -> (String, Vec<Refs>);
@@ -50,12 +80,17 @@ pub trait PackHandler: Send + Sync {
     ///
     async fn full_pack(&self, want: Vec<String>) -> Result<ReceiverStream<Vec<u8>>, GitError>;
 
+    /// Pack the `want_commits` (found by [PackHandler::walk_commits]) with their trees & blobs,
+    /// the objects reachable from the trees of `have` are not sent.
     async fn incremental_pack(
         &self,
-        want: Vec<String>,
+        want_commits: Vec<Commit>,
         have: Vec<String>,
+        options: &UploadOptions,
     ) -> Result<ReceiverStream<Vec<u8>>, GitError>;
 
+    async fn get_commits_by_hashes(&self, hashes: Vec<String>) -> Result<Vec<Commit>, MegaError>;
+
     async fn get_trees_by_hashes(&self, hashes: Vec<String>) -> Result<Vec<Tree>, MegaError>;
 
     async fn get_blobs_by_hashes(
@@ -86,6 +121,78 @@ pub trait PackHandler: Send + Sync {
         (head_hash, refs)
     }
 
+    /// Find the commits to send, walking from `want` to the commits the client has.
+    ///
+    /// When deepening, the walk is limited by `deepen` & `deepen-since` rather than `have`,
+    /// the commits at the limit become shallow. Otherwise the parents of the client's shallow
+    /// commits are not sent.
+    async fn walk_commits(
+        &self,
+        want: Vec<String>,
+        have: &[String],
+        options: &UploadOptions,
+    ) -> CommitWalk {
+        let deepening = options.is_deepening();
+        let mut walk = CommitWalk::default();
+        let mut visited: HashSet<String> = want.iter().cloned().collect();
+        let mut level = self.get_commits_by_hashes(want).await.unwrap();
+        let mut depth = 1;
+        while !level.is_empty() {
+            let mut expanded = vec![];
+            let mut parent_ids = vec![];
+            for commit in level.iter() {
+                let id = commit.id.to_string();
+                if options.depth.is_some_and(|max| depth >= max) {
+                    if !commit.parent_commit_ids.is_empty() {
+                        walk.shallow.push(id);
+                    }
+                    continue;
+                }
+                if !deepening && (have.contains(&id) || options.client_shallow.contains(&id)) {
+                    continue;
+                }
+                for parent in commit.parent_commit_ids.iter() {
+                    let parent = parent.to_string();
+                    if (deepening || !have.contains(&parent)) && visited.insert(parent.clone()) {
+                        parent_ids.push(parent);
+                    }
+                }
+                expanded.push(commit);
+            }
+            let parents = self.get_commits_by_hashes(parent_ids).await.unwrap();
+
+            // deepen-since: the older parents are not sent, their children become shallow
+            let too_old: HashSet<_> = parents
+                .iter()
+                .filter(|p| {
+                    options
+                        .deepen_since
+                        .is_some_and(|t| p.committer.timestamp < t)
+                })
+                .map(|p| p.id)
+                .collect();
+            for commit in expanded {
+                let id = commit.id.to_string();
+                if commit.parent_commit_ids.iter().any(|p| too_old.contains(p)) {
+                    walk.shallow.push(id);
+                } else if options.client_shallow.contains(&id) {
+                    walk.unshallow.push(id);
+                }
+            }
+            walk.commits.extend(
+                level
+                    .into_iter()
+                    .filter(|c| !have.contains(&c.id.to_string())),
+            );
+            level = parents
+                .into_iter()
+                .filter(|p| !too_old.contains(&p.id))
+                .collect();
+            depth += 1;
+        }
+        walk
+    }
+
     async fn unpack_stream(
         &self,
         pack_config: &PackConfig,
@@ -116,17 +223,23 @@ pub trait PackHandler: Send + Sync {
         Ok(receiver)
     }
 
+    /// Count the objects of the tree (including itself) not in `exist_objs`,
+    /// blobs are not counted if `omit_blobs` (partial clone)
     async fn traverse_for_count(
         &self,
         tree: Tree,
         exist_objs: &HashSet<String>,
         counted_obj: &mut HashSet<String>,
         obj_num: &AtomicUsize,
+        omit_blobs: bool,
     ) {
         let mut search_tree_ids = vec![];
         let mut search_blob_ids = vec![];
         for item in &tree.tree_items {
             let hash = item.id.to_string();
+            if item.mode != TreeItemMode::Tree && omit_blobs {
+                continue;
+            }
             if !exist_objs.contains(&hash) && counted_obj.insert(hash.clone()) {
                 if item.mode == TreeItemMode::Tree {
                     search_tree_ids.push(hash.clone())
@@ -138,7 +251,7 @@ pub trait PackHandler: Send + Sync {
         obj_num.fetch_add(search_blob_ids.len(), Ordering::SeqCst);
         let trees = self.get_trees_by_hashes(search_tree_ids).await.unwrap();
         for t in trees {
-            self.traverse_for_count(t, exist_objs, counted_obj, obj_num)
+            self.traverse_for_count(t, exist_objs, counted_obj, obj_num, omit_blobs)
                 .await;
         }
         obj_num.fetch_add(1, Ordering::SeqCst);
@@ -155,6 +268,7 @@ pub trait PackHandler: Send + Sync {
     /// - `tree`: The tree structure to traverse.
     /// - `exist_objs`: A mutable reference to a set containing already processed object IDs.
     /// - `sender`: An optional sender for sending traversal data.
+    /// - `omit_blobs`: Skip the blobs, for partial clone (`filter blob:none`).
     ///
     /// # Details
     /// - The function processes tree items, distinguishing between tree and blob items.
@@ -167,12 +281,16 @@ pub trait PackHandler: Send + Sync {
         tree: Tree,
         exist_objs: &mut HashSet<String>,
         sender: Option<&tokio::sync::mpsc::Sender<Entry>>,
+        omit_blobs: bool,
     ) {
         let mut search_tree_ids = vec![];
         let mut search_blob_ids = vec![];
 
         for item in &tree.tree_items {
             let hash = item.id.to_string();
+            if item.mode != TreeItemMode::Tree && omit_blobs {
+                continue;
+            }
             if exist_objs.insert(hash.clone()) {
                 if item.mode == TreeItemMode::Tree {
                     search_tree_ids.push(hash);
@@ -192,7 +310,7 @@ pub trait PackHandler: Send + Sync {
 
         let trees = self.get_trees_by_hashes(search_tree_ids).await.unwrap();
         for t in trees {
-            self.traverse(t, exist_objs, sender).await;
+            self.traverse(t, exist_objs, sender, omit_blobs).await;
         }
 
         if let Some(sender) = sender {
@@ -31,7 +31,7 @@ use mercury::{
 };
 
 use crate::{
-    pack::PackHandler,
+    pack::{PackHandler, UploadOptions},
     protocol::{
         import_refs::{RefCommand, Refs},
         mr::MergeRequest,
@@ -188,7 +188,7 @@ impl PackHandler for MonoRepo {
         trees.push(tree.clone());
         let mut exist_objs = HashSet::new();
         let mut counted_obj = HashSet::new();
-        self.traverse_for_count(tree.clone(), &exist_objs, &mut counted_obj, &obj_num)
+        self.traverse_for_count(tree.clone(), &exist_objs, &mut counted_obj, &obj_num, false)
             .await;
         obj_num.fetch_add(1, Ordering::SeqCst);
 
@@ -217,7 +217,7 @@ impl PackHandler for MonoRepo {
                 .unwrap()
                 .into();
             trees.push(tree.clone());
-            self.traverse_for_count(tree, &exist_objs, &mut counted_obj, &obj_num)
+            self.traverse_for_count(tree, &exist_objs, &mut counted_obj, &obj_num, false)
                 .await;
             obj_num.fetch_add(1, Ordering::SeqCst);
             entry_tx.send(commit.into()).await.unwrap();
@@ -227,7 +227,7 @@ impl PackHandler for MonoRepo {
         encoder.encode_async(entry_rx).await.unwrap();
         let mut send_exist = HashSet::new();
         for tree in trees {
-            self.traverse(tree, &mut send_exist, Some(&entry_tx))
+            self.traverse(tree, &mut send_exist, Some(&entry_tx), false)
                 .await;
         }
         entry_tx.send(commit.into()).await.unwrap();
@@ -237,44 +237,16 @@ impl PackHandler for MonoRepo {
 
     async fn incremental_pack(
         &self,
-        want: Vec<String>,
+        want_commits: Vec<Commit>,
         have: Vec<String>,
+        options: &UploadOptions,
     ) -> Result<ReceiverStream<Vec<u8>>, GitError> {
-        let mut want_clone = want.clone();
         let pack_config = &self.context.config.pack;
         let storage = self.context.services.mono_storage.clone();
         let obj_num = AtomicUsize::new(0);
 
         let mut exist_objs = HashSet::new();
 
-        let mut want_commits: Vec<Commit> = storage
-            .get_commits_by_hashes(&want_clone)
-            .await
-            .unwrap()
-            .into_iter()
-            .map(|x| x.into())
-            .collect();
-        let mut traversal_list: Vec<Commit> = want_commits.clone();
-
-        // traverse commit's all parents to find the commit that client does not have
-        while let Some(temp) = traversal_list.pop() {
-            for p_commit_id in temp.parent_commit_ids {
-                let p_commit_id = p_commit_id.to_string();
-
-                if !have.contains(&p_commit_id) && !want_clone.contains(&p_commit_id) {
-                    let parent: Commit = storage
-                        .get_commit_by_hash(&p_commit_id)
-                        .await
-                        .unwrap()
-                        .unwrap()
-                        .into();
-                    want_commits.push(parent.clone());
-                    want_clone.push(p_commit_id);
-                    traversal_list.push(parent);
-                }
-            }
-        }
-
         let want_tree_ids = want_commits
             .iter()
             .map(|c| c.tree_id.to_string())
@@ -295,7 +267,8 @@ impl PackHandler for MonoRepo {
             .await
             .unwrap();
         for have_tree in have_trees {
-            self.traverse(have_tree.into(), &mut exist_objs, None).await;
+            self.traverse(have_tree.into(), &mut exist_objs, None, options.omit_blobs)
+                .await;
         }
 
         let mut counted_obj = HashSet::new();
@@ -306,6 +279,7 @@ impl PackHandler for MonoRepo {
                 &exist_objs,
                 &mut counted_obj,
                 &obj_num,
+                options.omit_blobs,
             )
             .await;
         }
@@ -319,6 +293,7 @@ impl PackHandler for MonoRepo {
                 want_trees.get(&c.tree_id).unwrap().clone(),
                 &mut exist_objs,
                 Some(&entry_tx),
+                options.omit_blobs,
             )
             .await;
             entry_tx.send(c.into()).await.unwrap();
@@ -328,6 +303,18 @@ impl PackHandler for MonoRepo {
         Ok(ReceiverStream::new(stream_rx))
     }
 
+    async fn get_commits_by_hashes(&self, hashes: Vec<String>) -> Result<Vec<Commit>, MegaError> {
+        Ok(self
+            .context
+            .services
+            .mono_storage
+            .get_commits_by_hashes(&hashes)
+            .await?
+            .into_iter()
+            .map(|x| x.into())
+            .collect())
+    }
+
     async fn get_trees_by_hashes(&self, hashes: Vec<String>) -> Result<Vec<Tree>, MegaError> {
         Ok(self
             .context
@@ -8,6 +8,7 @@ use tokio_stream::wrappers::ReceiverStream;
 use callisto::db_enums::RefType;
 use common::errors::ProtocolError;
 
+use crate::pack::UploadOptions;
 use crate::protocol::import_refs::RefCommand;
 use crate::protocol::ZERO_ID;
 use crate::protocol::{Capability, ServiceType, SideBind, SmartProtocol, TransportProtocol};
@@ -30,7 +31,7 @@ const RECEIVE_CAP_LIST: &str = "report-status report-status-v2 delete-refs quiet
 const COMMON_CAP_LIST: &str = "side-band-64k ofs-delta agent=mega/0.1.0";
 
 // All other capabilities are only recognized by the upload-pack (fetch from server) process.
-const UPLOAD_CAP_LIST: &str = "multi_ack_detailed no-done include-tag ";
+const UPLOAD_CAP_LIST: &str = "multi_ack_detailed no-done include-tag shallow deepen-since filter ";
 
 impl SmartProtocol {
     /// # Retrieves the information about Git references (refs) for the specified service type.
@@ -93,6 +94,7 @@ impl SmartProtocol {
 
         let mut want: Vec<String> = Vec::new();
         let mut have: Vec<String> = Vec::new();
+        let mut options = UploadOptions::default();
         let mut last_common_commit = String::new();
 
         let mut read_first_line = false;
@@ -116,6 +118,9 @@ impl SmartProtocol {
                 b"have" => {
                     have.push(String::from_utf8(dst[5..45].to_vec()).unwrap());
                 }
+                b"shal" | b"deep" | b"filt" => {
+                    parse_upload_option(&mut options, core::str::from_utf8(&dst).unwrap())?;
+                }
                 b"done" => break,
                 other => {
                     tracing::error!(
@@ -132,18 +137,43 @@ impl SmartProtocol {
         }
 
         tracing::info!(
-            "want commands: {:?}\n have commands: {:?}\n caps:{:?}",
+            "want commands: {:?}\n have commands: {:?}\n caps:{:?}\n options: {:?}",
             want,
             have,
-            self.capabilities
+            self.capabilities,
+            options
         );
 
         let pack_data;
         let mut protocol_buf = BytesMut::new();
 
-        if have.is_empty() {
+        if have.is_empty() && options == UploadOptions::default() {
             pack_data = pack_handler.full_pack(want.clone()).await.unwrap();
             add_pkt_line_string(&mut protocol_buf, String::from("NAK\n"));
+            return Ok((pack_data, protocol_buf));
+        }
+
+        // shallow or partial clone, or fetch
+        let walk = pack_handler
+            .walk_commits(want.clone(), &have, &options)
+            .await;
+        if options.is_deepening() {
+            // shallow-info section
+            for hash in &walk.shallow {
+                add_pkt_line_string(&mut protocol_buf, format!("shallow {}\n", hash));
+            }
+            for hash in &walk.unshallow {
+                add_pkt_line_string(&mut protocol_buf, format!("unshallow {}\n", hash));
+            }
+            protocol_buf.put(&PKT_LINE_END_MARKER[..]);
+        }
+
+        if have.is_empty() {
+            pack_data = pack_handler
+                .incremental_pack(walk.commits, have, &options)
+                .await
+                .unwrap();
+            add_pkt_line_string(&mut protocol_buf, String::from("NAK\n"));
         } else {
             if self.capabilities.contains(&Capability::MultiAckDetailed) {
                 // multi_ack_detailed mode, the server will differentiate the ACKs where it is signaling that
@@ -159,7 +189,7 @@ impl SmartProtocol {
                     }
                 }
                 pack_data = pack_handler
-                    .incremental_pack(want.clone(), have)
+                    .incremental_pack(walk.commits, have, &options)
                     .await
                     .unwrap();
 
@@ -340,6 +370,25 @@ impl SmartProtocol {
     }
 }
 
+/// Parse the `shallow`, `deepen`, `deepen-since` & `filter` lines of the upload-pack request,
+/// only the `blob:none` filter is supported.
+fn parse_upload_option(options: &mut UploadOptions, line: &str) -> Result<(), ProtocolError> {
+    let line = line.trim_end();
+    let invalid = || ProtocolError::InvalidInput(format!("unsupported request: {}", line));
+    if let Some(hash) = line.strip_prefix("shallow ") {
+        options.client_shallow.push(hash.to_string());
+    } else if let Some(depth) = line.strip_prefix("deepen ") {
+        options.depth = Some(depth.parse().ok().filter(|d| *d > 0).ok_or_else(invalid)?);
+    } else if let Some(time) = line.strip_prefix("deepen-since ") {
+        options.deepen_since = Some(time.parse().map_err(|_| invalid())?);
+    } else if line == "filter blob:none" {
+        options.omit_blobs = true;
+    } else {
+        return Err(invalid());
+    }
+    Ok(())
+}
+
 fn read_until_white_space(bytes: &mut Bytes) -> String {
     let mut buf = Vec::new();
     while bytes.has_remaining() {
@@ -411,8 +460,11 @@ pub mod test {
     use bytes::{Bytes, BytesMut};
     use callisto::db_enums::RefType;
 
+    use crate::pack::UploadOptions;
     use crate::protocol::import_refs::{CommandType, RefCommand};
-    use crate::protocol::smart::{add_pkt_line_string, read_pkt_line, read_until_white_space};
+    use crate::protocol::smart::{
+        add_pkt_line_string, parse_upload_option, read_pkt_line, read_until_white_space,
+    };
     use crate::protocol::{Capability, SmartProtocol};
 
     #[test]
@@ -463,6 +515,30 @@ pub mod test {
         assert_eq!(result, "");
     }
 
+    #[test]
+    pub fn test_parse_upload_option() {
+        let mut options = UploadOptions::default();
+        let hash = "7bdc783132575d5b3e78400ace9971970ff43a18";
+        parse_upload_option(&mut options, &format!("shallow {}\n", hash)).unwrap();
+        parse_upload_option(&mut options, "deepen 2\n").unwrap();
+        parse_upload_option(&mut options, "deepen-since 1700000000\n").unwrap();
+        parse_upload_option(&mut options, "filter blob:none\n").unwrap();
+        assert_eq!(
+            options,
+            UploadOptions {
+                depth: Some(2),
+                deepen_since: Some(1700000000),
+                omit_blobs: true,
+                client_shallow: vec![hash.to_string()],
+            }
+        );
+        assert!(options.is_deepening());
+
+        assert!(parse_upload_option(&mut options, "deepen 0\n").is_err());
+        assert!(parse_upload_option(&mut options, "deepen-not refs/heads/main\n").is_err());
+        assert!(parse_upload_option(&mut options, "filter tree:0\n").is_err());
+    }
+
     #[test]
     pub fn test_parse_ref_update() {
         let mock = SmartProtocol::mock();
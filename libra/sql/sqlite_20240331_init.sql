@@ -5,6 +5,19 @@ CREATE TABLE IF NOT EXISTS `config` (
     `key` TEXT NOT NULL,
     `value` TEXT NOT NULL
 );
+CREATE TABLE IF NOT EXISTS `stash` (
+    `id` INTEGER PRIMARY KEY AUTOINCREMENT,
+    `commit` TEXT NOT NULL
+);
+CREATE TABLE IF NOT EXISTS `reflog` (
+    `id` INTEGER PRIMARY KEY AUTOINCREMENT,
+    `ref_name` TEXT NOT NULL,
+    `old_oid` TEXT NOT NULL,
+    `new_oid` TEXT NOT NULL,
+    `committer` TEXT NOT NULL,
+    `timestamp` INTEGER NOT NULL,
+    `message` TEXT NOT NULL
+);
 CREATE TABLE IF NOT EXISTS `reference` (
     `id` INTEGER PRIMARY KEY AUTOINCREMENT,
     -- name can't be ''
@@ -18,9 +31,9 @@ CREATE TABLE IF NOT EXISTS `reference` (
     )
 );
 --  (name, kind, remote) as unique key when remote is not null
-CREATE UNIQUE INDEX idx_name_kind_remote ON `reference`(`name`, `kind`, `remote`)
+CREATE UNIQUE INDEX IF NOT EXISTS idx_name_kind_remote ON `reference`(`name`, `kind`, `remote`)
 WHERE `remote` IS NOT NULL;
 
 -- (name, kind) as unique key when remote is null
-CREATE UNIQUE INDEX idx_name_kind ON `reference`(`name`, `kind`)
+CREATE UNIQUE INDEX IF NOT EXISTS idx_name_kind ON `reference`(`name`, `kind`)
 WHERE `remote` IS NULL;
\ No newline at end of file
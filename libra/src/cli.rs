@@ -50,6 +50,30 @@ enum Commands {
     Switch(command::switch::SwitchArgs),
     #[command(about = "Merge changes")]
     Merge(command::merge::MergeArgs),
+    #[command(about = "Reapply commits on top of another base tip")]
+    Rebase(command::rebase::RebaseArgs),
+    #[command(about = "Show various types of objects")]
+    Show(command::show::ShowArgs),
+    #[command(about = "Provide content, type or size information for repository objects")]
+    CatFile(command::cat_file::CatFileArgs),
+    #[command(about = "Show what revision and author last modified each line of a file")]
+    Blame(command::blame::BlameArgs),
+    #[command(about = "Apply the changes introduced by some existing commits")]
+    CherryPick(command::cherry_pick::CherryPickArgs),
+    #[command(about = "Revert some existing commits")]
+    Revert(command::revert::RevertArgs),
+    #[command(about = "Reset current HEAD to the specified state")]
+    Reset(command::reset::ResetArgs),
+    #[command(about = "Stash the changes in a dirty working directory away")]
+    Stash(command::stash::StashArgs),
+    #[command(about = "Manage multiple working trees attached to the same repository")]
+    Worktree(command::worktree::WorktreeArgs),
+    #[command(about = "Reduce your working tree to a subset of tracked files")]
+    SparseCheckout(command::sparse_checkout::SparseCheckoutArgs),
+    #[command(about = "Manage reflog information")]
+    Reflog(command::reflog::ReflogArgs),
+    #[command(about = "Create, list, delete tags")]
+    Tag(command::tag::TagArgs),
     #[command(about = "Update remote refs along with associated objects")]
     Push(command::push::PushArgs),
     #[command(about = "Download objects and refs from another repository")]
@@ -58,11 +82,21 @@ enum Commands {
     Pull(command::pull::PullArgs),
     #[command(about = "Show different between files")]
     Diff(command::diff::DiffArgs),
+    #[command(about = "Apply a patch to files and/or to the index")]
+    Apply(command::apply::ApplyArgs),
 
     #[command(subcommand, about = "Manage set of tracked repositories")]
     Remote(command::remote::RemoteCmds),
     #[command(about = "Manage repository configurations")]
     Config(command::config::ConfigArgs),
+    #[command(about = "Cleanup unnecessary files and optimize the local repository")]
+    Gc(command::gc::GcArgs),
+    #[command(about = "Pack unpacked objects in a repository")]
+    Repack(command::repack::RepackArgs),
+    #[command(about = "Verify the connectivity and validity of the objects in the database")]
+    Fsck(command::fsck::FsckArgs),
+    #[command(about = "Check the signatures of commits")]
+    VerifyCommit(command::verify_commit::VerifyCommitArgs),
 
     // other hidden commands
     #[command(
@@ -107,13 +141,30 @@ pub async fn parse_async(args: Option<&[&str]>) -> Result<(), GitError> {
         Commands::Commit(args) => command::commit::execute(args).await,
         Commands::Switch(args) => command::switch::execute(args).await,
         Commands::Merge(args) => command::merge::execute(args).await,
+        Commands::Rebase(args) => command::rebase::execute(args).await,
+        Commands::Show(args) => command::show::execute(args).await,
+        Commands::CatFile(args) => command::cat_file::execute(args).await?,
+        Commands::Blame(args) => command::blame::execute(args).await,
+        Commands::CherryPick(args) => command::cherry_pick::execute(args).await,
+        Commands::Revert(args) => command::revert::execute(args).await,
+        Commands::Reset(args) => command::reset::execute(args).await,
+        Commands::Stash(args) => command::stash::execute(args).await,
+        Commands::Worktree(args) => command::worktree::execute(args).await,
+        Commands::SparseCheckout(args) => command::sparse_checkout::execute(args).await,
+        Commands::Reflog(args) => command::reflog::execute(args).await,
+        Commands::Tag(args) => command::tag::execute(args).await,
         Commands::Push(args) => command::push::execute(args).await,
         Commands::IndexPack(args) => command::index_pack::execute(args),
         Commands::Fetch(args) => command::fetch::execute(args).await,
         Commands::Diff(args) => command::diff::execute(args).await,
+        Commands::Apply(args) => command::apply::execute(args).await?,
         Commands::Remote(cmd) => command::remote::execute(cmd).await,
         Commands::Pull(args) => command::pull::execute(args).await,
         Commands::Config(args) => command::config::execute(args).await,
+        Commands::Gc(args) => command::gc::execute(args).await,
+        Commands::Repack(args) => command::repack::execute(args).await,
+        Commands::Fsck(args) => command::fsck::execute(args).await?,
+        Commands::VerifyCommit(args) => command::verify_commit::execute(args).await?,
     }
     Ok(())
 }
@@ -5,7 +5,8 @@ use mercury::internal::index::{Index, IndexEntry};
 use mercury::internal::object::blob::Blob;
 use std::path::{Path, PathBuf};
 
-use crate::utils::{lfs, path, util};
+use crate::utils::path_ext::PathExt;
+use crate::utils::{ignore, lfs, path, util};
 
 #[derive(Parser, Debug)]
 pub struct AddArgs {
@@ -27,10 +28,13 @@ pub struct AddArgs {
     /// more detailed output
     #[clap(short, long)]
     pub verbose: bool,
+
+    /// allow adding otherwise ignored files
+    #[clap(short, long)]
+    pub force: bool,
 }
 
 pub async fn execute(args: AddArgs) {
-    // TODO .gitignore
     if !util::check_repo_exist() {
         return;
     }
@@ -57,15 +61,38 @@ pub async fn execute(args: AddArgs) {
         changes.deleted = util::filter_to_fit_paths(&changes.deleted, &paths);
     }
 
+    let index_file = path::index();
+    let mut index = Index::load(&index_file).unwrap();
+
     let mut files = changes.modified;
     files.extend(changes.deleted);
     // `--update` only operates on tracked files, not including `new` files
     if !args.update {
         files.extend(changes.new);
     }
+    // adding an unmerged (conflicted) file marks it as resolved
+    let unmerged: Vec<PathBuf> = index.unmerged_files().iter().map(PathBuf::from).collect();
+    files.extend(util::filter_to_fit_paths(&unmerged, &paths));
+
+    // ignored files are only added when they are given explicitly with `--force`
+    let ignored: Vec<PathBuf> = paths
+        .iter()
+        .filter(|p| p.is_file() && ignore::is_path_ignored(p))
+        .map(|p| p.to_workdir())
+        .filter(|p| !index.tracked(p.to_str().unwrap(), 0))
+        .collect();
+    if !ignored.is_empty() {
+        if args.force {
+            files.extend(ignored);
+        } else {
+            println!("The following paths are ignored by one of your .gitignore files:");
+            for file in &ignored {
+                println!("{}", util::workdir_to_current(file).display());
+            }
+            println!("hint: Use -f if you really want to add them.");
+        }
+    }
 
-    let index_file = path::index();
-    let mut index = Index::load(&index_file).unwrap();
     for file in &files {
         add_a_file(file, &mut index, args.verbose).await;
     }
@@ -85,7 +112,7 @@ async fn add_a_file(file: &Path, index: &mut Index, verbose: bool) {
         );
         return;
     }
-    if util::is_sub_path(file, util::storage_path()) {
+    if util::is_sub_path(file, workdir.join(util::ROOT_DIR)) {
         // file is in `.libra`
         // Git won't print this
         println!(
@@ -98,8 +125,17 @@ async fn add_a_file(file: &Path, index: &mut Index, verbose: bool) {
 
     let file_abs = util::workdir_to_absolute(file);
     let file_str = file.to_str().unwrap();
+    let unmerged = index.unmerged_files().iter().any(|f| f == file_str);
+    if unmerged {
+        index.remove_unmerged(file_str);
+    }
     if !file_abs.exists() {
-        if index.tracked(file_str, 0) {
+        if unmerged {
+            // conflict resolved by deleting the file
+            if verbose {
+                println!("removed: {}", file_str);
+            }
+        } else if index.tracked(file_str, 0) {
             // file is removed
             index.remove(file_str, 0);
             if verbose {
@@ -145,7 +181,7 @@ async fn add_a_file(file: &Path, index: &mut Index, verbose: bool) {
 
 /// Generate a `Blob` from a file
 /// - if the file is tracked by LFS, generate a `Blob` with pointer file
-fn gen_blob_from_file(path: impl AsRef<Path>) -> Blob {
+pub(crate) fn gen_blob_from_file(path: impl AsRef<Path>) -> Blob {
     if lfs::is_lfs_tracked(&path) {
         Blob::from_lfs_file(&path)
     } else {
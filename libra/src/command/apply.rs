@@ -0,0 +1,914 @@
+//! Apply the patches of `libra diff` (or `git diff`) to the working tree and/or the index.
+//!
+//! All the files are checked before any of them is written, so a patch is applied entirely or not at all.
+use std::fs;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+
+use clap::Parser;
+use flate2::read::ZlibDecoder;
+use mercury::errors::GitError;
+use mercury::hash::SHA1;
+use mercury::internal::index::{Index, IndexEntry};
+use mercury::internal::object::blob::Blob;
+use mercury::internal::object::types::ObjectType;
+
+use crate::command::{calc_file_blob_hash, fetch, load_object};
+use crate::utils::object_ext::BlobExt;
+use crate::utils::{base85, path, util};
+
+#[derive(Parser, Debug)]
+pub struct ApplyArgs {
+    /// The patch files, read from stdin if not given or `-`
+    pub patches: Vec<String>,
+
+    /// Only check if the patches can be applied, nothing is changed
+    #[clap(long)]
+    pub check: bool,
+
+    /// Apply to the index only, the working tree is not touched
+    #[clap(long, group = "target")]
+    pub cached: bool,
+
+    /// Apply to both the index and the working tree, which must match the index
+    #[clap(long, group = "target")]
+    pub index: bool,
+
+    /// Apply the patches in reverse
+    #[clap(short = 'R', long)]
+    pub reverse: bool,
+}
+
+/// Where the patches are applied
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Target {
+    WorkTree,
+    Index,
+    Both,
+}
+
+/// The patch of a file
+#[derive(Debug, Default)]
+struct FilePatch {
+    /// `None` for new files
+    old_path: Option<PathBuf>,
+    /// `None` for deleted files
+    new_path: Option<PathBuf>,
+    /// The old file is kept (`copy from`)
+    copy: bool,
+    /// The hashes of `index <old>..<new>`, full hashes are checked for binary patches
+    old_hash: Option<String>,
+    new_hash: Option<String>,
+    hunks: Vec<Hunk>,
+    /// `GIT binary patch`: the forward & reverse hunks
+    binary: Option<(BinaryHunk, Option<BinaryHunk>)>,
+    /// `Binary files ... differ`, which can't be applied
+    binary_without_data: bool,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Hunk {
+    /// 1-based, or the line before the hunk if the old range is empty
+    old_start: usize,
+    new_start: usize,
+    /// (` `, `-` or `+`, the line with `\n` unless it's the end of file without newline)
+    lines: Vec<(char, String)>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum BinaryHunk {
+    Literal(Vec<u8>),
+    Delta(Vec<u8>),
+}
+
+impl FilePatch {
+    fn path(&self) -> &Path {
+        self.new_path
+            .as_deref()
+            .or(self.old_path.as_deref())
+            .unwrap()
+    }
+
+    fn reverse(self) -> Result<FilePatch, String> {
+        if self.copy {
+            return Err(format!(
+                "{}: copies can't be applied in reverse",
+                self.path().display()
+            ));
+        }
+        let binary = match self.binary {
+            Some((_, Some(reverse))) => Some((reverse, None)),
+            Some((_, None)) => {
+                return Err(format!(
+                    "{}: the binary patch has no reverse hunk",
+                    self.path().display()
+                ))
+            }
+            None => None,
+        };
+        let hunks = self
+            .hunks
+            .into_iter()
+            .map(|hunk| Hunk {
+                old_start: hunk.new_start,
+                new_start: hunk.old_start,
+                lines: hunk
+                    .lines
+                    .into_iter()
+                    .map(|(tag, line)| match tag {
+                        '-' => ('+', line),
+                        '+' => ('-', line),
+                        _ => (tag, line),
+                    })
+                    .collect(),
+            })
+            .collect();
+        Ok(FilePatch {
+            old_path: self.new_path,
+            new_path: self.old_path,
+            copy: false,
+            old_hash: self.new_hash,
+            new_hash: self.old_hash,
+            hunks,
+            binary,
+            binary_without_data: self.binary_without_data,
+        })
+    }
+}
+
+pub async fn execute(args: ApplyArgs) -> Result<(), GitError> {
+    if !util::check_repo_exist() {
+        return Ok(());
+    }
+    let target = match (args.cached, args.index) {
+        (true, _) => Target::Index,
+        (_, true) => Target::Both,
+        _ => Target::WorkTree,
+    };
+
+    let mut patches = Vec::new();
+    let inputs = if args.patches.is_empty() {
+        vec!["-".to_string()]
+    } else {
+        args.patches.clone()
+    };
+    for input in inputs.iter() {
+        let text = if input == "-" {
+            let mut text = String::new();
+            io::stdin().read_to_string(&mut text).map(|_| text)
+        } else {
+            fs::read_to_string(input)
+        };
+        let text = text.map_err(|e| fail(format!("can't read patch '{}': {}", input, e)))?;
+        let parsed = parse_patch(&text).map_err(|e| fail(format!("{}: {}", input, e)))?;
+        if parsed.is_empty() {
+            return Err(fail(format!("No valid patches in input ({})", input)));
+        }
+        patches.extend(parsed);
+    }
+
+    let mut index = Index::load(path::index()).unwrap();
+    if target != Target::WorkTree {
+        // partial clone: fetch the preimages in the index at once
+        let files = patches
+            .iter()
+            .flat_map(|patch| [patch.old_path.as_ref(), patch.new_path.as_ref()]);
+        let blobs = files.flatten().filter_map(|file| index.get_hash(file.to_str().unwrap(), 0));
+        fetch::prefetch_blobs(blobs.collect::<Vec<_>>()).await;
+    }
+    // the new contents of the files in order, `None` if the file is deleted
+    let mut results: Vec<(PathBuf, Option<Vec<u8>>)> = Vec::new();
+    for patch in patches {
+        let patch = if args.reverse {
+            patch.reverse().map_err(fail)?
+        } else {
+            patch
+        };
+        apply_file_patch(&patch, target, &index, &mut results).map_err(fail)?;
+    }
+    if args.check {
+        return Ok(());
+    }
+
+    let workdir = util::working_dir();
+    for (file, content) in results.iter() {
+        let file_abs = util::workdir_to_absolute(file);
+        let name = file.to_str().unwrap();
+        if target != Target::Index {
+            match content {
+                Some(data) => {
+                    fs::create_dir_all(file_abs.parent().unwrap()).unwrap();
+                    fs::write(&file_abs, data).unwrap();
+                }
+                None => {
+                    if file_abs.exists() {
+                        fs::remove_file(&file_abs).unwrap();
+                        util::clear_empty_dir(&file_abs);
+                    }
+                }
+            }
+        }
+        if target != Target::WorkTree {
+            match content {
+                Some(data) => {
+                    let blob = Blob::from_content_bytes(data.clone());
+                    blob.save();
+                    let entry = if target == Target::Both {
+                        IndexEntry::new_from_file(file, blob.id, &workdir).unwrap()
+                    } else {
+                        IndexEntry::new_from_blob(name.to_string(), blob.id, data.len() as u32)
+                    };
+                    index.update(entry);
+                }
+                None => {
+                    index.remove(name, 0);
+                }
+            }
+        }
+    }
+    if target != Target::WorkTree {
+        index.save(path::index()).unwrap();
+    }
+    Ok(())
+}
+
+/// Print the error & convert it to [GitError]
+fn fail(message: String) -> GitError {
+    eprintln!("error: {}", message);
+    GitError::CustomError("patch failed".to_string())
+}
+
+/// Check the patch against the current content (or the result of previous patches),
+/// and record the new content to `results`
+fn apply_file_patch(
+    patch: &FilePatch,
+    target: Target,
+    index: &Index,
+    results: &mut Vec<(PathBuf, Option<Vec<u8>>)>,
+) -> Result<(), String> {
+    let current = |file: &Path| -> Result<Option<Vec<u8>>, String> {
+        match results.iter().rev().find(|(path, _)| path == file) {
+            Some((_, content)) => Ok(content.clone()),
+            None => read_current(file, target, index),
+        }
+    };
+    let where_ = match target {
+        Target::WorkTree => "working directory",
+        _ => "index",
+    };
+
+    let preimage = match &patch.old_path {
+        Some(old_path) => current(old_path)?
+            .ok_or_else(|| format!("{}: does not exist in {}", old_path.display(), where_))?,
+        None => Vec::new(),
+    };
+    if let Some(new_path) = &patch.new_path {
+        if patch.old_path.as_ref() != Some(new_path) && current(new_path)?.is_some() {
+            return Err(format!(
+                "{}: already exists in {}",
+                new_path.display(),
+                where_
+            ));
+        }
+    }
+
+    let file = patch.path().display();
+    let postimage = if let Some((hunk, _)) = &patch.binary {
+        // check the old content with the full hash
+        if let Some(old_hash) = patch.old_hash.as_ref().filter(|hash| hash.len() == 40) {
+            let hash = match patch.old_path {
+                Some(_) => SHA1::from_type_and_data(ObjectType::Blob, &preimage).to_string(),
+                None => "0".repeat(40),
+            };
+            if hash != *old_hash {
+                return Err(format!("{}: the patch does not apply", file));
+            }
+        }
+        match hunk {
+            BinaryHunk::Literal(data) => data.clone(),
+            BinaryHunk::Delta(delta) => {
+                apply_delta(&preimage, delta).map_err(|e| format!("{}: {}", file, e))?
+            }
+        }
+    } else if patch.binary_without_data {
+        return Err(format!(
+            "cannot apply binary patch to '{}' without full index line",
+            file
+        ));
+    } else {
+        let text = String::from_utf8(preimage)
+            .map_err(|_| format!("{}: cannot apply a text patch to a binary file", file))?;
+        apply_hunks(&text, &patch.hunks)
+            .map_err(|line| format!("patch failed: {}:{}", file, line))?
+            .into_bytes()
+    };
+
+    match &patch.new_path {
+        Some(new_path) => {
+            if let Some(old_path) = &patch.old_path {
+                if old_path != new_path && !patch.copy {
+                    results.push((old_path.clone(), None)); // renamed
+                }
+            }
+            results.push((new_path.clone(), Some(postimage)));
+        }
+        None => {
+            if !postimage.is_empty() {
+                return Err(format!("{}: removal patch leaves file contents", file));
+            }
+            results.push((patch.old_path.clone().unwrap(), None));
+        }
+    }
+    Ok(())
+}
+
+/// The content of the file in the target, `None` if it doesn't exist
+fn read_current(file: &Path, target: Target, index: &Index) -> Result<Option<Vec<u8>>, String> {
+    let file_abs = util::workdir_to_absolute(file);
+    let index_hash = index.get_hash(file.to_str().unwrap(), 0);
+    if target == Target::Both {
+        let workdir_hash = match file_abs.exists() {
+            true => Some(calc_file_blob_hash(&file_abs).map_err(|e| e.to_string())?),
+            false => None,
+        };
+        if workdir_hash != index_hash {
+            return Err(format!("{}: does not match index", file.display()));
+        }
+    }
+    match target {
+        Target::WorkTree => match file_abs.exists() {
+            true => fs::read(&file_abs).map(Some).map_err(|e| e.to_string()),
+            false => Ok(None),
+        },
+        Target::Index | Target::Both => match index_hash {
+            Some(hash) => load_object::<Blob>(&hash)
+                .map(|blob| Some(blob.data))
+                .map_err(|e| e.to_string()),
+            None => Ok(None),
+        },
+    }
+}
+
+/// Apply the hunks to the text, the hunks can be found at other lines (offset) but the context must match.
+/// - `Err` is the line number of the hunk that doesn't apply
+fn apply_hunks(text: &str, hunks: &[Hunk]) -> Result<String, usize> {
+    let lines: Vec<&str> = text.split_inclusive('\n').collect();
+    let mut result = String::with_capacity(text.len());
+    // the next line to apply
+    let mut next = 0;
+    // the difference between the found & expected lines of the previous hunk
+    let mut offset: isize = 0;
+    for hunk in hunks {
+        let old_lines: Vec<&str> = hunk
+            .lines
+            .iter()
+            .filter(|(tag, _)| *tag != '+')
+            .map(|(_, line)| line.as_str())
+            .collect();
+        let expected = match old_lines.is_empty() {
+            true => hunk.old_start,
+            false => hunk.old_start.saturating_sub(1),
+        };
+        let start = (expected as isize + offset).max(next as isize) as usize;
+        let found = find_lines(&lines, &old_lines, start, next).ok_or(hunk.old_start)?;
+        offset = found as isize - expected as isize;
+
+        result.extend(lines[next..found].iter().copied());
+        for (tag, line) in hunk.lines.iter() {
+            if *tag != '-' {
+                result.push_str(line);
+            }
+        }
+        next = found + old_lines.len();
+    }
+    result.extend(lines[next..].iter().copied());
+    Ok(result)
+}
+
+/// Find `expected` in `lines` from `start`, then the lines around it (but not before `min`)
+fn find_lines(lines: &[&str], expected: &[&str], start: usize, min: usize) -> Option<usize> {
+    let matches = |pos: usize| {
+        pos + expected.len() <= lines.len() && lines[pos..pos + expected.len()] == *expected
+    };
+    for distance in 0..=lines.len() {
+        if matches(start + distance) {
+            return Some(start + distance);
+        }
+        if distance > 0 && start >= min + distance && matches(start - distance) {
+            return Some(start - distance);
+        }
+    }
+    None
+}
+
+/// Apply the git delta (the format of packs): the sizes, then the instructions to copy from the base or insert data
+fn apply_delta(base: &[u8], delta: &[u8]) -> Result<Vec<u8>, String> {
+    let invalid = || "invalid delta".to_string();
+    let mut pos = 0;
+    let read_byte = |pos: &mut usize| -> Result<u8, String> {
+        let byte = *delta.get(*pos).ok_or_else(invalid)?;
+        *pos += 1;
+        Ok(byte)
+    };
+    // little-endian 7 bits per byte, the MSB means there are more bytes
+    let read_size = |pos: &mut usize| -> Result<usize, String> {
+        let mut size = 0;
+        for shift in (0..64).step_by(7) {
+            let byte = read_byte(pos)?;
+            size |= ((byte & 0x7f) as usize) << shift;
+            if byte & 0x80 == 0 {
+                return Ok(size);
+            }
+        }
+        Err(invalid())
+    };
+    if read_size(&mut pos)? != base.len() {
+        return Err("the delta doesn't match the old content".to_string());
+    }
+    // the size is untrusted, the result grows with the instructions and is bounded by it
+    let size = read_size(&mut pos)?;
+    let mut result = Vec::new();
+    while pos < delta.len() {
+        let instruction = read_byte(&mut pos)?;
+        if instruction & 0x80 != 0 {
+            // copy: the bits are the present bytes of the offset (4) & size (3)
+            let (mut offset, mut len) = (0usize, 0usize);
+            for i in 0..4 {
+                if instruction & (1 << i) != 0 {
+                    offset |= (read_byte(&mut pos)? as usize) << (8 * i);
+                }
+            }
+            for i in 0..3 {
+                if instruction & (0x10 << i) != 0 {
+                    len |= (read_byte(&mut pos)? as usize) << (8 * i);
+                }
+            }
+            if len == 0 {
+                len = 0x10000;
+            }
+            let data = base.get(offset..offset + len).ok_or_else(invalid)?;
+            if result.len() + data.len() > size {
+                return Err(invalid());
+            }
+            result.extend_from_slice(data);
+        } else if instruction != 0 {
+            // insert the following bytes
+            let len = instruction as usize;
+            let data = delta.get(pos..pos + len).ok_or_else(invalid)?;
+            if result.len() + data.len() > size {
+                return Err(invalid());
+            }
+            result.extend_from_slice(data);
+            pos += len;
+        } else {
+            return Err(invalid());
+        }
+    }
+    if result.len() != size {
+        return Err(invalid());
+    }
+    Ok(result)
+}
+
+/// Parse the patches of files, the text before the first `diff --git` (e.g. the commit message) is skipped
+fn parse_patch(text: &str) -> Result<Vec<FilePatch>, String> {
+    let lines: Vec<&str> = text.split_inclusive('\n').collect();
+    let mut patches = Vec::new();
+    let mut i = 0;
+    while i < lines.len() {
+        let Some(names) = lines[i].trim_end().strip_prefix("diff --git ") else {
+            i += 1;
+            continue;
+        };
+        let (old_path, new_path) =
+            parse_git_names(names).ok_or_else(|| format!("invalid 'diff --git' line {}", i + 1))?;
+        let mut patch = FilePatch {
+            old_path: Some(old_path),
+            new_path: Some(new_path),
+            ..Default::default()
+        };
+        i += 1;
+        while i < lines.len() && !lines[i].starts_with("diff --git ") {
+            let line = lines[i].trim_end_matches('\n');
+            if line.starts_with("@@ ") {
+                let (hunk, next) = parse_hunk(&lines, i)?;
+                patch.hunks.push(hunk);
+                i = next;
+                continue;
+            }
+            if line == "GIT binary patch" {
+                let (forward, next) = parse_binary_hunk(&lines, i + 1)?
+                    .ok_or_else(|| format!("corrupt binary patch at line {}", i + 2))?;
+                let (reverse, next) = match parse_binary_hunk(&lines, next)? {
+                    Some((reverse, next)) => (Some(reverse), next),
+                    None => (None, next),
+                };
+                patch.binary = Some((forward, reverse));
+                i = next;
+                continue;
+            }
+            if line.starts_with("new file mode ") {
+                patch.old_path = None;
+            } else if line.starts_with("deleted file mode ") {
+                patch.new_path = None;
+            } else if let Some(from) = line.strip_prefix("rename from ") {
+                patch.old_path = Some(PathBuf::from(from));
+            } else if let Some(to) = line.strip_prefix("rename to ") {
+                patch.new_path = Some(PathBuf::from(to));
+            } else if let Some(from) = line.strip_prefix("copy from ") {
+                patch.old_path = Some(PathBuf::from(from));
+                patch.copy = true;
+            } else if let Some(to) = line.strip_prefix("copy to ") {
+                patch.new_path = Some(PathBuf::from(to));
+            } else if let Some(hashes) = line.strip_prefix("index ") {
+                // `index <old>..<new> [<mode>]`
+                let hashes = hashes.split(' ').next().unwrap();
+                if let Some((old, new)) = hashes.split_once("..") {
+                    patch.old_hash = Some(old.to_string());
+                    patch.new_hash = Some(new.to_string());
+                }
+            } else if let Some(old) = line.strip_prefix("--- ") {
+                patch.old_path = parse_patch_path(old, "a/");
+            } else if let Some(new) = line.strip_prefix("+++ ") {
+                patch.new_path = parse_patch_path(new, "b/");
+            } else if line.starts_with("Binary files ") {
+                patch.binary_without_data = true;
+            }
+            i += 1;
+        }
+        if patch.old_path.is_none() && patch.new_path.is_none() {
+            return Err("a patch without file names".to_string());
+        }
+        for file in patch.old_path.iter().chain(patch.new_path.iter()) {
+            if !is_valid_path(file) {
+                return Err(format!("invalid path '{}'", file.display()));
+            }
+        }
+        patches.push(patch);
+    }
+    Ok(patches)
+}
+
+/// `a/<old> b/<new>` of `diff --git`, the paths are the same unless it's renamed (fixed by `rename from/to` later)
+fn parse_git_names(names: &str) -> Option<(PathBuf, PathBuf)> {
+    let names = names.strip_prefix("a/")?;
+    // `<path> b/<path>` if the paths are the same, which may contain ` b/`
+    if let Some(len) = names.len().checked_sub(3).filter(|len| len % 2 == 0) {
+        let len = len / 2;
+        if names.is_char_boundary(len)
+            && names[len..].starts_with(" b/")
+            && names[..len] == names[len + 3..]
+        {
+            return Some((PathBuf::from(&names[..len]), PathBuf::from(&names[..len])));
+        }
+    }
+    let (old, new) = names.split_once(" b/")?;
+    Some((PathBuf::from(old), PathBuf::from(new)))
+}
+
+/// The path must be relative & inside the working tree, like `git apply`:
+/// no absolute path, `.`, `..` or the repository directory
+fn is_valid_path(path: &Path) -> bool {
+    let Some(path) = path.to_str() else {
+        return false;
+    };
+    path.split(['/', '\\']).all(|name| {
+        !name.is_empty()
+            && name != "."
+            && name != ".."
+            && !name.eq_ignore_ascii_case(util::ROOT_DIR)
+    }) && !Path::new(path).has_root()
+}
+
+/// The path of `---` & `+++` lines without the prefix, `None` for `/dev/null`
+fn parse_patch_path(path: &str, prefix: &str) -> Option<PathBuf> {
+    // git appends a tab if the path contains spaces
+    let path = path.trim_end_matches('\t');
+    if path == "/dev/null" {
+        return None;
+    }
+    Some(PathBuf::from(path.strip_prefix(prefix).unwrap_or(path)))
+}
+
+/// Parse the hunk from the `@@ -<old start>,<old len> +<new start>,<new len> @@` line,
+/// return the hunk & the next line
+fn parse_hunk(lines: &[&str], start: usize) -> Result<(Hunk, usize), String> {
+    let corrupt = |i: usize| format!("corrupt patch at line {}", i + 1);
+    let parse_range = |range: &str| -> Option<(usize, usize)> {
+        match range.split_once(',') {
+            Some((start, len)) => Some((start.parse().ok()?, len.parse().ok()?)),
+            None => Some((range.parse().ok()?, 1)),
+        }
+    };
+    let ranges = lines[start]
+        .strip_prefix("@@ -")
+        .and_then(|rest| rest.split_once(" @@"))
+        .map(|(ranges, _)| ranges)
+        .ok_or_else(|| corrupt(start))?;
+    let (old, new) = ranges.split_once(" +").ok_or_else(|| corrupt(start))?;
+    let (old_start, mut old_len) = parse_range(old).ok_or_else(|| corrupt(start))?;
+    let (new_start, mut new_len) = parse_range(new).ok_or_else(|| corrupt(start))?;
+
+    let mut hunk = Hunk {
+        old_start,
+        new_start,
+        lines: Vec::new(),
+    };
+    let mut i = start + 1;
+    while old_len > 0 || new_len > 0 {
+        let line = *lines.get(i).ok_or_else(|| corrupt(i))?;
+        let (tag, content) = match line.chars().next() {
+            Some(tag @ (' ' | '-' | '+')) => (tag, &line[1..]),
+            // an empty context line, whose space may be removed by editors
+            Some('\n') => (' ', line),
+            Some('\\') => {
+                no_newline(&mut hunk);
+                i += 1;
+                continue;
+            }
+            _ => return Err(corrupt(i)),
+        };
+        match tag {
+            ' ' if old_len > 0 && new_len > 0 => {
+                old_len -= 1;
+                new_len -= 1;
+            }
+            '-' if old_len > 0 => old_len -= 1,
+            '+' if new_len > 0 => new_len -= 1,
+            _ => return Err(corrupt(i)),
+        }
+        hunk.lines.push((tag, content.to_string()));
+        i += 1;
+    }
+    // `\ No newline at end of file` of the last line
+    if lines.get(i).is_some_and(|line| line.starts_with('\\')) {
+        no_newline(&mut hunk);
+        i += 1;
+    }
+    Ok((hunk, i))
+}
+
+/// The last line of the hunk has no newline
+fn no_newline(hunk: &mut Hunk) {
+    if let Some((_, line)) = hunk.lines.last_mut() {
+        if line.ends_with('\n') {
+            line.pop();
+        }
+    }
+}
+
+/// Parse `literal <size>` or `delta <size>` & the base85 lines until an empty line,
+/// return `None` if it's not a binary hunk
+fn parse_binary_hunk(lines: &[&str], start: usize) -> Result<Option<(BinaryHunk, usize)>, String> {
+    let Some(header) = lines.get(start).map(|line| line.trim_end()) else {
+        return Ok(None);
+    };
+    let (literal, size) = if let Some(size) = header.strip_prefix("literal ") {
+        (true, size)
+    } else if let Some(size) = header.strip_prefix("delta ") {
+        (false, size)
+    } else {
+        return Ok(None);
+    };
+    let corrupt = |i: usize| format!("corrupt binary patch at line {}", i + 1);
+    let size: usize = size.parse().map_err(|_| corrupt(start))?;
+
+    let mut compressed = Vec::new();
+    let mut i = start + 1;
+    loop {
+        let line = lines.get(i).ok_or_else(|| corrupt(i))?.trim_end();
+        i += 1;
+        if line.is_empty() {
+            break;
+        }
+        // the length of the line: `A-Z` for 1-26 bytes, `a-z` for 27-52 bytes
+        let len = match line.as_bytes()[0] {
+            c @ b'A'..=b'Z' => c - b'A' + 1,
+            c @ b'a'..=b'z' => c - b'a' + 27,
+            _ => return Err(corrupt(i - 1)),
+        };
+        compressed.extend(base85::decode(&line[1..], len as usize).map_err(|_| corrupt(i - 1))?);
+    }
+    // the size is untrusted, read one more byte to find the data longer than it
+    let mut data = Vec::new();
+    ZlibDecoder::new(compressed.as_slice())
+        .take((size as u64).saturating_add(1))
+        .read_to_end(&mut data)
+        .map_err(|_| corrupt(start))?;
+    if data.len() != size {
+        return Err(corrupt(start));
+    }
+    let hunk = match literal {
+        true => BinaryHunk::Literal(data),
+        false => BinaryHunk::Delta(data),
+    };
+    Ok(Some((hunk, i)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::command::diff::{self, DiffOptions};
+    use crate::utils::test;
+
+    const PATCH: &str = "diff --git a/src/lib.rs b/src/lib.rs
+index 1234567..89abcde 100644
+--- a/src/lib.rs
++++ b/src/lib.rs
+@@ -2,3 +2,3 @@
+ b
+-c
++C
+ d
+@@ -7,2 +7,3 @@
+ g
+ h
++i
+\\ No newline at end of file
+diff --git a/new.txt b/new.txt
+new file mode 100644
+index 0000000..1234567
+--- /dev/null
++++ b/new.txt
+@@ -0,0 +1 @@
++new
+";
+
+    #[test]
+    fn test_parse_patch() {
+        let patches = parse_patch(PATCH).unwrap();
+        assert_eq!(patches.len(), 2);
+        let patch = &patches[0];
+        assert_eq!(patch.old_path, Some(PathBuf::from("src/lib.rs")));
+        assert_eq!(patch.new_path, Some(PathBuf::from("src/lib.rs")));
+        assert_eq!(patch.old_hash.as_deref(), Some("1234567"));
+        assert_eq!(patch.hunks.len(), 2);
+        assert_eq!(
+            patch.hunks[1].lines.last().unwrap(),
+            &('+', "i".to_string())
+        );
+        assert_eq!(patches[1].old_path, None);
+        assert_eq!(patches[1].new_path, Some(PathBuf::from("new.txt")));
+
+        assert_eq!(
+            parse_git_names("a/with b/space b/with b/space"),
+            Some((PathBuf::from("with b/space"), PathBuf::from("with b/space")))
+        );
+        assert_eq!(
+            parse_git_names("a/old.txt b/new.txt"),
+            Some((PathBuf::from("old.txt"), PathBuf::from("new.txt")))
+        );
+        assert_eq!(
+            parse_git_names("a/x b/x"),
+            Some((PathBuf::from("x"), PathBuf::from("x")))
+        );
+        assert_eq!(parse_git_names("a/x"), None);
+        assert!(parse_patch("@@ -1 +1 @@\n-a\n").unwrap().is_empty());
+
+        for path in ["../x", "/abs/path", ".libra/config", "dir/./x", "a/../../x"] {
+            let patch = format!("diff --git a/{0} b/{0}\n--- a/{0}\n+++ b/{0}\n", path);
+            assert!(parse_patch(&patch).is_err(), "{}", path);
+        }
+    }
+
+    #[test]
+    fn test_apply_hunks() {
+        let patch = &parse_patch(PATCH).unwrap()[0];
+        let text = "a\nb\nc\nd\ne\nf\ng\nh\n";
+        assert_eq!(
+            apply_hunks(text, &patch.hunks).unwrap(),
+            "a\nb\nC\nd\ne\nf\ng\nh\ni"
+        );
+        // the hunks are found with offset
+        let shifted = format!("0\n1\n{}", text);
+        assert_eq!(
+            apply_hunks(&shifted, &patch.hunks).unwrap(),
+            "0\n1\na\nb\nC\nd\ne\nf\ng\nh\ni"
+        );
+        // the context doesn't match
+        assert_eq!(apply_hunks("a\nb\nx\nd\n", &patch.hunks), Err(2));
+
+        let reversed = parse_patch(PATCH).unwrap().remove(0).reverse().unwrap();
+        assert_eq!(
+            apply_hunks("a\nb\nC\nd\ne\nf\ng\nh\ni", &reversed.hunks).unwrap(),
+            text
+        );
+    }
+
+    #[test]
+    fn test_apply_delta() {
+        let base = b"hello world";
+        // copy 6 bytes from 0, insert "libra", copy 0 bytes is invalid
+        let delta = [11, 11, 0x90, 6, 5, b'l', b'i', b'b', b'r', b'a'];
+        assert_eq!(apply_delta(base, &delta).unwrap(), b"hello libra");
+        assert!(apply_delta(b"other", &delta).is_err());
+        assert!(apply_delta(base, &[11, 12, 0x90, 6, 5, b'l']).is_err());
+        // the huge size in the header is not allocated
+        let huge = [
+            11, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x7f, 0x90, 6,
+        ];
+        assert!(apply_delta(base, &huge).is_err());
+    }
+
+    #[test]
+    fn test_parse_binary_hunk() {
+        let data = b"binary\0data";
+        let mut literal = Vec::new();
+        diff::write_binary_literal(data, &mut literal);
+        let literal = String::from_utf8(literal).unwrap();
+        let hunk = |header: &str| literal.replacen("literal 11", header, 1);
+        let parse = |text: &str| {
+            let lines: Vec<&str> = text.split_inclusive('\n').collect();
+            parse_binary_hunk(&lines, 0).map(|hunk| hunk.map(|(hunk, _)| hunk))
+        };
+        assert!(matches!(
+            parse(&hunk("literal 11")),
+            Ok(Some(BinaryHunk::Literal(d))) if d == data
+        ));
+        assert!(parse(&hunk("literal 10")).is_err());
+        assert!(parse(&hunk(&format!("literal {}", usize::MAX))).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_apply() {
+        test::setup_with_new_libra().await;
+        let _ = fs::remove_dir_all("c"); // left by the previous run
+        let old_text = Blob::from_content("line 1\nline 2\nline 3\n");
+        let new_text = Blob::from_content("line 1\nline two\nline 3\n");
+        let old_binary = Blob::from_content_bytes(vec![0xff, 0, 1, 2, 3]);
+        let new_binary = Blob::from_content_bytes(vec![0xff, 0, 9, 8, 7, 6]);
+        for blob in [&old_text, &new_text, &old_binary, &new_binary] {
+            blob.save();
+        }
+        let mut patch = Vec::new();
+        diff::diff(
+            vec![
+                (PathBuf::from("a.txt"), old_text.id),
+                (PathBuf::from("b.bin"), old_binary.id),
+            ],
+            vec![
+                (PathBuf::from("a.txt"), new_text.id),
+                (PathBuf::from("b.bin"), new_binary.id),
+                (PathBuf::from("c/new.txt"), old_text.id),
+            ],
+            vec![],
+            &DiffOptions {
+                binary: true,
+                ..Default::default()
+            },
+            &mut patch,
+        )
+        .await;
+        fs::write("changes.patch", &patch).unwrap();
+
+        test::ensure_file("a.txt", Some("line 1\nline 2\nline 3\n"));
+        fs::write("b.bin", &old_binary.data).unwrap();
+        crate::exec_async(vec!["add", "a.txt", "b.bin"])
+            .await
+            .unwrap();
+
+        crate::exec_async(vec!["apply", "--check", "changes.patch"])
+            .await
+            .unwrap();
+        assert_eq!(fs::read("a.txt").unwrap(), old_text.data);
+
+        crate::exec_async(vec!["apply", "changes.patch"])
+            .await
+            .unwrap();
+        assert_eq!(fs::read("a.txt").unwrap(), new_text.data);
+        assert_eq!(fs::read("b.bin").unwrap(), new_binary.data);
+        assert_eq!(fs::read("c/new.txt").unwrap(), old_text.data);
+        // the index is not changed
+        let index = Index::load(path::index()).unwrap();
+        assert_eq!(index.get_hash("a.txt", 0), Some(old_text.id));
+
+        // applied again: the new file exists
+        assert!(crate::exec_async(vec!["apply", "changes.patch"])
+            .await
+            .is_err());
+
+        crate::exec_async(vec!["apply", "-R", "changes.patch"])
+            .await
+            .unwrap();
+        assert_eq!(fs::read("a.txt").unwrap(), old_text.data);
+        assert_eq!(fs::read("b.bin").unwrap(), old_binary.data);
+        assert!(!Path::new("c/new.txt").exists());
+
+        // to the index only
+        crate::exec_async(vec!["apply", "--cached", "changes.patch"])
+            .await
+            .unwrap();
+        let index = Index::load(path::index()).unwrap();
+        assert_eq!(index.get_hash("a.txt", 0), Some(new_text.id));
+        assert_eq!(index.get_hash("b.bin", 0), Some(new_binary.id));
+        assert_eq!(index.get_hash("c/new.txt", 0), Some(old_text.id));
+        assert_eq!(fs::read("a.txt").unwrap(), old_text.data);
+
+        // the working tree doesn't match the index
+        assert!(
+            crate::exec_async(vec!["apply", "--index", "-R", "changes.patch"])
+                .await
+                .is_err()
+        );
+    }
+}
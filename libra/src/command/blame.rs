@@ -0,0 +1,352 @@
+use std::collections::{HashMap, VecDeque};
+use std::path::{Component, Path};
+
+use chrono::{DateTime, FixedOffset, Utc};
+use clap::Parser;
+use mercury::hash::SHA1;
+use mercury::internal::object::blob::Blob;
+use mercury::internal::object::commit::Commit;
+use mercury::internal::object::tree::{Tree, TreeItemMode};
+use similar::{Algorithm, DiffTag};
+
+use crate::utils::object_ext::{BlobExt, TreeExt};
+use crate::utils::util;
+
+use super::{fetch, get_target_commit, log};
+
+#[derive(Parser, Debug)]
+pub struct BlameArgs {
+    /// The file to annotate
+    pub file: String,
+
+    /// Annotate the file in the given revision, HEAD by default
+    pub rev: Option<String>,
+
+    /// Annotate only the given line range: `<start>,<end>`, `<start>,+<count>` or `<start>` (1-based)
+    #[clap(short = 'L', value_name = "start,end")]
+    pub line_range: Option<String>,
+}
+
+/// A line of the file, with the commit which introduced it
+#[derive(Debug, Clone)]
+pub struct BlameLine {
+    pub commit: SHA1,
+    /// 1-based line number in the annotated file
+    pub line_no: usize,
+    pub content: String,
+}
+
+pub async fn execute(args: BlameArgs) {
+    if !util::check_repo_exist() {
+        return;
+    }
+    let rev = args.rev.unwrap_or("HEAD".to_owned());
+    let commit = match get_target_commit(&rev).await {
+        Ok(commit) => commit,
+        Err(e) => {
+            eprintln!("fatal: {}", e);
+            return;
+        }
+    };
+    let file = util::to_workdir_path(&args.file);
+    let lines = match blame_file(&commit, &file).await {
+        Ok(lines) => lines,
+        Err(e) => {
+            eprintln!("fatal: {}", e);
+            return;
+        }
+    };
+    let lines = match args.line_range {
+        Some(range) => match parse_line_range(&range, lines.len()) {
+            Ok((start, end)) => &lines[start - 1..end],
+            Err(e) => {
+                eprintln!("fatal: {}", e);
+                return;
+            }
+        },
+        None => &lines[..],
+    };
+
+    let commits: HashMap<SHA1, Commit> = lines
+        .iter()
+        .map(|line| (line.commit, load_commit(&line.commit)))
+        .collect();
+    let author_width = commits
+        .values()
+        .map(|commit| commit.author.name.chars().count())
+        .max()
+        .unwrap_or(0);
+    let line_no_width = lines
+        .last()
+        .map_or(1, |line| line.line_no.to_string().len());
+    for line in lines {
+        let commit = &commits[&line.commit];
+        println!(
+            "{} ({:<author_width$} {} {:>line_no_width$}) {}",
+            &line.commit.to_string()[..8],
+            commit.author.name,
+            format_date(commit.author.timestamp, &commit.author.timezone),
+            line.line_no,
+            line.content.trim_end_matches(['\n', '\r']),
+        );
+    }
+}
+
+/// Attribute each line of `file` (to workdir) in `commit` to the commit which introduced it.
+/// - commits are visited from `commit` to the ancestors, a commit is visited after all its children
+/// - lines unchanged from a parent (by line diff) are passed to the parent, the rest are blamed on the commit
+pub async fn blame_file(commit: &SHA1, file: &Path) -> Result<Vec<BlameLine>, String> {
+    let target = load_commit(commit);
+    let blob = blob_at(&target.tree_id, file).ok_or(format!(
+        "no such path '{}' in {}",
+        file.display(),
+        commit
+    ))?;
+    let reachable = log::get_reachable_commits(commit.to_string()).await;
+    // partial clone: fetch all the versions of the file at once
+    fetch::prefetch_blobs(
+        reachable
+            .iter()
+            .filter_map(|c| blob_at(&c.tree_id, file))
+            .chain([blob]),
+    )
+    .await;
+    let content = String::from_utf8_lossy(&Blob::load(&blob).data).into_owned();
+    let final_lines: Vec<&str> = content.split_inclusive('\n').collect();
+
+    // number of children of each commit, to visit commits in topological order
+    let mut children: HashMap<SHA1, usize> = HashMap::new();
+    for c in reachable.iter() {
+        for parent in c.parent_commit_ids.iter() {
+            *children.entry(*parent).or_default() += 1;
+        }
+    }
+    let commits: HashMap<SHA1, Commit> = reachable.into_iter().map(|c| (c.id, c)).collect();
+
+    // lines to be blamed in each commit: (line in the commit's blob, line in the final file)
+    let mut pending: HashMap<SHA1, (SHA1, Vec<(usize, usize)>)> = HashMap::new();
+    pending.insert(
+        *commit,
+        (blob, (0..final_lines.len()).map(|i| (i, i)).collect()),
+    );
+    let mut blamed: Vec<Option<SHA1>> = vec![None; final_lines.len()];
+    let mut queue = VecDeque::from([*commit]);
+    while let Some(id) = queue.pop_front() {
+        let current = &commits[&id];
+        for parent in current.parent_commit_ids.iter() {
+            let count = children.get_mut(parent).unwrap();
+            *count -= 1;
+            // parents of shallow commits are not reachable
+            if *count == 0 && commits.contains_key(parent) {
+                queue.push_back(*parent);
+            }
+        }
+        let (blob, mut lines) = match pending.remove(&id) {
+            Some(pending) => pending,
+            None => continue,
+        };
+
+        let data = Blob::load(&blob).data;
+        let text = String::from_utf8_lossy(&data);
+        let current_lines: Vec<&str> = text.split_inclusive('\n').collect();
+        for parent in current.parent_commit_ids.iter() {
+            if lines.is_empty() {
+                break;
+            }
+            let parent_blob = match commits.get(parent).and_then(|p| blob_at(&p.tree_id, file)) {
+                Some(parent_blob) => parent_blob,
+                None => continue,
+            };
+            let passed = if parent_blob == blob {
+                std::mem::take(&mut lines)
+            } else {
+                let data = Blob::load(&parent_blob).data;
+                let text = String::from_utf8_lossy(&data);
+                let parent_lines: Vec<&str> = text.split_inclusive('\n').collect();
+                let unchanged = unchanged_lines(&parent_lines, &current_lines);
+                let mut passed = Vec::new();
+                lines.retain(|(line, final_line)| match unchanged.get(line) {
+                    Some(parent_line) => {
+                        passed.push((*parent_line, *final_line));
+                        false
+                    }
+                    None => true,
+                });
+                passed
+            };
+            if passed.is_empty() {
+                continue;
+            }
+            pending
+                .entry(*parent)
+                .or_insert((parent_blob, Vec::new()))
+                .1
+                .extend(passed);
+        }
+        for (_, final_line) in lines {
+            blamed[final_line] = Some(id);
+        }
+    }
+
+    Ok(final_lines
+        .into_iter()
+        .zip(blamed)
+        .enumerate()
+        .map(|(i, (content, commit))| BlameLine {
+            commit: commit.unwrap(),
+            line_no: i + 1,
+            content: content.to_owned(),
+        })
+        .collect())
+}
+
+/// Map each line of `new` which is unchanged from `old` to its index in `old`
+fn unchanged_lines(old: &[&str], new: &[&str]) -> HashMap<usize, usize> {
+    similar::capture_diff_slices(Algorithm::Myers, old, new)
+        .into_iter()
+        .filter(|op| op.tag() == DiffTag::Equal)
+        .flat_map(|op| op.new_range().zip(op.old_range()))
+        .collect()
+}
+
+/// Find the blob of `file` (to workdir) in the tree, without loading the whole tree recursively
+fn blob_at(tree: &SHA1, file: &Path) -> Option<SHA1> {
+    let mut tree = Tree::load(tree);
+    let mut components = file.components().peekable();
+    while let Some(Component::Normal(name)) = components.next() {
+        let name = name.to_str()?;
+        let item = tree.tree_items.iter().find(|item| item.name == name)?;
+        let is_tree = item.mode == TreeItemMode::Tree;
+        if components.peek().is_none() {
+            return if is_tree { None } else { Some(item.id) };
+        }
+        if !is_tree {
+            return None;
+        }
+        tree = Tree::load(&item.id);
+    }
+    None
+}
+
+fn load_commit(hash: &SHA1) -> Commit {
+    super::load_object(hash).unwrap()
+}
+
+/// e.g. `2024-01-01 12:00:00 +0800`, in the timezone of the signature
+fn format_date(timestamp: usize, timezone: &str) -> String {
+    let offset = parse_timezone(timezone).unwrap_or(FixedOffset::east_opt(0).unwrap());
+    let date = DateTime::<Utc>::from_timestamp(timestamp as i64, 0)
+        .unwrap()
+        .with_timezone(&offset);
+    format!("{} {}", date.format("%Y-%m-%d %H:%M:%S"), timezone)
+}
+
+/// Parse `+0800` / `-0530` to offset
+fn parse_timezone(timezone: &str) -> Option<FixedOffset> {
+    if timezone.len() != 5 {
+        return None;
+    }
+    let sign = match &timezone[..1] {
+        "+" => 1,
+        "-" => -1,
+        _ => return None,
+    };
+    let hours: i32 = timezone[1..3].parse().ok()?;
+    let minutes: i32 = timezone[3..5].parse().ok()?;
+    FixedOffset::east_opt(sign * (hours * 3600 + minutes * 60))
+}
+
+/// Parse `-L` to 1-based inclusive range, `total` is the number of lines of the file
+fn parse_line_range(range: &str, total: usize) -> Result<(usize, usize), String> {
+    let invalid = || format!("invalid line range: {}", range);
+    let (start, end) = match range.split_once(',') {
+        Some((start, end)) => (start, Some(end)),
+        None => (range, None),
+    };
+    let start = match start {
+        "" => 1,
+        start => start.parse::<usize>().map_err(|_| invalid())?,
+    };
+    if start == 0 {
+        return Err(invalid());
+    }
+    if start > total {
+        return Err(format!("file has only {} lines", total));
+    }
+    let end = match end {
+        None | Some("") => total,
+        Some(end) => match end.strip_prefix('+') {
+            Some(count) => {
+                let count = count.parse::<usize>().map_err(|_| invalid())?;
+                if count == 0 {
+                    return Err(invalid());
+                }
+                start + count - 1
+            }
+            None => end.parse::<usize>().map_err(|_| invalid())?,
+        },
+    };
+    if end < start {
+        return Err(invalid());
+    }
+    Ok((start, end.min(total)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::internal::head::Head;
+    use crate::utils::test;
+
+    #[test]
+    fn test_parse_line_range() {
+        assert_eq!(parse_line_range("2,4", 10), Ok((2, 4)));
+        assert_eq!(parse_line_range("2,+3", 10), Ok((2, 4)));
+        assert_eq!(parse_line_range("5", 10), Ok((5, 10)));
+        assert_eq!(parse_line_range(",3", 10), Ok((1, 3)));
+        assert_eq!(parse_line_range("8,20", 10), Ok((8, 10)));
+        assert!(parse_line_range("0,2", 10).is_err());
+        assert!(parse_line_range("4,2", 10).is_err());
+        assert!(parse_line_range("11", 10).is_err());
+        assert!(parse_line_range("a,b", 10).is_err());
+    }
+
+    #[test]
+    fn test_parse_timezone() {
+        assert_eq!(parse_timezone("+0800"), FixedOffset::east_opt(8 * 3600));
+        assert_eq!(
+            parse_timezone("-0530"),
+            FixedOffset::east_opt(-(5 * 3600 + 30 * 60))
+        );
+        assert_eq!(parse_timezone("0800"), None);
+    }
+
+    #[tokio::test]
+    async fn test_blame_file() {
+        test::setup_with_new_libra().await;
+        test::ensure_file("blame/a.txt", Some("1\n2\n3\n"));
+        crate::exec_async(vec!["add", "blame/a.txt"]).await.unwrap();
+        crate::exec_async(vec!["commit", "-m", "first"])
+            .await
+            .unwrap();
+        let first = Head::current_commit().await.unwrap();
+        test::ensure_file("blame/a.txt", Some("1\ntwo\n3\n4\n"));
+        crate::exec_async(vec!["add", "blame/a.txt"]).await.unwrap();
+        crate::exec_async(vec!["commit", "-m", "second"])
+            .await
+            .unwrap();
+        let second = Head::current_commit().await.unwrap();
+
+        let lines = blame_file(&second, Path::new("blame/a.txt")).await.unwrap();
+        let commits: Vec<SHA1> = lines.iter().map(|line| line.commit).collect();
+        assert_eq!(commits, vec![first, second, first, second]);
+        assert_eq!(lines[1].content, "two\n");
+        assert_eq!(lines[3].line_no, 4);
+
+        let lines = blame_file(&first, Path::new("blame/a.txt")).await.unwrap();
+        assert!(lines.iter().all(|line| line.commit == first));
+        assert!(blame_file(&first, Path::new("blame/none.txt"))
+            .await
+            .is_err());
+    }
+}
@@ -1,5 +1,6 @@
 use crate::{
-    command::get_target_commit, internal::{branch::Branch, config::Config, head::Head}
+    command::get_target_commit,
+    internal::{branch::Branch, config::Config, head::Head, worktree},
 };
 use clap::Parser;
 use colored::Colorize;
@@ -92,6 +93,10 @@ pub async fn create_branch(new_branch: String, branch_or_commit: Option<String>)
         panic!("fatal: A branch named '{}' already exists.", new_branch);
     }
 
+    let reason = format!(
+        "branch: Created from {}",
+        branch_or_commit.as_deref().unwrap_or("HEAD")
+    );
     let commit_id = match branch_or_commit {
         Some(branch_or_commit) => {
             let commit = get_target_commit(&branch_or_commit).await;
@@ -112,7 +117,7 @@ pub async fn create_branch(new_branch: String, branch_or_commit: Option<String>)
         .unwrap_or_else(|_| panic!("fatal: not a valid object name: '{}'", commit_id));
 
     // create branch
-    Branch::update_branch(&new_branch, &commit_id.to_string(), None).await;
+    Branch::update_branch(&new_branch, &commit_id.to_string(), None, &reason).await;
 }
 
 async fn delete_branch(branch_name: String) {
@@ -129,6 +134,13 @@ async fn delete_branch(branch_name: String) {
             );
         }
     }
+    if let Some(path) = worktree::find_checkout(&branch_name).await {
+        panic!(
+            "fatal: Cannot delete the branch '{}' which is checked out at '{}'",
+            branch_name,
+            path.display()
+        );
+    }
 
     Branch::delete_branch(&branch_name, None).await;
 }
@@ -235,6 +247,8 @@ mod tests {
             message: "first".to_string(),
             allow_empty: true,
             conventional: false,
+            gpg_sign: false,
+            no_verify: false,
         };
         commit::execute(commit_args).await;
         let first_commit_id = Branch::find_branch("master", None).await.unwrap().commit;
@@ -243,6 +257,8 @@ mod tests {
             message: "second".to_string(),
             allow_empty: true,
             conventional: false,
+            gpg_sign: false,
+            no_verify: false,
         };
         commit::execute(commit_args).await;
         let second_commit_id = Branch::find_branch("master", None).await.unwrap().commit;
@@ -321,10 +337,12 @@ mod tests {
             message: "first".to_string(),
             allow_empty: true,
             conventional: false,
+            gpg_sign: false,
+            no_verify: false,
         };
         commit::execute(args).await;
         let hash = Head::current_commit().await.unwrap();
-        Branch::update_branch("master", &hash.to_string(), Some("origin")).await; // create remote branch
+        Branch::update_branch("master", &hash.to_string(), Some("origin"), "test").await; // create remote branch
         assert!(get_target_commit("origin/master").await.is_ok());
 
         let args = BranchArgs {
@@ -353,6 +371,8 @@ mod tests {
             message: "first".to_string(),
             allow_empty: true,
             conventional: false,
+            gpg_sign: false,
+            no_verify: false,
         };
         commit::execute(args).await;
 
@@ -0,0 +1,154 @@
+use std::io::{self, Write};
+use std::str::FromStr;
+
+use clap::Parser;
+use mercury::errors::GitError;
+use mercury::hash::SHA1;
+use mercury::internal::object::tree::{Tree, TreeItem, TreeItemMode};
+use mercury::internal::object::types::ObjectType;
+use mercury::internal::object::ObjectTrait;
+
+use crate::utils::util;
+
+use super::{fetch, get_target_object};
+
+#[derive(Parser, Debug)]
+#[clap(group(clap::ArgGroup::new("mode").required(true)))]
+pub struct CatFileArgs {
+    /// Show the object type
+    #[clap(short = 't', group("mode"))]
+    pub show_type: bool,
+
+    /// Show the object size
+    #[clap(short = 's', group("mode"))]
+    pub size: bool,
+
+    /// Pretty-print the contents of the object based on its type
+    #[clap(short = 'p', group("mode"))]
+    pub pretty: bool,
+
+    /// Check if the object exists, print nothing if it does
+    #[clap(short = 'e', group("mode"))]
+    pub exists: bool,
+
+    /// The object to show: object hash (can be abbreviated), branch, tag or revision like `HEAD~1`
+    pub object: String,
+}
+
+/// Return error if the object is not found, so that the exit code is non-zero (`-e` prints nothing)
+pub async fn execute(args: CatFileArgs) -> Result<(), GitError> {
+    if !util::check_repo_exist() {
+        return Ok(());
+    }
+    let not_found = || GitError::ObjectNotFound(args.object.clone());
+    // a full hash is checked as it is, so `-e` prints nothing if it's missing
+    let full_hash = SHA1::from_str(&args.object).ok().filter(|_| args.exists);
+    let hash = match full_hash {
+        Some(hash) => hash,
+        None => match get_target_object(&args.object).await {
+            Ok(hash) => hash,
+            Err(e) => {
+                eprintln!("fatal: {}", e);
+                return Err(not_found());
+            }
+        },
+    };
+    let storage = util::objects_storage();
+    if args.exists {
+        return match storage.exist(&hash) {
+            true => Ok(()),
+            false => Err(not_found()),
+        };
+    }
+    fetch::prefetch_blobs([hash]).await; // partial clone: the blob may be omitted
+    let (object_type, data) = match (storage.get_object_type(&hash), storage.get(&hash)) {
+        (Ok(object_type), Ok(data)) => (object_type, data),
+        _ => {
+            eprintln!("fatal: Not a valid object name {}", args.object);
+            return Err(not_found());
+        }
+    };
+
+    if args.show_type {
+        println!("{}", object_type);
+    } else if args.size {
+        println!("{}", data.len());
+    } else if object_type == ObjectType::Tree {
+        let tree = Tree::from_bytes(&data, hash).unwrap();
+        for item in tree.tree_items.iter() {
+            println!("{}", format_tree_item(item));
+        }
+    } else {
+        // commit & tag are text, blob is printed as it is
+        io::stdout().write_all(&data).unwrap();
+    }
+    Ok(())
+}
+
+/// Format a tree entry like `git ls-tree`: `<mode> <type> <hash>\t<name>`
+pub(crate) fn format_tree_item(item: &TreeItem) -> String {
+    let object_type = match item.mode {
+        TreeItemMode::Tree => "tree",
+        TreeItemMode::Commit => "commit",
+        _ => "blob",
+    };
+    format!(
+        "{:0>6} {} {}\t{}",
+        String::from_utf8_lossy(item.mode.to_bytes()),
+        object_type,
+        item.id,
+        item.name
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use mercury::internal::object::blob::Blob;
+
+    use super::*;
+    use crate::utils::test;
+
+    #[test]
+    fn test_parse_args() {
+        let args = CatFileArgs::try_parse_from(["cat-file", "-t", "HEAD"]).unwrap();
+        assert!(args.show_type);
+        assert_eq!(args.object, "HEAD");
+        assert!(CatFileArgs::try_parse_from(["cat-file", "HEAD"]).is_err());
+        assert!(CatFileArgs::try_parse_from(["cat-file", "-t", "-p", "HEAD"]).is_err());
+    }
+
+    #[test]
+    fn test_format_tree_item() {
+        let hash = SHA1::new(&[1; 20]);
+        let item = TreeItem::new(TreeItemMode::Tree, hash, "src".to_string());
+        assert_eq!(
+            format_tree_item(&item),
+            format!("040000 tree {}\tsrc", hash)
+        );
+        let item = TreeItem::new(TreeItemMode::Blob, hash, "a.txt".to_string());
+        assert_eq!(
+            format_tree_item(&item),
+            format!("100644 blob {}\ta.txt", hash)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_exists() {
+        test::setup_with_new_libra().await;
+        let blob = Blob::from_content("cat-file");
+        util::objects_storage()
+            .put(&blob.id, &blob.data, blob.get_type())
+            .unwrap();
+        let id = blob.id.to_string();
+        assert!(crate::exec_async(vec!["cat-file", "-e", &id]).await.is_ok());
+
+        let missing = SHA1::new(&[2; 20]).to_string();
+        assert!(matches!(
+            crate::exec_async(vec!["cat-file", "-e", &missing]).await,
+            Err(GitError::ObjectNotFound(_))
+        ));
+        assert!(crate::exec_async(vec!["cat-file", "-t", &missing])
+            .await
+            .is_err());
+    }
+}
@@ -0,0 +1,471 @@
+use std::fs;
+use std::path::PathBuf;
+
+use clap::Parser;
+use common::utils::{format_commit_msg, parse_commit_msg};
+use mercury::hash::SHA1;
+use mercury::internal::index::Index;
+use mercury::internal::object::commit::Commit;
+
+use crate::internal::head::Head;
+use crate::utils::object_ext::CommitExt;
+use crate::utils::{path, util};
+
+use super::{commit, get_target_commit, merge, rebase, save_object, status, switch};
+
+/// Files in `.libra/sequencer`
+const TODO: &str = "todo";
+/// HEAD before the sequence started, for `--abort`
+const HEAD: &str = "head";
+/// `-x` is recorded to be used by the remaining commits
+const OPTS: &str = "opts";
+/// The operation in progress, `cherry-pick` or `revert`
+const OPERATION: &str = "operation";
+
+#[derive(Parser, Debug)]
+pub struct CherryPickArgs {
+    /// Commits to cherry-pick, applied in the given order
+    #[clap(required_unless_present_any(["continue_", "abort"]))]
+    pub commits: Vec<String>,
+
+    /// Append a line "(cherry picked from commit ...)" to the original commit message
+    #[clap(short = 'x')]
+    pub record_origin: bool,
+
+    /// Continue the operation after having resolved the conflicts
+    #[clap(long = "continue", group = "action", conflicts_with("commits"))]
+    pub continue_: bool,
+
+    /// Cancel the operation and return to the pre-sequence state
+    #[clap(long, group = "action", conflicts_with("commits"))]
+    pub abort: bool,
+}
+
+/// The operation of the sequencer, shared by `cherry-pick` & `revert`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum Operation {
+    CherryPick,
+    Revert,
+}
+
+impl Operation {
+    pub(crate) fn name(&self) -> &'static str {
+        match self {
+            Operation::CherryPick => "cherry-pick",
+            Operation::Revert => "revert",
+        }
+    }
+
+    /// The action written in the todo list
+    fn action(&self) -> &'static str {
+        match self {
+            Operation::CherryPick => "pick",
+            Operation::Revert => "revert",
+        }
+    }
+
+    fn from_action(action: &str) -> Option<Operation> {
+        match action {
+            "pick" => Some(Operation::CherryPick),
+            "revert" => Some(Operation::Revert),
+            _ => None,
+        }
+    }
+
+    fn from_name(name: &str) -> Option<Operation> {
+        match name {
+            "cherry-pick" => Some(Operation::CherryPick),
+            "revert" => Some(Operation::Revert),
+            _ => None,
+        }
+    }
+
+    /// `CHERRY_PICK_HEAD` or `REVERT_HEAD`, records the commit which stopped because of conflicts
+    fn head_file(&self) -> PathBuf {
+        match self {
+            Operation::CherryPick => path::cherry_pick_head(),
+            Operation::Revert => path::revert_head(),
+        }
+    }
+}
+
+pub async fn execute(args: CherryPickArgs) {
+    if !util::check_repo_exist() {
+        return;
+    }
+    if args.continue_ {
+        continue_sequence(Operation::CherryPick).await;
+    } else if args.abort {
+        abort_sequence(Operation::CherryPick).await;
+    } else {
+        start_sequence(Operation::CherryPick, &args.commits, args.record_origin).await;
+    }
+}
+
+/// Which operation is in progress, `None` if there is no sequencer state
+pub(crate) fn in_progress() -> Option<Operation> {
+    let name = fs::read_to_string(path::sequencer().join(OPERATION)).ok()?;
+    Operation::from_name(name.trim())
+}
+
+pub(crate) async fn start_sequence(op: Operation, commits: &[String], record_origin: bool) {
+    let sequencer = path::sequencer();
+    if sequencer.exists() {
+        eprintln!(
+            "error: {} is already in progress",
+            in_progress().unwrap_or(op).name()
+        );
+        eprintln!(
+            "hint: try \"libra {} (--continue | --abort)\"",
+            in_progress().unwrap_or(op).name()
+        );
+        return;
+    }
+    if path::merge_head().exists() || path::rebase_merge().exists() {
+        eprintln!("error: a merge or rebase is in progress");
+        eprintln!("fatal: {} failed", op.name());
+        return;
+    }
+    let orig_head = match Head::current_commit().await {
+        Some(commit) => commit,
+        None => {
+            eprintln!("fatal: cannot {} onto an empty HEAD", op.name());
+            return;
+        }
+    };
+    if status::has_uncommitted_changes().await {
+        eprintln!(
+            "error: your local changes would be overwritten by {}.",
+            op.name()
+        );
+        eprintln!("hint: commit your changes or stash them to proceed.");
+        eprintln!("fatal: {} failed", op.name());
+        return;
+    }
+
+    let mut todo = String::new();
+    for commit in commits {
+        let hash = match get_target_commit(commit).await {
+            Ok(hash) => hash,
+            Err(e) => {
+                eprintln!("fatal: bad revision '{}': {}", commit, e);
+                return;
+            }
+        };
+        let parents = Commit::load(&hash).parent_commit_ids.len();
+        if parents > 1 {
+            eprintln!("error: commit {} is a merge, which is not supported", hash);
+            eprintln!("fatal: {} failed", op.name());
+            return;
+        }
+        if op == Operation::Revert && parents == 0 {
+            eprintln!("error: cannot revert the root commit {}", hash);
+            eprintln!("fatal: {} failed", op.name());
+            return;
+        }
+        todo += &format!("{} {}\n", op.action(), hash);
+    }
+
+    fs::create_dir_all(&sequencer).unwrap();
+    fs::write(sequencer.join(TODO), todo).unwrap();
+    fs::write(sequencer.join(HEAD), orig_head.to_string()).unwrap();
+    let opts = if record_origin { "x\n" } else { "" };
+    fs::write(sequencer.join(OPTS), opts).unwrap();
+    fs::write(sequencer.join(OPERATION), op.name()).unwrap();
+    run_sequence().await;
+}
+
+/// Apply the commits in the todo list until it's empty or conflicts occur
+async fn run_sequence() {
+    let sequencer = path::sequencer();
+    loop {
+        let todo = fs::read_to_string(sequencer.join(TODO)).unwrap();
+        let mut lines: Vec<&str> = todo.lines().filter(|line| !line.is_empty()).collect();
+        if lines.is_empty() {
+            break;
+        }
+        let line = lines.remove(0);
+        let (op, hash) = parse_todo_line(line);
+        let mut rest = lines.join("\n");
+        if !rest.is_empty() {
+            rest.push('\n');
+        }
+
+        let conflicts = match apply_commit(op, &hash).await {
+            Ok(conflicts) => conflicts,
+            Err(e) => {
+                // nothing changed, the commit is kept in todo list
+                eprintln!("{}", e);
+                eprintln!("error: could not {} {}", op.action(), describe(&hash));
+                return;
+            }
+        };
+        fs::write(sequencer.join(TODO), rest).unwrap();
+        if !conflicts.is_empty() {
+            fs::write(op.head_file(), hash.to_string()).unwrap();
+            for file in conflicts.iter() {
+                println!("CONFLICT (content): Merge conflict in {}", file.display());
+            }
+            eprintln!("error: could not {} {}", op.action(), describe(&hash));
+            eprintln!("hint: After resolving the conflicts, mark them with");
+            eprintln!("hint: \"libra add <pathspec>\", then run");
+            eprintln!("hint: \"libra {} --continue\".", op.name());
+            eprintln!(
+                "hint: To abort and get back to the state before \"libra {}\",",
+                op.name()
+            );
+            eprintln!("hint: run \"libra {} --abort\".", op.name());
+            return;
+        }
+        commit_result(op, &hash).await;
+    }
+    fs::remove_dir_all(sequencer).unwrap();
+}
+
+fn parse_todo_line(line: &str) -> (Operation, SHA1) {
+    let (action, hash) = line.split_once(' ').unwrap();
+    (
+        Operation::from_action(action).unwrap(),
+        util::get_commit_base(hash.trim()).unwrap(),
+    )
+}
+
+/// Three-way merge the changes of `hash` into HEAD, the result is in index & worktree
+/// - cherry-pick: base is the parent of the commit, theirs is the commit
+/// - revert: base is the commit, theirs is its parent
+/// - return conflicted files
+async fn apply_commit(op: Operation, hash: &SHA1) -> Result<Vec<PathBuf>, String> {
+    let commit = Commit::load(hash);
+    let head = Commit::load(&Head::current_commit().await.unwrap());
+    let parent = commit
+        .parent_commit_ids
+        .first()
+        .map(|parent| Commit::load(parent).tree_id);
+    match op {
+        Operation::CherryPick => {
+            merge::merge_trees(
+                parent.as_ref(),
+                &head.tree_id,
+                &commit.tree_id,
+                "HEAD",
+                &describe(hash),
+            )
+            .await
+        }
+        Operation::Revert => {
+            merge::merge_trees(
+                Some(&commit.tree_id),
+                &head.tree_id,
+                &parent.unwrap(),
+                "HEAD",
+                &format!("parent of {}", describe(hash)),
+            )
+            .await
+        }
+    }
+}
+
+/// Commit the index for the applied commit, HEAD (and the branch) is moved to the new commit
+async fn commit_result(op: Operation, hash: &SHA1) {
+    let index = Index::load(path::index()).unwrap();
+    let tree = commit::create_tree(&index, &util::objects_storage(), "".into()).await;
+    let head = Commit::load(&Head::current_commit().await.unwrap());
+    if tree.id == head.tree_id {
+        println!(
+            "The previous {} is now empty, skipping {}",
+            op.name(),
+            describe(hash)
+        );
+        return;
+    }
+
+    let commit = Commit::load(hash);
+    let new_commit = match op {
+        Operation::CherryPick => {
+            let mut message = parse_commit_msg(&commit.message).0.trim_end().to_owned();
+            let record_origin = fs::read_to_string(path::sequencer().join(OPTS))
+                .unwrap_or_default()
+                .contains('x');
+            if record_origin {
+                message += &format!("\n\n(cherry picked from commit {})", hash);
+            }
+            rebase::new_commit(commit.author.clone(), tree.id, vec![head.id], &message)
+        }
+        Operation::Revert => {
+            let message = format!(
+                "Revert \"{}\"\n\nThis reverts commit {}.",
+                commit.format_message(),
+                hash
+            );
+            Commit::from_tree_id(tree.id, vec![head.id], &format_commit_msg(&message, None))
+        }
+    };
+    save_object(&new_commit, &new_commit.id).unwrap();
+    let reason = format!("{}: {}", op.name(), new_commit.format_message());
+    commit::update_head(&new_commit.id.to_string(), &reason).await;
+    println!(
+        "[{} {}] {}",
+        head_name().await,
+        &new_commit.id.to_string()[..7],
+        new_commit.format_message()
+    );
+}
+
+pub(crate) async fn continue_sequence(op: Operation) {
+    if in_progress() != Some(op) {
+        eprintln!("error: no {} in progress", op.name());
+        return;
+    }
+    let index = Index::load(path::index()).unwrap();
+    if !index.unmerged_files().is_empty() {
+        eprintln!("error: you need to resolve all conflicts first");
+        eprintln!("hint: mark them as resolved with \"libra add <pathspec>\"");
+        return;
+    }
+    let head_file = op.head_file();
+    if head_file.exists() {
+        let hash = fs::read_to_string(&head_file).unwrap();
+        commit_result(op, &util::get_commit_base(hash.trim()).unwrap()).await;
+        fs::remove_file(head_file).unwrap();
+    } else if status::has_uncommitted_changes().await {
+        // the stopped commit has been committed by `libra commit`, but there are new changes
+        eprintln!("error: cannot continue: You have uncommitted changes.");
+        return;
+    }
+    run_sequence().await;
+}
+
+pub(crate) async fn abort_sequence(op: Operation) {
+    if in_progress() != Some(op) {
+        eprintln!("error: no {} in progress", op.name());
+        return;
+    }
+    let sequencer = path::sequencer();
+    let orig_head = fs::read_to_string(sequencer.join(HEAD)).unwrap();
+    let orig_head = util::get_commit_base(orig_head.trim()).unwrap();
+
+    merge::clear_unmerged();
+    switch::restore_to_commit(orig_head).await;
+    let reason = format!("{} (abort): returning to {}", op.name(), orig_head);
+    commit::update_head(&orig_head.to_string(), &reason).await;
+    let head_file = op.head_file();
+    if head_file.exists() {
+        fs::remove_file(head_file).unwrap();
+    }
+    fs::remove_dir_all(sequencer).unwrap();
+}
+
+async fn head_name() -> String {
+    match Head::current().await {
+        Head::Branch(name) => name,
+        Head::Detached(_) => "detached HEAD".to_owned(),
+    }
+}
+
+/// e.g. `1a2b3c4... commit subject`
+fn describe(hash: &SHA1) -> String {
+    format!(
+        "{}... {}",
+        &hash.to_string()[..7],
+        Commit::load(hash).format_message()
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::test;
+
+    #[test]
+    fn test_parse_args() {
+        assert!(CherryPickArgs::try_parse_from(["cherry-pick", "abc", "def"]).is_ok());
+        assert!(CherryPickArgs::try_parse_from(["cherry-pick", "-x", "abc"]).is_ok());
+        assert!(CherryPickArgs::try_parse_from(["cherry-pick", "--continue"]).is_ok());
+        assert!(CherryPickArgs::try_parse_from(["cherry-pick"]).is_err());
+        assert!(CherryPickArgs::try_parse_from(["cherry-pick", "--abort", "abc"]).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_cherry_pick() {
+        test::setup_with_new_libra().await;
+        test::ensure_file("pick_a.txt", Some("a\n"));
+        crate::exec_async(vec!["add", "pick_a.txt"]).await.unwrap();
+        crate::exec_async(vec!["commit", "-m", "base"])
+            .await
+            .unwrap();
+        crate::exec_async(vec!["branch", "pick_feature"])
+            .await
+            .unwrap();
+        crate::exec_async(vec!["switch", "pick_feature"])
+            .await
+            .unwrap();
+        test::ensure_file("pick_b.txt", Some("b\n"));
+        crate::exec_async(vec!["add", "pick_b.txt"]).await.unwrap();
+        crate::exec_async(vec!["commit", "-m", "fix"])
+            .await
+            .unwrap();
+        let fix = Head::current_commit().await.unwrap();
+
+        crate::exec_async(vec!["switch", "master"]).await.unwrap();
+        let master = Head::current_commit().await.unwrap();
+        crate::exec_async(vec!["cherry-pick", "-x", "pick_feature"])
+            .await
+            .unwrap();
+        assert!(!path::sequencer().exists());
+        let head = Commit::load(&Head::current_commit().await.unwrap());
+        assert_eq!(head.parent_commit_ids, vec![master]);
+        assert!(head
+            .message
+            .contains(&format!("(cherry picked from commit {})", fix)));
+        assert!(util::workdir_to_absolute("pick_b.txt").exists());
+    }
+
+    #[tokio::test]
+    async fn test_cherry_pick_conflict() {
+        test::setup_with_new_libra().await;
+        test::ensure_file("pick_c.txt", Some("base\n"));
+        crate::exec_async(vec!["add", "pick_c.txt"]).await.unwrap();
+        crate::exec_async(vec!["commit", "-m", "base"])
+            .await
+            .unwrap();
+        crate::exec_async(vec!["branch", "pick_other"])
+            .await
+            .unwrap();
+        test::ensure_file("pick_c.txt", Some("ours\n"));
+        crate::exec_async(vec!["add", "pick_c.txt"]).await.unwrap();
+        crate::exec_async(vec!["commit", "-m", "ours"])
+            .await
+            .unwrap();
+        let ours = Head::current_commit().await.unwrap();
+
+        crate::exec_async(vec!["switch", "pick_other"])
+            .await
+            .unwrap();
+        test::ensure_file("pick_c.txt", Some("theirs\n"));
+        crate::exec_async(vec!["add", "pick_c.txt"]).await.unwrap();
+        crate::exec_async(vec!["commit", "-m", "theirs"])
+            .await
+            .unwrap();
+        crate::exec_async(vec!["switch", "master"]).await.unwrap();
+
+        crate::exec_async(vec!["cherry-pick", "pick_other"])
+            .await
+            .unwrap();
+        assert!(path::cherry_pick_head().exists());
+        assert_eq!(in_progress(), Some(Operation::CherryPick));
+        let index = Index::load(path::index()).unwrap();
+        assert_eq!(index.unmerged_files().len(), 1);
+
+        // resolve the conflict and continue
+        test::ensure_file("pick_c.txt", Some("resolved\n"));
+        crate::exec_async(vec!["add", "pick_c.txt"]).await.unwrap();
+        crate::exec_async(vec!["cherry-pick", "--continue"])
+            .await
+            .unwrap();
+        assert!(!path::sequencer().exists());
+        assert!(!path::cherry_pick_head().exists());
+        let head = Commit::load(&Head::current_commit().await.unwrap());
+        assert_eq!(head.parent_commit_ids, vec![ours]);
+        assert_eq!(head.format_message(), "theirs");
+    }
+}
@@ -6,6 +6,7 @@ use crate::command::restore::RestoreArgs;
 use crate::internal::branch::Branch;
 use crate::internal::config::{Config, RemoteConfig};
 use crate::internal::head::Head;
+use crate::internal::protocol::UploadPackOptions;
 use clap::Parser;
 use colored::Colorize;
 use scopeguard::defer;
@@ -23,9 +24,45 @@ pub struct CloneArgs {
 
     /// The local path to clone the repository to
     pub local_path: Option<String>,
+
+    /// Create a shallow clone with a history truncated to the specified number of commits
+    #[clap(long)]
+    pub depth: Option<usize>,
+
+    /// Create a shallow clone with a history after the specified time, e.g. `2024-01-01` or `2.weeks.ago`
+    #[clap(long, value_name = "date")]
+    pub shallow_since: Option<String>,
+
+    /// Partial clone, omit some objects and fetch them when needed, only `blob:none` is supported
+    #[clap(long, value_name = "filter-spec")]
+    pub filter: Option<String>,
 }
 
 pub async fn execute(args: CloneArgs) {
+    let deepen_since = match args.shallow_since.as_deref().map(util::parse_date).transpose() {
+        Ok(deepen_since) => deepen_since,
+        Err(e) => {
+            eprintln!("fatal: {}", e);
+            return;
+        }
+    };
+    if let Some(filter) = &args.filter {
+        if filter != "blob:none" {
+            eprintln!("fatal: invalid filter-spec '{}', only 'blob:none' is supported", filter);
+            return;
+        }
+    }
+    if args.depth == Some(0) {
+        eprintln!("fatal: depth 0 is not a positive number");
+        return;
+    }
+    let options = UploadPackOptions {
+        depth: args.depth,
+        deepen_since,
+        filter: args.filter,
+        shallow: Vec::new(),
+    };
+
     let mut remote_repo = args.remote_repo; // https://gitee.com/caiqihang2024/image-viewer2.0.git
                                             // must end with '/' or Url::join will work incorrectly
     if !remote_repo.ends_with('/') {
@@ -78,7 +115,14 @@ pub async fn execute(args: CloneArgs) {
         name: "origin".to_string(),
         url: remote_repo.clone(),
     };
-    fetch::fetch_repository(&remote_config, None).await;
+    let is_partial = options.filter.is_some();
+    fetch::fetch_repository(&remote_config, None, fetch::TagMode::All, options).await;
+    if is_partial {
+        // the omitted objects will be fetched from `origin` when needed
+        util::objects_storage()
+            .set_promisor_remote(&remote_repo)
+            .unwrap();
+    }
 
     /* setup */
     setup(remote_repo.clone()).await;
@@ -96,8 +140,9 @@ async fn setup(remote_repo: String) {
                 .await
                 .expect("origin HEAD branch not found");
 
-            Branch::update_branch(&name, &origin_head_branch.commit.to_string(), None).await;
-            Head::update(Head::Branch(name.to_owned()), None).await;
+            let reason = format!("clone: from {}", remote_repo);
+            Branch::update_branch(&name, &origin_head_branch.commit.to_string(), None, &reason).await;
+            Head::update(Head::Branch(name.to_owned()), None, &reason).await;
 
             // set config: remote.origin.url
             Config::insert("remote", Some(ORIGIN), "url", &remote_repo).await;
@@ -138,3 +183,24 @@ async fn setup(remote_repo: String) {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_args() {
+        let args = CloneArgs::try_parse_from([
+            "clone",
+            "https://example.com/repo.git",
+            "--depth",
+            "1",
+            "--filter",
+            "blob:none",
+        ])
+        .unwrap();
+        assert_eq!(args.depth, Some(1));
+        assert_eq!(args.filter.as_deref(), Some("blob:none"));
+        assert!(args.shallow_since.is_none());
+    }
+}
@@ -1,10 +1,14 @@
+use std::fs;
 use std::str::FromStr;
 use std::{collections::HashSet, path::PathBuf};
 
 use crate::internal::branch::Branch;
+use crate::internal::config::Config;
 use crate::internal::head::Head;
 use crate::utils::client_storage::ClientStorage;
+use crate::utils::hooks;
 use crate::utils::path;
+use crate::utils::signing;
 use crate::utils::util;
 use clap::Parser;
 use common::utils::{check_conventional_commits_message, format_commit_msg};
@@ -28,9 +32,25 @@ pub struct CommitArgs {
     /// check if commit message follows conventional commits
     #[arg(long, requires("message"))]
     pub conventional: bool,
+
+    /// sign the commit with `user.signingkey`, default to `commit.gpgsign` config
+    #[arg(short = 'S', long)]
+    pub gpg_sign: bool,
+
+    /// bypass the pre-commit and commit-msg hooks
+    #[arg(short = 'n', long)]
+    pub no_verify: bool,
 }
 
 pub async fn execute(args: CommitArgs) {
+    // the hook may update the index, so it runs before loading the index
+    if !args.no_verify {
+        if let Err(e) = hooks::run(hooks::PRE_COMMIT, &[], None).await {
+            eprintln!("fatal: {}", e);
+            return;
+        }
+    }
+
     /* check args */
     let index = Index::load(path::index()).unwrap();
     let storage = ClientStorage::init(path::objects());
@@ -39,7 +59,23 @@ pub async fn execute(args: CommitArgs) {
         println!("fatal: no changes added to commit, use --allow-empty to override");
         return;
     }
-    if args.conventional && !check_conventional_commits_message(&args.message) {
+    if !index.unmerged_files().is_empty() {
+        println!("error: Committing is not possible because you have unmerged files.");
+        println!("hint: Fix them up in the work tree, and then use 'libra add <file>'");
+        return;
+    }
+    let message = if args.no_verify {
+        args.message.clone()
+    } else {
+        match hooks::commit_msg(&args.message).await {
+            Ok(message) => message,
+            Err(e) => {
+                eprintln!("fatal: {}", e);
+                return;
+            }
+        }
+    };
+    if args.conventional && !check_conventional_commits_message(&message) {
         println!("fatal: commit message does not follow conventional commits");
         return;
     }
@@ -50,11 +86,31 @@ pub async fn execute(args: CommitArgs) {
     /* Create & save commit objects */
     let parents_commit_ids = get_parents_ids().await;
     // There must be a `blank line`(\n) before `message`, or remote unpack failed
-    let commit = Commit::from_tree_id(
+    let mut commit = Commit::from_tree_id(
         tree.id,
         parents_commit_ids,
-        &format_commit_msg(&args.message, None),
+        &format_commit_msg(&message, None),
     );
+    let gpg_sign =
+        args.gpg_sign || Config::get("commit", None, "gpgsign").await.as_deref() == Some("true");
+    if gpg_sign {
+        // the signature covers the commit without the `gpgsig` header
+        let signature = match signing::sign(&commit.to_data().unwrap()).await {
+            Ok(signature) => signature,
+            Err(e) => {
+                eprintln!("fatal: failed to sign the commit: {}", e);
+                return;
+            }
+        };
+        let header = signing::commit_header(&signature);
+        commit = Commit::new(
+            commit.author,
+            commit.committer,
+            commit.tree_id,
+            commit.parent_commit_ids,
+            &format_commit_msg(&message, Some(&header)),
+        );
+    }
 
     // TODO  default signature created in `from_tree_id`, wait `git config` to set correct user info
 
@@ -63,11 +119,30 @@ pub async fn execute(args: CommitArgs) {
         .unwrap();
 
     /* update HEAD */
-    update_head(&commit.id.to_string()).await;
+    let kind = if commit.parent_commit_ids.is_empty() {
+        " (initial)"
+    } else if commit.parent_commit_ids.len() > 1 {
+        " (merge)"
+    } else {
+        ""
+    };
+    let reason = format!("commit{}: {}", kind, commit.format_message());
+    update_head(&commit.id.to_string(), &reason).await;
+
+    // merge (or the conflicted cherry-pick / revert) is concluded
+    for file in [
+        path::merge_head(),
+        path::cherry_pick_head(),
+        path::revert_head(),
+    ] {
+        if file.exists() {
+            fs::remove_file(file).unwrap();
+        }
+    }
 }
 
 /// recursively create tree from index's tracked entries
-async fn create_tree(index: &Index, storage: &ClientStorage, current_root: PathBuf) -> Tree {
+pub async fn create_tree(index: &Index, storage: &ClientStorage, current_root: PathBuf) -> Tree {
     // blob created when add file to index
     let get_blob_entry = |path: &PathBuf| {
         let name = util::path_to_string(path);
@@ -141,27 +216,35 @@ async fn create_tree(index: &Index, storage: &ClientStorage, current_root: PathB
 }
 
 /// get current head commit id as parent, if in branch, get branch's commit id, if detached head, get head's commit id
+/// - if a merge is in progress, `MERGE_HEAD` is the second parent
 async fn get_parents_ids() -> Vec<SHA1> {
     // let current_commit_id = reference::Model::current_commit_hash(db).await.unwrap();
     let current_commit_id = Head::current_commit().await;
-    match current_commit_id {
+    let mut parents = match current_commit_id {
         Some(id) => vec![id],
         None => vec![], // first commit
+    };
+    let merge_head = path::merge_head();
+    if merge_head.exists() {
+        let merge_commit = fs::read_to_string(merge_head).unwrap();
+        parents.push(SHA1::from_str(merge_commit.trim()).unwrap());
     }
+    parents
 }
 
 /// update HEAD to new commit, if in branch, update branch's commit id, if detached head, update head's commit id
-async fn update_head(commit_id: &str) {
+/// - `reason` is the message of the reflog entry
+pub async fn update_head(commit_id: &str, reason: &str) {
     // let head = reference::Model::current_head(db).await.unwrap();
     match Head::current().await {
         Head::Branch(name) => {
             // in branch
-            Branch::update_branch(&name, commit_id, None).await;
+            Branch::update_branch(&name, commit_id, None, reason).await;
         }
         // None => {
         Head::Detached(_) => {
             let head = Head::Detached(SHA1::from_str(commit_id).unwrap());
-            Head::update(head, None).await;
+            Head::update(head, None, reason).await;
         }
     }
 }
@@ -224,6 +307,8 @@ mod test {
             message: "init".to_string(),
             allow_empty: false,
             conventional: false,
+            gpg_sign: false,
+            no_verify: false,
         };
         execute(args).await;
     }
@@ -237,6 +322,8 @@ mod test {
                 message: "init".to_string(),
                 allow_empty: true,
                 conventional: false,
+                gpg_sign: false,
+                no_verify: false,
             };
             execute(args).await;
 
@@ -264,6 +351,7 @@ mod test {
                 all: true,
                 update: false,
                 verbose: false,
+                force: false,
                 pathspec: vec![],
             };
             crate::command::add::execute(args).await;
@@ -274,6 +362,8 @@ mod test {
                 message: "add some files".to_string(),
                 allow_empty: false,
                 conventional: false,
+                gpg_sign: false,
+                no_verify: false,
             };
             execute(args).await;
 
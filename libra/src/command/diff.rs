@@ -2,11 +2,14 @@ use std::{
     collections::{HashMap, HashSet},
     fmt,
     io::{self, Write},
+    ops::Range,
     path::{Path, PathBuf},
 };
 
-use clap::Parser;
-use imara_diff::{intern::InternedInput, Algorithm, UnifiedDiffBuilder};
+use clap::{Parser, ValueEnum};
+use colored::Colorize;
+use flate2::{write::ZlibEncoder, Compression};
+use imara_diff::{intern::InternedInput, sources, Algorithm};
 use mercury::{
     hash::SHA1,
     internal::{
@@ -19,11 +22,11 @@ use similar;
 
 use crate::{
     command::{
-        get_target_commit, load_object,
+        fetch, get_target_commit, load_object,
         status::{self, changes_to_be_committed},
     },
     internal::head::Head,
-    utils::{object_ext::TreeExt, path, util},
+    utils::{base85, object_ext::TreeExt, path, rename, util},
 };
 
 #[cfg(unix)]
@@ -53,6 +56,85 @@ pub struct DiffArgs {
     // Print the result to file
     #[clap(long, value_name = "FILENAME")]
     pub output: Option<String>,
+
+    /// Detect renames, with the minimum similarity like `-M=90%` (default 50%)
+    #[clap(short = 'M', long = "find-renames", value_name = "N")]
+    #[clap(num_args = 0..=1, require_equals = true, default_missing_value = "50%")]
+    #[clap(value_parser = rename::parse_similarity)]
+    pub find_renames: Option<u8>,
+
+    /// Detect copies (from modified files) as well as renames, with the minimum similarity
+    #[clap(short = 'C', long = "find-copies", value_name = "N")]
+    #[clap(num_args = 0..=1, require_equals = true, default_missing_value = "50%")]
+    #[clap(value_parser = rename::parse_similarity)]
+    pub find_copies: Option<u8>,
+
+    /// Show a diffstat instead of the patch
+    #[clap(long, group = "format")]
+    pub stat: bool,
+
+    /// Show the numbers of added & deleted lines of each file, `-` for binary files
+    #[clap(long, group = "format")]
+    pub numstat: bool,
+
+    /// Show only the names of changed files
+    #[clap(long, group = "format")]
+    pub name_only: bool,
+
+    /// Show only the names & status (`A`, `D`, `M`, `R<n>`, `C<n>`) of changed files
+    #[clap(long, group = "format")]
+    pub name_status: bool,
+
+    /// Lines of context around changes
+    #[clap(short = 'U', long = "unified", value_name = "N", default_value_t = 3)]
+    pub unified: usize,
+
+    /// Show the changed words instead of lines, `plain` marks them like `[-old-]{+new+}`
+    #[clap(long, value_enum, value_name = "MODE")]
+    #[clap(num_args = 0..=1, require_equals = true, default_missing_value = "plain")]
+    pub word_diff: Option<WordDiff>,
+
+    /// Color the moved lines differently from other added & removed lines
+    #[clap(long)]
+    pub color_moved: bool,
+
+    /// Output the binary files as `GIT binary patch`, which can be applied by `libra apply`
+    #[clap(long)]
+    pub binary: bool,
+}
+
+/// How `--word-diff` shows the changed words
+#[derive(Debug, Clone, Copy, PartialEq, ValueEnum)]
+pub enum WordDiff {
+    /// `[-removed-]{+added+}`
+    Plain,
+    /// Removed words in red & added words in green
+    Color,
+}
+
+/// Options of the patch output
+#[derive(Debug, Clone, Copy)]
+pub struct DiffOptions {
+    /// Detect renames & copies
+    pub renames: Option<rename::Options>,
+    /// Lines of context around changes
+    pub context: usize,
+    pub word_diff: Option<WordDiff>,
+    pub color_moved: bool,
+    /// Output the binary files as `GIT binary patch`
+    pub binary: bool,
+}
+
+impl Default for DiffOptions {
+    fn default() -> Self {
+        DiffOptions {
+            renames: None,
+            context: 3,
+            word_diff: None,
+            color_moved: false,
+            binary: false,
+        }
+    }
 }
 
 pub async fn execute(args: DiffArgs) {
@@ -77,9 +159,10 @@ pub async fn execute(args: DiffArgs) {
         None => None,
     };
 
-    let old_blobs = match args.old {
+    // (blobs, whether they are from a commit)
+    let (old_blobs, old_saved) = match args.old {
         Some(ref source) => match get_target_commit(source).await {
-            Ok(commit_hash) => get_commit_blobs(&commit_hash).await,
+            Ok(commit_hash) => (get_commit_blobs(&commit_hash).await, true),
             Err(e) => {
                 eprintln!("fatal: {}, can't use as diff old source", e);
                 return;
@@ -89,18 +172,18 @@ pub async fn execute(args: DiffArgs) {
             // if the staged is not empty, use it as old commit. Otherwise, use HEAD
             if status::changes_to_be_committed().await.is_empty() {
                 let commit_hash = Head::current_commit().await.unwrap();
-                get_commit_blobs(&commit_hash).await
+                (get_commit_blobs(&commit_hash).await, true)
             } else {
                 let changes = changes_to_be_committed().await;
                 // diff didn't show untracked or deleted files
-                get_files_blobs(&changes.modified)
+                (get_files_blobs(&changes.modified), false)
             }
         }
     };
 
-    let new_blobs = match args.new {
+    let (new_blobs, new_saved) = match args.new {
         Some(ref source) => match get_target_commit(source).await {
-            Ok(commit_hash) => get_commit_blobs(&commit_hash).await,
+            Ok(commit_hash) => (get_commit_blobs(&commit_hash).await, true),
             Err(e) => {
                 eprintln!("fatal: {}, can't use as diff new source", e);
                 return;
@@ -113,18 +196,68 @@ pub async fn execute(args: DiffArgs) {
             } else {
                 // use working directory as new commit
                 // NOTE: git didn't show diff for untracked files, but we do
-                util::list_workdir_files().unwrap()
+                let mut files = util::list_workdir_files().unwrap();
+                // tracked files are not affected by ignore rules
+                let listed: HashSet<PathBuf> = files.iter().cloned().collect();
+                files.extend(index.tracked_files().into_iter().filter(|file| {
+                    !listed.contains(file) && util::workdir_to_absolute(file).exists()
+                }));
+                files
             };
-            get_files_blobs(&files)
+            (get_files_blobs(&files), false)
         }
     };
 
+    // partial clone: fetch the changed blobs of commits at once, the files in worktree are never saved
+    let old_set: HashSet<&(PathBuf, SHA1)> = old_blobs.iter().collect();
+    let new_set: HashSet<&(PathBuf, SHA1)> = new_blobs.iter().collect();
+    let mut wanted = Vec::new();
+    if old_saved {
+        wanted.extend(old_set.difference(&new_set).map(|(_, hash)| *hash));
+    }
+    if new_saved {
+        wanted.extend(new_set.difference(&old_set).map(|(_, hash)| *hash));
+    }
+    fetch::prefetch_blobs(wanted).await;
+
     // use pathspec to filter files
     let paths: Vec<PathBuf> = args.pathspec.iter().map(util::to_workdir_path).collect();
 
+    // `-C` implies `-M`
+    let renames = match (args.find_renames, args.find_copies) {
+        (_, Some(copy_threshold)) => Some(rename::Options {
+            threshold: args.find_renames.unwrap_or(copy_threshold),
+            copies: true,
+        }),
+        (Some(threshold), None) => Some(rename::Options {
+            threshold,
+            copies: false,
+        }),
+        (None, None) => None,
+    };
+
+    let options = DiffOptions {
+        renames,
+        context: args.unified,
+        word_diff: args.word_diff,
+        color_moved: args.color_moved,
+        binary: args.binary,
+    };
+
     let mut buf: Vec<u8> = Vec::new();
-    // filter files, cross old and new files, and pathspec
-    diff(old_blobs, new_blobs, paths.into_iter().collect(), &mut buf).await;
+    if args.stat || args.numstat || args.name_only || args.name_status {
+        let changes = collect_changes(old_blobs, new_blobs, &paths, renames);
+        if args.stat {
+            write_stat(&changes, &mut buf);
+        } else if args.numstat {
+            write_numstat(&changes, &mut buf);
+        } else {
+            write_names(&changes, args.name_status, &mut buf);
+        }
+    } else {
+        // filter files, cross old and new files, and pathspec
+        diff(old_blobs, new_blobs, paths, &options, &mut buf).await;
+    }
 
     match w {
         Some(ref mut file) => {
@@ -152,16 +285,60 @@ pub async fn execute(args: DiffArgs) {
     }
 }
 
-pub async fn diff(
+/// A changed file between the old & new blobs
+struct FileChange {
+    /// The same as `new_path` unless it's renamed or copied
+    old_path: PathBuf,
+    new_path: PathBuf,
+    old_hash: Option<SHA1>,
+    new_hash: Option<SHA1>,
+    rename: Option<rename::Rename>,
+}
+
+impl FileChange {
+    /// The path shown in `--stat` & `--name-only`
+    fn display_path(&self) -> String {
+        match self.rename {
+            Some(_) => format!("{} => {}", self.old_path.display(), self.new_path.display()),
+            None => self.new_path.display().to_string(),
+        }
+    }
+
+    /// `A`, `D`, `M`, `R<similarity>` or `C<similarity>`, like `git diff --name-status`
+    fn status(&self) -> String {
+        match (&self.rename, self.old_hash, self.new_hash) {
+            (Some(rename), _, _) => {
+                let kind = if rename.copy { 'C' } else { 'R' };
+                format!("{}{:03}", kind, rename.similarity)
+            }
+            (None, None, _) => "A".to_string(),
+            (None, _, None) => "D".to_string(),
+            _ => "M".to_string(),
+        }
+    }
+
+    fn old_content(&self) -> Vec<u8> {
+        self.old_hash
+            .map_or(Vec::new(), |hash| read_content(&self.old_path, &hash))
+    }
+
+    fn new_content(&self) -> Vec<u8> {
+        self.new_hash
+            .map_or(Vec::new(), |hash| read_content(&self.new_path, &hash))
+    }
+}
+
+/// Cross the old & new blobs, filtered by pathspec, sorted by path
+fn collect_changes(
     old_blobs: Vec<(PathBuf, SHA1)>,
     new_blobs: Vec<(PathBuf, SHA1)>,
-    filter: Vec<PathBuf>,
-    w: &mut dyn io::Write,
-) {
+    filter: &[PathBuf],
+    renames: Option<rename::Options>,
+) -> Vec<FileChange> {
     let old_blobs: HashMap<PathBuf, SHA1> = old_blobs.into_iter().collect();
     let new_blobs: HashMap<PathBuf, SHA1> = new_blobs.into_iter().collect();
     // unison set
-    let union_files: HashSet<PathBuf> = old_blobs.keys().chain(new_blobs.keys()).cloned().collect();
+    let union_files: HashSet<&PathBuf> = old_blobs.keys().chain(new_blobs.keys()).collect();
     tracing::debug!(
         "old blobs {:?}, new blobs {:?}, union files {:?}",
         old_blobs.len(),
@@ -169,82 +346,553 @@ pub async fn diff(
         union_files.len()
     );
 
-    let read_content = |file: &PathBuf, hash: &SHA1| {
-        // read content from blob or file
-        match load_object::<Blob>(hash) {
-            Ok(blob) => blob.data,
-            Err(_) => {
-                let file = util::workdir_to_absolute(file);
-                std::fs::read(&file)
-                    .map_err(|e| {
-                        eprintln!("fatal: could not read file '{}': {}", file.display(), e);
-                    })
-                    .unwrap()
-            }
-        }
+    let mut renames: HashMap<PathBuf, rename::Rename> = match renames {
+        Some(options) => rename::detect(&old_blobs, &new_blobs, options, read_content)
+            .into_iter()
+            .map(|r| (r.to.clone(), r))
+            .collect(),
+        None => HashMap::new(),
     };
+    // the sources of renames are not shown as deleted
+    let renamed_from: HashSet<PathBuf> = renames
+        .values()
+        .filter(|r| !r.copy)
+        .map(|r| r.from.clone())
+        .collect();
 
-    // filter files, cross old and new files, and pathspec
+    let mut changes = Vec::new();
     for file in union_files {
+        if renamed_from.contains(file) {
+            continue;
+        }
+        let rename = renames.remove(file);
+        // the old path of renamed (or copied) file
+        let old_file = rename.as_ref().map_or(file, |r| &r.from);
         // if new_file did't start with any path in filter, skip it
-        if !filter.is_empty() && !filter.iter().any(|path| file.sub_of(path)) {
+        if !filter.is_empty()
+            && !filter
+                .iter()
+                .any(|path| file.sub_of(path) || old_file.sub_of(path))
+        {
+            continue;
+        }
+        let new_hash = new_blobs.get(file).copied();
+        let old_hash = old_blobs.get(old_file).copied();
+        if new_hash == old_hash && rename.is_none() {
             continue;
         }
+        changes.push(FileChange {
+            old_path: old_file.clone(),
+            new_path: file.clone(),
+            old_hash,
+            new_hash,
+            rename,
+        });
+    }
+    changes.sort_by(|a, b| a.new_path.cmp(&b.new_path));
+    changes
+}
+
+/// Write the patch of the files, the format is controlled by `options`
+pub async fn diff(
+    old_blobs: Vec<(PathBuf, SHA1)>,
+    new_blobs: Vec<(PathBuf, SHA1)>,
+    filter: Vec<PathBuf>,
+    options: &DiffOptions,
+    w: &mut dyn io::Write,
+) {
+    let changes = collect_changes(old_blobs, new_blobs, &filter, options.renames);
+    let moved = if options.color_moved {
+        Some(MovedLines::new(&changes))
+    } else {
+        None
+    };
+    for change in changes.iter() {
+        write_file_patch(change, options, moved.as_ref(), w);
+    }
+}
 
-        let new_hash = new_blobs.get(&file);
-        let old_hash = old_blobs.get(&file);
+fn write_file_patch(
+    change: &FileChange,
+    options: &DiffOptions,
+    moved: Option<&MovedLines>,
+    w: &mut dyn io::Write,
+) {
+    let (old_file, file) = (&change.old_path, &change.new_path);
+    let (old_hash, new_hash) = (change.old_hash.as_ref(), change.new_hash.as_ref());
+    writeln!(
+        w,
+        "diff --git a/{} b/{}",
+        old_file.display(),
+        file.display()
+    )
+    .unwrap();
+
+    if let Some(rename) = &change.rename {
+        let kind = if rename.copy { "copy" } else { "rename" };
+        writeln!(w, "similarity index {}%", rename.similarity).unwrap();
+        writeln!(w, "{} from {}", kind, old_file.display()).unwrap();
+        writeln!(w, "{} to {}", kind, file.display()).unwrap();
         if new_hash == old_hash {
+            return; // the content is the same
+        }
+    } else if old_hash.is_none() {
+        writeln!(w, "new file mode 100644").unwrap();
+    } else if new_hash.is_none() {
+        writeln!(w, "deleted file mode 100644").unwrap();
+    }
+
+    // binary patches need the full hashes to check the file before applying
+    let (index_len, zeros_len) = if options.binary { (40, 40) } else { (8, 7) };
+    let short = |hash: Option<&SHA1>| match hash {
+        Some(hash) => hash.to_string()[0..index_len].to_string(),
+        None => "0".repeat(zeros_len),
+    };
+    writeln!(w, "index {}..{}", short(old_hash), short(new_hash)).unwrap();
+
+    let old_content = change.old_content();
+    let new_content = change.new_content();
+    let old_path = match old_hash {
+        Some(_) => format!("a/{}", old_file.display()),
+        None => "/dev/null".to_string(),
+    };
+    let new_path = match new_hash {
+        Some(_) => format!("b/{}", file.display()),
+        None => "/dev/null".to_string(),
+    };
+    // check is the content is valid utf-8 or maybe binary
+    match (
+        std::str::from_utf8(&old_content),
+        std::str::from_utf8(&new_content),
+    ) {
+        (Ok(old_text), Ok(new_text)) => {
+            // no hunks for empty files
+            if old_text != new_text {
+                writeln!(w, "--- {}", old_path).unwrap();
+                writeln!(w, "+++ {}", new_path).unwrap();
+                write_hunks(old_text, new_text, options, moved, w);
+            }
+        }
+        _ if options.binary => {
+            write_binary_patch(&old_content, &new_content, w);
+        }
+        _ => {
+            // TODO: Handle non-UTF-8 data as binary for now; consider optimization in the future.
+            let old_type = infer::get(&old_content);
+            let new_type = infer::get(&new_content);
+            writeln!(
+                w,
+                "Binary files a/{} and b/{} differ",
+                file_display(old_file, old_hash, old_type),
+                file_display(file, new_hash, new_type)
+            )
+            .unwrap();
+        }
+    }
+}
+
+/// Write the unified hunks (or the changed words) of the text
+fn write_hunks(
+    old: &str,
+    new: &str,
+    options: &DiffOptions,
+    moved: Option<&MovedLines>,
+    w: &mut dyn io::Write,
+) {
+    let diff = LineDiff::new(old, new);
+    // groups without changes may be produced with 0 context
+    for group in similar::group_diff_ops(diff.ops.clone(), options.context)
+        .into_iter()
+        .filter(|group| group.iter().any(|op| op.tag() != similar::DiffTag::Equal))
+    {
+        writeln!(w, "{}", similar::udiff::UnifiedHunkHeader::new(&group)).unwrap();
+        if let Some(mode) = options.word_diff {
+            write_word_diff(&diff, &group, mode, w);
+            continue;
+        }
+        let changes: Vec<similar::Change<&str>> =
+            group.iter().flat_map(|op| diff.changes(op)).collect();
+        let moved_flags = match moved {
+            Some(moved) => {
+                let lines: Vec<_> = changes.iter().map(|c| (c.tag(), c.value())).collect();
+                moved.flags(&lines)
+            }
+            None => vec![false; changes.len()],
+        };
+        for (change, is_moved) in changes.iter().zip(moved_flags) {
+            let line = format!("{}{}", change.tag(), change.value().trim_end_matches('\n'));
+            let line = match (moved, change.tag()) {
+                (None, _) | (_, similar::ChangeTag::Equal) => line.normal(),
+                (Some(_), similar::ChangeTag::Delete) if is_moved => line.magenta(),
+                (Some(_), similar::ChangeTag::Insert) if is_moved => line.cyan(),
+                (Some(_), similar::ChangeTag::Delete) => line.red(),
+                (Some(_), similar::ChangeTag::Insert) => line.green(),
+            };
+            writeln!(w, "{}", line).unwrap();
+            if change.missing_newline() {
+                writeln!(w, "\\ No newline at end of file").unwrap();
+            }
+        }
+    }
+}
+
+/// The line diff by the Histogram algorithm of imara-diff, as the ops of `similar` to be grouped into hunks,
+/// the lines keep their terminators
+struct LineDiff<'a> {
+    old: Vec<&'a str>,
+    new: Vec<&'a str>,
+    ops: Vec<similar::DiffOp>,
+}
+
+impl<'a> LineDiff<'a> {
+    fn new(old: &'a str, new: &'a str) -> Self {
+        let old_lines = sources::lines_with_terminator(old);
+        let new_lines = sources::lines_with_terminator(new);
+        let input = InternedInput::new(old_lines, new_lines);
+        let mut ops = Vec::new();
+        let (mut old_index, mut new_index) = (0, 0);
+        imara_diff::diff(
+            Algorithm::Histogram,
+            &input,
+            |before: Range<u32>, after: Range<u32>| {
+                let before = before.start as usize..before.end as usize;
+                let after = after.start as usize..after.end as usize;
+                if before.start > old_index {
+                    ops.push(similar::DiffOp::Equal {
+                        old_index,
+                        new_index,
+                        len: before.start - old_index,
+                    });
+                }
+                ops.push(match (before.is_empty(), after.is_empty()) {
+                    (false, false) => similar::DiffOp::Replace {
+                        old_index: before.start,
+                        old_len: before.len(),
+                        new_index: after.start,
+                        new_len: after.len(),
+                    },
+                    (false, true) => similar::DiffOp::Delete {
+                        old_index: before.start,
+                        old_len: before.len(),
+                        new_index: after.start,
+                    },
+                    _ => similar::DiffOp::Insert {
+                        old_index: before.start,
+                        new_index: after.start,
+                        new_len: after.len(),
+                    },
+                });
+                (old_index, new_index) = (before.end, after.end);
+            },
+        );
+        let old: Vec<&str> = old_lines.collect();
+        let new: Vec<&str> = new_lines.collect();
+        if old_index < old.len() {
+            ops.push(similar::DiffOp::Equal {
+                old_index,
+                new_index,
+                len: old.len() - old_index,
+            });
+        }
+        LineDiff { old, new, ops }
+    }
+
+    fn changes(&self, op: &similar::DiffOp) -> impl Iterator<Item = similar::Change<&'a str>> + '_ {
+        op.iter_changes(&self.old, &self.new)
+    }
+}
+
+/// Write the hunk by words: the unchanged lines as is, and the changed words are marked
+fn write_word_diff(
+    diff: &LineDiff,
+    group: &[similar::DiffOp],
+    mode: WordDiff,
+    w: &mut dyn io::Write,
+) {
+    let mut hunk = String::new();
+    for op in group {
+        let (tag, old_range, new_range) = op.as_tag_tuple();
+        let old_text = diff.old[old_range].concat();
+        if tag == similar::DiffTag::Equal {
+            hunk.push_str(&old_text);
             continue;
         }
+        let new_text = diff.new[new_range].concat();
+        let words = similar::TextDiff::from_words(&old_text, &new_text);
+        for change in words.iter_all_changes() {
+            let word = change.value();
+            let word = match (change.tag(), mode) {
+                (similar::ChangeTag::Equal, _) => word.to_string(),
+                (similar::ChangeTag::Delete, WordDiff::Plain) => format!("[-{}-]", word),
+                (similar::ChangeTag::Insert, WordDiff::Plain) => format!("{{+{}+}}", word),
+                (similar::ChangeTag::Delete, WordDiff::Color) => word.red().to_string(),
+                (similar::ChangeTag::Insert, WordDiff::Color) => word.green().to_string(),
+            };
+            hunk.push_str(&word);
+        }
+    }
+    // the last line may have no newline
+    if !hunk.ends_with('\n') {
+        hunk.push('\n');
+    }
+    write!(w, "{}", hunk).unwrap();
+}
+
+/// Write the `GIT binary patch`: the new content, then the old content to reverse it
+fn write_binary_patch(old: &[u8], new: &[u8], w: &mut dyn io::Write) {
+    writeln!(w, "GIT binary patch").unwrap();
+    write_binary_literal(new, w);
+    write_binary_literal(old, w);
+}
 
-        let old_content = match old_hash.as_ref() {
-            Some(hash) => read_content(&file, hash),
-            None => Vec::new(),
+/// `literal <size>`, then the zlib compressed data in base85 lines, ends with an empty line
+pub(crate) fn write_binary_literal(data: &[u8], w: &mut dyn io::Write) {
+    let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(data).unwrap();
+    let compressed = encoder.finish().unwrap();
+    writeln!(w, "literal {}", data.len()).unwrap();
+    for chunk in compressed.chunks(52) {
+        // the length of the line: `A-Z` for 1-26 bytes, `a-z` for 27-52 bytes
+        let len = match chunk.len() {
+            len @ 1..=26 => b'A' + len as u8 - 1,
+            len => b'a' + len as u8 - 27,
         };
-        let new_content = match new_hash.as_ref() {
-            Some(hash) => read_content(&file, hash),
-            None => Vec::new(),
+        writeln!(w, "{}{}", len as char, base85::encode(chunk)).unwrap();
+    }
+    writeln!(w).unwrap();
+}
+
+/// The removed & added lines of all the files, for `--color-moved`
+struct MovedLines {
+    deleted: HashSet<String>,
+    added: HashSet<String>,
+}
+
+impl MovedLines {
+    /// A block of moved lines should have at least so many alphanumeric characters,
+    /// to exclude the trivial lines like `}`
+    const MIN_ALNUM: usize = 20;
+
+    fn new(changes: &[FileChange]) -> Self {
+        let mut moved = MovedLines {
+            deleted: HashSet::new(),
+            added: HashSet::new(),
         };
+        for change in changes {
+            let old_content = change.old_content();
+            let new_content = change.new_content();
+            let (Ok(old), Ok(new)) = (
+                std::str::from_utf8(&old_content),
+                std::str::from_utf8(&new_content),
+            ) else {
+                continue;
+            };
+            let diff = LineDiff::new(old, new);
+            for line in diff.ops.iter().flat_map(|op| diff.changes(op)) {
+                let value = line.value().trim_end_matches('\n').to_string();
+                match line.tag() {
+                    similar::ChangeTag::Delete => moved.deleted.insert(value),
+                    similar::ChangeTag::Insert => moved.added.insert(value),
+                    similar::ChangeTag::Equal => false,
+                };
+            }
+        }
+        moved
+    }
 
-        writeln!(
-            w,
-            "diff --git a/{} b/{}",
-            file.display(),
-            file.display() // files name is always the same, current did't support rename
-        )
-        .unwrap();
+    /// Whether each change is in a moved block:
+    /// consecutive removed (added) lines which are added (removed) somewhere
+    fn flags(&self, lines: &[(similar::ChangeTag, &str)]) -> Vec<bool> {
+        let mut flags = vec![false; lines.len()];
+        let mut start = 0;
+        while start < lines.len() {
+            let tag = lines[start].0;
+            let others = match tag {
+                similar::ChangeTag::Delete => &self.added,
+                similar::ChangeTag::Insert => &self.deleted,
+                similar::ChangeTag::Equal => {
+                    start += 1;
+                    continue;
+                }
+            };
+            let is_moved = |(line_tag, line): &(similar::ChangeTag, &str)| {
+                *line_tag == tag && others.contains(line.trim_end_matches('\n'))
+            };
+            if !is_moved(&lines[start]) {
+                start += 1;
+                continue;
+            }
+            let mut end = start;
+            while end < lines.len() && is_moved(&lines[end]) {
+                end += 1;
+            }
+            let alnum: usize = lines[start..end]
+                .iter()
+                .map(|(_, line)| line.chars().filter(|c| c.is_alphanumeric()).count())
+                .sum();
+            if alnum >= Self::MIN_ALNUM {
+                flags[start..end].fill(true);
+            }
+            start = end;
+        }
+        flags
+    }
+}
 
-        if old_hash.is_none() {
-            writeln!(w, "new file mode 100644").unwrap();
-        } else if new_hash.is_none() {
-            writeln!(w, "deleted file mode 100644").unwrap();
-        }
-
-        let old_index = old_hash.map_or("0000000".to_string(), |h| h.to_string()[0..8].to_string());
-        let new_index = new_hash.map_or("0000000".to_string(), |h| h.to_string()[0..8].to_string());
-        writeln!(w, "index {}..{}", old_index, new_index).unwrap();
-        // check is the content is valid utf-8 or maybe binary
-        let old_type = infer::get(&old_content);
-        let new_type = infer::get(&new_content);
-        match (
-            String::from_utf8(old_content),
-            String::from_utf8(new_content),
-        ) {
-            (Ok(old_text), Ok(new_text)) => {
-                imara_diff_result(&old_text, &new_text, w);
+/// Write the diffstat like `git diff --stat`: the number of changed lines of each file, then the summary
+pub(crate) async fn diff_stat(
+    old_blobs: Vec<(PathBuf, SHA1)>,
+    new_blobs: Vec<(PathBuf, SHA1)>,
+    filter: Vec<PathBuf>,
+    w: &mut dyn io::Write,
+) {
+    let changes = collect_changes(old_blobs, new_blobs, &filter, None);
+    write_stat(&changes, w);
+}
+
+/// The insertions & deletions of the file, or the sizes of the old & new binary file
+type FileStat = Result<(usize, usize), (usize, usize)>;
+
+fn file_stat(change: &FileChange) -> FileStat {
+    let old_content = change.old_content();
+    let new_content = change.new_content();
+    match (
+        std::str::from_utf8(&old_content),
+        std::str::from_utf8(&new_content),
+    ) {
+        (Ok(old_text), Ok(new_text)) => Ok(count_changes(old_text, new_text)),
+        _ => Err((old_content.len(), new_content.len())),
+    }
+}
+
+fn write_stat(changes: &[FileChange], w: &mut dyn io::Write) {
+    let stats: Vec<(String, FileStat)> = changes
+        .iter()
+        .map(|change| (change.display_path(), file_stat(change)))
+        .collect();
+    if stats.is_empty() {
+        return;
+    }
+
+    const MAX_BAR: usize = 50;
+    let name_width = stats.iter().map(|(name, _)| name.len()).max().unwrap();
+    let max_changes = stats
+        .iter()
+        .filter_map(|(_, stat)| stat.as_ref().ok().map(|(ins, del)| ins + del))
+        .max()
+        .unwrap_or(0);
+    let count_width = max_changes.to_string().len();
+    let (mut insertions, mut deletions) = (0, 0);
+    for (name, stat) in stats.iter() {
+        match stat {
+            Ok((ins, del)) => {
+                insertions += ins;
+                deletions += del;
+                // scale the bar if there are too many changes
+                let (bar_ins, bar_del) = if max_changes > MAX_BAR {
+                    (
+                        (ins * MAX_BAR).div_ceil(max_changes),
+                        (del * MAX_BAR).div_ceil(max_changes),
+                    )
+                } else {
+                    (*ins, *del)
+                };
+                writeln!(
+                    w,
+                    " {:<name_width$} | {:>count_width$} {}{}",
+                    name,
+                    ins + del,
+                    "+".repeat(bar_ins).green(),
+                    "-".repeat(bar_del).red(),
+                )
+                .unwrap();
             }
-            _ => {
-                // TODO: Handle non-UTF-8 data as binary for now; consider optimization in the future.
+            Err((old_size, new_size)) => {
                 writeln!(
                     w,
-                    "Binary files a/{} and b/{} differ",
-                    file_display(&file, old_hash, old_type),
-                    file_display(&file, new_hash, new_type)
+                    " {:<name_width$} | Bin {} -> {} bytes",
+                    name, old_size, new_size
                 )
                 .unwrap();
             }
         }
     }
+    let plural = |n: usize, word: &str| format!("{} {}{}", n, word, if n == 1 { "" } else { "s" });
+    writeln!(
+        w,
+        " {} changed, {}(+), {}(-)",
+        plural(stats.len(), "file"),
+        plural(insertions, "insertion"),
+        plural(deletions, "deletion")
+    )
+    .unwrap();
+}
+
+/// `<insertions>\t<deletions>\t<path>` of each file, `-` for binary files
+fn write_numstat(changes: &[FileChange], w: &mut dyn io::Write) {
+    for change in changes {
+        match file_stat(change) {
+            Ok((ins, del)) => writeln!(w, "{}\t{}\t{}", ins, del, change.display_path()),
+            Err(_) => writeln!(w, "-\t-\t{}", change.display_path()),
+        }
+        .unwrap();
+    }
+}
+
+/// The paths (`--name-only`) or the status & paths (`--name-status`) of each file
+fn write_names(changes: &[FileChange], with_status: bool, w: &mut dyn io::Write) {
+    for change in changes {
+        // deleted files are shown with the old path
+        let path = match change.new_hash {
+            Some(_) => &change.new_path,
+            None => &change.old_path,
+        };
+        if !with_status {
+            writeln!(w, "{}", path.display()).unwrap();
+        } else if change.rename.is_some() {
+            writeln!(
+                w,
+                "{}\t{}\t{}",
+                change.status(),
+                change.old_path.display(),
+                change.new_path.display()
+            )
+            .unwrap();
+        } else {
+            writeln!(w, "{}\t{}", change.status(), path.display()).unwrap();
+        }
+    }
+}
+
+/// The number of inserted & deleted lines
+fn count_changes(old: &str, new: &str) -> (usize, usize) {
+    LineDiff::new(old, new)
+        .ops
+        .iter()
+        .fold((0, 0), |(ins, del), op| match op.as_tag_tuple() {
+            (similar::DiffTag::Insert, _, new_range) => (ins + new_range.len(), del),
+            (similar::DiffTag::Delete, old_range, _) => (ins, del + old_range.len()),
+            (similar::DiffTag::Replace, old_range, new_range) => {
+                (ins + new_range.len(), del + old_range.len())
+            }
+            (similar::DiffTag::Equal, _, _) => (ins, del),
+        })
+}
+
+/// Read the content from blob, or the file in working directory if the blob is not saved
+fn read_content(file: &Path, hash: &SHA1) -> Vec<u8> {
+    let file = util::workdir_to_absolute(file);
+    // the blob of a file in worktree is never saved, don't fetch it from the promisor remote of partial clone
+    if util::objects_storage().exist(hash) || !file.exists() {
+        if let Ok(blob) = load_object::<Blob>(hash) {
+            return blob.data;
+        }
+    }
+    std::fs::read(&file)
+        .map_err(|e| {
+            eprintln!("fatal: could not read file '{}': {}", file.display(), e);
+        })
+        .unwrap()
 }
 
 async fn get_commit_blobs(commit_hash: &SHA1) -> Vec<(PathBuf, SHA1)> {
@@ -326,19 +974,10 @@ fn similar_diff_result(old: &str, new: &str, w: &mut dyn io::Write) {
     }
 }
 
-fn imara_diff_result(old: &str, new: &str, w: &mut dyn io::Write) {
-    let input = InternedInput::new(old, new);
-    let diff = imara_diff::diff(
-        Algorithm::Histogram,
-        &input,
-        UnifiedDiffBuilder::new(&input),
-    );
-    write!(w, "{}", diff).unwrap();
-}
-
 #[cfg(test)]
 mod test {
     use super::*;
+    use crate::utils::test;
     #[test]
     fn test_args() {
         {
@@ -376,6 +1015,120 @@ mod test {
         }
     }
 
+    #[test]
+    fn test_rename_args() {
+        let args = DiffArgs::try_parse_from(["diff", "-M", "paths"]).unwrap();
+        assert_eq!(args.find_renames, Some(50));
+        assert_eq!(args.find_copies, None);
+        assert_eq!(args.pathspec, vec!["paths".to_string()]);
+
+        let args = DiffArgs::try_parse_from(["diff", "--find-renames=90%", "-C=8"]).unwrap();
+        assert_eq!(args.find_renames, Some(90));
+        assert_eq!(args.find_copies, Some(80));
+
+        assert!(DiffArgs::try_parse_from(["diff", "--find-renames=200%"]).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_diff_renames() {
+        test::setup_with_new_libra().await;
+        let content = "fn main() {\n    println!(\"hello\");\n}\n";
+        test::ensure_file("src/main.rs", Some(content));
+        test::ensure_file("bin/app.rs", Some(content));
+        let old_blobs = get_files_blobs(&[PathBuf::from("src/main.rs")]);
+        let new_blobs = get_files_blobs(&[PathBuf::from("bin/app.rs")]);
+
+        let mut buf = Vec::new();
+        let options = DiffOptions {
+            renames: Some(rename::Options::default()),
+            ..Default::default()
+        };
+        diff(
+            old_blobs.clone(),
+            new_blobs.clone(),
+            vec![],
+            &options,
+            &mut buf,
+        )
+        .await;
+        assert_eq!(
+            String::from_utf8(buf).unwrap(),
+            "diff --git a/src/main.rs b/bin/app.rs\nsimilarity index 100%\nrename from src/main.rs\nrename to bin/app.rs\n"
+        );
+
+        // a deletion & an addition without rename detection
+        let mut buf = Vec::new();
+        diff(
+            old_blobs.clone(),
+            new_blobs.clone(),
+            vec![],
+            &Default::default(),
+            &mut buf,
+        )
+        .await;
+        let output = String::from_utf8(buf).unwrap();
+        assert!(output.contains("deleted file mode 100644"));
+        assert!(output.contains("new file mode 100644"));
+
+        let changes = collect_changes(old_blobs, new_blobs, &[], options.renames);
+        let mut buf = Vec::new();
+        write_names(&changes, true, &mut buf);
+        assert_eq!(
+            String::from_utf8(buf).unwrap(),
+            "R100\tsrc/main.rs\tbin/app.rs\n"
+        );
+        let mut buf = Vec::new();
+        write_numstat(&changes, &mut buf);
+        assert_eq!(
+            String::from_utf8(buf).unwrap(),
+            "0\t0\tsrc/main.rs => bin/app.rs\n"
+        );
+    }
+
+    #[test]
+    fn test_write_hunks() {
+        let old = "a\nb\nc\nd\ne\nf\ng\nh\n";
+        let new = "a\nB\nc\nd\ne\nf\ng\nh";
+        let mut buf = Vec::new();
+        let options = DiffOptions {
+            context: 1,
+            ..Default::default()
+        };
+        write_hunks(old, new, &options, None, &mut buf);
+        assert_eq!(
+            String::from_utf8(buf).unwrap(),
+            "@@ -1,3 +1,3 @@\n a\n-b\n+B\n c\n@@ -7,2 +7,2 @@\n g\n-h\n+h\n\\ No newline at end of file\n"
+        );
+
+        let mut buf = Vec::new();
+        let options = DiffOptions {
+            context: 0,
+            word_diff: Some(WordDiff::Plain),
+            ..Default::default()
+        };
+        write_hunks("one two three\n", "one 2 three\n", &options, None, &mut buf);
+        assert_eq!(
+            String::from_utf8(buf).unwrap(),
+            "@@ -1 +1 @@\none [-two-]{+2+} three\n"
+        );
+    }
+
+    #[test]
+    fn test_moved_lines() {
+        let moved = MovedLines {
+            deleted: HashSet::from(["fn moved_helper_function() {".to_string(), "}".to_string()]),
+            added: HashSet::from(["}".to_string()]),
+        };
+        let lines = [
+            (similar::ChangeTag::Equal, "fn main() {\n"),
+            (similar::ChangeTag::Insert, "fn moved_helper_function() {\n"),
+            (similar::ChangeTag::Insert, "}\n"),
+            (similar::ChangeTag::Delete, "}\n"),
+        ];
+        // `}` alone is too short to be a moved block
+        assert_eq!(moved.flags(&lines), vec![false, true, true, false]);
+    }
+
     #[test]
     fn test_similar_diff_result() {
         let old = "Hello World\nThis is the second line.\nThis is the third.";
@@ -385,4 +1138,13 @@ mod test {
         let result = String::from_utf8(buf).unwrap();
         println!("{}", result);
     }
+
+    #[test]
+    fn test_count_changes() {
+        let old = "a\nb\nc\n";
+        let new = "a\nB\nc\nd\n";
+        assert_eq!(count_changes(old, new), (2, 1));
+        assert_eq!(count_changes("", new), (4, 0));
+        assert_eq!(count_changes(old, old), (0, 0));
+    }
 }
@@ -1,4 +1,5 @@
 use std::io;
+use std::str::FromStr;
 use std::vec;
 use std::{collections::HashSet, fs, io::Write};
 use std::time::Instant;
@@ -9,7 +10,6 @@ use mercury::internal::object::commit::Commit;
 use mercury::hash::SHA1;
 use tokio::io::{AsyncRead, AsyncReadExt};
 use tokio_util::io::StreamReader;
-use url::Url;
 
 use crate::command::load_object;
 use crate::{
@@ -18,9 +18,10 @@ use crate::{
         branch::Branch,
         config::{Config, RemoteConfig},
         head::Head,
-        protocol::{https_client::HttpsClient, ProtocolClient},
+        protocol::{self, https_client::DiscoveredReference, ProtocolClient, RemoteClient, UploadPackOptions},
+        tag::Tag,
     },
-    utils::{self, path_ext::PathExt},
+    utils::{self, path_ext::PathExt, shallow},
 };
 use crate::utils::util;
 
@@ -38,15 +39,41 @@ pub struct FetchArgs {
     /// Fetch all remotes.
     #[clap(long, short, conflicts_with("repository"))]
     pub all: bool,
+
+    /// Fetch all tags from the remote, not only the tags pointing into the fetched history
+    #[clap(long, short)]
+    pub tags: bool,
+
+    /// Do not fetch tags
+    #[clap(long, conflicts_with("tags"))]
+    pub no_tags: bool,
+}
+
+/// How tags (`refs/tags/*`) are fetched
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TagMode {
+    /// Tags pointing to the fetched objects (auto-following by `include-tag`)
+    Follow,
+    /// All tags, like `--tags`
+    All,
+    /// No tags, like `--no-tags`
+    None,
 }
 
 pub async fn execute(args: FetchArgs) {
     tracing::debug!("`fetch` args: {:?}", args);
     tracing::warn!("didn't test yet");
+    let tag_mode = if args.tags {
+        TagMode::All
+    } else if args.no_tags {
+        TagMode::None
+    } else {
+        TagMode::Follow
+    };
     if args.all {
         let remotes = Config::all_remote_configs().await;
         let tasks = remotes.into_iter().map(|remote| async move {
-            fetch_repository(&remote, None).await;
+            fetch_repository(&remote, None, tag_mode, UploadPackOptions::default()).await;
         });
         futures::future::join_all(tasks).await;
     } else {
@@ -62,7 +89,9 @@ pub async fn execute(args: FetchArgs) {
         };
         let remote_config = Config::remote_config(&remote).await;
         match remote_config {
-            Some(remote_config) => fetch_repository(&remote_config, args.refspec).await,
+            Some(remote_config) => {
+                fetch_repository(&remote_config, args.refspec, tag_mode, UploadPackOptions::default()).await
+            }
             None => {
                 tracing::error!("remote config '{}' not found", remote);
                 eprintln!("fatal: '{}' does not appear to be a libra repository", remote);
@@ -73,7 +102,14 @@ pub async fn execute(args: FetchArgs) {
 
 /// Fetch from remote repository
 /// - `branch` is optional, if `None`, fetch all branches
-pub async fn fetch_repository(remote_config: &RemoteConfig, branch: Option<String>) {
+/// - `tag_mode` decides which tags are fetched
+/// - `options`: `depth`, `deepen_since` & `filter` for shallow & partial clone, the shallow commits are filled in here
+pub async fn fetch_repository(
+    remote_config: &RemoteConfig,
+    branch: Option<String>,
+    tag_mode: TagMode,
+    mut options: UploadPackOptions,
+) {
     println!("fetching from {}{}", remote_config.name,
              if let Some(branch) = &branch {
                 format!(" ({})", branch)
@@ -82,22 +118,27 @@ pub async fn fetch_repository(remote_config: &RemoteConfig, branch: Option<Strin
             });
 
     // fetch remote
-    let url = match Url::parse(&remote_config.url) {
+    let url = match protocol::parse_url(&remote_config.url) {
         Ok(url) => url,
         Err(e) => {
             eprintln!("fatal: invalid URL '{}': {}", remote_config.url, e);
             return;
         }
     };
-    let http_client = HttpsClient::from_url(&url);
+    let client = RemoteClient::from_url(&url);
 
-    let refs = match http_client.discovery_reference(UploadPack).await {
-        Ok(refs) => refs,
+    let (refs, capabilities) = match client.discovery_reference(UploadPack).await {
+        Ok(discovered) => discovered,
         Err(e) => {
             eprintln!("fatal: {}", e);
             return;
         }
     };
+    options.shallow = shallow::load().iter().map(|c| c.to_string()).collect();
+    if let Err(e) = options.check_capabilities(&capabilities) {
+        eprintln!("fatal: {}", e);
+        return;
+    }
 
     if refs.is_empty() {
         tracing::warn!("fetch empty, no refs found");
@@ -105,6 +146,12 @@ pub async fn fetch_repository(remote_config: &RemoteConfig, branch: Option<Strin
     }
 
     let remote_head = refs.iter().find(|r| r._ref == "HEAD").cloned();
+    // annotated tags are followed by `^{}` refs, which are the objects they point to
+    let ref_tags = refs
+        .iter()
+        .filter(|r| r._ref.starts_with("refs/tags/") && !r._ref.ends_with("^{}"))
+        .cloned()
+        .collect::<Vec<_>>();
     // remote branches
     let mut ref_heads = refs // DO NOT use `refs` later
         .into_iter()
@@ -122,24 +169,110 @@ pub async fn fetch_repository(remote_config: &RemoteConfig, branch: Option<Strin
         }
     }
 
-    let want = ref_heads
+    let mut want = ref_heads
         .iter()
         .map(|r| r._hash.clone())
         .collect::<Vec<_>>();
+    if tag_mode == TagMode::All {
+        let storage = util::objects_storage();
+        want.extend(
+            ref_tags
+                .iter()
+                .filter(|r| !storage.exist(&SHA1::from_str(&r._hash).unwrap()))
+                .map(|r| r._hash.clone()),
+        );
+    }
     let have = current_have().await; // TODO: return `DiscRef` rather than only hash, to compare `have` & `want` more accurately
 
-    let mut result_stream = http_client
-        .fetch_objects(&have, &want)
+    match fetch_pack(&client, &have, &want, &options, true).await {
+        Ok((shallow_commits, unshallow_commits)) => {
+            shallow::update(&shallow_commits, &unshallow_commits);
+        }
+        Err(e) => {
+            eprintln!("fatal: {}", e);
+            return;
+        }
+    }
+
+    /* update reference  */
+    for r in &ref_heads {
+        let branch_name = r._ref.strip_prefix("refs/heads/").unwrap();
+        let remote = Some(remote_config.name.as_str());
+        Branch::update_branch(branch_name, &r._hash, remote, "fetch").await;
+    }
+    if tag_mode != TagMode::None {
+        update_tags(&ref_tags).await;
+    }
+    match remote_head {
+        Some(remote_head) => {
+            let remote_head_ref = ref_heads
+                .iter()
+                .find(|r| r._hash == remote_head._hash);
+
+            match remote_head_ref {
+                Some(remote_head_ref) => {
+                    let remote_head_branch = remote_head_ref._ref.strip_prefix("refs/heads/").unwrap();
+                    Head::update(Head::Branch(remote_head_branch.to_owned()), Some(&remote_config.name), "fetch").await;
+                }
+                None => {
+                    if branch.is_none() {
+                        eprintln!("remote HEAD not found");
+                    } else {
+                        // normal: remote HEAD usually points to master
+                        tracing::debug!("Specified branch not found in remote HEAD");
+                    }
+                }
+            }
+        }
+        None => {
+            tracing::warn!("fetch empty, remote HEAD not found");
+        }
+    }
+}
+
+/// Send `have` & `want` to `git-upload-pack`, then receive and save the PACK file (with .idx)
+/// - return the `shallow` & `unshallow` commits sent by the server if deepening
+/// - `verbose`: print the progress of the server & receiving
+async fn fetch_pack(
+    client: &RemoteClient,
+    have: &Vec<String>,
+    want: &Vec<String>,
+    options: &UploadPackOptions,
+    verbose: bool,
+) -> Result<(Vec<SHA1>, Vec<SHA1>), String> {
+    let mut result_stream = client
+        .fetch_objects(have, want, options)
         .await
-        .unwrap();
+        .map_err(|e| e.to_string())?;
 
     let mut reader = StreamReader::new(&mut result_stream);
+    // shallow-info section, ends with a flush-pkt
+    let mut shallow_commits = Vec::new();
+    let mut unshallow_commits = Vec::new();
+    if options.is_deepening() {
+        loop {
+            let (len, data) = read_pkt_line(&mut reader).await.map_err(|e| e.to_string())?;
+            if len == 0 {
+                break;
+            }
+            let line = String::from_utf8_lossy(&data);
+            if let Some(hash) = line.strip_prefix("shallow ") {
+                shallow_commits.push(SHA1::from_str(hash.trim()).unwrap());
+            } else if let Some(hash) = line.strip_prefix("unshallow ") {
+                unshallow_commits.push(SHA1::from_str(hash.trim()).unwrap());
+            }
+        }
+    }
     let mut pack_data = Vec::new();
     let mut reach_pack = false;
-    let bar = ProgressBar::new_spinner();
+    let bar = if verbose {
+        ProgressBar::new_spinner()
+    } else {
+        ProgressBar::hidden()
+    };
     let time = Instant::now();
     loop {
-        let (len, data) = read_pkt_line(&mut reader).await.unwrap();
+        let (len, data) = read_pkt_line(&mut reader).await.map_err(|e| e.to_string())?;
         if len == 0 {
             break;
         }
@@ -160,10 +293,11 @@ pub async fn fetch_repository(remote_config: &RemoteConfig, branch: Option<Strin
                 1 => { // Data
                     pack_data.extend(data); // TODO: decode meanwhile & calc progress
                 }
-                2 => { // Progress
+                2 if verbose => { // Progress
                     print!("{}", String::from_utf8_lossy(data));
                     std::io::stdout().flush().unwrap();
                 }
+                2 => {}
                 3 => { // Error
                     eprintln!("{}", String::from_utf8_lossy(data));
                 }
@@ -171,7 +305,7 @@ pub async fn fetch_repository(remote_config: &RemoteConfig, branch: Option<Strin
                     eprintln!("unknown side-band-64k code: {}", code);
                 }
             }
-        } else if &data != b"NAK\n" { // 1.front info (server progress), ignore NAK (first line)
+        } else if verbose && &data != b"NAK\n" { // 1.front info (server progress), ignore NAK (first line)
             print!("{}", String::from_utf8_lossy(&data)); // data contains '\r' & '\n' at end
             std::io::stdout().flush().unwrap();
         }
@@ -185,7 +319,9 @@ pub async fn fetch_repository(remote_config: &RemoteConfig, branch: Option<Strin
         let checksum = SHA1::from_bytes(&pack_data[pack_data.len() - 20..]);
         assert_eq!(hash, checksum);
         let checksum = checksum.to_string();
-        println!("checksum: {}", checksum);
+        if verbose {
+            println!("checksum: {}", checksum);
+        }
 
         if pack_data.len() > 32 { // 12 header + 20 hash
             let pack_file = utils::path::objects()
@@ -209,36 +345,55 @@ pub async fn fetch_repository(remote_config: &RemoteConfig, branch: Option<Strin
             index_version: None,
         });
     }
+    Ok((shallow_commits, unshallow_commits))
+}
 
-    /* update reference  */
-    for r in &ref_heads {
-        let branch_name = r._ref.strip_prefix("refs/heads/").unwrap();
-        let remote = Some(remote_config.name.as_str());
-        Branch::update_branch(branch_name, &r._hash, remote).await;
+/// Fetch the missing objects from the promisor remote of a partial clone (`--filter`) in one request,
+/// want-only without `have`, they are saved in a new PACK
+async fn fetch_missing_objects(url: &str, objects: &[SHA1]) -> Result<(), String> {
+    let url = protocol::parse_url(url).map_err(|e| e.to_string())?;
+    let want: Vec<String> = objects.iter().map(|o| o.to_string()).collect();
+    let client = RemoteClient::from_url(&url);
+    fetch_pack(&client, &Vec::new(), &want, &UploadPackOptions::default(), false)
+        .await
+        .map(|_| ())
+}
+
+/// Fetch the blobs omitted by the partial clone in one request before an operation reads them,
+/// the object storage doesn't fetch them on demand. Nothing to do if it's not a partial clone.
+/// - `blobs` must be saved in the remote, e.g. not the files in the worktree
+pub async fn prefetch_blobs(blobs: impl IntoIterator<Item = SHA1>) {
+    let storage = util::objects_storage();
+    let url = match storage.promisor_remote() {
+        Some(url) => url,
+        None => return,
+    };
+    let missing: HashSet<SHA1> = blobs.into_iter().filter(|blob| !storage.exist(blob)).collect();
+    if missing.is_empty() {
+        return;
     }
-    match remote_head {
-        Some(remote_head) => {
-            let remote_head_ref = ref_heads
-                .iter()
-                .find(|r| r._hash == remote_head._hash);
+    let missing: Vec<SHA1> = missing.into_iter().collect();
+    if let Err(e) = fetch_missing_objects(&url, &missing).await {
+        eprintln!("warning: failed to fetch missing blobs: {}", e);
+    }
+}
 
-            match remote_head_ref {
-                Some(remote_head_ref) => {
-                    let remote_head_branch = remote_head_ref._ref.strip_prefix("refs/heads/").unwrap();
-                    Head::update(Head::Branch(remote_head_branch.to_owned()), Some(&remote_config.name)).await;
-                }
-                None => {
-                    if branch.is_none() {
-                        eprintln!("remote HEAD not found");
-                    } else {
-                        // normal: remote HEAD usually points to master
-                        tracing::debug!("Specified branch not found in remote HEAD");
-                    }
-                }
+/// Save the remote tags whose objects have been fetched, existing local tags are not overwritten
+async fn update_tags(ref_tags: &[DiscoveredReference]) {
+    let storage = util::objects_storage();
+    for r in ref_tags {
+        let name = r._ref.strip_prefix("refs/tags/").unwrap();
+        let hash = SHA1::from_str(&r._hash).unwrap();
+        match Tag::find_tag(name).await {
+            Some(tag) if tag.object != hash => {
+                println!(" ! [rejected] {} -> {} (would clobber existing tag)", name, name);
             }
-        }
-        None => {
-            tracing::warn!("fetch empty, remote HEAD not found");
+            Some(_) => {}
+            None if storage.exist(&hash) => {
+                Tag::update_tag(name, &hash).await;
+                println!(" * [new tag] {} -> {}", name, name);
+            }
+            None => {} // not in the fetched history
         }
     }
 }
@@ -278,14 +433,21 @@ async fn current_have() -> Vec<String> {
             check_and_insert(&commit, &mut inserted, &mut c_pending);
         }
     }
+    let shallow_commits = shallow::load();
     let mut have = Vec::new();
     while have.len() < 32 && !c_pending.is_empty() {
         let item = c_pending.pop().unwrap();
         have.push(item.commit.to_string());
 
+        if shallow_commits.contains(&item.commit) {
+            continue; // parents are not fetched
+        }
         let commit: Commit = load_object(&item.commit).unwrap();
         for parent in commit.parent_commit_ids {
-            let parent: Commit = load_object(&parent).unwrap();
+            let parent: Commit = match load_object(&parent) {
+                Ok(parent) => parent,
+                Err(_) => continue,
+            };
             check_and_insert(&parent, &mut inserted, &mut c_pending);
         }
     }
@@ -0,0 +1,424 @@
+use std::collections::HashSet;
+use std::fs::File;
+use std::io::{BufReader, Read, Seek, SeekFrom};
+use std::panic;
+use std::path::Path;
+
+use clap::Parser;
+use mercury::errors::GitError;
+use mercury::hash::SHA1;
+use mercury::internal::object::commit::Commit;
+use mercury::internal::object::tag::Tag;
+use mercury::internal::object::tree::TreeItemMode;
+use mercury::internal::object::types::ObjectType;
+use mercury::internal::object::ObjectTrait;
+use sha1::{Digest, Sha1};
+
+use crate::utils::{shallow, util};
+
+use super::gc;
+
+#[derive(Parser, Debug)]
+pub struct FsckArgs {
+    /// Only check the connectivity of the objects, don't verify the content (hash) of every object
+    #[clap(long)]
+    pub connectivity_only: bool,
+
+    /// Do not print the dangling objects
+    #[clap(long)]
+    pub no_dangling: bool,
+}
+
+/// Problems found by [fsck]
+#[derive(Debug, Default)]
+pub(crate) struct FsckReport {
+    /// missing, corrupt and malformed objects, one message for each
+    pub errors: Vec<String>,
+    /// unreachable objects which are not referenced by other unreachable objects
+    pub dangling: Vec<(ObjectType, SHA1)>,
+}
+
+/// Return error if any missing, corrupt or malformed object is found, so that the exit code is non-zero
+pub async fn execute(args: FsckArgs) -> Result<(), GitError> {
+    if !util::check_repo_exist() {
+        return Ok(());
+    }
+    let roots = gc::gc_roots().await;
+    let report = fsck(roots, !args.connectivity_only);
+
+    if !args.no_dangling {
+        for (obj_type, hash) in report.dangling.iter() {
+            println!("dangling {} {}", obj_type, hash);
+        }
+    }
+    for error in report.errors.iter() {
+        eprintln!("{}", error);
+    }
+    if report.errors.is_empty() {
+        Ok(())
+    } else {
+        Err(GitError::CustomError(format!(
+            "fsck found {} errors",
+            report.errors.len()
+        )))
+    }
+}
+
+/// Check the object storage & the object graph from `roots`
+/// - `verify_content`: recompute the hash of every object in loose & packed storage, and the checksums of PACKs
+/// - objects omitted by partial clone and parents of shallow commits are not reported as missing
+pub(crate) fn fsck(roots: Vec<SHA1>, verify_content: bool) -> FsckReport {
+    let storage = util::objects_storage();
+    let mut report = FsckReport::default();
+    let loose: HashSet<SHA1> = storage.list_objects_loose().into_iter().collect();
+    let packed = storage.list_objects_pack();
+    let mut corrupt = HashSet::new();
+
+    if verify_content {
+        for pack in storage.list_all_packs() {
+            if let Err(e) = verify_pack_checksum(&pack) {
+                report.errors.push(format!(
+                    "error: {}: {}",
+                    pack.file_name().unwrap().to_string_lossy(),
+                    e
+                ));
+            }
+            for hash in storage.list_pack_objects(&pack) {
+                if let Err(e) = verify_object(&hash, storage.read_packed(&pack, &hash)) {
+                    report.errors.push(format!("error: {}: {}", hash, e));
+                    corrupt.insert(hash);
+                }
+            }
+        }
+        for hash in loose.iter() {
+            if let Err(e) = verify_object(hash, storage.read_loose(hash).map(Some)) {
+                report.errors.push(format!("error: {}: {}", hash, e));
+                corrupt.insert(*hash);
+            }
+        }
+    }
+
+    // connectivity from the roots
+    let is_partial = storage.promisor_remote().is_some();
+    let shallow_commits = shallow::load();
+    let mut reachable = HashSet::new();
+    let mut stack: Vec<(SHA1, Option<ObjectType>)> =
+        roots.into_iter().map(|root| (root, None)).collect();
+    while let Some((hash, expected)) = stack.pop() {
+        if !reachable.insert(hash) || corrupt.contains(&hash) {
+            continue;
+        }
+        if !loose.contains(&hash) && !packed.contains(&hash) {
+            if !(is_partial && expected == Some(ObjectType::Blob)) {
+                let obj_type = expected.map_or("object".to_string(), |t| t.to_string());
+                report.errors.push(format!("missing {} {}", obj_type, hash));
+            }
+            continue;
+        }
+        let (data, obj_type) = match storage.get_with_type(&hash) {
+            Ok(obj) => obj,
+            Err(e) => {
+                report.errors.push(format!("error: {}: {}", hash, e));
+                continue;
+            }
+        };
+        if let Some(expected) = expected.filter(|t| *t != obj_type) {
+            report.errors.push(format!(
+                "error: {}: expected {}, but is {}",
+                hash, expected, obj_type
+            ));
+            continue;
+        }
+        match references(&hash, obj_type, &data) {
+            Ok(refs) => {
+                let refs = refs.into_iter().filter(|(_, t)| {
+                    // parents of shallow commits are not fetched
+                    !(obj_type == ObjectType::Commit
+                        && *t == ObjectType::Commit
+                        && shallow_commits.contains(&hash))
+                });
+                stack.extend(refs.map(|(h, t)| (h, Some(t))));
+            }
+            Err(e) => report
+                .errors
+                .push(format!("error in {} {}: {}", obj_type, hash, e)),
+        }
+    }
+
+    // dangling: the tips of the unreachable objects
+    let unreachable: Vec<SHA1> = loose
+        .union(&packed)
+        .filter(|hash| !reachable.contains(*hash) && !corrupt.contains(*hash))
+        .copied()
+        .collect();
+    let mut types = Vec::with_capacity(unreachable.len());
+    let mut referenced = HashSet::new();
+    for hash in unreachable.iter() {
+        let (data, obj_type) = match storage.get_with_type(hash) {
+            Ok(obj) => obj,
+            Err(_) => continue,
+        };
+        if let Ok(refs) = references(hash, obj_type, &data) {
+            referenced.extend(refs.into_iter().map(|(h, _)| h));
+        }
+        types.push((obj_type, *hash));
+    }
+    report.dangling = types
+        .into_iter()
+        .filter(|(_, hash)| !referenced.contains(hash))
+        .collect();
+    report.dangling.sort_by_key(|(_, hash)| *hash);
+    report
+}
+
+/// Recompute the hash of the object read from storage
+fn verify_object(
+    hash: &SHA1,
+    obj: Result<Option<(Vec<u8>, ObjectType)>, GitError>,
+) -> Result<(), String> {
+    match obj {
+        Ok(Some((data, obj_type))) => {
+            let actual = SHA1::from_type_and_data(obj_type, &data);
+            if actual != *hash {
+                return Err(format!("hash mismatch, the content is {}", actual));
+            }
+            Ok(())
+        }
+        Ok(None) => Err("listed in index but not found in pack".to_string()),
+        Err(e) => Err(format!("object corrupt: {}", e)),
+    }
+}
+
+/// The trailing checksum of the PACK, and the copy of it in the .idx file
+fn verify_pack_checksum(pack: &Path) -> Result<(), String> {
+    let mut file = File::open(pack).map_err(|e| e.to_string())?;
+    let len = file.metadata().map_err(|e| e.to_string())?.len();
+    if len < 32 {
+        return Err("pack is truncated".to_string());
+    }
+    // packs may be much larger than the memory, hash them by chunks
+    let mut hasher = Sha1::new();
+    let mut content = BufReader::new(&mut file).take(len - 20);
+    let mut buf = vec![0; 64 * 1024];
+    loop {
+        let n = content.read(&mut buf).map_err(|e| e.to_string())?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buf[..n]);
+    }
+    let mut checksum = [0u8; 20];
+    file.seek(SeekFrom::Start(len - 20))
+        .and_then(|_| file.read_exact(&mut checksum))
+        .map_err(|e| e.to_string())?;
+    if hasher.finalize().as_slice() != checksum {
+        return Err("pack checksum mismatch".to_string());
+    }
+
+    // .idx ends with the pack checksum & its own checksum
+    let mut idx = File::open(pack.with_extension("idx")).map_err(|e| e.to_string())?;
+    let mut idx_checksum = [0u8; 20];
+    idx.seek(SeekFrom::End(-40))
+        .and_then(|_| idx.read_exact(&mut idx_checksum))
+        .map_err(|_| "pack checksum does not match its index".to_string())?;
+    if idx_checksum != checksum {
+        return Err("pack checksum does not match its index".to_string());
+    }
+    Ok(())
+}
+
+/// Objects referenced by the object, with the expected types
+fn references(
+    hash: &SHA1,
+    obj_type: ObjectType,
+    data: &[u8],
+) -> Result<Vec<(SHA1, ObjectType)>, String> {
+    match obj_type {
+        ObjectType::Commit => {
+            let commit: Commit = parse(hash, data)?;
+            let mut refs = vec![(commit.tree_id, ObjectType::Tree)];
+            refs.extend(
+                commit
+                    .parent_commit_ids
+                    .into_iter()
+                    .map(|parent| (parent, ObjectType::Commit)),
+            );
+            Ok(refs)
+        }
+        ObjectType::Tree => Ok(check_tree(data)?
+            .into_iter()
+            .filter_map(|(mode, _, id)| match mode {
+                TreeItemMode::Commit => None, // submodule, not in this repository
+                TreeItemMode::Tree => Some((id, ObjectType::Tree)),
+                _ => Some((id, ObjectType::Blob)),
+            })
+            .collect()),
+        ObjectType::Tag => {
+            let tag: Tag = parse(hash, data)?;
+            Ok(vec![(tag.object_hash, tag.object_type)])
+        }
+        _ => Ok(vec![]),
+    }
+}
+
+/// Parse the object, the parsers may panic on malformed data, which is caught and reported as an error
+fn parse<T: ObjectTrait>(hash: &SHA1, data: &[u8]) -> Result<T, String> {
+    panic::catch_unwind(|| T::from_bytes(data, *hash))
+        .map_err(|_| "malformed object".to_string())?
+        .map_err(|e| e.to_string())
+}
+
+/// Parse the raw tree entries (`<mode> <name>\0<20 bytes hash>`) and check them like `git fsck`:
+/// valid modes & names, sorted without duplicates
+fn check_tree(data: &[u8]) -> Result<Vec<(TreeItemMode, String, SHA1)>, String> {
+    let mut entries = Vec::new();
+    let mut names = HashSet::new();
+    let mut last_key: Option<Vec<u8>> = None;
+    let mut rest = data;
+    while !rest.is_empty() {
+        let space = rest
+            .iter()
+            .position(|b| *b == b' ')
+            .ok_or("truncated entry")?;
+        let nul = rest.iter().position(|b| *b == 0).ok_or("truncated entry")?;
+        if nul < space || rest.len() < nul + 21 {
+            return Err("truncated entry".to_string());
+        }
+        let mode = match &rest[..space] {
+            b"100644" | b"100664" => TreeItemMode::Blob,
+            b"100755" => TreeItemMode::BlobExecutable,
+            b"40000" | b"040000" => TreeItemMode::Tree,
+            b"120000" => TreeItemMode::Link,
+            b"160000" => TreeItemMode::Commit,
+            mode => return Err(format!("bad file mode {}", String::from_utf8_lossy(mode))),
+        };
+        let name = &rest[space + 1..nul];
+        let id = SHA1::from_bytes(&rest[nul + 1..nul + 21]);
+        rest = &rest[nul + 21..];
+
+        let name_str = String::from_utf8_lossy(name).into_owned();
+        if name.is_empty() {
+            return Err("empty filename".to_string());
+        }
+        if name.contains(&b'/') {
+            return Err(format!("filename '{}' contains '/'", name_str));
+        }
+        if matches!(name, b"." | b".." | b".git" | b".libra") {
+            return Err(format!("filename '{}' is not allowed", name_str));
+        }
+        if !names.insert(name.to_vec()) {
+            return Err(format!("duplicate entries '{}'", name_str));
+        }
+        // trees are sorted as if their names end with '/'
+        let mut key = name.to_vec();
+        if mode == TreeItemMode::Tree {
+            key.push(b'/');
+        }
+        if last_key.as_ref().is_some_and(|last| *last > key) {
+            return Err(format!("not properly sorted at '{}'", name_str));
+        }
+        last_key = Some(key);
+        entries.push((mode, name_str, id));
+    }
+    Ok(entries)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::test;
+    use mercury::internal::object::blob::Blob;
+
+    fn tree_entry(mode: &str, name: &str, id: &SHA1) -> Vec<u8> {
+        let mut entry = format!("{} {}\0", mode, name).into_bytes();
+        entry.extend(id.0);
+        entry
+    }
+
+    #[test]
+    fn test_check_tree() {
+        let id = SHA1::new(&[1]);
+        let data = [
+            tree_entry("100644", "a.txt", &id),
+            tree_entry("40000", "a", &id),
+            tree_entry("100755", "b", &id),
+        ]
+        .concat();
+        let entries = check_tree(&data).unwrap();
+        assert_eq!(entries.len(), 3);
+        assert_eq!(entries[1].0, TreeItemMode::Tree);
+
+        let unsorted = [
+            tree_entry("100644", "b", &id),
+            tree_entry("100644", "a", &id),
+        ]
+        .concat();
+        assert!(check_tree(&unsorted).unwrap_err().contains("sorted"));
+        let duplicate = [
+            tree_entry("100644", "a", &id),
+            tree_entry("40000", "a", &id),
+        ]
+        .concat();
+        assert!(check_tree(&duplicate).unwrap_err().contains("duplicate"));
+        assert!(check_tree(&tree_entry("100645", "a", &id)).is_err());
+        assert!(check_tree(&tree_entry("100644", "..", &id)).is_err());
+        assert!(check_tree(&data[..data.len() - 1]).is_err());
+    }
+
+    #[test]
+    fn test_verify_pack_checksum() {
+        let dir = tempfile::tempdir().unwrap();
+        let pack = dir.path().join("pack-test.pack");
+        let content = vec![7u8; 100 * 1024];
+        let checksum = SHA1::new(&content);
+        std::fs::write(&pack, [content.as_slice(), &checksum.0].concat()).unwrap();
+        let idx = [vec![0u8; 1024], checksum.0.to_vec(), vec![0u8; 20]].concat();
+        std::fs::write(pack.with_extension("idx"), idx).unwrap();
+        assert!(verify_pack_checksum(&pack).is_ok());
+
+        std::fs::write(pack.with_extension("idx"), [0u8; 1064]).unwrap();
+        assert!(verify_pack_checksum(&pack).unwrap_err().contains("index"));
+        let corrupt = [vec![8u8; 100 * 1024], checksum.0.to_vec()].concat();
+        std::fs::write(&pack, corrupt).unwrap();
+        assert!(verify_pack_checksum(&pack)
+            .unwrap_err()
+            .contains("mismatch"));
+    }
+
+    #[tokio::test]
+    async fn test_fsck() {
+        test::setup_with_new_libra().await;
+        test::ensure_file("fsck_a.txt", Some("a\n"));
+        crate::exec_async(vec!["add", "fsck_a.txt"]).await.unwrap();
+        crate::exec_async(vec!["commit", "-m", "first"])
+            .await
+            .unwrap();
+        let roots = gc::gc_roots().await;
+        let report = fsck(roots.clone(), true);
+        assert!(report.errors.is_empty(), "{:?}", report.errors);
+        assert!(report.dangling.is_empty());
+
+        // dangling & corrupt objects
+        let storage = util::objects_storage();
+        let dangling = Blob::from_content("dangling");
+        storage
+            .put(&dangling.id, &dangling.data, ObjectType::Blob)
+            .unwrap();
+        let fake = SHA1::new(&[2]);
+        storage.put(&fake, b"corrupt", ObjectType::Blob).unwrap();
+        let report = fsck(roots.clone(), true);
+        assert_eq!(report.dangling, vec![(ObjectType::Blob, dangling.id)]);
+        assert_eq!(report.errors.len(), 1);
+        assert!(report.errors[0].contains(&fake.to_string()));
+        storage.remove_loose(&fake).unwrap();
+
+        // missing blob
+        let blob = Blob::from_content("a\n");
+        storage.remove_loose(&blob.id).unwrap();
+        let report = fsck(roots, false);
+        assert_eq!(report.errors.len(), 1);
+        assert!(report.errors[0].starts_with("missing"));
+        assert!(report.errors[0].contains(&blob.id.to_string()));
+        assert!(crate::exec_async(vec!["fsck"]).await.is_err());
+    }
+}
@@ -0,0 +1,310 @@
+use std::collections::HashSet;
+use std::fs;
+use std::path::Path;
+use std::str::FromStr;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use clap::Parser;
+use mercury::hash::SHA1;
+use mercury::internal::index::Index;
+use mercury::internal::object::commit::Commit;
+use mercury::internal::object::tag::Tag as TagObject;
+use mercury::internal::object::tree::{Tree, TreeItemMode};
+use mercury::internal::object::types::ObjectType;
+
+use crate::internal::branch::Branch;
+use crate::internal::config::Config;
+use crate::internal::head::Head;
+use crate::internal::reflog::Reflog;
+use crate::internal::stash::Stash;
+use crate::internal::tag::Tag;
+use crate::internal::worktree::Worktree;
+use crate::utils::{path, shallow, util};
+
+use super::load_object;
+use super::repack::{self, DEFAULT_WINDOW};
+
+/// Window size of delta compression with `--aggressive`, same as git
+const AGGRESSIVE_WINDOW: usize = 250;
+
+#[derive(Parser, Debug)]
+pub struct GcArgs {
+    /// Prune the unreachable objects older than the date, e.g. `now`, `2.weeks.ago` or `2024-01-01`
+    #[clap(long, value_name = "date", default_value = "2.weeks.ago")]
+    pub prune: String,
+
+    /// Do not prune any unreachable objects
+    #[clap(long)]
+    pub no_prune: bool,
+
+    /// Optimize the repository more aggressively, with a larger delta window
+    #[clap(long)]
+    pub aggressive: bool,
+}
+
+pub async fn execute(args: GcArgs) {
+    if !util::check_repo_exist() {
+        return;
+    }
+    // the objects modified at or before `expire` are pruned
+    let expire = if args.no_prune || args.prune == "never" {
+        None
+    } else if args.prune == "now" {
+        // not truncated to seconds, so the objects written just now are pruned too
+        Some(SystemTime::now())
+    } else {
+        match util::parse_date(&args.prune) {
+            Ok(date) => Some(UNIX_EPOCH + Duration::from_secs(date.max(0) as u64)),
+            Err(e) => {
+                eprintln!("fatal: {}", e);
+                return;
+            }
+        }
+    };
+    let window = if args.aggressive {
+        AGGRESSIVE_WINDOW
+    } else {
+        DEFAULT_WINDOW
+    };
+
+    let storage = util::objects_storage();
+    let reachable = reachable_objects(gc_roots().await);
+    let old_packs = storage.list_all_packs();
+    let loose: HashSet<SHA1> = storage.list_objects_loose().into_iter().collect();
+    let packed = storage.list_objects_pack();
+
+    // unreachable objects in packs are exploded to loose objects if not expired,
+    // with the age of their pack, so that they will be pruned after the grace period
+    let mut kept = 0;
+    let mut pruned = 0;
+    for pack in old_packs.iter() {
+        let pack_mtime = fs::metadata(pack).and_then(|m| m.modified()).unwrap();
+        for obj in storage.list_pack_objects(pack) {
+            if reachable.contains(&obj) || loose.contains(&obj) {
+                continue;
+            }
+            if expire.is_some_and(|expire| pack_mtime <= expire) {
+                pruned += 1;
+                continue;
+            }
+            let data = storage.get(&obj).unwrap();
+            let obj_type = storage.get_object_type(&obj).unwrap();
+            storage.put(&obj, &data, obj_type).unwrap();
+            storage.set_loose_mtime(&obj, pack_mtime).unwrap();
+            kept += 1;
+        }
+    }
+
+    // all reachable objects are consolidated into a single pack
+    let objects: Vec<SHA1> = reachable
+        .iter()
+        .filter(|obj| loose.contains(obj) || packed.contains(obj))
+        .copied()
+        .collect();
+    let new_pack = if objects.is_empty() {
+        None
+    } else {
+        match repack::write_pack(&objects, window).await {
+            Ok(pack) => Some(pack),
+            Err(e) => {
+                eprintln!("fatal: {}", e);
+                return;
+            }
+        }
+    };
+    let redundant: Vec<_> = old_packs
+        .into_iter()
+        .filter(|pack| Some(pack) != new_pack.as_ref())
+        .collect();
+    repack::remove_packs(&redundant);
+
+    for obj in loose.iter() {
+        if reachable.contains(obj) {
+            storage.remove_loose(obj).unwrap(); // packed
+            continue;
+        }
+        let mtime = storage.loose_mtime(obj).unwrap();
+        if expire.is_some_and(|expire| mtime <= expire) {
+            storage.remove_loose(obj).unwrap();
+            pruned += 1;
+        } else {
+            kept += 1;
+        }
+    }
+
+    println!("Packed {} reachable objects", objects.len());
+    if pruned > 0 {
+        println!("Pruned {} unreachable objects", pruned);
+    }
+    if kept > 0 {
+        println!("Kept {} unreachable objects in grace period", kept);
+    }
+}
+
+/// The starting points of reachability: HEAD, branches (local & remote), tags, stash entries, reflog entries,
+/// the index, and the commits recorded by in-progress operations (merge, rebase, cherry-pick, etc.),
+/// HEADs & indexes of all the worktrees are included
+pub(crate) async fn gc_roots() -> Vec<SHA1> {
+    let mut roots = Vec::new();
+    if let Some(head) = Head::current_commit().await {
+        roots.push(head);
+    }
+    let mut remotes: Vec<Option<String>> = Config::all_remote_configs()
+        .await
+        .into_iter()
+        .map(|remote| Some(remote.name))
+        .collect();
+    remotes.push(None);
+    for remote in remotes {
+        for branch in Branch::list_branches(remote.as_deref()).await {
+            roots.push(branch.commit);
+        }
+    }
+    roots.extend(Tag::list_tags().await.into_iter().map(|tag| tag.object));
+    roots.extend(Stash::list().await.into_iter().map(|stash| stash.commit));
+    for ref_name in Reflog::refs().await {
+        for entry in Reflog::list(&ref_name).await {
+            roots.extend(entry.old);
+            roots.push(entry.new);
+        }
+    }
+
+    if let Head::Detached(commit) = Head::main_worktree().await {
+        roots.push(commit);
+    }
+    let mut index_files = vec![path::index(), util::storage_path().join("index")];
+    for worktree in Worktree::list() {
+        // detached HEAD & in-progress states
+        roots.extend(hashes_in(&worktree.dir));
+        index_files.push(worktree.dir.join("index"));
+    }
+    index_files.dedup();
+    for index_file in index_files {
+        let index = Index::load(index_file).unwrap();
+        for stage in 0..=3 {
+            roots.extend(index.tracked_entries(stage).iter().map(|entry| entry.hash));
+        }
+    }
+    for state in [
+        path::merge_head(),
+        path::cherry_pick_head(),
+        path::revert_head(),
+        path::rebase_merge(),
+        path::sequencer(),
+    ] {
+        roots.extend(hashes_in(&state));
+    }
+    roots
+}
+
+/// All hashes (40 hex digits) in the file, or the files in the directory
+fn hashes_in(state: &Path) -> Vec<SHA1> {
+    if state.is_dir() {
+        return fs::read_dir(state)
+            .unwrap()
+            .flat_map(|entry| hashes_in(&entry.unwrap().path()))
+            .collect();
+    }
+    fs::read_to_string(state)
+        .unwrap_or_default()
+        .split(|c: char| !c.is_ascii_hexdigit())
+        .filter(|word| word.len() == 40)
+        .filter_map(|word| SHA1::from_str(word).ok())
+        .collect()
+}
+
+/// All objects reachable from `roots`
+/// - missing objects are skipped (e.g. omitted by partial clone), they are never fetched here
+/// - parents of shallow commits are not walked
+pub(crate) fn reachable_objects(roots: Vec<SHA1>) -> HashSet<SHA1> {
+    let storage = util::objects_storage();
+    let shallow_commits = shallow::load();
+    let mut reachable = HashSet::new();
+    let mut stack = roots;
+    while let Some(obj) = stack.pop() {
+        if reachable.contains(&obj) || !storage.exist(&obj) {
+            continue;
+        }
+        reachable.insert(obj);
+        match storage.get_object_type(&obj).unwrap() {
+            ObjectType::Commit => {
+                let commit: Commit = load_object(&obj).unwrap();
+                stack.push(commit.tree_id);
+                if !shallow_commits.contains(&obj) {
+                    stack.extend(commit.parent_commit_ids);
+                }
+            }
+            ObjectType::Tree => {
+                let tree: Tree = load_object(&obj).unwrap();
+                for item in tree.tree_items {
+                    match item.mode {
+                        TreeItemMode::Commit => {} // submodule, not in this repository
+                        TreeItemMode::Tree => stack.push(item.id),
+                        _ => {
+                            // blob, no need to load
+                            if storage.exist(&item.id) {
+                                reachable.insert(item.id);
+                            }
+                        }
+                    }
+                }
+            }
+            ObjectType::Tag => {
+                let tag: TagObject = load_object(&obj).unwrap();
+                stack.push(tag.object_hash);
+            }
+            _ => {}
+        }
+    }
+    reachable
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::test;
+    use mercury::internal::object::blob::Blob;
+    use mercury::internal::object::ObjectTrait;
+
+    #[tokio::test]
+    async fn test_gc() {
+        test::setup_with_new_libra().await;
+        test::ensure_file("gc_a.txt", Some("a\n"));
+        crate::exec_async(vec!["add", "gc_a.txt"]).await.unwrap();
+        crate::exec_async(vec!["commit", "-m", "first"])
+            .await
+            .unwrap();
+        let head = Head::current_commit().await.unwrap();
+
+        let storage = util::objects_storage();
+        let unreachable = Blob::from_content("unreachable");
+        storage
+            .put(&unreachable.id, &unreachable.data, unreachable.get_type())
+            .unwrap();
+        let reachable = reachable_objects(gc_roots().await);
+        assert!(reachable.contains(&head));
+        assert!(!reachable.contains(&unreachable.id));
+
+        // the unreachable object is new, kept in grace period
+        crate::exec_async(vec!["gc"]).await.unwrap();
+        assert_eq!(storage.list_all_packs().len(), 1);
+        assert_eq!(storage.list_objects_loose(), vec![unreachable.id]);
+        assert!(storage.exist_in_pack(&head));
+
+        crate::exec_async(vec!["gc", "--prune=now"]).await.unwrap();
+        assert!(storage.list_objects_loose().is_empty());
+        assert!(!storage.exist(&unreachable.id));
+        assert!(storage.get(&head).is_ok());
+    }
+
+    #[test]
+    fn test_hashes_in() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("state");
+        let hash = SHA1::new(&[1; 20]);
+        fs::write(&file, format!("pick {} message\nnot-a-hash\n", hash)).unwrap();
+        assert_eq!(hashes_in(&file), vec![hash]);
+        assert_eq!(hashes_in(dir.path()), vec![hash]);
+        assert!(hashes_in(&dir.path().join("none")).is_empty());
+    }
+}
@@ -1,15 +1,20 @@
-use std::collections::BTreeMap;
-use std::io::Write;
+use std::collections::{BTreeMap, HashMap};
+use std::io::{Read, Seek, SeekFrom, Write};
 use std::path::PathBuf;
 use std::sync::{Arc, Mutex};
 
 use byteorder::{BigEndian, WriteBytesExt};
 use clap::Parser;
+use flate2::Crc;
 use sha1::{Digest, Sha1};
 
+use mercury::hash::SHA1;
 use mercury::internal::pack::Pack;
 use mercury::errors::GitError;
 
+/// Magic number of .idx version 2, `\377tOc`
+pub const IDX_V2_MAGIC: [u8; 4] = [0xff, b't', b'O', b'c'];
+
 #[derive(Parser, Debug)]
 pub struct IndexPackArgs {
     /// Pack file path
@@ -43,21 +48,16 @@ pub fn execute(args: IndexPackArgs) {
         return;
     }
 
-    if let Some(version) = args.index_version {
-        match version {
-            1 => build_index_v1(&pack_file, &index_file).unwrap(),
-            2 => println!("support later"),
-            _ => eprintln!("fatal: unsupported index version"),
-        }
-    } else {
-        // default version = 1
-        build_index_v1(&pack_file, &index_file).unwrap();
+    // default version = 2
+    match args.index_version.unwrap_or(2) {
+        1 => build_index_v1(&pack_file, &index_file).unwrap(),
+        2 => build_index_v2(&pack_file, &index_file).unwrap(),
+        _ => eprintln!("fatal: unsupported index version"),
     }
 }
 
-/// Build index file for pack file, version 1
-/// [pack-format](https://git-scm.com/docs/pack-format)
-pub fn build_index_v1(pack_file: &str, index_file: &str) -> Result<(), GitError> {
+/// Decode the pack file, return the offsets of all objects (sorted by hash) and the pack checksum
+fn decode_offsets(pack_file: &str) -> Result<(BTreeMap<SHA1, usize>, SHA1), GitError> {
     let pack_path = PathBuf::from(pack_file);
     let tmp_path = pack_path.parent().unwrap();
     let pack_file = std::fs::File::open(pack_file)?;
@@ -68,34 +68,109 @@ pub fn build_index_v1(pack_file: &str, index_file: &str) -> Result<(), GitError>
     pack.decode(&mut pack_reader, move |entry, offset| {
         obj_map_c.lock().unwrap().insert(entry.hash, offset);
     })?;
+    let obj_map = Arc::try_unwrap(obj_map).unwrap().into_inner().unwrap();
+    Ok((obj_map, pack.signature))
+}
+
+/// The fan-out table: N-th entry records the number of objects whose first byte of hash is less than or equal to N
+fn fan_out<'a>(hashes: impl Iterator<Item = &'a SHA1>) -> Vec<u8> {
+    let mut counts = [0u32; 256];
+    for hash in hashes {
+        counts[hash.0[0] as usize] += 1;
+    }
+    let mut fan_out = Vec::with_capacity(256 * 4);
+    let mut cnt: u32 = 0;
+    for count in counts {
+        cnt += count;
+        fan_out.write_u32::<BigEndian>(cnt).unwrap();
+    }
+    fan_out
+}
+
+/// Build index file for pack file, version 2
+/// - with CRC32 of each packed object, and 64-bit offsets for the packs over 2 GiB
+/// - [pack-format](https://git-scm.com/docs/pack-format)
+pub fn build_index_v2(pack_file: &str, index_file: &str) -> Result<(), GitError> {
+    let (obj_map, signature) = decode_offsets(pack_file)?;
+
+    // CRC32 of the packed (compressed) data of each object, objects are contiguous and end at the trailing checksum
+    let mut offsets: Vec<usize> = obj_map.values().copied().collect();
+    offsets.sort();
+    let mut pack_reader = std::io::BufReader::new(std::fs::File::open(pack_file)?);
+    let pack_len = pack_reader.get_ref().metadata()?.len() as usize;
+    pack_reader.seek(SeekFrom::Start(offsets.first().copied().unwrap_or(12) as u64))?;
+    let mut crcs = HashMap::with_capacity(offsets.len());
+    for (i, offset) in offsets.iter().enumerate() {
+        let end = offsets.get(i + 1).copied().unwrap_or(pack_len - 20);
+        let mut data = vec![0; end - offset];
+        pack_reader.read_exact(&mut data)?;
+        let mut crc = Crc::new();
+        crc.update(&data);
+        crcs.insert(*offset, crc.sum());
+    }
+    let entries: Vec<(SHA1, u64, u32)> = obj_map
+        .iter()
+        .map(|(hash, offset)| (*hash, *offset as u64, crcs[offset]))
+        .collect();
+
+    let mut index_file = std::fs::File::create(index_file)?;
+    write_index_v2(&entries, &signature, &mut index_file)?;
+    tracing::debug!("Index file is written to {:?}", index_file);
+    Ok(())
+}
+
+/// Write .idx version 2, `entries` are (hash, offset, crc32) sorted by hash
+pub fn write_index_v2(
+    entries: &[(SHA1, u64, u32)],
+    pack_signature: &SHA1,
+    w: &mut impl Write,
+) -> Result<(), GitError> {
+    let mut data = Vec::new();
+    // header: magic number & version
+    data.write_all(&IDX_V2_MAGIC)?;
+    data.write_u32::<BigEndian>(2)?;
+    data.extend(fan_out(entries.iter().map(|(hash, _, _)| hash)));
+    // sorted object names
+    for (hash, _, _) in entries {
+        data.write_all(&hash.0)?;
+    }
+    // CRC32 of the packed object data
+    for (_, _, crc) in entries {
+        data.write_u32::<BigEndian>(*crc)?;
+    }
+    // 4-byte offsets, if the MSB is set, the rest 31 bits are the index into the 8-byte offsets table
+    let mut large_offsets = Vec::new();
+    for (_, offset, _) in entries {
+        if *offset < 0x8000_0000 {
+            data.write_u32::<BigEndian>(*offset as u32)?;
+        } else {
+            data.write_u32::<BigEndian>(0x8000_0000 | large_offsets.len() as u32)?;
+            large_offsets.push(*offset);
+        }
+    }
+    for offset in large_offsets {
+        data.write_u64::<BigEndian>(offset)?;
+    }
+    // A copy of the pack checksum at the end of the corresponding pack-file.
+    data.write_all(&pack_signature.0)?;
+    // Index checksum of all of the above.
+    let index_hash: [u8; 20] = Sha1::digest(&data).into();
+    data.write_all(&index_hash)?;
+    w.write_all(&data)?;
+    Ok(())
+}
+
+/// Build index file for pack file, version 1
+/// [pack-format](https://git-scm.com/docs/pack-format)
+pub fn build_index_v1(pack_file: &str, index_file: &str) -> Result<(), GitError> {
+    let (obj_map, signature) = decode_offsets(pack_file)?;
 
     let mut index_hash = Sha1::new();
     let mut index_file = std::fs::File::create(index_file)?;
     // fan-out table
     // The header consists of 256 4-byte network byte order integers.
-    // N-th entry of this table records the number of objects in the corresponding pack,
-    // the first byte of whose object name is less than or equal to N.
     // This is called the first-level fan-out table.
-    let mut i: u8 = 0;
-    let mut cnt: u32 = 0;
-    let mut fan_out = Vec::with_capacity(256 * 4);
-    let obj_map = Arc::try_unwrap(obj_map).unwrap().into_inner().unwrap();
-    for (hash, _) in obj_map.iter() { // sorted
-        let first_byte = hash.0[0];
-        while first_byte > i { // `while` rather than `if` to fill the gap, e.g. 0, 1, 2, 2, 2, 6
-            fan_out.write_u32::<BigEndian>(cnt)?;
-            i += 1;
-        }
-        cnt += 1;
-    }
-    // fill the rest
-    loop {
-        fan_out.write_u32::<BigEndian>(cnt)?;
-        if i == 255 {
-            break;
-        }
-        i += 1;
-    }
+    let fan_out = fan_out(obj_map.keys());
     index_hash.update(&fan_out);
     index_file.write_all(&fan_out)?;
 
@@ -103,6 +178,9 @@ pub fn build_index_v1(pack_file: &str, index_file: &str) -> Result<(), GitError>
     // object is stored in the pack-file as the offset from the beginning.
     // one object name of the appropriate size (20 bytes).
     for (hash, offset) in obj_map {
+        if offset > u32::MAX as usize {
+            return Err(GitError::InvalidPackFile("pack is too large for index version 1".to_string()));
+        }
         let mut buf = Vec::with_capacity(24);
         buf.write_u32::<BigEndian>(offset as u32)?;
         buf.write_all(&hash.0)?;
@@ -111,9 +189,9 @@ pub fn build_index_v1(pack_file: &str, index_file: &str) -> Result<(), GitError>
         index_file.write_all(&buf)?;
     }
 
-    index_hash.update(pack.signature.0);
+    index_hash.update(signature.0);
     // A copy of the pack checksum at the end of the corresponding pack-file.
-    index_file.write_all(&pack.signature.0)?;
+    index_file.write_all(&signature.0)?;
     let index_hash:[u8; 20] = index_hash.finalize().into();
     // Index checksum of all of the above.
     index_file.write_all(&index_hash)?;
@@ -34,7 +34,7 @@ pub async fn init() -> io::Result<()> {
     }
 
     // Create .libra & sub-dirs
-    let dirs = ["objects/pack", "objects/info", "info"];
+    let dirs = ["objects/pack", "objects/info", "info", "hooks"];
     for dir in dirs {
         fs::create_dir_all(root_dir.join(dir))?;
     }
@@ -146,7 +146,7 @@ mod tests {
     #[tokio::test]
     async fn test_init() {
         // Set up the test environment without a Libra repository
-        test::setup_clean_testing_env();
+        test::setup_clean_testing_env().await;
 
         // Run the init function
         init().await.unwrap();
@@ -1,20 +1,26 @@
-use std::cmp::min;
-use std::collections::HashSet;
+use std::collections::{BinaryHeap, HashMap, HashSet};
 
-use crate::command::load_object;
+use crate::command::{diff, load_object, tag};
 use crate::internal::branch::Branch;
+use crate::internal::config::Config;
 use crate::internal::head::Head;
+use crate::internal::tag::Tag;
+use crate::utils::object_ext::TreeExt;
+use crate::utils::path_ext::PathExt;
+use crate::utils::{shallow, signing, util};
 use clap::Parser;
 use colored::Colorize;
-#[cfg(unix)]
+use regex::Regex;
 use std::io::Write;
 #[cfg(unix)]
 use std::process::{Command, Stdio};
 
 use std::collections::VecDeque;
+use std::path::PathBuf;
 use std::str::FromStr;
 use mercury::hash::SHA1;
 use mercury::internal::object::commit::Commit;
+use mercury::internal::object::tree::Tree;
 
 use common::utils::parse_commit_msg;
 #[derive(Parser, Debug)]
@@ -22,11 +28,61 @@ pub struct LogArgs {
     /// Limit the number of output
     #[clap(short, long)]
     pub number: Option<usize>,
+
+    /// Show each commit in one line: the abbreviated hash and the subject
+    #[clap(long)]
+    pub oneline: bool,
+
+    /// Draw the commit history as an ASCII graph, implies `--topo-order`
+    #[clap(long)]
+    pub graph: bool,
+
+    /// Show no parents before all of their children, and avoid mixing multiple lines of history
+    #[clap(long, conflicts_with = "date_order")]
+    pub topo_order: bool,
+
+    /// Show no parents before all of their children, otherwise in commit timestamp order (default)
+    #[clap(long)]
+    pub date_order: bool,
+
+    /// Only show the commits whose author matches the pattern (regex)
+    #[clap(long, value_name = "pattern")]
+    pub author: Option<String>,
+
+    /// Only show the commits more recent than the date, e.g. `2.weeks.ago` or `2024-01-01`
+    #[clap(long, alias = "after", value_name = "date")]
+    pub since: Option<String>,
+
+    /// Only show the commits older than the date
+    #[clap(long, alias = "before", value_name = "date")]
+    pub until: Option<String>,
+
+    /// Only show the commits whose message matches the pattern (regex)
+    #[clap(long, value_name = "pattern")]
+    pub grep: Option<String>,
+
+    /// Show the number of changed lines of each file
+    #[clap(long)]
+    pub stat: bool,
+
+    /// Show the changes of each commit as patch
+    #[clap(short = 'p', long)]
+    pub patch: bool,
+
+    /// Check the signatures of signed commits
+    #[clap(long)]
+    pub show_signature: bool,
+
+    /// Only show the commits modifying the paths
+    #[clap(last = true)]
+    pub pathspec: Vec<String>,
 }
 
 ///  Get all reachable commits from the given commit hash
-///  **didn't consider the order of the commits**
+///  **didn't consider the order of the commits**, see [sort_commits]
+///  - in a shallow repository, the parents of shallow commits are not walked
 pub async fn get_reachable_commits(commit_hash: String) -> Vec<Commit> {
+    let shallow_commits = shallow::load();
     let mut queue = VecDeque::new();
     let mut commit_set: HashSet<String> = HashSet::new(); // to avoid duplicate commits because of circular reference
     let mut reachable_commits: Vec<Commit> = Vec::new();
@@ -42,7 +98,11 @@ pub async fn get_reachable_commits(commit_hash: String) -> Vec<Commit> {
         }
         commit_set.insert(commit_id);
 
-        let parent_commit_ids = commit.parent_commit_ids.clone();
+        let parent_commit_ids = if shallow_commits.contains(&commit_id_hash) {
+            vec![] // parents are not fetched
+        } else {
+            commit.parent_commit_ids.clone()
+        };
         for parent_commit_id in parent_commit_ids {
             queue.push_back(parent_commit_id.to_string());
         }
@@ -51,77 +111,434 @@ pub async fn get_reachable_commits(commit_hash: String) -> Vec<Commit> {
     reachable_commits
 }
 
-pub async fn execute(args: LogArgs) {
-    #[cfg(unix)]
-    let mut process = Command::new("less") // create a pipe to less
-        .arg("-R") // raw control characters
-        .arg("-F") 
-        .stdin(Stdio::piped())
-        .stdout(Stdio::inherit())
-        .spawn()
-        .expect("failed to execute process");
+/// Sort the commits so that no parent is shown before all of its children
+/// - `topo`: show all commits of a line of history (following the first parents) together, like `--topo-order`
+/// - otherwise, the commits are shown in commit timestamp order, like `--date-order`
+pub fn sort_commits(commits: Vec<Commit>, topo: bool) -> Vec<Commit> {
+    let mut children_count: HashMap<SHA1, usize> = commits.iter().map(|c| (c.id, 0)).collect();
+    for commit in commits.iter() {
+        for parent in commit.parent_commit_ids.iter() {
+            if let Some(count) = children_count.get_mut(parent) {
+                *count += 1;
+            }
+        }
+    }
+    let mut commits: HashMap<SHA1, Commit> = commits.into_iter().map(|c| (c.id, c)).collect();
+
+    // the commits whose children are all shown, ordered by timestamp
+    let mut tips: Vec<(usize, SHA1)> = children_count
+        .iter()
+        .filter(|(_, count)| **count == 0)
+        .map(|(id, _)| (commits[id].committer.timestamp, *id))
+        .collect();
+    tips.sort();
+    let mut stack = tips.clone(); // for topo order, the latest on top
+    let mut heap = BinaryHeap::from(tips); // for date order
+
+    let mut sorted = Vec::with_capacity(commits.len());
+    loop {
+        let next = if topo { stack.pop() } else { heap.pop() };
+        let Some((_, id)) = next else {
+            break;
+        };
+        let commit = commits.remove(&id).unwrap();
+        // the first parent is pushed last, to be shown next in topo order
+        for parent in commit.parent_commit_ids.iter().rev() {
+            if let Some(count) = children_count.get_mut(parent) {
+                *count -= 1;
+                if *count == 0 {
+                    let item = (commits[parent].committer.timestamp, *parent);
+                    if topo {
+                        stack.push(item);
+                    } else {
+                        heap.push(item);
+                    }
+                }
+            }
+        }
+        sorted.push(commit);
+    }
+    sorted
+}
+
+/// Filters of `--author`, `--grep`, `--since` & `--until`
+struct CommitFilter {
+    author: Option<Regex>,
+    grep: Option<Regex>,
+    since: Option<i64>,
+    until: Option<i64>,
+}
+
+impl CommitFilter {
+    fn new(args: &LogArgs) -> Result<Self, String> {
+        let regex = |pattern: &Option<String>| {
+            pattern
+                .as_deref()
+                .map(|p| Regex::new(p).map_err(|e| format!("invalid pattern '{}': {}", p, e)))
+                .transpose()
+        };
+        let date = |date: &Option<String>| date.as_deref().map(util::parse_date).transpose();
+        Ok(CommitFilter {
+            author: regex(&args.author)?,
+            grep: regex(&args.grep)?,
+            since: date(&args.since)?,
+            until: date(&args.until)?,
+        })
+    }
+
+    fn matches(&self, commit: &Commit) -> bool {
+        let timestamp = commit.committer.timestamp as i64;
+        let author = format!("{} <{}>", commit.author.name, commit.author.email);
+        let (message, _) = parse_commit_msg(&commit.message);
+        self.author.as_ref().is_none_or(|re| re.is_match(&author))
+            && self.grep.as_ref().is_none_or(|re| re.is_match(message))
+            && self.since.is_none_or(|since| timestamp >= since)
+            && self.until.is_none_or(|until| timestamp <= until)
+    }
+}
+
+/// Whether the commit modifies the files under `paths`, compared with each of its parents,
+/// merge commits same as any parent are not shown, like git's default history simplification
+fn modifies_paths(commit: &Commit, parents: &[SHA1], paths: &[PathBuf]) -> bool {
+    let items_in_paths = |tree_id: &SHA1| -> Vec<(PathBuf, SHA1)> {
+        Tree::load(tree_id)
+            .get_plain_items()
+            .into_iter()
+            .filter(|(file, _)| paths.iter().any(|path| file.sub_of(path)))
+            .collect()
+    };
+    let items = items_in_paths(&commit.tree_id);
+    if parents.is_empty() {
+        return !items.is_empty();
+    }
+    parents.iter().all(|parent| {
+        let parent: Commit = load_object(parent).unwrap();
+        items_in_paths(&parent.tree_id) != items
+    })
+}
+
+/// The parents of the commit among the shown commits, skipping the hidden ones,
+/// so that the graph is connected when some commits are filtered out
+fn visible_parents(
+    commit: &SHA1,
+    shown: &HashSet<SHA1>,
+    parents_of: &HashMap<SHA1, Vec<SHA1>>,
+) -> Vec<SHA1> {
+    let mut parents = Vec::new();
+    let mut visited = HashSet::new();
+    let mut stack: Vec<SHA1> = parents_of[commit].iter().rev().copied().collect();
+    while let Some(id) = stack.pop() {
+        if !visited.insert(id) {
+            continue;
+        }
+        if shown.contains(&id) {
+            parents.push(id);
+        } else {
+            stack.extend(parents_of[&id].iter().rev());
+        }
+    }
+    parents
+}
+
+/// Refs pointing to each commit, e.g. `HEAD -> master`, `origin/master`, `tag: v1.0`
+async fn decorations() -> HashMap<SHA1, Vec<String>> {
+    let mut decorations: HashMap<SHA1, Vec<String>> = HashMap::new();
+    let head = Head::current().await;
+    if let Some(commit) = Head::current_commit().await {
+        let label = match &head {
+            Head::Branch(name) => format!("{}{}", "HEAD -> ".blue(), name.green()),
+            Head::Detached(_) => "HEAD".blue().to_string(),
+        };
+        decorations.entry(commit).or_default().push(label);
+    }
+    for branch in Branch::list_branches(None).await {
+        if matches!(&head, Head::Branch(name) if *name == branch.name) {
+            continue;
+        }
+        let label = branch.name.green().to_string();
+        decorations.entry(branch.commit).or_default().push(label);
+    }
+    for remote in Config::all_remote_configs().await {
+        for branch in Branch::list_branches(Some(&remote.name)).await {
+            let label = format!("{}/{}", remote.name, branch.name).red().to_string();
+            decorations.entry(branch.commit).or_default().push(label);
+        }
+    }
+    for tag in Tag::list_tags().await {
+        let label = format!("tag: {}", tag.name).yellow().to_string();
+        decorations
+            .entry(tag::peel_to_commit(&tag.object))
+            .or_default()
+            .push(label);
+    }
+    decorations
+}
+
+/// The result of the signature check for `--show-signature`, `None` if the commit is not signed
+async fn signature_status(commit: &SHA1) -> Option<String> {
+    match signing::verify_object(commit).await {
+        Ok(Some(verified)) if verified.is_trusted() => {
+            Some(verified.to_string().green().to_string())
+        }
+        Ok(Some(verified)) => Some(verified.to_string().yellow().to_string()),
+        Ok(None) => None,
+        Err(e) => Some(e.as_str().red().to_string()),
+    }
+}
+
+/// Format the commit like `git log`, with the diffstat or patch against the first parent if required
+async fn format_commit(
+    commit: &Commit,
+    decorations: &HashMap<SHA1, Vec<String>>,
+    args: &LogArgs,
+    paths: &[PathBuf],
+) -> String {
+    let decoration = match decorations.get(&commit.id) {
+        Some(labels) => format!(
+            " {}{}{}",
+            "(".yellow(),
+            labels.join(&", ".yellow().to_string()),
+            ")".yellow()
+        ),
+        None => String::new(),
+    };
+    let (msg, _) = parse_commit_msg(&commit.message);
+    let signature = if args.show_signature {
+        signature_status(&commit.id).await
+    } else {
+        None
+    };
+    let mut message = if args.oneline {
+        let signature = signature.map(|line| line + "\n").unwrap_or_default();
+        format!(
+            "{}{}{} {}\n",
+            signature,
+            commit.id.to_string()[..7].yellow(),
+            decoration,
+            msg.lines().next().unwrap_or_default()
+        )
+    } else {
+        let mut message = format!(
+            "{} {}{}\n",
+            "commit".yellow(),
+            commit.id.to_string().yellow(),
+            decoration
+        );
+        if let Some(signature) = signature {
+            message.push_str(&format!("{}\n", signature));
+        }
+        if commit.parent_commit_ids.len() > 1 {
+            let parents: Vec<String> = commit
+                .parent_commit_ids
+                .iter()
+                .map(|parent| parent.to_string()[..7].to_owned())
+                .collect();
+            message.push_str(&format!("Merge: {}\n", parents.join(" ")));
+        }
+        message.push_str(&format!("Author: {}", commit.author));
+        message.push_str(&format!("\n{}\n\n", msg.trim_end()));
+        message
+    };
+
+    if args.stat || args.patch {
+        let old_blobs = match commit.parent_commit_ids.first() {
+            Some(parent) => {
+                let parent: Commit = load_object(parent).unwrap();
+                Tree::load(&parent.tree_id).get_plain_items()
+            }
+            None => Vec::new(),
+        };
+        let new_blobs = Tree::load(&commit.tree_id).get_plain_items();
+        let mut buf = Vec::new();
+        if args.stat {
+            diff::diff_stat(
+                old_blobs.clone(),
+                new_blobs.clone(),
+                paths.to_vec(),
+                &mut buf,
+            )
+            .await;
+            if args.oneline || args.patch {
+                writeln!(buf).unwrap();
+            }
+        }
+        if args.patch {
+            diff::diff(old_blobs, new_blobs, paths.to_vec(), &Default::default(), &mut buf).await;
+        }
+        message.push_str(&String::from_utf8_lossy(&buf));
+        if !args.oneline {
+            message.push('\n');
+        }
+    }
+    message
+}
+
+/// ASCII graph like `git log --graph`, each column is a line of history, waiting for its next commit
+#[derive(Debug, Default)]
+struct Graph {
+    columns: Vec<SHA1>,
+}
+
+impl Graph {
+    /// Draw the commit in the graph, with the lines of its text
+    fn render(&mut self, commit: &SHA1, parents: &[SHA1], text: &str) -> Vec<String> {
+        let mut rows = Vec::new();
+        let col = match self.columns.iter().position(|c| c == commit) {
+            Some(col) => col,
+            None => {
+                self.columns.push(*commit);
+                self.columns.len() - 1
+            }
+        };
+        // other lines of history end at this commit: `|/`
+        while let Some(j) = self
+            .columns
+            .iter()
+            .rposition(|c| c == commit)
+            .filter(|j| *j != col)
+        {
+            rows.push(Self::shift_left(self.columns.len(), j, true));
+            self.columns.remove(j);
+        }
+
+        let mut prefixes = vec![(0..self.columns.len())
+            .map(|i| if i == col { "*" } else { "|" })
+            .collect::<Vec<_>>()
+            .join(" ")];
+        match parents.split_first() {
+            None => {
+                if col + 1 < self.columns.len() {
+                    prefixes.push(Self::shift_left(self.columns.len(), col, false));
+                }
+                self.columns.remove(col);
+            }
+            Some((first, others)) => {
+                self.columns[col] = *first;
+                // new lines of history start from a merge commit: `|\`
+                let mut new_columns = 0;
+                for parent in others {
+                    if !self.columns.contains(parent) {
+                        new_columns += 1;
+                        self.columns.insert(col + new_columns, *parent);
+                    }
+                }
+                if new_columns > 0 {
+                    let mut row = vec![' '; self.columns.len() * 2];
+                    for i in 0..self.columns.len() {
+                        if i <= col {
+                            row[i * 2] = '|';
+                        } else {
+                            row[i * 2 - 1] = '\\';
+                        }
+                    }
+                    prefixes.push(row.into_iter().collect::<String>().trim_end().to_owned());
+                }
+            }
+        }
+        let continuation = vec!["|"; self.columns.len()].join(" ");
+
+        let lines: Vec<&str> = text.lines().collect();
+        let width = prefixes
+            .iter()
+            .map(|p| p.len())
+            .chain([continuation.len()])
+            .max()
+            .unwrap();
+        for i in 0..lines.len().max(prefixes.len()) {
+            let prefix = prefixes.get(i).unwrap_or(&continuation);
+            let line = lines.get(i).unwrap_or(&"");
+            rows.push(format!("{:<width$} {}", prefix, line).trim_end().to_owned());
+        }
+        rows
+    }
+
+    /// The columns after `j` move left by one, e.g. `| |/ /`
+    /// - `draw_j`: column `j` is merged into the left one, or it ends
+    fn shift_left(n: usize, j: usize, draw_j: bool) -> String {
+        let mut row = vec![' '; n * 2];
+        for i in 0..n {
+            if i < j {
+                row[i * 2] = '|';
+            } else if i > j || draw_j {
+                row[i * 2 - 1] = '/';
+            }
+        }
+        row.into_iter().collect::<String>().trim_end().to_owned()
+    }
+}
 
+pub async fn execute(args: LogArgs) {
+    if !util::check_repo_exist() {
+        return;
+    }
     let head = Head::current().await;
     // check if the current branch has any commits
     if let Head::Branch(branch_name) = head.to_owned() {
         let branch = Branch::find_branch(&branch_name, None).await;
         if branch.is_none() {
-            panic!(
-                "fatal: your current branch '{}' does not have any commits yet ",
+            eprintln!(
+                "fatal: your current branch '{}' does not have any commits yet",
                 branch_name
             );
+            return;
         }
     }
+    let filter = match CommitFilter::new(&args) {
+        Ok(filter) => filter,
+        Err(e) => {
+            eprintln!("fatal: {}", e);
+            return;
+        }
+    };
+    let paths: Vec<PathBuf> = args.pathspec.iter().map(util::to_workdir_path).collect();
 
     let commit_hash = Head::current_commit().await.unwrap().to_string();
+    let reachable_commits = get_reachable_commits(commit_hash).await;
+    // parents in the reachable commits (not in shallow history)
+    let reachable: HashSet<SHA1> = reachable_commits.iter().map(|c| c.id).collect();
+    let parents_of: HashMap<SHA1, Vec<SHA1>> = reachable_commits
+        .iter()
+        .map(|c| {
+            let parents = c.parent_commit_ids.iter().filter(|p| reachable.contains(p));
+            (c.id, parents.copied().collect())
+        })
+        .collect();
+    let commits: Vec<Commit> = sort_commits(reachable_commits, args.graph || args.topo_order)
+        .into_iter()
+        .filter(|commit| filter.matches(commit))
+        .filter(|commit| {
+            paths.is_empty() || modifies_paths(commit, &parents_of[&commit.id], &paths)
+        })
+        .take(args.number.unwrap_or(usize::MAX))
+        .collect();
+    let shown: HashSet<SHA1> = commits.iter().map(|c| c.id).collect();
+    let decorations = decorations().await;
 
-    let mut reachable_commits = get_reachable_commits(commit_hash.clone()).await;
-    // default sort with signature time
-    reachable_commits.sort_by(|a, b| b.committer.timestamp.cmp(&a.committer.timestamp));
-
-    let max_output_number = min(args.number.unwrap_or(usize::MAX), reachable_commits.len());
-    let mut output_number = 0;
-    for commit in reachable_commits {
-        if output_number >= max_output_number {
-            break;
-        }
-        output_number += 1;
-        let mut message = {
-            let mut message = format!(
-                "{} {}",
-                "commit".yellow(),
-                &commit.id.to_string().yellow()
-            );
-
-            // TODO other branch's head should shown branch name
-            if output_number == 1 {
-                message = format!("{} {}{}", message, "(".yellow(), "HEAD".blue());
-                if let Head::Branch(name) = head.to_owned() {
-                    // message += &"-> ".blue();
-                    // message += &head.name.as_ref().unwrap().green();
-                    message = format!("{}{}{}", message, " -> ".blue(), name.green());
-                }
-                message = format!("{}{}", message, ")".yellow());
-            }
-            message
-        };
-        message.push_str(&format!("\nAuthor: {}", commit.author));
-        let (msg, _) = parse_commit_msg(&commit.message);
-        message.push_str(&format!("\n{}\n", msg));
+    #[cfg(unix)]
+    let mut process = Command::new("less") // create a pipe to less
+        .arg("-R") // raw control characters
+        .arg("-F")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::inherit())
+        .spawn()
+        .expect("failed to execute process");
+    #[cfg(unix)]
+    let mut w: Box<dyn Write> = Box::new(process.stdin.take().expect("Failed to capture stdin"));
+    #[cfg(not(unix))]
+    let mut w: Box<dyn Write> = Box::new(std::io::stdout());
 
-        #[cfg(unix)]
-        {
-            if let Some(ref mut stdin) = process.stdin {
-                writeln!(stdin, "{}", message).unwrap();
-            } else {
-                eprintln!("Failed to capture stdin");
-            }
+    let mut graph = Graph::default();
+    for commit in commits.iter() {
+        let mut message = format_commit(commit, &decorations, &args, &paths).await;
+        if args.graph {
+            let parents = visible_parents(&commit.id, &shown, &parents_of);
+            message = graph.render(&commit.id, &parents, &message).join("\n") + "\n";
         }
-        #[cfg(not(unix))]
-        {
-            println!("{}", message);
+        if w.write_all(message.as_bytes()).is_err() {
+            break; // pager quit
         }
     }
+    drop(w);
     #[cfg(unix)]
     {
         let _ = process.wait().expect("failed to wait on child");
@@ -144,12 +561,82 @@ mod tests {
         assert_eq!(reachable_commits.len(), 6);
     }
 
+    #[tokio::test]
+    async fn test_sort_commits() {
+        test::setup_with_new_libra().await;
+        let commit_id = create_test_commit_tree().await;
+        let messages = |commits: Vec<Commit>| -> Vec<String> {
+            commits
+                .iter()
+                .map(|c| c.message.trim().to_owned())
+                .collect()
+        };
+
+        let commits = get_reachable_commits(commit_id.clone()).await;
+        let date_order = messages(sort_commits(commits, false));
+        assert_eq!(
+            date_order,
+            ["Commit_6", "Commit_5", "Commit_4", "Commit_3", "Commit_2", "Commit_1"]
+        );
+        let commits = get_reachable_commits(commit_id).await;
+        let topo_order = messages(sort_commits(commits, true));
+        assert_eq!(
+            topo_order,
+            ["Commit_6", "Commit_3", "Commit_5", "Commit_4", "Commit_2", "Commit_1"]
+        );
+    }
+
+    #[test]
+    fn test_graph() {
+        let [a, b, c, d] = [1, 2, 3, 4].map(|i| SHA1::new(&[i; 20]));
+        // d is a merge of b & c, which are both children of a
+        let mut graph = Graph::default();
+        let mut rows = Vec::new();
+        rows.extend(graph.render(&d, &[b, c], "d"));
+        rows.extend(graph.render(&c, &[a], "c"));
+        rows.extend(graph.render(&b, &[a], "b"));
+        rows.extend(graph.render(&a, &[], "a"));
+        assert_eq!(rows, ["*   d", "|\\", "| * c", "* | b", "|/", "* a"]);
+        assert!(graph.columns.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_log_filters() {
+        test::setup_with_new_libra().await;
+        for (file, message) in [("log_a.txt", "feat: add a"), ("log_b.txt", "fix: add b")] {
+            test::ensure_file(file, Some(file));
+            crate::exec_async(vec!["add", file]).await.unwrap();
+            crate::exec_async(vec!["commit", "-m", message])
+                .await
+                .unwrap();
+        }
+        let head: Commit = load_object(&Head::current_commit().await.unwrap()).unwrap();
+        let first = head.parent_commit_ids.clone();
+
+        let args =
+            LogArgs::try_parse_from(["log", "--grep", "^fix", "--since", "yesterday"]).unwrap();
+        let filter = CommitFilter::new(&args).unwrap();
+        assert!(filter.matches(&head));
+        let args = LogArgs::try_parse_from(["log", "--author", "nobody", "--grep", "fix"]).unwrap();
+        assert!(!CommitFilter::new(&args).unwrap().matches(&head));
+
+        let paths = vec![PathBuf::from("log_a.txt")];
+        assert!(!modifies_paths(&head, &first, &paths));
+        assert!(modifies_paths(&head, &first, &[PathBuf::from("log_b.txt")]));
+
+        let args = LogArgs::try_parse_from(["log", "--stat", "--oneline"]).unwrap();
+        let message = format_commit(&head, &HashMap::new(), &args, &[]).await;
+        assert!(message.contains("fix: add b"));
+        assert!(message.contains("log_b.txt | 1 "));
+        assert!(message.contains("1 file changed, 1 insertion(+), 0 deletions(-)"));
+    }
+
     #[tokio::test]
     async fn test_execute_log() {
         test::setup_with_new_libra().await;
         let _ = create_test_commit_tree().await;
 
-        let args = LogArgs { number: Some(6) };
+        let args = LogArgs::try_parse_from(["log", "-n", "6", "--graph", "--oneline"]).unwrap();
         execute(args).await;
     }
 
@@ -209,7 +696,7 @@ mod tests {
             _ => panic!("should be branch"),
         };
 
-        Branch::update_branch(&branch_name, &commit_6.id.to_string(), None).await;
+        Branch::update_branch(&branch_name, &commit_6.id.to_string(), None, "test").await;
 
         commit_6.id.to_string()
     }
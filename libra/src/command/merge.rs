@@ -1,25 +1,71 @@
+use std::collections::{BTreeSet, HashMap};
+use std::fs;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+
 use clap::Parser;
+use common::utils::format_commit_msg;
+use mercury::hash::SHA1;
+use mercury::internal::index::{Index, IndexEntry};
+use mercury::internal::object::blob::Blob;
 use mercury::internal::object::commit::Commit;
+use mercury::internal::object::tree::Tree;
+use similar::{Algorithm, DiffTag};
 
 use crate::{
     internal::{branch::Branch, head::Head},
-    utils::util,
+    utils::{
+        hooks,
+        object_ext::{BlobExt, TreeExt},
+        path,
+        sparse::Sparse,
+        util,
+    },
 };
 
 use super::{
-    get_target_commit,
+    commit, fetch, get_target_commit,
     load_object, log,
     restore::{self, RestoreArgs},
+    save_object, status,
 };
 
 #[derive(Parser, Debug)]
 pub struct MergeArgs {
     /// The branch to merge into the current branch, could be remote branch
-    pub branch: String,
+    #[clap(required_unless_present("abort"))]
+    pub branch: Option<String>,
+
+    /// Abort the current conflict resolution process, and reconstruct the pre-merge state
+    #[clap(long, conflicts_with("branch"))]
+    pub abort: bool,
+
+    /// Bypass the pre-merge-commit and commit-msg hooks
+    #[clap(long)]
+    pub no_verify: bool,
+
+    /// Refuse to merge unless the current branch can be fast-forwarded
+    #[clap(long, conflicts_with("no_ff"))]
+    pub ff_only: bool,
+
+    /// Create a merge commit even when the merge resolves as a fast-forward
+    #[clap(long)]
+    pub no_ff: bool,
 }
 
 pub async fn execute(args: MergeArgs) {
-    let target_commit_hash = get_target_commit(&args.branch).await;
+    if args.abort {
+        abort_merge().await;
+        return;
+    }
+    if path::merge_head().exists() {
+        eprintln!("fatal: You have not concluded your merge (MERGE_HEAD exists).");
+        eprintln!("Please, commit your changes before you merge.");
+        return;
+    }
+
+    let branch = args.branch.unwrap();
+    let target_commit_hash = get_target_commit(&branch).await;
     if target_commit_hash.is_err() {
         eprintln!("{}", target_commit_hash.err().unwrap());
         return;
@@ -39,21 +85,29 @@ pub async fn execute(args: MergeArgs) {
     if lca.id == target_commit.id {
         // no need to merge
         println!("Already up to date.");
-    } else if lca.id == current_commit.id {
+    } else if lca.id == current_commit.id && !args.no_ff {
         println!(
             "Updating {}..{}",
             &current_commit.id.to_string()[..6],
             &target_commit.id.to_string()[..6]
         );
         // fast-forward merge
-        merge_ff(target_commit).await;
+        merge_ff(&branch, target_commit).await;
+    } else if args.ff_only {
+        eprintln!("fatal: Not possible to fast-forward, aborting.");
     } else {
-        // didn't support yet
-        eprintln!("fatal: Not possible to fast-forward merge, try merge manually");
+        merge_three_way(
+            &branch,
+            &lca,
+            &current_commit,
+            &target_commit,
+            args.no_verify,
+        )
+        .await;
     }
 }
 
-async fn lca_commit(lhs: &Commit, rhs: &Commit) -> Option<Commit> {
+pub(crate) async fn lca_commit(lhs: &Commit, rhs: &Commit) -> Option<Commit> {
     let lhs_reachable = log::get_reachable_commits(lhs.id.to_string()).await;
     let rhs_reachable = log::get_reachable_commits(rhs.id.to_string()).await;
 
@@ -82,16 +136,17 @@ async fn lca_commit(lhs: &Commit, rhs: &Commit) -> Option<Commit> {
 }
 
 /// try merge in fast-forward mode, if it's not possible, do nothing
-async fn merge_ff(commit: Commit) {
+async fn merge_ff(branch: &str, commit: Commit) {
     println!("Fast-forward");
     // fast-forward merge
     let head = Head::current().await;
+    let reason = format!("merge {}: Fast-forward", branch);
     match head {
         Head::Branch(branch_name) => {
-            Branch::update_branch(&branch_name, &commit.id.to_string(), None).await;
+            Branch::update_branch(&branch_name, &commit.id.to_string(), None, &reason).await;
         }
         Head::Detached(_) => {
-            Head::update(Head::Detached(commit.id), None).await;
+            Head::update(Head::Detached(commit.id), None, &reason).await;
         }
     }
     // change the working directory to the commit
@@ -104,3 +159,429 @@ async fn merge_ff(commit: Commit) {
     })
     .await;
 }
+
+/// Merge `target` into HEAD with three-way merge based on their common ancestor `base`.
+/// - create a merge commit with two parents if there is no conflict
+/// - otherwise, leave conflicts in index & worktree and record `MERGE_HEAD` for the next `commit`,
+///   so does a failed `pre-merge-commit` or `commit-msg` hook
+async fn merge_three_way(
+    branch: &str,
+    base: &Commit,
+    current: &Commit,
+    target: &Commit,
+    no_verify: bool,
+) {
+    if status::has_uncommitted_changes().await {
+        eprintln!("error: Your local changes would be overwritten by merge.");
+        eprintln!("Please commit your changes before you merge.");
+        return;
+    }
+
+    let conflicts = match merge_trees(
+        Some(&base.tree_id),
+        &current.tree_id,
+        &target.tree_id,
+        "HEAD",
+        branch,
+    )
+    .await
+    {
+        Ok(conflicts) => conflicts,
+        Err(e) => {
+            eprintln!("{}", e);
+            return;
+        }
+    };
+
+    if !conflicts.is_empty() {
+        fs::write(path::merge_head(), target.id.to_string()).unwrap();
+        for file in conflicts.iter() {
+            println!("CONFLICT (content): Merge conflict in {}", file.display());
+        }
+        println!("Automatic merge failed; fix conflicts and then commit the result.");
+        return;
+    }
+
+    let mut message = format!("Merge branch '{}'", branch);
+    if !no_verify {
+        let verified = match hooks::run(hooks::PRE_MERGE_COMMIT, &[], None).await {
+            Ok(()) => hooks::commit_msg(&message).await,
+            Err(e) => Err(e),
+        };
+        match verified {
+            Ok(verified) => message = verified,
+            Err(e) => {
+                fs::write(path::merge_head(), target.id.to_string()).unwrap();
+                eprintln!("error: {}", e);
+                println!("Not committing merge; use 'libra commit' to complete the merge.");
+                return;
+            }
+        }
+    }
+
+    // the hook may update the index
+    let index = Index::load(path::index()).unwrap();
+    let tree = commit::create_tree(&index, &util::objects_storage(), "".into()).await;
+    let merge_commit = Commit::from_tree_id(
+        tree.id,
+        vec![current.id, target.id],
+        &format_commit_msg(&message, None),
+    );
+    save_object(&merge_commit, &merge_commit.id).unwrap();
+    let reason = format!("merge {}: Merge made by the 'recursive' strategy.", branch);
+    commit::update_head(&merge_commit.id.to_string(), &reason).await;
+    println!("Merge made by the 'recursive' strategy.");
+}
+
+/// Abort a conflicted merge: drop `MERGE_HEAD` and restore index & worktree to HEAD
+async fn abort_merge() {
+    let merge_head = path::merge_head();
+    if !merge_head.exists() {
+        eprintln!("fatal: There is no merge to abort (MERGE_HEAD missing).");
+        return;
+    }
+
+    clear_unmerged();
+    restore::execute(RestoreArgs {
+        worktree: true,
+        staged: true,
+        source: None,
+        pathspec: vec![util::working_dir_string()],
+    })
+    .await;
+    fs::remove_file(merge_head).unwrap();
+}
+
+/// Drop conflict entries (stage 1-3) from index, files only brought by `theirs` are removed from worktree.
+/// - then index & worktree can be restored to HEAD
+pub(crate) fn clear_unmerged() {
+    let index_file = path::index();
+    let mut index = Index::load(&index_file).unwrap();
+    for file in index.unmerged_files() {
+        // stage 2 is `ours`, if it doesn't exist, the file is brought by merge only
+        if !index.tracked(&file, 2) {
+            let file_abs = util::workdir_to_absolute(&file);
+            if file_abs.exists() {
+                fs::remove_file(&file_abs).unwrap();
+                util::clear_empty_dir(&file_abs);
+            }
+        }
+        index.remove_unmerged(&file);
+    }
+    index.save(&index_file).unwrap();
+}
+
+/// Three-way merge `theirs` tree into `ours` tree, based on `base` tree, file by file.
+/// The result is written to the index & worktree, conflicts are recorded as stage 1/2/3 entries.
+/// - `base`: tree of the common ancestor, `None` means empty tree
+/// - `ours`: must be the tree of HEAD, which is expected to match the index & worktree
+/// - `ours_label` & `theirs_label`: shown in conflict markers
+/// - return conflicted files (to workdir)
+pub async fn merge_trees(
+    base: Option<&SHA1>,
+    ours: &SHA1,
+    theirs: &SHA1,
+    ours_label: &str,
+    theirs_label: &str,
+) -> Result<Vec<PathBuf>, String> {
+    let load_items =
+        |tree: &SHA1| -> HashMap<PathBuf, SHA1> { Tree::load(tree).get_plain_items().into_iter().collect() };
+    let base_items = base.map(load_items).unwrap_or_default();
+    let ours_items = load_items(ours);
+    let theirs_items = load_items(theirs);
+
+    // sorted, for stable output
+    let paths: BTreeSet<&PathBuf> = base_items
+        .keys()
+        .chain(ours_items.keys())
+        .chain(theirs_items.keys())
+        .collect();
+
+    // untracked files (not in `ours`) can't be overwritten
+    let overwritten: Vec<&PathBuf> = paths
+        .iter()
+        .filter(|p| {
+            !ours_items.contains_key(**p)
+                && theirs_items.contains_key(**p)
+                && base_items.get(**p) != theirs_items.get(**p)
+                && util::workdir_to_absolute(p).exists()
+        })
+        .cloned()
+        .collect();
+    if !overwritten.is_empty() {
+        let mut msg =
+            "error: The following untracked working tree files would be overwritten by merge:"
+                .to_string();
+        for file in overwritten {
+            msg += &format!("\n\t{}", file.display());
+        }
+        msg += "\nPlease move or remove them before you merge.";
+        return Err(msg);
+    }
+
+    // partial clone: fetch the blobs changed by theirs at once
+    let changed = paths
+        .iter()
+        .filter(|p| theirs_items.get(**p) != ours_items.get(**p))
+        .filter(|p| theirs_items.get(**p) != base_items.get(**p))
+        .flat_map(|p| [base_items.get(*p), ours_items.get(*p), theirs_items.get(*p)]);
+    fetch::prefetch_blobs(changed.flatten().copied()).await;
+
+    let index_file = path::index();
+    let mut index = Index::load(&index_file).unwrap();
+    let sparse = Sparse::load();
+    let mut conflicts = Vec::new();
+    for file in paths {
+        let (b, o, t) = (
+            base_items.get(file),
+            ours_items.get(file),
+            theirs_items.get(file),
+        );
+        if o == t || b == t {
+            continue; // keep ours
+        }
+        if b == o {
+            // only changed by theirs
+            match t {
+                Some(hash) => checkout_blob(&mut index, file, hash, sparse.as_ref()).await,
+                None => remove_file(&mut index, file),
+            }
+            continue;
+        }
+
+        // changed by both sides
+        match (o, t) {
+            (Some(o), Some(t)) => {
+                // add/add conflict uses empty base
+                let base_data = b.map(|h| Blob::load(h).data).unwrap_or_default();
+                let (ours_data, theirs_data) = (Blob::load(o).data, Blob::load(t).data);
+                let texts = (
+                    String::from_utf8(base_data),
+                    String::from_utf8(ours_data),
+                    String::from_utf8(theirs_data),
+                );
+                if let (Ok(base_text), Ok(ours_text), Ok(theirs_text)) = texts {
+                    let (merged, conflict) =
+                        merge_text(&base_text, &ours_text, &theirs_text, ours_label, theirs_label);
+                    if !conflict {
+                        let blob = Blob::from_content(&merged);
+                        blob.save();
+                        checkout_blob(&mut index, file, &blob.id, sparse.as_ref()).await;
+                        continue;
+                    }
+                    util::write_file(merged.as_bytes(), &util::workdir_to_absolute(file)).unwrap();
+                } // binary conflict: keep ours in worktree
+            }
+            // modify/delete conflict: keep the modified version in worktree
+            (None, Some(t)) => restore::restore_to_file(t, file).await.unwrap(),
+            _ => {}
+        }
+        add_conflict_entries(&mut index, file, [b, o, t]);
+        conflicts.push(file.clone());
+    }
+    index.save(&index_file).unwrap();
+    Ok(conflicts)
+}
+
+/// write blob to worktree & update index (stage 0)
+/// - files excluded by sparse checkout are only written to index
+async fn checkout_blob(index: &mut Index, file: &PathBuf, hash: &SHA1, sparse: Option<&Sparse>) {
+    if sparse.is_some_and(|sparse| !sparse.contains(file)) {
+        let name = file.to_str().unwrap();
+        let size = Blob::load(hash).data.len() as u32;
+        index.update(IndexEntry::new_from_blob(name.to_string(), *hash, size));
+        index.set_skip_worktree(name, true);
+        return;
+    }
+    restore::restore_to_file(hash, file).await.unwrap();
+    index.update(IndexEntry::new_from_file(file, *hash, &util::working_dir()).unwrap());
+}
+
+/// remove file from worktree & index
+fn remove_file(index: &mut Index, file: &PathBuf) {
+    let file_abs = util::workdir_to_absolute(file);
+    if file_abs.exists() {
+        fs::remove_file(&file_abs).unwrap();
+        util::clear_empty_dir(&file_abs);
+    }
+    index.remove(file.to_str().unwrap(), 0);
+}
+
+/// replace stage 0 entry of `file` with stage 1 (base), 2 (ours), 3 (theirs) entries
+fn add_conflict_entries(index: &mut Index, file: &Path, hashes: [Option<&SHA1>; 3]) {
+    let name = file.to_str().unwrap();
+    index.remove(name, 0);
+    for (stage, hash) in (1..=3).zip(hashes) {
+        if let Some(hash) = hash {
+            let size = Blob::load(hash).data.len() as u32;
+            let mut entry = IndexEntry::new_from_blob(name.to_string(), *hash, size);
+            entry.flags.stage = stage;
+            index.add(entry);
+        }
+    }
+}
+
+/// A region of base lines replaced by some lines of one side
+struct Hunk {
+    base: Range<usize>,
+    side: Range<usize>,
+}
+
+fn diff_hunks(base: &[&str], side: &[&str]) -> Vec<Hunk> {
+    similar::capture_diff_slices(Algorithm::Myers, base, side)
+        .into_iter()
+        .filter(|op| op.tag() != DiffTag::Equal)
+        .map(|op| Hunk {
+            base: op.old_range(),
+            side: op.new_range(),
+        })
+        .collect()
+}
+
+/// apply `hunks` of one side to `base[lo..hi]`
+fn apply_hunks(base: &[&str], side: &[&str], hunks: &[Hunk], lo: usize, hi: usize) -> String {
+    let mut text = String::new();
+    let mut pos = lo;
+    for hunk in hunks {
+        text.extend(base[pos..hunk.base.start].iter().copied());
+        text.extend(side[hunk.side.clone()].iter().copied());
+        pos = hunk.base.end;
+    }
+    text.extend(base[pos..hi].iter().copied());
+    text
+}
+
+/// Line-based three-way merge (like `diff3`).
+/// Changes of both sides are taken if they don't overlap, otherwise surrounded with conflict markers.
+/// - return (merged text, has conflict)
+pub fn merge_text(
+    base: &str,
+    ours: &str,
+    theirs: &str,
+    ours_label: &str,
+    theirs_label: &str,
+) -> (String, bool) {
+    let base_lines: Vec<&str> = base.split_inclusive('\n').collect();
+    let ours_lines: Vec<&str> = ours.split_inclusive('\n').collect();
+    let theirs_lines: Vec<&str> = theirs.split_inclusive('\n').collect();
+    let ours_hunks = diff_hunks(&base_lines, &ours_lines);
+    let theirs_hunks = diff_hunks(&base_lines, &theirs_lines);
+
+    let mut merged = String::new();
+    let mut conflict = false;
+    let (mut i, mut j) = (0, 0); // next hunk of ours & theirs
+    let mut pos = 0; // base lines before `pos` have been processed
+    while i < ours_hunks.len() || j < theirs_hunks.len() {
+        let (ours_start, theirs_start) = (i, j);
+        // a chunk starts from the first hunk, and grows while overlapping (or adjacent) hunks exist
+        let first = match (ours_hunks.get(i), theirs_hunks.get(j)) {
+            (Some(o), Some(t)) if o.base.start <= t.base.start => {
+                i += 1;
+                o
+            }
+            (Some(o), None) => {
+                i += 1;
+                o
+            }
+            (_, Some(t)) => {
+                j += 1;
+                t
+            }
+            (None, None) => unreachable!(),
+        };
+        let (lo, mut hi) = (first.base.start, first.base.end);
+        loop {
+            if let Some(o) = ours_hunks.get(i).filter(|o| o.base.start <= hi) {
+                hi = hi.max(o.base.end);
+                i += 1;
+            } else if let Some(t) = theirs_hunks.get(j).filter(|t| t.base.start <= hi) {
+                hi = hi.max(t.base.end);
+                j += 1;
+            } else {
+                break;
+            }
+        }
+
+        merged.extend(base_lines[pos..lo].iter().copied());
+        let ours_chunk = &ours_hunks[ours_start..i];
+        let theirs_chunk = &theirs_hunks[theirs_start..j];
+        let ours_text = apply_hunks(&base_lines, &ours_lines, ours_chunk, lo, hi);
+        let theirs_text = apply_hunks(&base_lines, &theirs_lines, theirs_chunk, lo, hi);
+        if theirs_chunk.is_empty() || ours_text == theirs_text {
+            merged.push_str(&ours_text);
+        } else if ours_chunk.is_empty() {
+            merged.push_str(&theirs_text);
+        } else {
+            conflict = true;
+            let ensure_newline = |text: &mut String| {
+                if !text.is_empty() && !text.ends_with('\n') {
+                    text.push('\n');
+                }
+            };
+            merged.push_str(&format!("<<<<<<< {}\n", ours_label));
+            merged.push_str(&ours_text);
+            ensure_newline(&mut merged);
+            merged.push_str("=======\n");
+            merged.push_str(&theirs_text);
+            ensure_newline(&mut merged);
+            merged.push_str(&format!(">>>>>>> {}\n", theirs_label));
+        }
+        pos = hi;
+    }
+    merged.extend(base_lines[pos..].iter().copied());
+    (merged, conflict)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_merge_text_clean() {
+        let base = "a\nb\nc\nd\ne\n";
+        let ours = "A\nb\nc\nd\ne\n";
+        let theirs = "a\nb\nc\nd\nE\n";
+        let (merged, conflict) = merge_text(base, ours, theirs, "HEAD", "feature");
+        assert!(!conflict);
+        assert_eq!(merged, "A\nb\nc\nd\nE\n");
+    }
+
+    #[test]
+    fn test_merge_text_same_change() {
+        let base = "a\nb\nc\n";
+        let ours = "a\nB\nc\n";
+        let (merged, conflict) = merge_text(base, ours, ours, "HEAD", "feature");
+        assert!(!conflict);
+        assert_eq!(merged, ours);
+    }
+
+    #[test]
+    fn test_merge_text_conflict() {
+        let base = "a\nb\nc\n";
+        let ours = "a\nours\nc\n";
+        let theirs = "a\ntheirs\nc\n";
+        let (merged, conflict) = merge_text(base, ours, theirs, "HEAD", "feature");
+        assert!(conflict);
+        assert_eq!(
+            merged,
+            "a\n<<<<<<< HEAD\nours\n=======\ntheirs\n>>>>>>> feature\nc\n"
+        );
+    }
+
+    #[test]
+    fn test_merge_text_add_add_without_newline() {
+        let (merged, conflict) = merge_text("", "ours", "theirs", "HEAD", "feature");
+        assert!(conflict);
+        assert_eq!(merged, "<<<<<<< HEAD\nours\n=======\ntheirs\n>>>>>>> feature\n");
+    }
+
+    #[test]
+    fn test_parse_args() {
+        assert!(MergeArgs::try_parse_from(["merge", "dev"]).is_ok());
+        assert!(MergeArgs::try_parse_from(["merge", "--abort"]).is_ok());
+        assert!(MergeArgs::try_parse_from(["merge"]).is_err());
+        assert!(MergeArgs::try_parse_from(["merge", "dev", "--abort"]).is_err());
+        assert!(MergeArgs::try_parse_from(["merge", "dev", "--ff-only"]).is_ok());
+        assert!(MergeArgs::try_parse_from(["merge", "dev", "--ff-only", "--no-ff"]).is_err());
+    }
+}
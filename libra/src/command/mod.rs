@@ -1,9 +1,15 @@
 pub mod add;
+pub mod apply;
+pub mod blame;
 pub mod branch;
+pub mod cat_file;
+pub mod cherry_pick;
 pub mod clone;
 pub mod commit;
 pub mod diff;
 pub mod fetch;
+pub mod fsck;
+pub mod gc;
 pub mod index_pack;
 pub mod init;
 pub mod lfs;
@@ -11,15 +17,28 @@ pub mod log;
 pub mod merge;
 pub mod pull;
 pub mod push;
+pub mod rebase;
+pub mod reflog;
 pub mod remote;
 pub mod remove;
+pub mod repack;
+pub mod reset;
 pub mod restore;
+pub mod revert;
+pub mod show;
+pub mod sparse_checkout;
+pub mod stash;
 pub mod status;
 pub mod switch;
+pub mod tag;
+pub mod verify_commit;
+pub mod worktree;
 pub mod config;
 
 use crate::internal::branch::Branch;
 use crate::internal::head::Head;
+use crate::internal::reflog::Reflog;
+use crate::internal::tag::Tag;
 use crate::internal::protocol::https_client::BasicAuth;
 use crate::utils;
 use crate::utils::object_ext::BlobExt;
@@ -99,10 +118,28 @@ pub fn calc_file_blob_hash(path: impl AsRef<Path>) -> io::Result<SHA1> {
     Ok(blob.id)
 }
 
-/// Get the commit hash from branch name or commit hash, support remote branch
+/// Get the commit hash from branch name, tag name or commit hash, support remote branch
+/// - support ancestry suffixes, e.g. `HEAD~2`, `main^2`, see [util::navigate_rev]
+/// - support reflog selectors, e.g. `HEAD@{2}`, `main@{yesterday}`, see [Reflog::resolve]
 pub async fn get_target_commit(branch_or_commit: &str) -> Result<SHA1, Box<dyn std::error::Error>> {
+    let (base, suffix) = util::split_rev_suffix(branch_or_commit);
+    let commit = get_base_commit(base).await?;
+    Ok(util::navigate_rev(commit, suffix)?)
+}
+
+async fn get_base_commit(branch_or_commit: &str) -> Result<SHA1, Box<dyn std::error::Error>> {
+    // `<ref>@{<n>}` or `<ref>@{<date>}`, resolved through reflog
+    if let Some((name, selector)) = branch_or_commit
+        .strip_suffix('}')
+        .and_then(|rev| rev.split_once("@{"))
+    {
+        let ref_name = Reflog::full_ref_name(name).await?;
+        return Ok(Reflog::resolve(&ref_name, selector).await?);
+    }
     if branch_or_commit == HEAD {
-        return Ok(Head::current_commit().await.unwrap());
+        return Head::current_commit()
+            .await
+            .ok_or("HEAD does not point to a commit".into());
     }
 
     let possible_branches = Branch::search_branch(branch_or_commit).await;
@@ -112,6 +149,9 @@ pub async fn get_target_commit(branch_or_commit: &str) -> Result<SHA1, Box<dyn s
     }
 
     if possible_branches.is_empty() {
+        if let Some(tag) = Tag::find_tag(branch_or_commit).await {
+            return Ok(tag::peel_to_commit(&tag.object));
+        }
         let storage = util::objects_storage();
         let possible_commits = storage.search(branch_or_commit);
         if possible_commits.len() > 1 {
@@ -126,6 +166,23 @@ pub async fn get_target_commit(branch_or_commit: &str) -> Result<SHA1, Box<dyn s
     }
 }
 
+/// Get the object hash from a revision (see [get_target_commit]) or an (abbreviated) object hash.
+/// - unlike [get_target_commit], annotated tags are not peeled, and trees & blobs are allowed
+pub async fn get_target_object(name: &str) -> Result<SHA1, String> {
+    if let Some(tag) = Tag::find_tag(name).await {
+        return Ok(tag.object);
+    }
+    if let Ok(commit) = get_target_commit(name).await {
+        return Ok(commit);
+    }
+    let possible_objects = util::objects_storage().search(name);
+    match possible_objects.len() {
+        0 => Err(format!("Not a valid object name {}", name)),
+        1 => Ok(possible_objects[0]),
+        _ => Err(format!("short object ID {} is ambiguous", name)),
+    }
+}
+
 #[cfg(test)]
 mod test {
     use common::utils::{format_commit_msg, parse_commit_msg};
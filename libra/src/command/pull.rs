@@ -1,6 +1,7 @@
 use crate::internal::{config::Config, head::Head};
+use crate::utils::path;
 
-use super::{fetch, merge};
+use super::{fetch, merge, rebase, stash};
 use clap::Parser;
 #[derive(Parser, Debug)]
 pub struct PullArgs {
@@ -10,31 +11,175 @@ pub struct PullArgs {
     /// The refspec to pull, usually a branch name
     #[clap(requires("repository"))]
     refspec: Option<String>,
+
+    /// Rebase the current branch on top of the upstream branch instead of merging, overrides `pull.rebase`
+    #[clap(short, long, overrides_with("no_rebase"))]
+    rebase: bool,
+
+    /// Merge the upstream branch into the current branch, overrides `pull.rebase`
+    #[clap(long, overrides_with("rebase"))]
+    no_rebase: bool,
+
+    /// Only update the current branch if it can be fast-forwarded, overrides `pull.ff`
+    #[clap(long, conflicts_with("no_ff"))]
+    ff_only: bool,
+
+    /// Create a merge commit even when the merge resolves as a fast-forward, overrides `pull.ff`
+    #[clap(long)]
+    no_ff: bool,
+
+    /// Stash local changes before pulling and reapply them after,
+    /// overrides `rebase.autoStash` or `merge.autoStash`
+    #[clap(long, overrides_with("no_autostash"))]
+    autostash: bool,
+
+    /// Don't stash local changes before pulling
+    #[clap(long, overrides_with("autostash"))]
+    no_autostash: bool,
+}
+
+/// How to integrate the upstream branch into the current branch
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Strategy {
+    Rebase,
+    /// Fast-forward if possible, otherwise create a merge commit
+    Merge,
+    FastForwardOnly,
+    NoFastForward,
 }
 
 pub async fn execute(args: PullArgs) {
+    let strategy = resolve_strategy(
+        &args,
+        Config::get("pull", None, "rebase").await.as_deref(),
+        Config::get("pull", None, "ff").await.as_deref(),
+    );
+    let autostash = if args.autostash || args.no_autostash {
+        args.autostash
+    } else {
+        let section = match strategy {
+            Strategy::Rebase => "rebase",
+            _ => "merge",
+        };
+        let value = Config::get(section, None, "autoStash").await;
+        value.as_deref().and_then(parse_bool) == Some(true)
+    };
+
     fetch::execute(fetch::FetchArgs {
         repository: args.repository,
         refspec: args.refspec,
         all: false,
+        tags: false,
+        no_tags: false,
     }).await;
 
     let head = Head::current().await;
-    match head {
+    let upstream = match head {
         Head::Branch(name) => match Config::branch_config(&name).await {
-            Some(branch_config) => {
-                let merge_args = merge::MergeArgs {
-                    branch: format!("{}/{}", branch_config.remote, branch_config.merge),
-                };
-                merge::execute(merge_args).await;
-            }
+            Some(branch_config) => format!("{}/{}", branch_config.remote, branch_config.merge),
             None => {
                 eprintln!("There is no tracking information for the current branch.");
-                eprintln!("hint: set up a tracking branch with `libra branch --set-upstream-to=<remote>/<branch>`")
+                eprintln!("hint: set up a tracking branch with `libra branch --set-upstream-to=<remote>/<branch>`");
+                return;
             }
         },
         _ => {
             eprintln!("You are not currently on a branch.");
+            return;
         }
+    };
+
+    let stash = match autostash {
+        true => stash::autostash().await,
+        false => None,
+    };
+    if strategy == Strategy::Rebase {
+        rebase::execute(rebase::RebaseArgs {
+            upstream: Some(upstream),
+            interactive: false,
+            continue_: false,
+            abort: false,
+            skip: false,
+        }).await;
+    } else {
+        merge::execute(merge::MergeArgs {
+            branch: Some(upstream),
+            abort: false,
+            no_verify: false,
+            ff_only: strategy == Strategy::FastForwardOnly,
+            no_ff: strategy == Strategy::NoFastForward,
+        }).await;
+    }
+
+    if let Some(stash) = stash {
+        if path::rebase_merge().exists() || path::merge_head().exists() {
+            // the stash would conflict with the unresolved files
+            println!("Your local changes are saved in stash@{{0}}.");
+            println!("Run \"libra stash pop\" once the conflicts are resolved.");
+        } else {
+            stash::pop_autostash(&stash).await;
+        }
+    }
+}
+
+/// Command line options take precedence over `pull.rebase` & `pull.ff`, `pull.ff` is ignored when rebasing
+fn resolve_strategy(args: &PullArgs, rebase_config: Option<&str>, ff_config: Option<&str>) -> Strategy {
+    let rebase = if args.rebase || args.no_rebase {
+        args.rebase
+    } else {
+        rebase_config.and_then(parse_bool).unwrap_or(false)
+    };
+    if rebase {
+        Strategy::Rebase
+    } else if args.ff_only {
+        Strategy::FastForwardOnly
+    } else if args.no_ff {
+        Strategy::NoFastForward
+    } else {
+        match ff_config {
+            Some("only") => Strategy::FastForwardOnly,
+            Some(value) if parse_bool(value) == Some(false) => Strategy::NoFastForward,
+            _ => Strategy::Merge,
+        }
+    }
+}
+
+/// Boolean values of git config
+fn parse_bool(value: &str) -> Option<bool> {
+    match value.to_lowercase().as_str() {
+        "true" | "yes" | "on" | "1" => Some(true),
+        "false" | "no" | "off" | "0" => Some(false),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn strategy(args: &[&str], rebase: Option<&str>, ff: Option<&str>) -> Strategy {
+        let args = PullArgs::try_parse_from([&["pull"][..], args].concat()).unwrap();
+        resolve_strategy(&args, rebase, ff)
+    }
+
+    #[test]
+    fn test_parse_args() {
+        assert!(PullArgs::try_parse_from(["pull", "--rebase", "--autostash"]).is_ok());
+        assert!(PullArgs::try_parse_from(["pull", "-r", "origin", "main"]).is_ok());
+        assert!(PullArgs::try_parse_from(["pull", "--ff-only", "--no-ff"]).is_err());
+        let args = PullArgs::try_parse_from(["pull", "--rebase", "--no-rebase"]).unwrap();
+        assert!(!args.rebase && args.no_rebase);
+    }
+
+    #[test]
+    fn test_resolve_strategy() {
+        assert_eq!(strategy(&[], None, None), Strategy::Merge);
+        assert_eq!(strategy(&[], Some("true"), None), Strategy::Rebase);
+        assert_eq!(strategy(&["--no-rebase"], Some("true"), None), Strategy::Merge);
+        assert_eq!(strategy(&["--rebase"], Some("false"), Some("only")), Strategy::Rebase);
+        assert_eq!(strategy(&[], Some("false"), Some("only")), Strategy::FastForwardOnly);
+        assert_eq!(strategy(&[], None, Some("false")), Strategy::NoFastForward);
+        assert_eq!(strategy(&["--ff-only"], None, Some("false")), Strategy::FastForwardOnly);
+        assert_eq!(strategy(&["--no-ff"], None, Some("only")), Strategy::NoFastForward);
     }
 }
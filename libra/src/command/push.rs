@@ -1,61 +1,165 @@
-use std::collections::{HashSet, VecDeque};
-use std::io::Write;
-use std::str::FromStr;
-use bytes::BytesMut;
+use crate::command::{branch, get_target_commit, repack, tag};
+use crate::internal::branch::Branch;
+use crate::internal::config::Config;
+use crate::internal::head::Head;
+use crate::internal::protocol::https_client::DiscoveredReference;
+use crate::internal::protocol::lfs_client::LFSClient;
+use crate::internal::protocol::{self, ProtocolClient, RemoteClient};
+use crate::internal::tag::Tag;
+use crate::utils::hooks;
+use crate::utils::object_ext::{BlobExt, CommitExt, TreeExt};
+use crate::utils::{lfs, shallow, util};
+use bytes::{Bytes, BytesMut};
+use ceres::protocol::smart::{add_pkt_line_string, read_pkt_line};
+use ceres::protocol::ServiceType::ReceivePack;
 use clap::Parser;
 use colored::Colorize;
-use tokio::sync::mpsc;
-use url::Url;
-use ceres::protocol::ServiceType::ReceivePack;
-use ceres::protocol::smart::{add_pkt_line_string, read_pkt_line};
+use futures::stream::{self, StreamExt};
 use mercury::hash::SHA1;
 use mercury::internal::object::blob::Blob;
 use mercury::internal::object::commit::Commit;
+use mercury::internal::object::tag::Tag as TagObject;
 use mercury::internal::object::tree::{Tree, TreeItemMode};
-use mercury::internal::pack::encode::PackEncoder;
+use mercury::internal::object::types::ObjectType;
+use mercury::internal::object::ObjectTrait;
 use mercury::internal::pack::entry::Entry;
-use crate::command::branch;
-use crate::internal::branch::Branch;
-use crate::internal::config::Config;
-use crate::internal::head::Head;
-use crate::internal::protocol::https_client::HttpsClient;
-use crate::internal::protocol::lfs_client::LFSClient;
-use crate::internal::protocol::ProtocolClient;
-use crate::utils::object_ext::{BlobExt, CommitExt, TreeExt};
+use std::collections::{HashSet, VecDeque};
+use std::io::{Error as IoError, Write};
+use std::str::FromStr;
+
+/// Objects tried as the delta bases of an object, the same as `pack.window` of git
+const DELTA_WINDOW_SIZE: usize = 10;
 
 #[derive(Parser, Debug)]
-pub struct PushArgs { // TODO --force
+pub struct PushArgs {
     /// repository, e.g. origin
-    #[clap(requires("refspec"))]
     repository: Option<String>,
-    /// ref to push, e.g. master
+    /// refs to push: `[+]<src>[:<dst>]`, e.g. `master`, `HEAD:refs/heads/dev`, `+dev` (force), `:dev` (delete)
     #[clap(requires("repository"))]
-    refspec: Option<String>,
+    refspecs: Vec<String>,
 
-    #[clap(long, short = 'u', requires("refspec"), requires("repository"))]
+    #[clap(long, short = 'u', requires("refspecs"))]
     set_upstream: bool,
+
+    /// update the remote refs even if they are not ancestors of the local refs
+    #[clap(long, short = 'f')]
+    force: bool,
+
+    /// force only if the remote ref is `<expect>`, or its remote-tracking branch if not given
+    #[clap(long, value_name = "REF[:EXPECT]", num_args = 0..=1, require_equals = true, default_missing_value = "")]
+    force_with_lease: Option<String>,
+
+    /// delete the refs from remote
+    #[clap(long, short = 'd', requires("refspecs"), conflicts_with_all(["all", "tags"]))]
+    delete: bool,
+
+    /// push all branches (`refs/heads/*`)
+    #[clap(long, conflicts_with("refspecs"))]
+    all: bool,
+
+    /// push all tags (`refs/tags/*`)
+    #[clap(long)]
+    tags: bool,
+
+    /// update all refs or none of them, the remote must support `atomic`
+    #[clap(long)]
+    atomic: bool,
+
+    /// do everything except actually sending the updates
+    #[clap(long, short = 'n')]
+    dry_run: bool,
+
+    /// bypass the pre-push hook
+    #[clap(long)]
+    no_verify: bool,
 }
 
-pub async fn execute(args: PushArgs) {
-    if args.repository.is_some() ^ args.refspec.is_some() { // must provide both or none
-        eprintln!("fatal: both repository and refspec should be provided");
-        return;
+/// An update of a remote ref
+#[derive(Debug, Clone, PartialEq)]
+struct RefUpdate {
+    /// The local ref (full name) or revision, `None` for deletion
+    src: Option<String>,
+    /// The remote ref, e.g. `refs/heads/master`
+    dst: String,
+    /// The hash in remote, zero if the ref doesn't exist
+    old: SHA1,
+    /// Zero for deletion
+    new: SHA1,
+    /// `+<src>:<dst>` or `--force`
+    force: bool,
+    /// `--force-with-lease`: the expected hash in remote
+    lease: Option<SHA1>,
+}
+
+impl RefUpdate {
+    fn is_delete(&self) -> bool {
+        self.new == SHA1::default()
+    }
+
+    /// Nothing to push, but deleting a missing ref is not up-to-date: it's rejected by [check_update]
+    fn is_up_to_date(&self) -> bool {
+        !self.is_delete() && self.old == self.new
+    }
+
+    /// `src -> dst` in short names for output
+    fn display(&self) -> String {
+        let dst = short_ref(&self.dst);
+        match &self.src {
+            Some(src) => format!("{} -> {}", short_ref(src), dst),
+            None => dst.to_string(),
+        }
     }
-    if args.set_upstream && args.refspec.is_none() {
-        eprintln!("fatal: --set-upstream requires a branch name");
+
+    /// The output of a successful update, e.g. ` * [new branch] master -> master`
+    fn summary(&self) -> String {
+        let kind = if self.dst.starts_with("refs/tags/") {
+            "tag"
+        } else {
+            "branch"
+        };
+        let (old, new) = (self.old.to_string(), self.new.to_string());
+        if self.is_delete() {
+            format!(" - {:<17} {}", "[deleted]", self.display())
+        } else if self.old == SHA1::default() {
+            format!(" * {:<17} {}", format!("[new {}]", kind), self.display())
+        } else if self.force || self.lease.is_some() {
+            format!(
+                " + {:<17} {} (forced update)",
+                format!("{}...{}", &old[..7], &new[..7]),
+                self.display()
+            )
+        } else {
+            format!(
+                "   {:<17} {}",
+                format!("{}..{}", &old[..7], &new[..7]),
+                self.display()
+            )
+        }
+    }
+}
+
+pub async fn execute(args: PushArgs) {
+    if args.set_upstream && args.delete {
+        eprintln!("fatal: --delete doesn't make sense with --set-upstream");
         return;
     }
 
-    let branch = match Head::current().await {
-        Head::Branch(name) => name,
-        Head::Detached(_) => panic!("fatal: HEAD is detached while pushing"),
+    let head_branch = match Head::current().await {
+        Head::Branch(name) => Some(name),
+        Head::Detached(_) => None,
     };
 
-    let repository = match args.repository {
+    let repository = match args.repository.clone() {
         Some(repo) => repo,
         None => {
+            let Some(branch) = &head_branch else {
+                eprintln!(
+                    "fatal: HEAD is detached, please specify the repository and refs to push"
+                );
+                return;
+            };
             // e.g. [branch "master"].remote = origin
-            let remote = Config::get_remote(&branch).await;
+            let remote = Config::get_remote(branch).await;
             if let Some(remote) = remote {
                 remote
             } else {
@@ -66,161 +170,668 @@ pub async fn execute(args: PushArgs) {
     };
     let repo_url = Config::get_remote_url(&repository).await;
 
-    let branch = args.refspec.unwrap_or(branch);
-    let commit_hash = Branch::find_branch(&branch, None).await.unwrap().commit.to_string();
-
-    println!("pushing {}({}) to {}({})", branch, commit_hash, repository, repo_url);
-
-    let url = Url::parse(&repo_url).unwrap();
-    let client = HttpsClient::from_url(&url);
+    let url = match protocol::parse_url(&repo_url) {
+        Ok(url) => url,
+        Err(e) => {
+            eprintln!("fatal: invalid URL '{}': {}", repo_url, e);
+            return;
+        }
+    };
+    let client = RemoteClient::from_url(&url);
     let refs = match client.discovery_reference(ReceivePack).await {
-        Ok(refs) => refs,
+        Ok((refs, _)) => refs,
         Err(e) => {
             eprintln!("fatal: {}", e);
             return;
         }
     };
 
-    let tracked_branch = Config::get("branch", Some(&branch), "merge")
-        .await // New branch may not have tracking branch
-        .unwrap_or_else(|| format!("refs/heads/{}", branch));
+    let mut updates = match collect_updates(&args, head_branch.as_deref(), &refs).await {
+        Ok(updates) => updates,
+        Err(e) => {
+            eprintln!("fatal: {}", e);
+            return;
+        }
+    };
+    if let Some(lease) = &args.force_with_lease {
+        if let Err(e) = set_leases(&mut updates, lease, &repository).await {
+            eprintln!("fatal: {}", e);
+            return;
+        }
+    }
+    updates.retain(|update| !update.is_up_to_date());
 
-    let tracked_ref = refs.iter().find(|r| r._ref == tracked_branch);
-    // [0; 20] if new branch
-    let remote_hash = tracked_ref.map(|r| r._hash.clone()).unwrap_or(SHA1::default().to_string());
-    if remote_hash == commit_hash {
+    // check locally, the remote may reject the updates again
+    let mut rejected = Vec::new();
+    updates.retain(|update| match check_update(update) {
+        Ok(()) => true,
+        Err(reason) => {
+            rejected.push((update.clone(), reason));
+            false
+        }
+    });
+    if updates.is_empty() && rejected.is_empty() {
         println!("Everything up-to-date");
         return;
     }
 
-    let mut data = BytesMut::new();
-    add_pkt_line_string(&mut data, format!("{} {} {}\0report-status\n",
-                                           remote_hash,
-                                           commit_hash,
-                                           tracked_branch));
-    data.extend_from_slice(b"0000");
-    tracing::debug!("{:?}", data);
+    println!("To {}", repo_url);
+    for (update, reason) in rejected.iter() {
+        if update.is_delete() {
+            eprintln!(
+                "error: unable to delete '{}': {}",
+                short_ref(&update.dst),
+                reason
+            );
+            continue;
+        }
+        eprintln!(
+            "{}",
+            format!(" ! {:<17} {} ({})", "[rejected]", update.display(), reason).red()
+        );
+    }
+    if !rejected.is_empty() && args.atomic {
+        eprintln!("error: atomic push failed, no refs are updated");
+    }
+    if updates.is_empty() || (!rejected.is_empty() && args.atomic) {
+        eprintln!("error: failed to push some refs to '{}'", repo_url);
+        return;
+    }
 
-    // TODO 考虑remote有多个refs，可以少发一点commits
-    let objs = incremental_objs(
-        SHA1::from_str(&commit_hash).unwrap(),
-        SHA1::from_str(&remote_hash).unwrap()
-    );
-
-    { // upload lfs files
-        let client = LFSClient::from_url(&url);
-        let res = client.push_objects(&objs).await;
-        if res.is_err() {
-            eprintln!("fatal: LFS files upload failed, stop pushing");
+    if !args.no_verify {
+        // <local ref> <local hash> <remote ref> <remote hash>
+        let hook_input: String = updates
+            .iter()
+            .map(|update| {
+                let local_ref = update.src.as_deref().unwrap_or("(delete)");
+                format!(
+                    "{} {} {} {}\n",
+                    local_ref, update.new, update.dst, update.old
+                )
+            })
+            .collect();
+        if let Err(e) = hooks::run(
+            hooks::PRE_PUSH,
+            &[&repository, &repo_url],
+            Some(hook_input.as_bytes()),
+        )
+        .await
+        {
+            eprintln!("error: {}", e);
+            eprintln!("error: failed to push some refs to '{}'", repo_url);
             return;
         }
     }
 
-    // let (tx, rx) = mpsc::channel::<Entry>();
-    let (entry_tx, entry_rx) = mpsc::channel(1_000_000);
-    let (stream_tx, mut stream_rx) = mpsc::channel(1_000_000);
-    
-    let encoder = PackEncoder::new(objs.len(), 0, stream_tx); // TODO: diff slow, so window_size = 0
-    encoder.encode_async(entry_rx).await.unwrap();
-
-    for entry in objs {
-        // TODO progress bar
-        entry_tx.send(entry).await.unwrap();
+    if args.dry_run {
+        for update in updates.iter() {
+            println!("{}", update.summary());
+        }
+        return;
     }
-    drop(entry_tx);
 
-    println!("Compression...");
-    let mut pack_data = Vec::new();
-    while let Some(chunk) = stream_rx.recv().await {
-        pack_data.extend(chunk);
+    let mut caps = vec!["report-status"];
+    if updates.iter().any(|update| update.is_delete()) {
+        caps.push("delete-refs");
     }
-    data.extend_from_slice(&pack_data);
-    println!("Delta compression done.");
+    if args.atomic {
+        caps.push("atomic");
+    }
+    let mut data = BytesMut::new();
+    for (i, update) in updates.iter().enumerate() {
+        // capabilities are sent with the first command
+        let caps = if i == 0 {
+            format!("\0{}", caps.join(" "))
+        } else {
+            String::new()
+        };
+        add_pkt_line_string(
+            &mut data,
+            format!("{} {} {}{}\n", update.old, update.new, update.dst, caps),
+        );
+    }
+    data.extend_from_slice(b"0000");
+    tracing::debug!("{:?}", data);
 
-    let res = client.send_pack(data.freeze()).await.unwrap(); // TODO: send stream
+    // the pack must not be sent if there are only deletions
+    let mut pack = None;
+    if updates.iter().any(|update| !update.is_delete()) {
+        let remote_commits = known_remote_commits(&refs);
+        let mut objs = HashSet::new();
+        for update in updates.iter().filter(|update| !update.is_delete()) {
+            objs.extend(tag_objs(&update.new, &remote_commits));
+        }
+        // only the hashes are collected, the data is loaded while encoding
+        let order = match repack::pack_order(objs) {
+            Ok(order) => order,
+            Err(e) => {
+                eprintln!("fatal: {}", e);
+                return;
+            }
+        };
 
-    if res.status() != 200 {
-        eprintln!("status code: {}", res.status());
+        {
+            // upload lfs files, the pointers are small blobs
+            let pointers: Vec<Entry> = order
+                .iter()
+                .filter(|(_, obj_type, size)| {
+                    *obj_type == ObjectType::Blob && *size <= lfs::LFS_POINTER_MAX_SIZE
+                })
+                .map(|(obj, _, _)| Blob::load(obj).into())
+                .collect();
+            let client = LFSClient::from_url(&url);
+            let res = client.push_objects(&pointers).await;
+            if res.is_err() {
+                eprintln!("fatal: LFS files upload failed, stop pushing");
+                return;
+            }
+        }
+
+        // the pack is sent while encoding, the channels limit the memory
+        let total: u64 = order.iter().map(|(_, _, size)| *size as u64).sum();
+        let order = order.into_iter().map(|(obj, _, _)| obj).collect();
+        println!("Writing objects...");
+        let progress_bar = util::default_progress_bar(total);
+        match repack::encode_stream(order, DELTA_WINDOW_SIZE, Some(progress_bar)).await {
+            Ok(encoding) => pack = Some(encoding),
+            Err(e) => {
+                eprintln!("fatal: {}", e);
+                return;
+            }
+        }
     }
-    let mut data = res.bytes().await.unwrap();
-    let (_, pkt_line) = read_pkt_line(&mut data);
-    if pkt_line != "unpack ok\n" {
-        eprintln!("fatal: unpack failed");
-        return;
+
+    let commands = data.freeze();
+    // fails at the end if the pack can't be encoded, so that the remote doesn't get a broken pack
+    let pack_stream = stream::unfold(pack, |pack| async move {
+        let (mut pack, task) = pack?;
+        match pack.recv().await {
+            Some(chunk) => Some((Ok(Bytes::from(chunk)), Some((pack, task)))),
+            None => match task.await {
+                Ok(Ok(())) => None,
+                Ok(Err(e)) => Some((Err(IoError::other(e)), None)),
+                Err(e) => Some((Err(IoError::other(e)), None)),
+            },
+        }
+    });
+    let body = stream::once(async move { Ok::<_, IoError>(commands) }).chain(pack_stream);
+    let data = match client.send_pack(body.boxed()).await {
+        Ok(data) => data,
+        Err(e) => {
+            eprintln!("fatal: {}", e);
+            return;
+        }
+    };
+    let statuses = match parse_report_status(data) {
+        Ok(statuses) => statuses,
+        Err(e) => {
+            eprintln!("fatal: {}", e);
+            return;
+        }
+    };
+
+    let mut failed = !rejected.is_empty();
+    for update in updates.iter() {
+        let status = statuses
+            .iter()
+            .find(|(dst, _)| *dst == update.dst)
+            .map(|(_, status)| status);
+        match status {
+            Some(Ok(())) => {
+                println!("{}", update.summary());
+                update_tracking_branch(update, &repository).await;
+            }
+            Some(Err(reason)) => {
+                eprintln!(
+                    "{}",
+                    format!(
+                        " ! {:<17} {} ({})",
+                        "[remote rejected]",
+                        update.display(),
+                        reason
+                    )
+                    .red()
+                );
+                failed = true;
+            }
+            None => {
+                eprintln!(
+                    "{}",
+                    format!(
+                        " ! {:<17} {} (no status)",
+                        "[remote failure]",
+                        update.display()
+                    )
+                    .red()
+                );
+                failed = true;
+            }
+        }
     }
-    let (_, pkt_line) = read_pkt_line(&mut data);
-    if !pkt_line.starts_with("ok".as_ref()) {
-        eprintln!("fatal: ref update failed [{:?}]", pkt_line);
+    if failed {
+        eprintln!("error: failed to push some refs to '{}'", repo_url);
         return;
     }
-    let (len, _) = read_pkt_line(&mut data);
-    assert_eq!(len, 0);
 
     println!("{}", "Push success".green());
 
     // set after push success
     if args.set_upstream {
-        branch::set_upstream(&branch, &format!("{}/{}", repository, branch)).await;
+        for update in updates.iter() {
+            let branch = update
+                .src
+                .as_deref()
+                .and_then(|src| src.strip_prefix("refs/heads/"));
+            if let (Some(branch), Some(remote_branch)) =
+                (branch, update.dst.strip_prefix("refs/heads/"))
+            {
+                branch::set_upstream(branch, &format!("{}/{}", repository, remote_branch)).await;
+            }
+        }
     }
 }
 
-/// collect all commits from `commit_id` to root commit
-fn collect_history_commits(commit_id: &SHA1) -> HashSet<SHA1> {
-    if commit_id == &SHA1::default() { // 0000...0000 means not exist
-        return HashSet::new();
+/// Resolve the refs to push: refspecs, `--all`, `--tags`,
+/// or the current branch to its tracked branch if nothing is specified.
+async fn collect_updates(
+    args: &PushArgs,
+    head_branch: Option<&str>,
+    refs: &[DiscoveredReference],
+) -> Result<Vec<RefUpdate>, String> {
+    let mut updates = Vec::new();
+    for spec in args.refspecs.iter() {
+        let (force, src, dst) = split_refspec(spec);
+        let update = if args.delete {
+            if !dst.is_empty() {
+                return Err(format!(
+                    "--delete only accepts plain target ref names: '{}'",
+                    spec
+                ));
+            }
+            delete_update(src)
+        } else if src.is_empty() {
+            delete_update(dst)
+        } else {
+            resolve_refspec(src, dst, head_branch).await?
+        };
+        updates.push(RefUpdate {
+            force: force || args.force,
+            ..update
+        });
+    }
+
+    if args.all {
+        for branch in Branch::list_branches(None).await {
+            let full_ref = format!("refs/heads/{}", branch.name);
+            updates.push(new_update(
+                full_ref.clone(),
+                full_ref,
+                branch.commit,
+                args.force,
+            ));
+        }
+    } else if args.refspecs.is_empty() {
+        let branch = head_branch.ok_or("HEAD is detached, please specify the refs to push")?;
+        let commit = Branch::find_branch(branch, None)
+            .await
+            .ok_or(format!("branch '{}' has no commits", branch))?
+            .commit;
+        let tracked_branch = Config::get("branch", Some(branch), "merge")
+            .await // New branch may not have tracking branch
+            .unwrap_or_else(|| format!("refs/heads/{}", branch));
+        updates.push(new_update(
+            format!("refs/heads/{}", branch),
+            tracked_branch,
+            commit,
+            args.force,
+        ));
+    }
+    if args.tags {
+        for tag in Tag::list_tags().await {
+            let full_ref = format!("refs/tags/{}", tag.name);
+            if !updates.iter().any(|update| update.dst == full_ref) {
+                updates.push(new_update(
+                    full_ref.clone(),
+                    full_ref,
+                    tag.object,
+                    args.force,
+                ));
+            }
+        }
+    }
+
+    for update in updates.iter_mut() {
+        if let Some(r) = refs.iter().find(|r| r._ref == update.dst) {
+            update.old = SHA1::from_str(&r._hash).unwrap();
+        }
+    }
+    Ok(updates)
+}
+
+fn new_update(src: String, dst: String, new: SHA1, force: bool) -> RefUpdate {
+    RefUpdate {
+        src: Some(src),
+        dst,
+        old: SHA1::default(),
+        new,
+        force,
+        lease: None,
+    }
+}
+
+fn delete_update(dst: &str) -> RefUpdate {
+    RefUpdate {
+        src: None,
+        dst: full_ref(dst, false),
+        old: SHA1::default(),
+        new: SHA1::default(),
+        force: false,
+        lease: None,
+    }
+}
+
+/// `[+]<src>[:<dst>]` -> (force, src, dst), `dst` is empty if not given
+fn split_refspec(spec: &str) -> (bool, &str, &str) {
+    let (force, spec) = match spec.strip_prefix('+') {
+        Some(spec) => (true, spec),
+        None => (false, spec),
+    };
+    let (src, dst) = spec.split_once(':').unwrap_or((spec, ""));
+    (force, src, dst)
+}
+
+/// Complete the short name to `refs/heads/<name>` (or `refs/tags/<name>`)
+fn full_ref(name: &str, is_tag: bool) -> String {
+    if name.starts_with("refs/") {
+        name.to_string()
+    } else if is_tag {
+        format!("refs/tags/{}", name)
+    } else {
+        format!("refs/heads/{}", name)
+    }
+}
+
+/// `refs/heads/master` -> `master`
+fn short_ref(name: &str) -> &str {
+    name.strip_prefix("refs/heads/")
+        .or_else(|| name.strip_prefix("refs/tags/"))
+        .unwrap_or(name)
+}
+
+/// Resolve `<src>` to a local branch, tag or commit, `<dst>` is the same ref as `<src>` if not given
+async fn resolve_refspec(
+    src: &str,
+    dst: &str,
+    head_branch: Option<&str>,
+) -> Result<RefUpdate, String> {
+    let branch = match src {
+        "HEAD" => head_branch,
+        _ if src.starts_with("refs/tags/") => None,
+        _ => Some(src.strip_prefix("refs/heads/").unwrap_or(src)),
+    };
+    let found = match branch {
+        Some(branch) => Branch::find_branch(branch, None)
+            .await
+            .map(|found| (Some(format!("refs/heads/{}", branch)), found.commit)),
+        None => None,
+    };
+    let (local_ref, new) = match found {
+        Some(found) => found,
+        None => resolve_tag_or_commit(src).await?,
+    };
+
+    let is_tag = local_ref
+        .as_deref()
+        .is_some_and(|r| r.starts_with("refs/tags/"));
+    let dst = match (dst, &local_ref) {
+        ("", Some(local_ref)) => local_ref.clone(),
+        ("", None) => {
+            return Err(format!(
+                "the destination of '{}' must be specified, e.g. '{}:<branch>'",
+                src, src
+            ))
+        }
+        (dst, _) => full_ref(dst, is_tag),
+    };
+    Ok(new_update(
+        local_ref.unwrap_or(src.to_string()),
+        dst,
+        new,
+        false,
+    ))
+}
+
+/// The local tag (full name) & its object, or the commit of the revision
+async fn resolve_tag_or_commit(src: &str) -> Result<(Option<String>, SHA1), String> {
+    let tag_name = src.strip_prefix("refs/tags/").unwrap_or(src);
+    if let Some(tag) = Tag::find_tag(tag_name).await {
+        return Ok((Some(format!("refs/tags/{}", tag.name)), tag.object));
+    }
+    match get_target_commit(src).await {
+        Ok(commit) => Ok((None, commit)),
+        Err(_) => Err(format!("src refspec '{}' does not match any", src)),
+    }
+}
+
+/// `--force-with-lease[=<ref>[:<expect>]]`: set the expected remote hashes,
+/// which are the remote-tracking branches if not given
+async fn set_leases(
+    updates: &mut [RefUpdate],
+    lease: &str,
+    repository: &str,
+) -> Result<(), String> {
+    let (ref_name, expect) = match lease.split_once(':') {
+        Some((ref_name, expect)) => (ref_name, Some(expect)),
+        None => (lease, None),
+    };
+    let expect = match expect {
+        None => None,
+        Some("") => Some(SHA1::default()), // the ref must not exist
+        Some(expect) => match SHA1::from_str(expect) {
+            Ok(hash) => Some(hash),
+            Err(_) => Some(
+                get_target_commit(expect)
+                    .await
+                    .map_err(|_| format!("cannot parse expected object name '{}'", expect))?,
+            ),
+        },
+    };
+    for update in updates.iter_mut() {
+        if !ref_name.is_empty()
+            && full_ref(ref_name, false) != update.dst
+            && ref_name != short_ref(&update.dst)
+        {
+            continue;
+        }
+        let lease = match expect {
+            Some(expect) => expect,
+            None => {
+                let Some(branch) = update.dst.strip_prefix("refs/heads/") else {
+                    continue; // no remote-tracking ref, the same as `--force-with-lease` not given
+                };
+                match Branch::find_branch(branch, Some(repository)).await {
+                    Some(tracking) => tracking.commit,
+                    None => SHA1::default(),
+                }
+            }
+        };
+        update.lease = Some(lease);
+    }
+    Ok(())
+}
+
+/// Check whether the update would be accepted, return the reason if rejected
+fn check_update(update: &RefUpdate) -> Result<(), String> {
+    if update.is_delete() {
+        return match update.old == SHA1::default() {
+            true => Err("remote ref does not exist".to_string()),
+            false => Ok(()),
+        };
+    }
+    if let Some(lease) = update.lease {
+        return match lease == update.old {
+            true => Ok(()),
+            false => Err("stale info".to_string()),
+        };
+    }
+    if update.force || update.old == SHA1::default() {
+        return Ok(());
+    }
+    if update.dst.starts_with("refs/tags/") {
+        return Err("already exists".to_string());
+    }
+    if !util::objects_storage().exist(&update.old) {
+        return Err("fetch first".to_string());
+    }
+    let old = tag::peel_to_commit(&update.old);
+    let new = tag::peel_to_commit(&update.new);
+    if !collect_history_commits(&[new]).contains(&old) {
+        return Err("non-fast-forward".to_string());
+    }
+    Ok(())
+}
+
+/// The commits of the remote refs which exist locally, the objects reachable from them are not sent
+fn known_remote_commits(refs: &[DiscoveredReference]) -> Vec<SHA1> {
+    let storage = util::objects_storage();
+    let commits: HashSet<SHA1> = refs
+        .iter()
+        .filter_map(|r| SHA1::from_str(&r._hash).ok())
+        .filter(|hash| storage.exist(hash))
+        .map(|hash| tag::peel_to_commit(&hash))
+        .filter(|hash| storage.is_object_type(hash, ObjectType::Commit))
+        .collect();
+    commits.into_iter().collect()
+}
+
+/// The status of a ref update reported by the remote: (ref, Ok or the reason of failure)
+type RefStatus = (String, Result<(), String>);
+
+/// Parse the response of `report-status` (or `report-status-v2`):
+/// `unpack ok`, then `ok <ref>` or `ng <ref> <reason>` for each ref (`option` lines of v2 are ignored)
+fn parse_report_status(mut data: Bytes) -> Result<Vec<RefStatus>, String> {
+    let (_, pkt_line) = read_pkt_line(&mut data);
+    let unpack = String::from_utf8_lossy(&pkt_line);
+    match unpack.trim_end().strip_prefix("unpack ") {
+        Some("ok") => {}
+        Some(error) => return Err(format!("unpack failed: {}", error)),
+        None => return Err(format!("unexpected response: {:?}", unpack)),
+    }
+    let mut statuses = Vec::new();
+    loop {
+        let (len, pkt_line) = read_pkt_line(&mut data);
+        if len == 0 {
+            break;
+        }
+        let line = String::from_utf8_lossy(&pkt_line);
+        let line = line.trim_end();
+        if let Some(ref_name) = line.strip_prefix("ok ") {
+            statuses.push((ref_name.to_string(), Ok(())));
+        } else if let Some(rest) = line.strip_prefix("ng ") {
+            let (ref_name, reason) = rest.split_once(' ').unwrap_or((rest, "failed"));
+            statuses.push((ref_name.to_string(), Err(reason.to_string())));
+        } else if !line.starts_with("option ") {
+            return Err(format!("unexpected response: {:?}", line));
+        }
     }
+    Ok(statuses)
+}
 
+/// Update (or delete) the remote-tracking branch of the pushed branch
+async fn update_tracking_branch(update: &RefUpdate, repository: &str) {
+    let Some(branch) = update.dst.strip_prefix("refs/heads/") else {
+        return;
+    };
+    if !update.is_delete() {
+        Branch::update_branch(
+            branch,
+            &update.new.to_string(),
+            Some(repository),
+            "update by push",
+        )
+        .await;
+    } else if Branch::find_branch(branch, Some(repository))
+        .await
+        .is_some()
+    {
+        Branch::delete_branch(branch, Some(repository)).await;
+    }
+}
+
+/// Objects needed by the ref: tag objects (if annotated) and the commits not in `remote_refs`
+fn tag_objs(object: &SHA1, remote_refs: &[SHA1]) -> HashSet<SHA1> {
+    let mut objs = HashSet::new();
+    let storage = util::objects_storage();
+    let mut object = *object;
+    while storage.is_object_type(&object, ObjectType::Tag) {
+        objs.insert(object);
+        let data = storage.get(&object).unwrap();
+        object = TagObject::from_bytes(&data, object).unwrap().object_hash;
+    }
+    objs.extend(incremental_objs(object, remote_refs));
+    objs
+}
+
+/// collect all commits from `commit_ids` to root commit (or shallow commits, whose parents are not fetched)
+fn collect_history_commits(commit_ids: &[SHA1]) -> HashSet<SHA1> {
+    let shallow_commits = shallow::load();
     let mut commits = HashSet::new();
-    let mut queue = VecDeque::new();
-    queue.push_back(*commit_id);
+    let mut queue: VecDeque<SHA1> = commit_ids
+        .iter()
+        .filter(|id| **id != SHA1::default()) // 0000...0000 means not exist
+        .copied()
+        .collect();
     while let Some(commit) = queue.pop_front() {
-        commits.insert(commit);
-
-        let commit = Commit::load(&commit);
-        for parent in commit.parent_commit_ids.iter() {
-            queue.push_back(*parent);
+        if !commits.insert(commit) || shallow_commits.contains(&commit) {
+            continue; // visited, or parents not fetched
         }
+        let commit = Commit::load(&commit);
+        queue.extend(commit.parent_commit_ids.iter().copied());
     }
     commits
 }
 
-fn incremental_objs(local_ref: SHA1, remote_ref: SHA1) -> HashSet<Entry> {
-    tracing::debug!("local_ref: {}, remote_ref: {}", local_ref, remote_ref);
+/// Objects reachable from `local_ref` but not from any of `remote_refs`
+fn incremental_objs(local_ref: SHA1, remote_refs: &[SHA1]) -> HashSet<SHA1> {
+    tracing::debug!("local_ref: {}, remote_refs: {:?}", local_ref, remote_refs);
+    let shallow_commits = shallow::load();
 
-    // just fast-forward optimization
-    if remote_ref != SHA1::default() { // remote exists
+    // just fast-forward optimization: a linear history from one of the remote refs
+    if !remote_refs.is_empty() {
+        // remote exists
         let mut commit = Commit::load(&local_ref);
         let mut commits = Vec::new();
         let mut ok = true;
         loop {
             commits.push(commit.id);
-            if commit.id == remote_ref {
+            if remote_refs.contains(&commit.id) {
                 break;
             }
-            if commit.parent_commit_ids.len() != 1 { // merge commit or root commit
+            if commit.parent_commit_ids.len() != 1 || shallow_commits.contains(&commit.id) {
+                // merge commit, root commit or shallow commit
                 ok = false;
                 break;
             }
             // update commit to it's only parent
             commit = Commit::load(&commit.parent_commit_ids[0]);
         }
-        if ok { // fast-forward
+        if ok {
+            // fast-forward
             let mut objs = HashSet::new();
             commits.reverse(); // from old to new
             for i in 0..commits.len() - 1 {
                 let old_tree = Commit::load(&commits[i]).tree_id;
                 let new_commit = Commit::load(&commits[i + 1]);
                 objs.extend(diff_tree_objs(Some(&old_tree), &new_commit.tree_id));
-                objs.insert(new_commit.into());
+                objs.insert(new_commit.id);
             }
             return objs;
         }
     }
 
-
     let mut objs = HashSet::new();
     let mut visit = HashSet::new(); // avoid duplicate commit visit
-    let exist_commits = collect_history_commits(&remote_ref);
+    let exist_commits = collect_history_commits(remote_refs);
     let mut queue = VecDeque::new();
     if !exist_commits.contains(&local_ref) {
         queue.push_back(local_ref);
@@ -230,8 +841,15 @@ fn incremental_objs(local_ref: SHA1, remote_ref: SHA1) -> HashSet<Entry> {
 
     while let Some(commit) = queue.pop_front() {
         let commit = Commit::load(&commit);
-        let parents = &commit.parent_commit_ids;
-        if parents.is_empty() {
+        let is_shallow = shallow_commits.contains(&commit.id);
+        let parents: &[SHA1] = match is_shallow {
+            true => &[], // parents are not fetched
+            false => &commit.parent_commit_ids,
+        };
+        if is_shallow {
+            // send the whole tree, like the root commit
+            objs.extend(diff_tree_objs(None, &commit.tree_id));
+        } else if parents.is_empty() {
             if root_commit.is_none() {
                 root_commit = Some(commit.id);
             } else if root_commit != Some(commit.id) {
@@ -246,7 +864,7 @@ fn incremental_objs(local_ref: SHA1, remote_ref: SHA1) -> HashSet<Entry> {
                 visit.insert(*parent);
             }
         }
-        objs.insert(commit.into());
+        objs.insert(commit.id);
 
         print!("Counting objects: {}\r", objs.len());
         std::io::stdout().flush().unwrap();
@@ -264,7 +882,8 @@ fn incremental_objs(local_ref: SHA1, remote_ref: SHA1) -> HashSet<Entry> {
 
 /// calc objects that in `new_tree` but not in `old_tree`
 /// - if `old_tree` is None, return all objects in `new_tree` (include tree itself)
-fn diff_tree_objs(old_tree: Option<&SHA1>, new_tree: &SHA1) -> HashSet<Entry> { // TODO: skip objs that has been added in caller
+fn diff_tree_objs(old_tree: Option<&SHA1>, new_tree: &SHA1) -> HashSet<SHA1> {
+    // TODO: skip objs that has been added in caller
     let mut objs = HashSet::new();
     if let Some(old_tree) = old_tree {
         if old_tree == new_tree {
@@ -273,16 +892,17 @@ fn diff_tree_objs(old_tree: Option<&SHA1>, new_tree: &SHA1) -> HashSet<Entry> {
     }
 
     let new_tree = Tree::load(new_tree);
-    objs.insert(new_tree.clone().into()); // tree itself
+    objs.insert(new_tree.id); // tree itself
 
     let old_items = match old_tree {
         Some(tree) => {
             let tree = Tree::load(tree);
-            tree.tree_items.iter()
+            tree.tree_items
+                .iter()
                 .map(|item| item.id)
                 .collect::<HashSet<_>>()
         }
-        None => HashSet::new()
+        None => HashSet::new(),
     };
 
     for item in new_tree.tree_items.iter() {
@@ -291,12 +911,13 @@ fn diff_tree_objs(old_tree: Option<&SHA1>, new_tree: &SHA1) -> HashSet<Entry> {
                 TreeItemMode::Tree => {
                     objs.extend(diff_tree_objs(None, &item.id)); //TODO optimize, find same name tree
                 }
-                _ => { // TODO: submodule (TreeItemMode: Commit)
-                    if item.mode == TreeItemMode::Commit { // (160000)| Gitlink (Submodule)
+                _ => {
+                    // TODO: submodule (TreeItemMode: Commit)
+                    if item.mode == TreeItemMode::Commit {
+                        // (160000)| Gitlink (Submodule)
                         eprintln!("{}", "Warning: Submodule is not supported yet".red());
                     }
-                    let blob = Blob::load(&item.id);
-                    objs.insert(blob.into());
+                    objs.insert(item.id); // blob, not loaded
                 }
             }
         }
@@ -306,27 +927,66 @@ fn diff_tree_objs(old_tree: Option<&SHA1>, new_tree: &SHA1) -> HashSet<Entry> {
 }
 
 #[cfg(test)]
-mod test{
+mod test {
     use super::*;
+    use crate::utils::test;
+    use mercury::internal::object::tree::TreeItem;
     #[test]
     fn test_parse_args_success() {
         let args = vec!["push"];
         let args = PushArgs::parse_from(args);
         assert_eq!(args.repository, None);
-        assert_eq!(args.refspec, None);
+        assert!(args.refspecs.is_empty());
         assert!(!args.set_upstream);
 
         let args = vec!["push", "origin", "master"];
         let args = PushArgs::parse_from(args);
         assert_eq!(args.repository, Some("origin".to_string()));
-        assert_eq!(args.refspec, Some("master".to_string()));
+        assert_eq!(args.refspecs, vec!["master".to_string()]);
         assert!(!args.set_upstream);
 
         let args = vec!["push", "-u", "origin", "master"];
         let args = PushArgs::parse_from(args);
         assert_eq!(args.repository, Some("origin".to_string()));
-        assert_eq!(args.refspec, Some("master".to_string()));
+        assert_eq!(args.refspecs, vec!["master".to_string()]);
         assert!(args.set_upstream);
+
+        let args = vec!["push", "--tags"];
+        let args = PushArgs::parse_from(args);
+        assert!(args.tags);
+
+        let args = vec![
+            "push",
+            "origin",
+            "--all",
+            "--force-with-lease",
+            "--atomic",
+            "-n",
+        ];
+        let args = PushArgs::parse_from(args);
+        assert!(args.all && args.atomic && args.dry_run);
+        assert_eq!(args.force_with_lease, Some(String::new()));
+
+        let args = vec![
+            "push",
+            "origin",
+            "--force-with-lease=master:abc",
+            "-f",
+            "+dev",
+            "HEAD:main",
+        ];
+        let args = PushArgs::parse_from(args);
+        assert!(args.force);
+        assert_eq!(args.force_with_lease, Some("master:abc".to_string()));
+        assert_eq!(
+            args.refspecs,
+            vec!["+dev".to_string(), "HEAD:main".to_string()]
+        );
+
+        let args = vec!["push", "origin", "--delete", "dev", "v1.0"];
+        let args = PushArgs::parse_from(args);
+        assert!(args.delete);
+        assert_eq!(args.refspecs.len(), 2);
     }
 
     #[test]
@@ -343,9 +1003,171 @@ mod test{
         let args = PushArgs::try_parse_from(args);
         assert!(args.is_err());
 
-        let args = vec!["push", "origin"];
+        let args = vec!["push", "origin", "--delete"];
         let args = PushArgs::try_parse_from(args);
         assert!(args.is_err());
+
+        let args = vec!["push", "origin", "--all", "master"];
+        let args = PushArgs::try_parse_from(args);
+        assert!(args.is_err());
+    }
+
+    #[test]
+    fn test_refspec() {
+        assert_eq!(split_refspec("master"), (false, "master", ""));
+        assert_eq!(
+            split_refspec("+HEAD:refs/heads/dev"),
+            (true, "HEAD", "refs/heads/dev")
+        );
+        assert_eq!(split_refspec(":dev"), (false, "", "dev"));
+        assert_eq!(full_ref("dev", false), "refs/heads/dev");
+        assert_eq!(full_ref("v1.0", true), "refs/tags/v1.0");
+        assert_eq!(full_ref("refs/tags/v1.0", false), "refs/tags/v1.0");
+        assert_eq!(short_ref("refs/heads/feature/a"), "feature/a");
+    }
+
+    #[test]
+    fn test_check_update() {
+        let hash = SHA1::from_str("1234567890123456789012345678901234567890").unwrap();
+        let other = SHA1::from_str("abcdefabcdefabcdefabcdefabcdefabcdefabcd").unwrap();
+        let update = RefUpdate {
+            src: Some("refs/heads/master".to_string()),
+            dst: "refs/heads/master".to_string(),
+            old: SHA1::default(),
+            new: hash,
+            force: false,
+            lease: None,
+        };
+        assert!(check_update(&update).is_ok()); // new branch
+        assert_eq!(
+            update.summary(),
+            format!(" * {:<17} master -> master", "[new branch]")
+        );
+
+        let tag = RefUpdate {
+            dst: "refs/tags/v1".to_string(),
+            old: other,
+            ..update.clone()
+        };
+        assert_eq!(check_update(&tag), Err("already exists".to_string()));
+        assert!(check_update(&RefUpdate { force: true, ..tag }).is_ok());
+
+        let stale = RefUpdate {
+            old: other,
+            lease: Some(hash),
+            ..update.clone()
+        };
+        assert_eq!(check_update(&stale), Err("stale info".to_string()));
+        assert!(check_update(&RefUpdate {
+            lease: Some(other),
+            ..stale
+        })
+        .is_ok());
+
+        let delete = RefUpdate {
+            src: None,
+            new: SHA1::default(),
+            ..update
+        };
+        assert!(!delete.is_up_to_date());
+        assert_eq!(
+            check_update(&delete),
+            Err("remote ref does not exist".to_string())
+        );
+        let delete = RefUpdate {
+            old: other,
+            ..delete
+        };
+        assert!(check_update(&delete).is_ok());
+        assert_eq!(delete.summary(), format!(" - {:<17} master", "[deleted]"));
     }
 
-}
\ No newline at end of file
+    #[test]
+    fn test_parse_report_status() {
+        let mut data = BytesMut::new();
+        add_pkt_line_string(&mut data, "unpack ok\n".to_string());
+        add_pkt_line_string(&mut data, "ok refs/heads/master\n".to_string());
+        // report-status-v2
+        add_pkt_line_string(&mut data, "option forced-update\n".to_string());
+        add_pkt_line_string(
+            &mut data,
+            "ng refs/heads/dev non-fast-forward\n".to_string(),
+        );
+        data.extend_from_slice(b"0000");
+        let statuses = parse_report_status(data.freeze()).unwrap();
+        assert_eq!(
+            statuses,
+            vec![
+                ("refs/heads/master".to_string(), Ok(())),
+                (
+                    "refs/heads/dev".to_string(),
+                    Err("non-fast-forward".to_string())
+                ),
+            ]
+        );
+
+        let mut data = BytesMut::new();
+        add_pkt_line_string(&mut data, "unpack index-pack failed\n".to_string());
+        data.extend_from_slice(b"0000");
+        assert!(parse_report_status(data.freeze()).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_push_from_shallow_clone() {
+        test::setup_with_new_libra().await;
+        let storage = util::objects_storage();
+        let save = |commit: &Commit| {
+            storage
+                .put(&commit.id, &commit.to_data().unwrap(), commit.get_type())
+                .unwrap();
+        };
+        let blob = Blob::from_content("shallow");
+        storage.put(&blob.id, &blob.data, blob.get_type()).unwrap();
+        let item = TreeItem::new(TreeItemMode::Blob, blob.id, "a.txt".to_string());
+        let tree = Tree::from_tree_items(vec![item]).unwrap();
+        storage
+            .put(&tree.id, &tree.to_data().unwrap(), tree.get_type())
+            .unwrap();
+        // the parent of `base` is not fetched
+        let missing = SHA1::new(&[1; 20]);
+        let base = Commit::from_tree_id(tree.id, vec![missing], "base");
+        let head = Commit::from_tree_id(tree.id, vec![base.id], "head");
+        save(&base);
+        save(&head);
+        shallow::update(&[base.id], &[]);
+
+        // the remote doesn't have the branch: the whole tree of the shallow commit is sent
+        let objs = incremental_objs(head.id, &[]);
+        assert_eq!(objs, HashSet::from([head.id, base.id, tree.id, blob.id]));
+        // the remote has `base`
+        assert_eq!(
+            incremental_objs(head.id, &[base.id]),
+            HashSet::from([head.id])
+        );
+
+        let update = new_update(
+            "master".to_string(),
+            "refs/heads/master".to_string(),
+            head.id,
+            false,
+        );
+        assert!(check_update(&RefUpdate {
+            old: base.id,
+            ..update
+        })
+        .is_ok());
+        let update = new_update(
+            "master".to_string(),
+            "refs/heads/master".to_string(),
+            base.id,
+            false,
+        );
+        assert_eq!(
+            check_update(&RefUpdate {
+                old: head.id,
+                ..update
+            }),
+            Err("non-fast-forward".to_string())
+        );
+    }
+}
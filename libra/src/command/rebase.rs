@@ -0,0 +1,714 @@
+use std::collections::HashSet;
+use std::fs;
+use std::path::PathBuf;
+
+use clap::Parser;
+use common::utils::{format_commit_msg, parse_commit_msg};
+use mercury::hash::SHA1;
+use mercury::internal::index::Index;
+use mercury::internal::object::commit::Commit;
+use mercury::internal::object::signature::Signature;
+
+use crate::internal::branch::Branch;
+use crate::internal::head::Head;
+use crate::utils::object_ext::CommitExt;
+use crate::utils::{path, util};
+
+use super::{
+    commit, get_target_commit, log, merge,
+    restore::{self, RestoreArgs},
+    save_object, status, switch,
+};
+
+/// Files in `.libra/rebase-merge`
+const HEAD_NAME: &str = "head-name";
+const ORIG_HEAD: &str = "orig-head";
+const ONTO: &str = "onto";
+const TODO: &str = "git-rebase-todo";
+const DONE: &str = "done";
+/// the todo line which stopped the rebase because of conflicts
+const STOPPED: &str = "stopped";
+const MESSAGE: &str = "message";
+/// the last commit created by this rebase, which `squash` & `fixup` amend
+const AMEND: &str = "amend";
+
+#[derive(Parser, Debug)]
+pub struct RebaseArgs {
+    /// Upstream branch or commit to replay the commits onto
+    #[clap(required_unless_present_any(["continue_", "abort", "skip"]))]
+    pub upstream: Option<String>,
+
+    /// Make a list of the commits which are about to be rebased, and let the user edit it before rebasing
+    #[clap(short, long, requires("upstream"))]
+    pub interactive: bool,
+
+    /// Continue the rebasing process after having resolved a merge conflict
+    #[clap(long = "continue", group = "action", conflicts_with("upstream"))]
+    pub continue_: bool,
+
+    /// Abort the rebase operation and reset HEAD to the original branch
+    #[clap(long, group = "action", conflicts_with("upstream"))]
+    pub abort: bool,
+
+    /// Restart the rebasing process by skipping the current commit
+    #[clap(long, group = "action", conflicts_with("upstream"))]
+    pub skip: bool,
+}
+
+/// Commands of the todo list
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Action {
+    Pick,
+    Reword,
+    Squash,
+    Fixup,
+    Drop,
+}
+
+impl Action {
+    fn name(&self) -> &'static str {
+        match self {
+            Action::Pick => "pick",
+            Action::Reword => "reword",
+            Action::Squash => "squash",
+            Action::Fixup => "fixup",
+            Action::Drop => "drop",
+        }
+    }
+
+    fn from_str(s: &str) -> Option<Action> {
+        match s {
+            "p" | "pick" => Some(Action::Pick),
+            "r" | "reword" => Some(Action::Reword),
+            "s" | "squash" => Some(Action::Squash),
+            "f" | "fixup" => Some(Action::Fixup),
+            "d" | "drop" => Some(Action::Drop),
+            _ => None,
+        }
+    }
+}
+
+/// One line of the todo list: `<action> <commit> [<subject>]`
+#[derive(Debug, Clone)]
+struct TodoItem {
+    action: Action,
+    commit: SHA1,
+}
+
+const TODO_HELP: &str = "
+# Commands:
+# p, pick <commit> = use commit
+# r, reword <commit> = use commit, but edit the commit message
+# s, squash <commit> = use commit, but meld into previous commit
+# f, fixup <commit> = like \"squash\", but discard this commit's log message
+# d, drop <commit> = remove commit
+#
+# These lines can be re-ordered; they are executed from top to bottom.
+#
+# If you remove a line here THAT COMMIT WILL BE LOST.
+#
+# However, if you remove everything, the rebase will be aborted.
+";
+
+pub async fn execute(args: RebaseArgs) {
+    if args.continue_ || args.abort || args.skip {
+        if !path::rebase_merge().exists() {
+            eprintln!("fatal: No rebase in progress?");
+            return;
+        }
+        if args.continue_ {
+            continue_rebase().await;
+        } else if args.abort {
+            abort_rebase().await;
+        } else {
+            skip_commit().await;
+        }
+        return;
+    }
+    start_rebase(&args.upstream.unwrap(), args.interactive).await;
+}
+
+async fn start_rebase(upstream: &str, interactive: bool) {
+    let rebase_dir = path::rebase_merge();
+    if rebase_dir.exists() {
+        eprintln!("fatal: It seems that there is already a rebase-merge directory.");
+        eprintln!("Use \"libra rebase (--continue | --abort | --skip)\" to resume or abort it.");
+        return;
+    }
+    if path::merge_head().exists() {
+        eprintln!("fatal: You have not concluded your merge (MERGE_HEAD exists).");
+        return;
+    }
+    if status::has_uncommitted_changes().await {
+        eprintln!("error: cannot rebase: You have uncommitted changes.");
+        eprintln!("error: Please commit or stash them.");
+        return;
+    }
+    let orig_head = match Head::current_commit().await {
+        Some(commit) => commit,
+        None => {
+            eprintln!("fatal: no commits yet, nothing to rebase");
+            return;
+        }
+    };
+    let onto = match get_target_commit(upstream).await {
+        Ok(commit) => commit,
+        Err(e) => {
+            eprintln!("fatal: invalid upstream '{}': {}", upstream, e);
+            return;
+        }
+    };
+    let head_name = match Head::current().await {
+        Head::Branch(name) => name,
+        Head::Detached(_) => String::new(),
+    };
+
+    let commits = commits_to_replay(&orig_head, &onto).await;
+    let base = merge::lca_commit(&Commit::load(&orig_head), &Commit::load(&onto)).await;
+    if !interactive && base.is_some_and(|base| base.id == onto) {
+        println!("Current branch {} is up to date.", head_display(&head_name));
+        return;
+    }
+
+    let mut todo: String = commits
+        .iter()
+        .map(|commit| {
+            format!(
+                "pick {} {}\n",
+                short_hash(&commit.id),
+                commit.format_message()
+            )
+        })
+        .collect();
+    todo += &format!(
+        "\n# Rebase {}..{} onto {} ({} commands)\n#{}",
+        short_hash(&onto),
+        short_hash(&orig_head),
+        short_hash(&onto),
+        commits.len(),
+        TODO_HELP
+    );
+
+    fs::create_dir_all(&rebase_dir).unwrap();
+    fs::write(rebase_dir.join(HEAD_NAME), &head_name).unwrap();
+    fs::write(rebase_dir.join(ORIG_HEAD), orig_head.to_string()).unwrap();
+    fs::write(rebase_dir.join(ONTO), onto.to_string()).unwrap();
+    fs::write(rebase_dir.join(TODO), &todo).unwrap();
+    fs::write(rebase_dir.join(DONE), "").unwrap();
+
+    if interactive {
+        let todo_file = rebase_dir.join(TODO);
+        if let Err(e) = util::edit_file(&todo_file) {
+            eprintln!("error: {}", e);
+            fs::remove_dir_all(&rebase_dir).unwrap();
+            return;
+        }
+        let todo = fs::read_to_string(&todo_file).unwrap();
+        match parse_todo(&todo) {
+            Ok(items) if items.is_empty() => {
+                println!("Nothing to do");
+                fs::remove_dir_all(&rebase_dir).unwrap();
+                return;
+            }
+            Ok(_) => {}
+            Err(e) => {
+                eprintln!("error: {}", e);
+                fs::remove_dir_all(&rebase_dir).unwrap();
+                return;
+            }
+        }
+    }
+
+    // detach HEAD at `onto`, then replay the commits one by one
+    switch::restore_to_commit(onto).await;
+    let reason = format!("rebase (start): checkout {}", upstream);
+    Head::update(Head::Detached(onto), None, &reason).await;
+    run_todo().await;
+}
+
+/// Commits reachable from `head` but not from `onto`, excluding merge commits.
+/// - topological order: parents before children
+async fn commits_to_replay(head: &SHA1, onto: &SHA1) -> Vec<Commit> {
+    let upstream: HashSet<SHA1> = log::get_reachable_commits(onto.to_string())
+        .await
+        .into_iter()
+        .map(|commit| commit.id)
+        .collect();
+
+    // post-order DFS
+    let mut commits = Vec::new();
+    let mut visited = HashSet::new();
+    let mut stack = vec![(*head, false)];
+    while let Some((id, parents_done)) = stack.pop() {
+        if parents_done {
+            let commit = Commit::load(&id);
+            if commit.parent_commit_ids.len() <= 1 {
+                commits.push(commit);
+            }
+            continue;
+        }
+        if upstream.contains(&id) || !visited.insert(id) {
+            continue;
+        }
+        stack.push((id, true));
+        let commit = Commit::load(&id);
+        for parent in commit.parent_commit_ids.iter().rev() {
+            stack.push((*parent, false));
+        }
+    }
+    commits
+}
+
+/// Parse the todo list, ignore empty lines & comments
+fn parse_todo(todo: &str) -> Result<Vec<TodoItem>, String> {
+    let mut items = Vec::new();
+    for line in todo.lines() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let item = parse_todo_line(line)?;
+        if items.is_empty() && matches!(item.action, Action::Squash | Action::Fixup) {
+            return Err(format!(
+                "cannot '{}' without a previous commit",
+                line.split_whitespace().next().unwrap()
+            ));
+        }
+        items.push(item);
+    }
+    Ok(items)
+}
+
+fn parse_todo_line(line: &str) -> Result<TodoItem, String> {
+    let mut parts = line.split_whitespace();
+    let action = parts.next().unwrap_or_default();
+    let action = Action::from_str(action).ok_or(format!("invalid command '{}'", action))?;
+    let commit = match parts.next() {
+        Some(commit) => {
+            util::get_commit_base(commit).map_err(|_| format!("invalid line: {}", line))?
+        }
+        None => return Err(format!("missing commit: {}", line)),
+    };
+    Ok(TodoItem { action, commit })
+}
+
+/// Execute the todo list until it's empty or conflicts occur
+async fn run_todo() {
+    let rebase_dir = path::rebase_merge();
+    loop {
+        let todo = fs::read_to_string(rebase_dir.join(TODO)).unwrap();
+        let mut lines: Vec<&str> = todo.lines().collect();
+        let next = lines.iter().position(|line| {
+            let line = line.trim();
+            !line.is_empty() && !line.starts_with('#')
+        });
+        let next = match next {
+            Some(next) => next,
+            None => break,
+        };
+        let line = lines.remove(next).trim().to_owned();
+        let item = match parse_todo_line(&line) {
+            Ok(item) => item,
+            Err(e) => {
+                eprintln!("error: {}", e);
+                eprintln!(
+                    "You can fix this with 'libra rebase --abort' or by editing '{}'",
+                    rebase_dir.join(TODO).display()
+                );
+                return;
+            }
+        };
+
+        let conflicts = match apply_item(&item).await {
+            Ok(conflicts) => conflicts,
+            Err(e) => {
+                // nothing changed, the line is kept in todo list
+                eprintln!("{}", e);
+                eprintln!("error: could not apply {}", describe(&item.commit));
+                return;
+            }
+        };
+        let mut rest = lines.join("\n");
+        rest.push('\n');
+        fs::write(rebase_dir.join(TODO), rest).unwrap();
+        let mut done = fs::read_to_string(rebase_dir.join(DONE)).unwrap_or_default();
+        done += &format!("{}\n", line);
+        fs::write(rebase_dir.join(DONE), done).unwrap();
+
+        if !conflicts.is_empty() {
+            fs::write(rebase_dir.join(STOPPED), &line).unwrap();
+            for file in conflicts.iter() {
+                println!("CONFLICT (content): Merge conflict in {}", file.display());
+            }
+            eprintln!("error: could not apply {}", describe(&item.commit));
+            eprintln!("hint: Resolve all conflicts manually, mark them as resolved with");
+            eprintln!("hint: \"libra add <pathspec>\", then run \"libra rebase --continue\".");
+            eprintln!("hint: You can instead skip this commit: run \"libra rebase --skip\".");
+            eprintln!("hint: To abort and get back to the state before \"libra rebase\", run \"libra rebase --abort\".");
+            return;
+        }
+    }
+    finish_rebase().await;
+}
+
+/// Apply the changes of the commit onto HEAD, then commit them if there is no conflict
+/// - return conflicted files
+async fn apply_item(item: &TodoItem) -> Result<Vec<PathBuf>, String> {
+    if item.action == Action::Drop {
+        return Ok(Vec::new());
+    }
+    if matches!(item.action, Action::Squash | Action::Fixup) && amend_target().is_none() {
+        return Err(format!(
+            "error: cannot '{}' without a previous commit",
+            item.action.name()
+        ));
+    }
+    let commit = Commit::load(&item.commit);
+    let head = Commit::load(&Head::current_commit().await.unwrap());
+    let base = commit
+        .parent_commit_ids
+        .first()
+        .map(|parent| Commit::load(parent).tree_id);
+    let conflicts = merge::merge_trees(
+        base.as_ref(),
+        &head.tree_id,
+        &commit.tree_id,
+        "HEAD",
+        &describe(&item.commit),
+    )
+    .await?;
+    if conflicts.is_empty() {
+        commit_item(item).await;
+    }
+    Ok(conflicts)
+}
+
+/// Commit the index for the todo item, HEAD is moved to the new commit
+async fn commit_item(item: &TodoItem) {
+    let index = Index::load(path::index()).unwrap();
+    let tree = commit::create_tree(&index, &util::objects_storage(), "".into()).await;
+    let head = Commit::load(&Head::current_commit().await.unwrap());
+    let commit = Commit::load(&item.commit);
+    let message = parse_commit_msg(&commit.message).0.to_owned();
+
+    let new_commit = match item.action {
+        Action::Pick | Action::Reword => {
+            if tree.id == head.tree_id {
+                println!(
+                    "dropping {} -- patch contents already upstream",
+                    describe(&item.commit)
+                );
+                return;
+            }
+            let message = match item.action {
+                Action::Reword => edit_message(&message),
+                _ => message,
+            };
+            new_commit(commit.author.clone(), tree.id, vec![head.id], &message)
+        }
+        Action::Squash | Action::Fixup => {
+            // not `HEAD`'s parents: HEAD may be `onto` if no commit has been picked
+            let target = match amend_target() {
+                Some(target) => Commit::load(&target),
+                None => {
+                    eprintln!(
+                        "error: cannot '{}' without a previous commit",
+                        item.action.name()
+                    );
+                    return;
+                }
+            };
+            let head_message = parse_commit_msg(&target.message).0.to_owned();
+            let message = match item.action {
+                Action::Squash => {
+                    edit_message(&format!("{}\n\n{}", head_message.trim_end(), message))
+                }
+                _ => head_message,
+            };
+            new_commit(
+                target.author.clone(),
+                tree.id,
+                target.parent_commit_ids.clone(),
+                &message,
+            )
+        }
+        Action::Drop => return,
+    };
+    save_object(&new_commit, &new_commit.id).unwrap();
+    fs::write(path::rebase_merge().join(AMEND), new_commit.id.to_string()).unwrap();
+    let reason = format!(
+        "rebase ({}): {}",
+        item.action.name(),
+        new_commit.format_message()
+    );
+    Head::update(Head::Detached(new_commit.id), None, &reason).await;
+}
+
+/// The commit to be amended by `squash` & `fixup`, `None` if nothing has been picked yet
+fn amend_target() -> Option<SHA1> {
+    let amend = fs::read_to_string(path::rebase_merge().join(AMEND)).ok()?;
+    amend.trim().parse().ok()
+}
+
+/// Create a commit with the original `author`, the committer is the same as `libra commit`
+pub(crate) fn new_commit(
+    author: Signature,
+    tree_id: SHA1,
+    parents: Vec<SHA1>,
+    message: &str,
+) -> Commit {
+    let message = format_commit_msg(message, None);
+    let committer = Commit::from_tree_id(tree_id, parents.clone(), &message).committer;
+    Commit::new(author, committer, tree_id, parents, &message)
+}
+
+/// Let the user edit the commit message, lines starting with `#` are ignored
+/// - keep `message` if the result is empty
+fn edit_message(message: &str) -> String {
+    let file = path::rebase_merge().join(MESSAGE);
+    let content = format!(
+        "{}\n\n# Please enter the commit message for your changes. Lines starting\n# with '#' will be ignored.\n",
+        message.trim_end()
+    );
+    fs::write(&file, content).unwrap();
+    if let Err(e) = util::edit_file(&file) {
+        eprintln!("error: {}, keep the original message", e);
+        return message.to_owned();
+    }
+    let edited = fs::read_to_string(&file).unwrap();
+    let edited: Vec<&str> = edited
+        .lines()
+        .filter(|line| !line.starts_with('#'))
+        .collect();
+    let edited = edited.join("\n").trim().to_owned();
+    if edited.is_empty() {
+        message.to_owned()
+    } else {
+        edited + "\n"
+    }
+}
+
+async fn continue_rebase() {
+    let index = Index::load(path::index()).unwrap();
+    if !index.unmerged_files().is_empty() {
+        eprintln!("error: you need to resolve all conflicts first");
+        eprintln!("hint: mark them as resolved with \"libra add <pathspec>\"");
+        return;
+    }
+    let stopped = path::rebase_merge().join(STOPPED);
+    if stopped.exists() {
+        let line = fs::read_to_string(&stopped).unwrap();
+        match parse_todo_line(&line) {
+            Ok(item) => commit_item(&item).await,
+            Err(e) => eprintln!("error: {}", e),
+        }
+        fs::remove_file(stopped).unwrap();
+    } else if status::has_uncommitted_changes().await {
+        eprintln!("error: cannot continue: You have uncommitted changes.");
+        return;
+    }
+    run_todo().await;
+}
+
+async fn skip_commit() {
+    merge::clear_unmerged();
+    restore::execute(RestoreArgs {
+        worktree: true,
+        staged: true,
+        source: None,
+        pathspec: vec![util::working_dir_string()],
+    })
+    .await;
+    let stopped = path::rebase_merge().join(STOPPED);
+    if stopped.exists() {
+        fs::remove_file(stopped).unwrap();
+    }
+    run_todo().await;
+}
+
+async fn abort_rebase() {
+    let rebase_dir = path::rebase_merge();
+    let orig_head = fs::read_to_string(rebase_dir.join(ORIG_HEAD)).unwrap();
+    let orig_head = util::get_commit_base(orig_head.trim()).unwrap();
+    let head_name = fs::read_to_string(rebase_dir.join(HEAD_NAME)).unwrap_or_default();
+
+    merge::clear_unmerged();
+    switch::restore_to_commit(orig_head).await;
+    if head_name.is_empty() {
+        let reason = format!("rebase (abort): returning to {}", orig_head);
+        Head::update(Head::Detached(orig_head), None, &reason).await;
+    } else {
+        // the branch is not changed during rebasing
+        let reason = format!("rebase (abort): returning to refs/heads/{}", head_name);
+        Head::update(Head::Branch(head_name), None, &reason).await;
+    }
+    fs::remove_dir_all(rebase_dir).unwrap();
+}
+
+/// Update the original branch to HEAD and clean up the state
+async fn finish_rebase() {
+    let rebase_dir = path::rebase_merge();
+    let head_name = fs::read_to_string(rebase_dir.join(HEAD_NAME)).unwrap_or_default();
+    let head = Head::current_commit().await.unwrap();
+    if head_name.is_empty() {
+        println!("Successfully rebased and updated detached HEAD.");
+    } else {
+        let onto = fs::read_to_string(rebase_dir.join(ONTO)).unwrap_or_default();
+        let reason = format!(
+            "rebase (finish): refs/heads/{} onto {}",
+            head_name,
+            onto.trim()
+        );
+        Branch::update_branch(&head_name, &head.to_string(), None, &reason).await;
+        let reason = format!("rebase (finish): returning to refs/heads/{}", head_name);
+        Head::update(Head::Branch(head_name.clone()), None, &reason).await;
+        println!("Successfully rebased and updated refs/heads/{}.", head_name);
+    }
+    fs::remove_dir_all(rebase_dir).unwrap();
+}
+
+fn short_hash(hash: &SHA1) -> String {
+    hash.to_string()[..7].to_owned()
+}
+
+/// e.g. `1a2b3c4... commit subject`
+fn describe(hash: &SHA1) -> String {
+    format!(
+        "{}... {}",
+        short_hash(hash),
+        Commit::load(hash).format_message()
+    )
+}
+
+fn head_display(head_name: &str) -> &str {
+    if head_name.is_empty() {
+        "HEAD"
+    } else {
+        head_name
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::test;
+
+    #[test]
+    fn test_parse_args() {
+        assert!(RebaseArgs::try_parse_from(["rebase", "main"]).is_ok());
+        assert!(RebaseArgs::try_parse_from(["rebase", "-i", "main"]).is_ok());
+        assert!(RebaseArgs::try_parse_from(["rebase", "--continue"]).is_ok());
+        assert!(RebaseArgs::try_parse_from(["rebase"]).is_err());
+        assert!(RebaseArgs::try_parse_from(["rebase", "--abort", "--skip"]).is_err());
+        assert!(RebaseArgs::try_parse_from(["rebase", "main", "--continue"]).is_err());
+    }
+
+    #[test]
+    fn test_action_from_str() {
+        assert_eq!(Action::from_str("p"), Some(Action::Pick));
+        assert_eq!(Action::from_str("fixup"), Some(Action::Fixup));
+        assert_eq!(Action::from_str("edit"), None);
+    }
+
+    #[tokio::test]
+    async fn test_rebase() {
+        test::setup_with_new_libra().await;
+        test::ensure_file("rebase_a.txt", Some("a\n"));
+        crate::exec_async(vec!["add", "rebase_a.txt"])
+            .await
+            .unwrap();
+        crate::exec_async(vec!["commit", "-m", "base"])
+            .await
+            .unwrap();
+        crate::exec_async(vec!["branch", "rebase_feature"])
+            .await
+            .unwrap();
+
+        test::ensure_file("rebase_b.txt", Some("b\n"));
+        crate::exec_async(vec!["add", "rebase_b.txt"])
+            .await
+            .unwrap();
+        crate::exec_async(vec!["commit", "-m", "upstream"])
+            .await
+            .unwrap();
+        let upstream = Head::current_commit().await.unwrap();
+
+        crate::exec_async(vec!["switch", "rebase_feature"])
+            .await
+            .unwrap();
+        test::ensure_file("rebase_c.txt", Some("c\n"));
+        crate::exec_async(vec!["add", "rebase_c.txt"])
+            .await
+            .unwrap();
+        crate::exec_async(vec!["commit", "-m", "feature"])
+            .await
+            .unwrap();
+
+        execute(RebaseArgs::try_parse_from(["rebase", "master"]).unwrap()).await;
+        assert!(!path::rebase_merge().exists());
+        assert!(matches!(Head::current().await, Head::Branch(name) if name == "rebase_feature"));
+        let head = Commit::load(&Head::current_commit().await.unwrap());
+        assert_eq!(head.parent_commit_ids, vec![upstream]);
+        assert_eq!(parse_commit_msg(&head.message).0, "feature");
+        assert!(util::workdir_to_absolute("rebase_b.txt").exists());
+    }
+
+    #[tokio::test]
+    async fn test_squash_without_picked_commit() {
+        test::setup_with_new_libra().await;
+        test::ensure_file("squash_a.txt", Some("a\n"));
+        crate::exec_async(vec!["add", "squash_a.txt"])
+            .await
+            .unwrap();
+        crate::exec_async(vec!["commit", "-m", "base"])
+            .await
+            .unwrap();
+        let onto = Head::current_commit().await.unwrap();
+        test::ensure_file("squash_b.txt", Some("b\n"));
+        crate::exec_async(vec!["add", "squash_b.txt"])
+            .await
+            .unwrap();
+        crate::exec_async(vec!["commit", "-m", "feature"])
+            .await
+            .unwrap();
+        let feature = Head::current_commit().await.unwrap();
+
+        // `fixup` is the first command after the picked commit is dropped
+        let rebase_dir = path::rebase_merge();
+        fs::create_dir_all(&rebase_dir).unwrap();
+        fs::write(rebase_dir.join(HEAD_NAME), "master").unwrap();
+        fs::write(rebase_dir.join(ORIG_HEAD), feature.to_string()).unwrap();
+        fs::write(rebase_dir.join(ONTO), onto.to_string()).unwrap();
+        fs::write(rebase_dir.join(TODO), format!("fixup {}\n", feature)).unwrap();
+        fs::write(rebase_dir.join(DONE), "").unwrap();
+        switch::restore_to_commit(onto).await;
+        Head::update(Head::Detached(onto), None, "test").await;
+
+        run_todo().await;
+        assert_eq!(Head::current_commit().await.unwrap(), onto);
+        assert!(fs::read_to_string(rebase_dir.join(TODO))
+            .unwrap()
+            .starts_with("fixup"));
+        abort_rebase().await;
+        assert_eq!(Head::current_commit().await.unwrap(), feature);
+    }
+
+    #[tokio::test]
+    async fn test_parse_todo() {
+        test::setup_with_new_libra().await;
+        let commit = Commit::from_tree_id(SHA1::new(&[1; 20]), vec![], "test");
+        save_object(&commit, &commit.id).unwrap();
+        let hash = short_hash(&commit.id);
+
+        let todo = format!("pick {hash} test\n\n# comment\ns {hash}\n");
+        let items = parse_todo(&todo).unwrap();
+        assert_eq!(items.len(), 2);
+        assert_eq!(items[0].action, Action::Pick);
+        assert_eq!(items[1].action, Action::Squash);
+        assert_eq!(items[1].commit, commit.id);
+
+        assert!(parse_todo(&format!("fixup {hash}\n")).is_err());
+        assert!(parse_todo(&format!("edit {hash}\n")).is_err());
+        assert!(parse_todo("").unwrap().is_empty());
+    }
+}
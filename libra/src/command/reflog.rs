@@ -0,0 +1,196 @@
+use clap::{Parser, Subcommand};
+use colored::Colorize;
+
+use crate::internal::reflog::{Reflog, HEAD};
+use crate::utils::util;
+
+#[derive(Parser, Debug)]
+pub struct ReflogArgs {
+    /// `show` if not specified
+    #[command(subcommand)]
+    pub command: Option<ReflogCmds>,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum ReflogCmds {
+    /// Show the movements of the ref, the latest first
+    Show {
+        /// `HEAD`, branch or remote branch, `HEAD` by default
+        #[clap(value_name = "ref")]
+        ref_name: Option<String>,
+    },
+    /// Prune the reflog entries older than the date
+    Expire {
+        /// e.g. `now`, `90.days.ago` or `2024-01-01`, `all` to prune all entries, `never` to keep all
+        #[clap(long, value_name = "date", default_value = "90.days.ago")]
+        expire: String,
+        /// Process the reflog of all refs
+        #[clap(long)]
+        all: bool,
+        /// The refs to process
+        #[clap(value_name = "ref", required_unless_present("all"))]
+        refs: Vec<String>,
+    },
+    /// Delete single entries from the reflog
+    Delete {
+        /// e.g. `HEAD@{2}` or `main@{0}`
+        #[clap(required = true)]
+        entries: Vec<String>,
+    },
+}
+
+pub async fn execute(args: ReflogArgs) {
+    if !util::check_repo_exist() {
+        return;
+    }
+    match args.command.unwrap_or(ReflogCmds::Show { ref_name: None }) {
+        ReflogCmds::Show { ref_name } => show(ref_name.as_deref().unwrap_or(HEAD)).await,
+        ReflogCmds::Expire { expire, all, refs } => expire_reflog(&expire, all, refs).await,
+        ReflogCmds::Delete { entries } => delete(entries).await,
+    }
+}
+
+async fn show(name: &str) {
+    let ref_name = match Reflog::full_ref_name(name).await {
+        Ok(ref_name) => ref_name,
+        Err(e) => {
+            eprintln!("fatal: {}", e);
+            return;
+        }
+    };
+    for (n, entry) in Reflog::list(&ref_name).await.iter().enumerate() {
+        println!(
+            "{} {}@{{{}}}: {}",
+            entry.new.to_string()[..7].yellow(),
+            name,
+            n,
+            entry.message
+        );
+    }
+}
+
+async fn expire_reflog(expire: &str, all: bool, refs: Vec<String>) {
+    let timestamp = match expire {
+        "never" => return,
+        "all" => None,
+        date => match util::parse_date(date) {
+            Ok(timestamp) => Some(timestamp),
+            Err(e) => {
+                eprintln!("fatal: {}", e);
+                return;
+            }
+        },
+    };
+    let refs = if all {
+        Reflog::refs().await
+    } else {
+        let mut full_refs = Vec::new();
+        for name in refs {
+            match Reflog::full_ref_name(&name).await {
+                Ok(ref_name) => full_refs.push(ref_name),
+                Err(e) => {
+                    eprintln!("fatal: {}", e);
+                    return;
+                }
+            }
+        }
+        full_refs
+    };
+    for ref_name in refs {
+        Reflog::expire(&ref_name, timestamp).await;
+    }
+}
+
+async fn delete(entries: Vec<String>) {
+    // look up all entries before deleting, so that the numbers are not shifted
+    let mut to_delete = Vec::new();
+    for entry in entries {
+        let Some((name, n)) = parse_entry(&entry) else {
+            eprintln!("fatal: not a reflog entry: {}", entry);
+            return;
+        };
+        let ref_name = match Reflog::full_ref_name(name).await {
+            Ok(ref_name) => ref_name,
+            Err(e) => {
+                eprintln!("fatal: {}", e);
+                return;
+            }
+        };
+        match Reflog::find(&ref_name, n).await {
+            Some(reflog) => to_delete.push(reflog),
+            None => {
+                eprintln!("error: reflog entry {} does not exist", entry);
+                return;
+            }
+        }
+    }
+    for reflog in to_delete {
+        reflog.delete().await;
+    }
+}
+
+/// Parse `<ref>@{<n>}`
+fn parse_entry(entry: &str) -> Option<(&str, usize)> {
+    let (name, n) = entry.strip_suffix('}')?.split_once("@{")?;
+    Some((name, n.parse().ok()?))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::command::get_target_commit;
+    use crate::internal::head::Head;
+    use crate::utils::test;
+
+    use super::*;
+
+    #[test]
+    fn test_parse_entry() {
+        assert_eq!(parse_entry("HEAD@{2}"), Some(("HEAD", 2)));
+        assert_eq!(parse_entry("@{0}"), Some(("", 0)));
+        assert_eq!(parse_entry("main@{yesterday}"), None);
+        assert_eq!(parse_entry("main"), None);
+    }
+
+    #[tokio::test]
+    async fn test_reflog() {
+        test::setup_with_new_libra().await;
+        for (file, message) in [("reflog_a.txt", "first"), ("reflog_b.txt", "second")] {
+            test::ensure_file(file, Some(file));
+            crate::exec_async(vec!["add", file]).await.unwrap();
+            crate::exec_async(vec!["commit", "-m", message])
+                .await
+                .unwrap();
+        }
+        let second = Head::current_commit().await.unwrap();
+        crate::exec_async(vec!["reset", "--hard", "HEAD~1"])
+            .await
+            .unwrap();
+        let first = Head::current_commit().await.unwrap();
+
+        let head_log = Reflog::list(HEAD).await;
+        assert_eq!(head_log.len(), 3);
+        assert_eq!(head_log[0].message, "reset: moving to HEAD~1");
+        assert_eq!(head_log[1].message, "commit: second");
+        assert_eq!(head_log[2].message, "commit (initial): first");
+        assert_eq!(head_log[2].old, None);
+
+        // the lost commit is recoverable
+        assert_eq!(get_target_commit("HEAD@{1}").await.unwrap(), second);
+        assert_eq!(get_target_commit("HEAD@{1}~1").await.unwrap(), first);
+        assert_eq!(get_target_commit("@{0}").await.unwrap(), first);
+        assert_eq!(get_target_commit("HEAD@{now}").await.unwrap(), first);
+        assert!(get_target_commit("HEAD@{5}").await.is_err());
+
+        crate::exec_async(vec!["reflog", "delete", "HEAD@{0}", "HEAD@{1}"])
+            .await
+            .unwrap();
+        let head_log = Reflog::list(HEAD).await;
+        assert_eq!(head_log.len(), 1);
+        assert_eq!(head_log[0].new, first);
+
+        crate::exec_async(vec!["reflog", "expire", "--expire=all", "--all"])
+            .await
+            .unwrap();
+        assert!(Reflog::refs().await.is_empty());
+    }
+}
@@ -0,0 +1,273 @@
+use std::cmp::Reverse;
+use std::collections::HashSet;
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use clap::Parser;
+use indicatif::ProgressBar;
+use mercury::hash::SHA1;
+use mercury::internal::object::types::ObjectType;
+use mercury::internal::pack::encode::PackEncoder;
+use mercury::internal::pack::entry::Entry;
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+
+use crate::command::index_pack;
+use crate::utils::{path, util};
+
+/// Default window size of delta compression, same as git
+pub(crate) const DEFAULT_WINDOW: usize = 10;
+/// Number of objects or chunks buffered between the storage, encoder & file
+const CHANNEL_SIZE: usize = 64;
+
+/// The task feeding the encoder, see [`encode_stream`]
+pub(crate) type EncodeTask = JoinHandle<Result<(), String>>;
+
+#[derive(Parser, Debug)]
+pub struct RepackArgs {
+    /// Pack all objects into a single pack, including the objects in existing packs
+    #[clap(short = 'a', long)]
+    pub all: bool,
+
+    /// After packing, remove the redundant packs and loose objects
+    #[clap(short = 'd', long)]
+    pub delete: bool,
+
+    /// The number of objects considered as delta bases, 0 to disable delta compression
+    #[clap(long, default_value_t = DEFAULT_WINDOW)]
+    pub window: usize,
+}
+
+pub async fn execute(args: RepackArgs) {
+    if !util::check_repo_exist() {
+        return;
+    }
+    let storage = util::objects_storage();
+    let old_packs = storage.list_all_packs();
+    let loose = storage.list_objects_loose();
+    let mut objects: HashSet<SHA1> = loose.iter().copied().collect();
+    if args.all {
+        objects.extend(storage.list_objects_pack());
+    } else {
+        // the loose objects which are already packed are not packed again
+        objects.retain(|obj| !storage.exist_in_pack(obj));
+    }
+    let objects: Vec<SHA1> = objects.into_iter().collect();
+    let new_pack = if objects.is_empty() {
+        println!("Nothing new to pack.");
+        None
+    } else {
+        match write_pack(&objects, args.window).await {
+            Ok(pack) => {
+                println!("Packed {} objects into {}", objects.len(), pack.display());
+                Some(pack)
+            }
+            Err(e) => {
+                eprintln!("fatal: {}", e);
+                return;
+            }
+        }
+    };
+
+    if args.delete {
+        if args.all {
+            // the new pack may have the same name as an old one if nothing changed
+            let redundant: Vec<PathBuf> = old_packs
+                .into_iter()
+                .filter(|pack| Some(pack) != new_pack.as_ref())
+                .collect();
+            remove_packs(&redundant);
+        }
+        for obj in loose {
+            storage.remove_loose(&obj).unwrap();
+        }
+    }
+}
+
+/// Write the objects into a new pack (with .idx) in `objects/pack`, return the path of the pack.
+/// - objects are streamed from the storage through the encoder to a temporary file, which is renamed
+///   after the .idx is built, so readers never see a pack without index
+pub(crate) async fn write_pack(objects: &[SHA1], window: usize) -> Result<PathBuf, String> {
+    if objects.is_empty() {
+        return Err("no objects to pack".to_string());
+    }
+    let order = pack_order(objects.iter().copied())?
+        .into_iter()
+        .map(|(obj, _, _)| obj)
+        .collect();
+
+    let pack_dir = path::objects().join("pack");
+    fs::create_dir_all(&pack_dir).map_err(|e| e.to_string())?;
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_nanos();
+    let tmp_pack = pack_dir.join(format!("tmp_pack_{}_{}.tmp", std::process::id(), nanos));
+    let checksum = match encode_to_file(order, window, &tmp_pack).await {
+        Ok(checksum) => checksum,
+        Err(e) => {
+            let _ = fs::remove_file(&tmp_pack);
+            return Err(e);
+        }
+    };
+
+    let pack_file = pack_dir.join(format!("pack-{}.pack", checksum));
+    let tmp_idx = tmp_pack.with_extension("idx.tmp");
+    index_pack::build_index_v2(tmp_pack.to_str().unwrap(), tmp_idx.to_str().unwrap())
+        .map_err(|e| e.to_string())?;
+    fs::rename(&tmp_idx, pack_file.with_extension("idx")).map_err(|e| e.to_string())?;
+    fs::rename(&tmp_pack, &pack_file).map_err(|e| e.to_string())?;
+    Ok(pack_file)
+}
+
+/// Sort the objects by type & size (descending), so that similar objects are close in the delta window, like git.
+/// - return the objects with their types & sizes, the data is not kept
+pub(crate) fn pack_order(
+    objects: impl IntoIterator<Item = SHA1>,
+) -> Result<Vec<(SHA1, ObjectType, usize)>, String> {
+    let storage = util::objects_storage();
+    let mut order = Vec::new();
+    for obj in objects {
+        let (obj_type, size) = storage
+            .get_type_and_size(&obj)
+            .map_err(|e| format!("failed to read object {}: {}", obj, e))?;
+        order.push((obj, obj_type, size));
+    }
+    order.sort_by_key(|&(obj, obj_type, size)| (type_order(obj_type), Reverse(size), obj));
+    Ok(order)
+}
+
+/// Stream the objects (in order) from the storage through the encoder, only a few objects are in memory at once.
+/// - return the receiver of the pack data, and the task which fails if any object can't be read or encoded
+/// - `progress`: increased by the size of each object
+pub(crate) async fn encode_stream(
+    objects: Vec<SHA1>,
+    window: usize,
+    progress: Option<ProgressBar>,
+) -> Result<(mpsc::Receiver<Vec<u8>>, EncodeTask), String> {
+    let storage = util::objects_storage();
+    let (entry_tx, entry_rx) = mpsc::channel(CHANNEL_SIZE);
+    let (stream_tx, stream_rx) = mpsc::channel(CHANNEL_SIZE);
+    let encoder = PackEncoder::new(objects.len(), window, stream_tx);
+    let encoding = encoder
+        .encode_async(entry_rx)
+        .await
+        .map_err(|e| e.to_string())?;
+    let task = tokio::spawn(async move {
+        for obj in objects {
+            let (data, obj_type) = match storage.get_with_type(&obj) {
+                Ok(object) => object,
+                Err(e) => {
+                    encoding.abort(); // the pack data ends without checksum
+                    return Err(format!("failed to read object {}: {}", obj, e));
+                }
+            };
+            if let Some(progress) = &progress {
+                progress.inc(data.len() as u64);
+            }
+            let entry = Entry {
+                obj_type,
+                data,
+                hash: obj,
+            };
+            if entry_tx.send(entry).await.is_err() {
+                break; // the encoder failed, reported below
+            }
+        }
+        drop(entry_tx);
+        if let Some(progress) = &progress {
+            progress.finish();
+        }
+        encoding
+            .await
+            .map_err(|e| format!("failed to encode pack: {}", e))
+    });
+    Ok((stream_rx, task))
+}
+
+/// Encode the objects in order into `file`, return the checksum of the pack
+async fn encode_to_file(objects: Vec<SHA1>, window: usize, file: &Path) -> Result<SHA1, String> {
+    let mut file = fs::File::create(file).map_err(|e| e.to_string())?;
+    let (mut stream_rx, task) = encode_stream(objects, window, None).await?;
+    let mut trailer = Vec::new(); // the checksum is the last chunk
+    while let Some(chunk) = stream_rx.recv().await {
+        if let Err(e) = file.write_all(&chunk) {
+            task.abort();
+            return Err(e.to_string());
+        }
+        trailer = chunk;
+    }
+    file.flush().map_err(|e| e.to_string())?;
+    task.await.map_err(|e| e.to_string())??;
+    if trailer.len() != 20 {
+        return Err("failed to encode pack: no checksum".to_string());
+    }
+    Ok(SHA1::from_bytes(&trailer))
+}
+
+/// Remove the packs and their .idx files
+pub(crate) fn remove_packs(packs: &[PathBuf]) {
+    for pack in packs {
+        let _ = fs::remove_file(pack.with_extension("idx"));
+        fs::remove_file(pack).unwrap();
+    }
+}
+
+/// Commits first, then trees, blobs & tags
+fn type_order(obj_type: ObjectType) -> u8 {
+    match obj_type {
+        ObjectType::Commit => 0,
+        ObjectType::Tree => 1,
+        ObjectType::Blob => 2,
+        _ => 3,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::test;
+
+    #[tokio::test]
+    async fn test_repack() {
+        test::setup_with_new_libra().await;
+        test::ensure_file("repack_a.txt", Some(&"hello libra\n".repeat(100)));
+        crate::exec_async(vec!["add", "repack_a.txt"])
+            .await
+            .unwrap();
+        crate::exec_async(vec!["commit", "-m", "first"])
+            .await
+            .unwrap();
+        test::ensure_file("repack_a.txt", Some(&"hello libra\n".repeat(101)));
+        crate::exec_async(vec!["add", "repack_a.txt"])
+            .await
+            .unwrap();
+        crate::exec_async(vec!["commit", "-m", "second"])
+            .await
+            .unwrap();
+
+        let storage = util::objects_storage();
+        let loose = storage.list_objects_loose();
+        assert!(!loose.is_empty());
+        crate::exec_async(vec!["repack", "-d"]).await.unwrap();
+        assert!(storage.list_objects_loose().is_empty());
+        assert_eq!(storage.list_all_packs().len(), 1);
+        for obj in loose.iter() {
+            assert!(storage.exist_in_pack(obj));
+            assert!(storage.get(obj).is_ok());
+        }
+
+        // repack all the packs into one
+        test::ensure_file("repack_b.txt", Some("b\n"));
+        crate::exec_async(vec!["add", "repack_b.txt"])
+            .await
+            .unwrap();
+        crate::exec_async(vec!["repack", "-d"]).await.unwrap();
+        assert_eq!(storage.list_all_packs().len(), 2);
+        crate::exec_async(vec!["repack", "-a", "-d"]).await.unwrap();
+        assert_eq!(storage.list_all_packs().len(), 1);
+        assert_eq!(storage.list_objects_pack().len(), loose.len() + 1);
+    }
+}
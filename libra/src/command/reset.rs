@@ -0,0 +1,243 @@
+use std::fs;
+use std::path::PathBuf;
+
+use clap::Parser;
+use mercury::hash::SHA1;
+use mercury::internal::index::Index;
+use mercury::internal::object::commit::Commit;
+use mercury::internal::object::tree::Tree;
+
+use crate::internal::head::Head;
+use crate::utils::object_ext::{CommitExt, TreeExt};
+use crate::utils::{path, util};
+
+use super::{commit, get_target_commit, merge, restore, status, switch};
+
+#[derive(Parser, Debug)]
+pub struct ResetArgs {
+    /// The commit to reset to, `HEAD` by default
+    pub commit: Option<String>,
+
+    /// Only move HEAD (and the branch), keep the index and the working tree
+    #[clap(long, group = "mode")]
+    pub soft: bool,
+
+    /// Move HEAD and reset the index, keep the working tree (default)
+    #[clap(long, group = "mode")]
+    pub mixed: bool,
+
+    /// Move HEAD, reset the index and the working tree, all changes to tracked files are discarded
+    #[clap(long, group = "mode")]
+    pub hard: bool,
+
+    /// Reset the index entries of the paths to `commit`, HEAD is not moved
+    #[clap(last = true)]
+    pub pathspec: Vec<String>,
+}
+
+pub async fn execute(args: ResetArgs) {
+    if !util::check_repo_exist() {
+        return;
+    }
+    let mut pathspec = args.pathspec;
+    let mut commit = args.commit;
+    // `libra reset <path>` without `--`, like git
+    if let Some(arg) = commit.as_ref() {
+        if pathspec.is_empty()
+            && get_target_commit(arg).await.is_err()
+            && PathBuf::from(arg).exists()
+        {
+            pathspec.push(arg.clone());
+            commit = None;
+        }
+    }
+
+    if !pathspec.is_empty() {
+        if args.soft || args.hard {
+            let mode = if args.soft { "soft" } else { "hard" };
+            eprintln!("fatal: Cannot do {} reset with paths.", mode);
+            return;
+        }
+        reset_paths(commit, &pathspec).await;
+        return;
+    }
+
+    let target = match resolve_commit(commit.as_deref()).await {
+        Some(target) => target,
+        None => return,
+    };
+    let reason = format!("reset: moving to {}", commit.as_deref().unwrap_or("HEAD"));
+    if args.soft {
+        if path::merge_head().exists() {
+            eprintln!("fatal: Cannot do a soft reset in the middle of a merge.");
+            return;
+        }
+        commit::update_head(&target.to_string(), &reason).await;
+        return;
+    }
+
+    if args.hard {
+        // files only brought by an unfinished merge are removed
+        merge::clear_unmerged();
+        switch::restore_to_commit(target).await;
+    } else {
+        reset_index(&target).await;
+    }
+    commit::update_head(&target.to_string(), &reason).await;
+    // the conflicted merge (or cherry-pick / revert) is dropped
+    for file in [
+        path::merge_head(),
+        path::cherry_pick_head(),
+        path::revert_head(),
+    ] {
+        if file.exists() {
+            fs::remove_file(file).unwrap();
+        }
+    }
+
+    if args.hard {
+        let commit = Commit::load(&target);
+        println!(
+            "HEAD is now at {} {}",
+            &target.to_string()[..7],
+            commit.format_message()
+        );
+    } else {
+        print_unstaged_changes();
+    }
+}
+
+/// Resolve the commit to reset to, `HEAD` by default
+async fn resolve_commit(commit: Option<&str>) -> Option<SHA1> {
+    let commit = commit.unwrap_or("HEAD");
+    if commit == "HEAD" && Head::current_commit().await.is_none() {
+        eprintln!(
+            "fatal: ambiguous argument 'HEAD': unknown revision or path not in the working tree."
+        );
+        return None;
+    }
+    match get_target_commit(commit).await {
+        Ok(target) => Some(target),
+        Err(e) => {
+            eprintln!("fatal: {}", e);
+            None
+        }
+    }
+}
+
+/// Reset the index (stage 0, conflicts are dropped) to the tree of `commit`, worktree is not changed
+async fn reset_index(commit: &SHA1) {
+    let index_file = path::index();
+    let mut index = Index::load(&index_file).unwrap();
+    for file in index.unmerged_files() {
+        index.remove_unmerged(&file);
+    }
+    index.save(&index_file).unwrap();
+
+    let tree = Tree::load(&Commit::load(commit).tree_id);
+    restore::restore_index(&vec![util::working_dir()], &tree.get_plain_items()).await;
+}
+
+/// Reset the index entries of `pathspec` to `commit` (`HEAD` by default), like `restore --staged --source`
+/// - if there is no commit yet, the files are removed from the index
+async fn reset_paths(commit: Option<String>, pathspec: &[String]) {
+    let target_blobs = match commit {
+        None if Head::current_commit().await.is_none() => vec![],
+        commit => match resolve_commit(commit.as_deref()).await {
+            Some(target) => Tree::load(&Commit::load(&target).tree_id).get_plain_items(),
+            None => return,
+        },
+    };
+    let paths: Vec<PathBuf> = pathspec.iter().map(PathBuf::from).collect();
+    restore::restore_index(&paths, &target_blobs).await;
+    print_unstaged_changes();
+}
+
+fn print_unstaged_changes() {
+    let unstaged = status::changes_to_be_staged().to_relative();
+    if unstaged.modified.is_empty() && unstaged.deleted.is_empty() {
+        return;
+    }
+    println!("Unstaged changes after reset:");
+    for file in unstaged.modified.iter() {
+        println!("M\t{}", file.display());
+    }
+    for file in unstaged.deleted.iter() {
+        println!("D\t{}", file.display());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::test;
+
+    #[test]
+    fn test_parse_args() {
+        let args = ResetArgs::try_parse_from(["reset", "HEAD~1"]).unwrap();
+        assert_eq!(args.commit, Some("HEAD~1".to_string()));
+        assert!(!args.soft && !args.mixed && !args.hard);
+
+        let args = ResetArgs::try_parse_from(["reset", "--hard", "abc123"]).unwrap();
+        assert!(args.hard);
+
+        let args = ResetArgs::try_parse_from(["reset", "HEAD", "--", "a.txt", "b"]).unwrap();
+        assert_eq!(args.pathspec, vec!["a.txt", "b"]);
+
+        assert!(ResetArgs::try_parse_from(["reset", "--soft", "--hard"]).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_reset_modes() {
+        test::setup_with_new_libra().await;
+        test::ensure_file("reset_a.txt", Some("1"));
+        crate::exec_async(vec!["add", "reset_a.txt"]).await.unwrap();
+        crate::exec_async(vec!["commit", "-m", "first"])
+            .await
+            .unwrap();
+        let first = Head::current_commit().await.unwrap();
+        test::ensure_file("reset_a.txt", Some("2"));
+        crate::exec_async(vec!["add", "reset_a.txt"]).await.unwrap();
+        crate::exec_async(vec!["commit", "-m", "second"])
+            .await
+            .unwrap();
+        let second = Head::current_commit().await.unwrap();
+
+        // soft: only HEAD is moved, the change is staged
+        crate::exec_async(vec!["reset", "--soft", &first.to_string()])
+            .await
+            .unwrap();
+        assert_eq!(Head::current_commit().await.unwrap(), first);
+        assert_eq!(status::changes_to_be_committed().await.modified.len(), 1);
+
+        // mixed: the change is unstaged
+        crate::exec_async(vec!["reset", &first.to_string()])
+            .await
+            .unwrap();
+        assert!(status::changes_to_be_committed().await.is_empty());
+        assert_eq!(status::changes_to_be_staged().modified.len(), 1);
+
+        // hard: back to the second commit, then discard the change
+        crate::exec_async(vec!["reset", "--hard", &second.to_string()])
+            .await
+            .unwrap();
+        assert_eq!(Head::current_commit().await.unwrap(), second);
+        test::ensure_file("reset_a.txt", Some("3"));
+        crate::exec_async(vec!["reset", "--hard"]).await.unwrap();
+        assert_eq!(fs::read_to_string("reset_a.txt").unwrap(), "2");
+        assert!(!status::has_uncommitted_changes().await);
+    }
+
+    #[tokio::test]
+    async fn test_reset_paths() {
+        test::setup_with_new_libra().await;
+        test::ensure_file("reset_b.txt", Some("b"));
+        crate::exec_async(vec!["add", "reset_b.txt"]).await.unwrap();
+        // no commit yet, the file is unstaged
+        crate::exec_async(vec!["reset", "reset_b.txt"])
+            .await
+            .unwrap();
+        let index = Index::load(path::index()).unwrap();
+        assert!(!index.tracked("reset_b.txt", 0));
+    }
+}
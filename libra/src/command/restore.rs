@@ -3,6 +3,7 @@ use crate::internal::head::Head;
 use mercury::internal::index::{Index, IndexEntry};
 use crate::utils::object_ext::{BlobExt, CommitExt, TreeExt};
 use crate::utils::path_ext::PathExt;
+use crate::utils::sparse::Sparse;
 use crate::utils::{lfs, path, util};
 use clap::Parser;
 use std::collections::{HashMap, HashSet};
@@ -14,7 +15,7 @@ use mercury::internal::object::blob::Blob;
 use mercury::internal::object::commit::Commit;
 use mercury::internal::object::tree::Tree;
 use mercury::internal::object::types::ObjectType;
-use crate::command::calc_file_blob_hash;
+use crate::command::{calc_file_blob_hash, fetch};
 
 #[derive(Parser, Debug)]
 pub struct RestoreArgs {
@@ -122,7 +123,7 @@ pub async fn execute(args: RestoreArgs) {
         restore_worktree(&paths, &target_blobs).await;
     }
     if staged {
-        restore_index(&paths, &target_blobs);
+        restore_index(&paths, &target_blobs).await;
     }
 }
 
@@ -139,7 +140,7 @@ fn preprocess_blobs(blobs: &[(PathBuf, SHA1)]) -> HashMap<PathBuf, SHA1> {
 /// Restore a blob to file.
 /// If blob is an LFS pointer, download the actual file from LFS server.
 /// - `path` : to workdir
-async fn restore_to_file(hash: &SHA1, path: &PathBuf) -> io::Result<()> {
+pub async fn restore_to_file(hash: &SHA1, path: &PathBuf) -> io::Result<()> {
     let blob = Blob::load(hash);
     let path_abs = util::workdir_to_absolute(path);
     if let Some(parent) = path_abs.parent() {
@@ -217,13 +218,19 @@ pub async fn restore_worktree(filter: &Vec<PathBuf>, target_blobs: &[(PathBuf, S
     file_paths.extend(deleted_files);
 
     let index = Index::load(path::index()).unwrap();
+    let sparse = Sparse::load();
+    let mut restored = Vec::new();
     for path_wd in &file_paths {
         let path_abs = util::workdir_to_absolute(path_wd);
         if !path_abs.exists() {
             // file not exist, deleted or illegal
+            if sparse.as_ref().is_some_and(|sparse| !sparse.contains(path_wd)) {
+                // excluded by sparse checkout, only restored to index
+                continue;
+            }
             if target_blobs.contains_key(path_wd) {
                 // file in target_blobs (deleted), need to restore
-                restore_to_file(&target_blobs[path_wd], path_wd).await.unwrap();
+                restored.push(path_wd);
             } else {
                 // not in target_commit and workdir (illegal path), user input
                 unreachable!("It should be checked before");
@@ -236,7 +243,7 @@ pub async fn restore_worktree(filter: &Vec<PathBuf>, target_blobs: &[(PathBuf, S
                 // both in target & worktree: 1. modified 2. same
                 if hash != target_blobs[path_wd] {
                     // modified
-                    restore_to_file(&target_blobs[path_wd], path_wd).await.unwrap();
+                    restored.push(path_wd);
                 } // else: same, keep
             } else {
                 // not in target but in worktree: New file
@@ -248,6 +255,12 @@ pub async fn restore_worktree(filter: &Vec<PathBuf>, target_blobs: &[(PathBuf, S
             }
         }
     }
+
+    // partial clone: fetch the missing blobs at once
+    fetch::prefetch_blobs(restored.iter().map(|path_wd| target_blobs[*path_wd])).await;
+    for path_wd in restored {
+        restore_to_file(&target_blobs[path_wd], path_wd).await.unwrap();
+    }
 }
 
 /// Get the deleted files in the `index`(vs target_blobs), filtered by `filters`
@@ -268,7 +281,7 @@ fn get_index_deleted_files_in_filters(
         .collect() // HashSet auto deduplication
 }
 
-pub fn restore_index(filter: &Vec<PathBuf>, target_blobs: &[(PathBuf, SHA1)]) {
+pub async fn restore_index(filter: &Vec<PathBuf>, target_blobs: &[(PathBuf, SHA1)]) {
     let target_blobs = preprocess_blobs(target_blobs);
 
     let idx_file = path::index();
@@ -278,6 +291,13 @@ pub fn restore_index(filter: &Vec<PathBuf>, target_blobs: &[(PathBuf, SHA1)]) {
     let mut file_paths = util::filter_to_fit_paths(&index.tracked_files(), filter);
     file_paths.extend(deleted_files_index); // maybe we should not integrate them rater than deal separately
 
+    // partial clone: the blobs are read for the sizes, fetch the missing ones at once
+    let changed = file_paths
+        .iter()
+        .filter_map(|path| target_blobs.get(path).map(|hash| (path, hash)))
+        .filter(|(path, hash)| !index.verify_hash(&path.to_string_or_panic(), 0, hash));
+    fetch::prefetch_blobs(changed.map(|(_, hash)| *hash).collect::<Vec<_>>()).await;
+
     for path in &file_paths {
         // to workdir
         let path_str = path.to_string_or_panic();
@@ -318,5 +338,8 @@ pub fn restore_index(filter: &Vec<PathBuf>, target_blobs: &[(PathBuf, SHA1)]) {
             }
         }
     }
+    if let Some(sparse) = Sparse::load() {
+        sparse.mark_index(&mut index);
+    }
     index.save(&idx_file).unwrap(); // DO NOT forget to save
 }
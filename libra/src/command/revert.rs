@@ -0,0 +1,76 @@
+use clap::Parser;
+
+use crate::utils::util;
+
+use super::cherry_pick::{self, Operation};
+
+#[derive(Parser, Debug)]
+pub struct RevertArgs {
+    /// Commits to revert, reverted in the given order
+    #[clap(required_unless_present_any(["continue_", "abort"]))]
+    pub commits: Vec<String>,
+
+    /// Continue the operation after having resolved the conflicts
+    #[clap(long = "continue", group = "action", conflicts_with("commits"))]
+    pub continue_: bool,
+
+    /// Cancel the operation and return to the pre-sequence state
+    #[clap(long, group = "action", conflicts_with("commits"))]
+    pub abort: bool,
+}
+
+/// Record new commits reverting the changes of the given commits, see [cherry_pick] for the details
+pub async fn execute(args: RevertArgs) {
+    if !util::check_repo_exist() {
+        return;
+    }
+    if args.continue_ {
+        cherry_pick::continue_sequence(Operation::Revert).await;
+    } else if args.abort {
+        cherry_pick::abort_sequence(Operation::Revert).await;
+    } else {
+        cherry_pick::start_sequence(Operation::Revert, &args.commits, false).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use mercury::internal::object::commit::Commit;
+
+    use super::*;
+    use crate::internal::head::Head;
+    use crate::utils::object_ext::CommitExt;
+    use crate::utils::test;
+
+    #[tokio::test]
+    async fn test_revert() {
+        test::setup_with_new_libra().await;
+        test::ensure_file("revert_a.txt", Some("a\n"));
+        crate::exec_async(vec!["add", "revert_a.txt"])
+            .await
+            .unwrap();
+        crate::exec_async(vec!["commit", "-m", "base"])
+            .await
+            .unwrap();
+        test::ensure_file("revert_a.txt", Some("changed\n"));
+        test::ensure_file("revert_b.txt", Some("b\n"));
+        crate::exec_async(vec!["add", "revert_a.txt", "revert_b.txt"])
+            .await
+            .unwrap();
+        crate::exec_async(vec!["commit", "-m", "change"])
+            .await
+            .unwrap();
+        let change = Head::current_commit().await.unwrap();
+
+        crate::exec_async(vec!["revert", "HEAD"]).await.unwrap();
+        let head = Commit::load(&Head::current_commit().await.unwrap());
+        assert_eq!(head.parent_commit_ids, vec![change]);
+        assert_eq!(head.format_message(), "Revert \"change\"");
+        assert!(head
+            .message
+            .contains(&format!("This reverts commit {}.", change)));
+        assert!(!util::workdir_to_absolute("revert_b.txt").exists());
+        let content = std::fs::read_to_string(util::workdir_to_absolute("revert_a.txt")).unwrap();
+        assert_eq!(content, "a\n");
+    }
+}
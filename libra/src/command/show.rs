@@ -0,0 +1,168 @@
+use std::io::{self, Write};
+
+use clap::Parser;
+use colored::Colorize;
+use common::utils::parse_commit_msg;
+use mercury::hash::SHA1;
+use mercury::internal::object::blob::Blob;
+use mercury::internal::object::commit::Commit;
+use mercury::internal::object::tag::Tag;
+use mercury::internal::object::tree::{Tree, TreeItemMode};
+use mercury::internal::object::types::ObjectType;
+
+use crate::utils::object_ext::TreeExt;
+use crate::utils::util;
+
+use super::{diff, fetch, get_target_object, load_object};
+
+#[derive(Parser, Debug)]
+pub struct ShowArgs {
+    /// The object to show: commit, tag, tree or blob, HEAD by default
+    pub object: Option<String>,
+
+    /// Don't show the diff of the commit
+    #[clap(short = 's', long)]
+    pub no_patch: bool,
+}
+
+pub async fn execute(args: ShowArgs) {
+    if !util::check_repo_exist() {
+        return;
+    }
+    let name = args.object.unwrap_or("HEAD".to_owned());
+    let hash = match get_target_object(&name).await {
+        Ok(hash) => hash,
+        Err(e) => {
+            eprintln!("fatal: {}", e);
+            return;
+        }
+    };
+    let mut buf: Vec<u8> = Vec::new();
+    match show_object(&hash, &name, !args.no_patch, &mut buf).await {
+        Ok(_) => io::stdout().write_all(&buf).unwrap(),
+        Err(e) => eprintln!("fatal: {}", e),
+    }
+}
+
+/// Write the object to `w` according to its type, the object referred by a tag is shown after the tag
+async fn show_object(
+    hash: &SHA1,
+    name: &str,
+    patch: bool,
+    w: &mut dyn io::Write,
+) -> Result<(), String> {
+    fetch::prefetch_blobs([*hash]).await; // partial clone: the blob may be omitted
+    let object_type = util::objects_storage()
+        .get_object_type(hash)
+        .map_err(|_| format!("bad object {}", name))?;
+    match object_type {
+        ObjectType::Commit => {
+            let commit: Commit = load_object(hash).map_err(|e| e.to_string())?;
+            show_commit(&commit, patch, w).await;
+        }
+        ObjectType::Tag => {
+            let tag: Tag = load_object(hash).map_err(|e| e.to_string())?;
+            writeln!(w, "{} {}", "tag".yellow(), tag.tag_name).unwrap();
+            write!(w, "Tagger: {}", tag.tagger).unwrap();
+            writeln!(w, "\n{}\n", parse_commit_msg(&tag.message).0.trim_end()).unwrap();
+            let target = tag.object_hash.to_string();
+            Box::pin(show_object(&tag.object_hash, &target, patch, w)).await?;
+        }
+        ObjectType::Tree => {
+            let tree: Tree = load_object(hash).map_err(|e| e.to_string())?;
+            writeln!(w, "{} {}\n", "tree".yellow(), name).unwrap();
+            for item in tree.tree_items.iter() {
+                let suffix = if item.mode == TreeItemMode::Tree {
+                    "/"
+                } else {
+                    ""
+                };
+                writeln!(w, "{}{}", item.name, suffix).unwrap();
+            }
+        }
+        ObjectType::Blob => {
+            let blob: Blob = load_object(hash).map_err(|e| e.to_string())?;
+            w.write_all(&blob.data).unwrap();
+        }
+        _ => return Err(format!("bad object {}", name)),
+    }
+    Ok(())
+}
+
+/// Commit header & message like `log`, then the diff against the first parent
+async fn show_commit(commit: &Commit, patch: bool, w: &mut dyn io::Write) {
+    writeln!(
+        w,
+        "{} {}",
+        "commit".yellow(),
+        commit.id.to_string().yellow()
+    )
+    .unwrap();
+    if commit.parent_commit_ids.len() > 1 {
+        let parents: Vec<String> = commit
+            .parent_commit_ids
+            .iter()
+            .map(|parent| parent.to_string()[..7].to_owned())
+            .collect();
+        writeln!(w, "Merge: {}", parents.join(" ")).unwrap();
+    }
+    write!(w, "Author: {}", commit.author).unwrap();
+    let (message, _) = parse_commit_msg(&commit.message);
+    writeln!(w, "\n{}\n", message.trim_end()).unwrap();
+    if !patch {
+        return;
+    }
+
+    let old_blobs = match commit.parent_commit_ids.first() {
+        Some(parent) => {
+            let parent: Commit = load_object(parent).unwrap();
+            Tree::load(&parent.tree_id).get_plain_items()
+        }
+        None => Vec::new(),
+    };
+    let new_blobs = Tree::load(&commit.tree_id).get_plain_items();
+    diff::diff(old_blobs, new_blobs, vec![], &Default::default(), w).await;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::internal::head::Head;
+    use crate::utils::test;
+
+    #[tokio::test]
+    async fn test_show_object() {
+        test::setup_with_new_libra().await;
+        test::ensure_file("show_a.txt", Some("hello\n"));
+        crate::exec_async(vec!["add", "show_a.txt"]).await.unwrap();
+        crate::exec_async(vec!["commit", "-m", "add show_a"])
+            .await
+            .unwrap();
+        crate::exec_async(vec!["tag", "v1", "-m", "release v1"])
+            .await
+            .unwrap();
+        let head = Head::current_commit().await.unwrap();
+
+        let mut buf = Vec::new();
+        show_object(&head, "HEAD", true, &mut buf).await.unwrap();
+        let output = String::from_utf8(buf).unwrap();
+        assert!(output.contains(&head.to_string()));
+        assert!(output.contains("add show_a"));
+        assert!(output.contains("+hello"));
+
+        let tag = get_target_object("v1").await.unwrap();
+        let mut buf = Vec::new();
+        show_object(&tag, "v1", false, &mut buf).await.unwrap();
+        let output = String::from_utf8(buf).unwrap();
+        assert!(output.contains("release v1"));
+        assert!(output.contains(&head.to_string()));
+        assert!(!output.contains("+hello"));
+
+        let commit: Commit = load_object(&head).unwrap();
+        let mut buf = Vec::new();
+        show_object(&commit.tree_id, "HEAD^{tree}", false, &mut buf)
+            .await
+            .unwrap();
+        assert!(String::from_utf8(buf).unwrap().contains("show_a.txt"));
+    }
+}
@@ -0,0 +1,192 @@
+use std::fs;
+
+use clap::{Parser, Subcommand};
+use mercury::internal::index::{Index, IndexEntry};
+
+use crate::utils::sparse::Sparse;
+use crate::utils::{path, util};
+
+use super::{calc_file_blob_hash, restore};
+
+#[derive(Parser, Debug)]
+pub struct SparseCheckoutArgs {
+    #[command(subcommand)]
+    pub command: SparseCheckoutCmds,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum SparseCheckoutCmds {
+    /// Enable sparse checkout, only the files in the root directory are checked out at first
+    Init,
+    /// Check out only the given directories (relative to the root of the worktree) and the files in their parents
+    Set {
+        #[clap(required = true)]
+        dirs: Vec<String>,
+    },
+    /// Add the directories to the sparse checkout
+    Add {
+        #[clap(required = true)]
+        dirs: Vec<String>,
+    },
+    /// List the directories checked out
+    List,
+    /// Disable sparse checkout and check out all the files
+    Disable,
+}
+
+pub async fn execute(args: SparseCheckoutArgs) {
+    if !util::check_repo_exist() {
+        return;
+    }
+    match args.command {
+        SparseCheckoutCmds::Init => {
+            // keep the patterns if it's already enabled
+            apply(Some(Sparse::load().unwrap_or_default())).await;
+        }
+        SparseCheckoutCmds::Set { dirs } => match Sparse::new(&dirs) {
+            Some(sparse) => apply(Some(sparse)).await,
+            None => eprintln!("fatal: only directory names are allowed in cone mode"),
+        },
+        SparseCheckoutCmds::Add { dirs } => match Sparse::load() {
+            Some(mut sparse) => match sparse.add(&dirs) {
+                true => apply(Some(sparse)).await,
+                false => eprintln!("fatal: only directory names are allowed in cone mode"),
+            },
+            None => eprintln!("fatal: no sparse-checkout to add to"),
+        },
+        SparseCheckoutCmds::List => match Sparse::load() {
+            Some(sparse) => sparse.dirs().for_each(|dir| println!("{}", dir)),
+            None => eprintln!("fatal: this worktree is not sparse"),
+        },
+        SparseCheckoutCmds::Disable => apply(None).await,
+    }
+}
+
+/// Check out or remove the files to match the patterns, set `skip_worktree` flags, then save the patterns.
+/// - `None`: disable sparse checkout, all the files are checked out
+/// - files with local changes are kept in the worktree
+async fn apply(sparse: Option<Sparse>) {
+    let index_file = path::index();
+    let mut index = Index::load(&index_file).unwrap();
+    if !index.unmerged_files().is_empty() {
+        eprintln!("error: you need to resolve your current index first");
+        return;
+    }
+
+    let workdir = util::working_dir();
+    for file in index.tracked_files() {
+        let name = file.to_str().unwrap();
+        let hash = index.get_hash(name, 0).unwrap();
+        let file_abs = util::workdir_to_absolute(&file);
+        let included = match &sparse {
+            Some(sparse) => sparse.contains(&file),
+            None => true,
+        };
+        if included {
+            if !index.is_skip_worktree(name) {
+                continue;
+            }
+            if !file_abs.exists() {
+                restore::restore_to_file(&hash, &file).await.unwrap();
+                index.update(IndexEntry::new_from_file(&file, hash, &workdir).unwrap());
+            }
+            index.set_skip_worktree(name, false);
+        } else if !index.is_skip_worktree(name) {
+            if file_abs.exists() {
+                if calc_file_blob_hash(&file_abs).unwrap() != hash {
+                    eprintln!(
+                        "warning: not removing '{}' which has local changes",
+                        file.display()
+                    );
+                    continue;
+                }
+                fs::remove_file(&file_abs).unwrap();
+                util::clear_empty_dir(&file_abs);
+            }
+            index.set_skip_worktree(name, true);
+        }
+    }
+    index.save(&index_file).unwrap();
+
+    match sparse {
+        Some(sparse) => sparse.save(),
+        None => Sparse::remove(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::command::status;
+    use crate::utils::test;
+
+    #[test]
+    fn test_parse_args() {
+        assert!(SparseCheckoutArgs::try_parse_from(["sparse-checkout", "set", "a", "b/c"]).is_ok());
+        assert!(SparseCheckoutArgs::try_parse_from(["sparse-checkout", "add"]).is_err());
+        assert!(SparseCheckoutArgs::try_parse_from(["sparse-checkout", "disable"]).is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_sparse_checkout() {
+        test::setup_with_new_libra().await;
+        let _ = fs::remove_dir_all("sparse");
+        let files = [
+            "sparse/root.txt",
+            "sparse/a/file.txt",
+            "sparse/a/b/file.txt",
+            "sparse/c/file.txt",
+        ];
+        for file in files {
+            test::ensure_file(file, Some(file));
+        }
+        crate::exec_async(vec!["add", "sparse"]).await.unwrap();
+        crate::exec_async(vec!["commit", "-m", "sparse"])
+            .await
+            .unwrap();
+        let exists = |file: &str| util::workdir_to_absolute(file).exists();
+
+        crate::exec_async(vec!["sparse-checkout", "set", "sparse/a/b"])
+            .await
+            .unwrap();
+        assert!(exists("sparse/root.txt") && exists("sparse/a/file.txt"));
+        assert!(exists("sparse/a/b/file.txt"));
+        assert!(!exists("sparse/c"));
+        let index = Index::load(path::index()).unwrap();
+        assert!(index.is_skip_worktree("sparse/c/file.txt"));
+        assert!(!index.is_skip_worktree("sparse/a/b/file.txt"));
+        let unstaged = status::changes_to_be_staged();
+        assert!(unstaged.deleted.is_empty() && unstaged.modified.is_empty());
+
+        // `restore` doesn't check out the excluded files
+        crate::exec_async(vec!["restore", "--staged", "--worktree", "sparse"])
+            .await
+            .unwrap();
+        assert!(!exists("sparse/c/file.txt"));
+        let index = Index::load(path::index()).unwrap();
+        assert!(index.is_skip_worktree("sparse/c/file.txt"));
+
+        crate::exec_async(vec!["sparse-checkout", "add", "sparse/c"])
+            .await
+            .unwrap();
+        assert!(exists("sparse/c/file.txt"));
+        assert_eq!(
+            Sparse::load().unwrap().dirs().collect::<Vec<_>>(),
+            vec!["sparse/a/b", "sparse/c"]
+        );
+
+        crate::exec_async(vec!["sparse-checkout", "set", "sparse/c"])
+            .await
+            .unwrap();
+        assert!(!exists("sparse/a/b"));
+        crate::exec_async(vec!["sparse-checkout", "disable"])
+            .await
+            .unwrap();
+        assert!(Sparse::load().is_none());
+        for file in files {
+            assert!(exists(file));
+        }
+        let index = Index::load(path::index()).unwrap();
+        assert!(!index.is_skip_worktree("sparse/a/b/file.txt"));
+    }
+}
@@ -0,0 +1,547 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+
+use clap::{Parser, Subcommand};
+use common::utils::format_commit_msg;
+use mercury::hash::SHA1;
+use mercury::internal::index::{Index, IndexEntry};
+use mercury::internal::object::blob::Blob;
+use mercury::internal::object::commit::Commit;
+use mercury::internal::object::tree::Tree;
+
+use crate::internal::head::Head;
+use crate::internal::stash::Stash;
+use crate::utils::object_ext::{BlobExt, CommitExt, TreeExt};
+use crate::utils::{path, util};
+
+use super::{
+    add, commit, diff, fetch,
+    restore::{self, RestoreArgs},
+    save_object, status,
+};
+
+#[derive(Parser, Debug)]
+pub struct StashArgs {
+    /// `push` if not specified
+    #[command(subcommand)]
+    pub command: Option<StashCmds>,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum StashCmds {
+    /// Save local changes to a new stash entry and roll them back to HEAD
+    Push {
+        /// Also stash untracked files, then clean them up
+        #[clap(short = 'u', long)]
+        include_untracked: bool,
+        /// Description of the stash entry
+        #[clap(short, long)]
+        message: Option<String>,
+        /// Only stash the files matching the pathspec
+        pathspec: Vec<String>,
+    },
+    /// Apply a stash entry and remove it from the stash list
+    Pop {
+        /// `stash@{<n>}` or `<n>`, the latest entry by default
+        stash: Option<String>,
+    },
+    /// Apply a stash entry, but keep it in the stash list
+    Apply {
+        /// `stash@{<n>}` or `<n>`, the latest entry by default
+        stash: Option<String>,
+    },
+    /// List the stash entries
+    List,
+    /// Show the changes recorded in a stash entry
+    Show {
+        /// Show the changes as patch
+        #[clap(short, long)]
+        patch: bool,
+        /// `stash@{<n>}` or `<n>`, the latest entry by default
+        stash: Option<String>,
+    },
+    /// Remove a stash entry from the stash list
+    Drop {
+        /// `stash@{<n>}` or `<n>`, the latest entry by default
+        stash: Option<String>,
+    },
+}
+
+pub async fn execute(args: StashArgs) {
+    if !util::check_repo_exist() {
+        return;
+    }
+    let command = args.command.unwrap_or(StashCmds::Push {
+        include_untracked: false,
+        message: None,
+        pathspec: vec![],
+    });
+    match command {
+        StashCmds::Push {
+            include_untracked,
+            message,
+            pathspec,
+        } => {
+            push(include_untracked, message, pathspec).await;
+        }
+        StashCmds::Pop { stash } => {
+            if let Some((n, stash)) = find_stash(stash).await {
+                if apply(&stash).await {
+                    drop_stash(n, &stash).await;
+                } else {
+                    println!("The stash entry is kept in case you need it again.");
+                }
+            }
+        }
+        StashCmds::Apply { stash } => {
+            if let Some((_, stash)) = find_stash(stash).await {
+                apply(&stash).await;
+            }
+        }
+        StashCmds::List => {
+            for (n, stash) in Stash::list().await.iter().enumerate() {
+                println!(
+                    "stash@{{{}}}: {}",
+                    n,
+                    Commit::load(&stash.commit).format_message()
+                );
+            }
+        }
+        StashCmds::Show { patch, stash } => {
+            if let Some((_, stash)) = find_stash(stash).await {
+                show(&stash, patch).await;
+            }
+        }
+        StashCmds::Drop { stash } => {
+            if let Some((n, stash)) = find_stash(stash).await {
+                drop_stash(n, &stash).await;
+            }
+        }
+    }
+}
+
+/// Parse `stash@{<n>}` or `<n>`
+fn parse_stash_ref(stash: &str) -> Option<usize> {
+    let n = match stash.strip_prefix("stash@{") {
+        Some(rest) => rest.strip_suffix('}')?,
+        None => stash,
+    };
+    n.parse().ok()
+}
+
+/// Find the stash entry by user input, print error if not found
+async fn find_stash(stash: Option<String>) -> Option<(usize, Stash)> {
+    let stash = stash.unwrap_or("stash@{0}".to_string());
+    let n = match parse_stash_ref(&stash) {
+        Some(n) => n,
+        None => {
+            eprintln!("error: '{}' is not a stash-like commit", stash);
+            return None;
+        }
+    };
+    match Stash::find(n).await {
+        Some(entry) => Some((n, entry)),
+        None if n == 0 => {
+            eprintln!("No stash entries found.");
+            None
+        }
+        None => {
+            eprintln!("error: stash@{{{}}} is not a valid reference", n);
+            None
+        }
+    }
+}
+
+/// Save changes as commits, like git:
+/// - `I`: index, parent is HEAD
+/// - `U`: untracked files (optional), no parent
+/// - `W`: worktree, parents are HEAD, `I` (and `U`), which is the stash entry
+///
+/// Return `true` if a stash entry is created
+async fn push(include_untracked: bool, message: Option<String>, pathspec: Vec<String>) -> bool {
+    let head_commit = match Head::current_commit().await {
+        Some(commit) => Commit::load(&commit),
+        None => {
+            eprintln!("fatal: You do not have the initial commit yet");
+            return false;
+        }
+    };
+    let index = Index::load(path::index()).unwrap();
+    if !index.unmerged_files().is_empty() {
+        eprintln!("error: could not save stash, you have unmerged files");
+        return false;
+    }
+
+    let pathspec = if pathspec.is_empty() {
+        vec![util::working_dir_string()]
+    } else {
+        pathspec
+    };
+    // abs or relative to current dir
+    let filters: Vec<PathBuf> = pathspec.iter().map(PathBuf::from).collect();
+    let in_filters =
+        |file: &PathBuf| util::is_sub_of_paths(util::workdir_to_absolute(file), &filters);
+
+    let staged = status::changes_to_be_committed().await;
+    let unstaged = status::changes_to_be_staged();
+    let untracked: Vec<PathBuf> = if include_untracked {
+        unstaged
+            .new
+            .iter()
+            .filter(|f| in_filters(f))
+            .cloned()
+            .collect()
+    } else {
+        vec![]
+    };
+    let has_changes = [&staged.new, &staged.modified, &staged.deleted]
+        .into_iter()
+        .chain([&unstaged.modified, &unstaged.deleted])
+        .flatten()
+        .any(in_filters);
+    if !has_changes && untracked.is_empty() {
+        println!("No local changes to save");
+        return false;
+    }
+
+    let (index_tree, worktree_tree) = build_trees(&head_commit.tree_id, &in_filters).await;
+    let head_name = match Head::current().await {
+        Head::Branch(name) => name,
+        Head::Detached(_) => "(no branch)".to_string(),
+    };
+    let head_desc = format!(
+        "{}: {} {}",
+        head_name,
+        &head_commit.id.to_string()[..7],
+        head_commit.format_message()
+    );
+
+    let index_commit = Commit::from_tree_id(
+        index_tree,
+        vec![head_commit.id],
+        &format_commit_msg(&format!("index on {}", head_desc), None),
+    );
+    save_object(&index_commit, &index_commit.id).unwrap();
+    let mut parents = vec![head_commit.id, index_commit.id];
+    if !untracked.is_empty() {
+        let tree = build_untracked_tree(&untracked).await;
+        let untracked_commit = Commit::from_tree_id(
+            tree,
+            vec![],
+            &format_commit_msg(&format!("untracked files on {}", head_desc), None),
+        );
+        save_object(&untracked_commit, &untracked_commit.id).unwrap();
+        parents.push(untracked_commit.id);
+    }
+    let message = match message {
+        Some(msg) => format!("On {}: {}", head_name, msg),
+        None => format!("WIP on {}", head_desc),
+    };
+    let stash_commit =
+        Commit::from_tree_id(worktree_tree, parents, &format_commit_msg(&message, None));
+    save_object(&stash_commit, &stash_commit.id).unwrap();
+    Stash::push(&stash_commit.id).await;
+
+    // roll back the stashed files to HEAD
+    restore::execute(RestoreArgs {
+        worktree: true,
+        staged: true,
+        source: None,
+        pathspec,
+    })
+    .await;
+    for file in untracked {
+        let file_abs = util::workdir_to_absolute(&file);
+        fs::remove_file(&file_abs).unwrap();
+        util::clear_empty_dir(&file_abs);
+    }
+    println!("Saved working directory and index state {}", message);
+    true
+}
+
+/// Build trees of the index & worktree, files out of the pathspec are kept as HEAD.
+/// - return (index tree, worktree tree)
+async fn build_trees(head_tree: &SHA1, in_filters: &impl Fn(&PathBuf) -> bool) -> (SHA1, SHA1) {
+    let storage = util::objects_storage();
+    let workdir = util::working_dir();
+    let head_items: HashMap<PathBuf, SHA1> = Tree::load(head_tree)
+        .get_plain_items()
+        .into_iter()
+        .collect();
+
+    let mut index = Index::load(path::index()).unwrap();
+    for file in index.tracked_files() {
+        if !in_filters(&file) && !head_items.contains_key(&file) {
+            index.remove(file.to_str().unwrap(), 0);
+        }
+    }
+    let kept = head_items.iter().filter(|(file, hash)| {
+        !in_filters(file) && !index.verify_hash(file.to_str().unwrap(), 0, hash)
+    });
+    fetch::prefetch_blobs(kept.map(|(_, hash)| *hash)).await; // partial clone: for the sizes
+    for (file, hash) in head_items.iter() {
+        let name = file.to_str().unwrap();
+        if !in_filters(file) && !index.verify_hash(name, 0, hash) {
+            let size = Blob::load(hash).data.len() as u32;
+            index.update(IndexEntry::new_from_blob(name.to_string(), *hash, size));
+        }
+    }
+    let index_tree = commit::create_tree(&index, &storage, "".into()).await;
+
+    // changes of tracked files in worktree
+    for file in index.tracked_files() {
+        let name = file.to_str().unwrap();
+        let file_abs = util::workdir_to_absolute(&file);
+        if !in_filters(&file) {
+            continue;
+        }
+        if !file_abs.exists() {
+            index.remove(name, 0);
+        } else if index.is_modified(name, 0, &workdir) {
+            let blob = add::gen_blob_from_file(&file_abs);
+            if !index.verify_hash(name, 0, &blob.id) {
+                blob.save();
+                index.update(IndexEntry::new_from_file(&file, blob.id, &workdir).unwrap());
+            }
+        }
+    }
+    let worktree_tree = commit::create_tree(&index, &storage, "".into()).await;
+    (index_tree.id, worktree_tree.id)
+}
+
+/// Build a tree of the untracked files (to workdir)
+async fn build_untracked_tree(files: &[PathBuf]) -> SHA1 {
+    let workdir = util::working_dir();
+    let mut index = Index::new();
+    for file in files {
+        let blob = add::gen_blob_from_file(util::workdir_to_absolute(file));
+        blob.save();
+        index.add(IndexEntry::new_from_file(file, blob.id, &workdir).unwrap());
+    }
+    commit::create_tree(&index, &util::objects_storage(), "".into())
+        .await
+        .id
+}
+
+/// Changed files between two trees: (file, old, new)
+fn diff_trees(old: &SHA1, new: &SHA1) -> Vec<(PathBuf, Option<SHA1>, Option<SHA1>)> {
+    let old_items: HashMap<PathBuf, SHA1> = Tree::load(old).get_plain_items().into_iter().collect();
+    let new_items: HashMap<PathBuf, SHA1> = Tree::load(new).get_plain_items().into_iter().collect();
+    let mut files: Vec<&PathBuf> = old_items.keys().chain(new_items.keys()).collect();
+    files.sort();
+    files.dedup();
+    files
+        .into_iter()
+        .map(|file| {
+            (
+                file.clone(),
+                old_items.get(file).cloned(),
+                new_items.get(file).cloned(),
+            )
+        })
+        .filter(|(_, old, new)| old != new)
+        .collect()
+}
+
+/// Apply the stash onto HEAD by three-way merge, changes of tracked files are not staged (except new files).
+/// - return `false` if failed or conflicts occurred
+async fn apply(stash: &Stash) -> bool {
+    let stash_commit = Commit::load(&stash.commit);
+    let base_tree = Commit::load(&stash_commit.parent_commit_ids[0]).tree_id;
+    let head_tree = match Head::current_commit().await {
+        Some(commit) => Commit::load(&commit).tree_id,
+        None => {
+            eprintln!("fatal: You do not have the initial commit yet");
+            return false;
+        }
+    };
+    if !Index::load(path::index())
+        .unwrap()
+        .unmerged_files()
+        .is_empty()
+    {
+        eprintln!("error: could not apply stash, you have unmerged files");
+        return false;
+    }
+
+    let untracked_items = match stash_commit.parent_commit_ids.get(2) {
+        Some(commit) => Tree::load(&Commit::load(commit).tree_id).get_plain_items(),
+        None => vec![],
+    };
+    let existing: Vec<&PathBuf> = untracked_items
+        .iter()
+        .map(|(file, _)| file)
+        .filter(|file| util::workdir_to_absolute(file).exists())
+        .collect();
+    if !existing.is_empty() {
+        for file in existing {
+            eprintln!("{} already exists, no checkout", file.display());
+        }
+        eprintln!("error: could not restore untracked files from stash");
+        return false;
+    }
+
+    // `merge_trees` overwrites the files changed by the stash
+    let stashed_files: Vec<PathBuf> = diff_trees(&base_tree, &stash_commit.tree_id)
+        .into_iter()
+        .map(|(file, _, _)| file)
+        .collect();
+    let staged = status::changes_to_be_committed().await;
+    let unstaged = status::changes_to_be_staged();
+    let dirty: Vec<&PathBuf> = [&staged.new, &staged.modified, &staged.deleted]
+        .into_iter()
+        .chain([&unstaged.modified, &unstaged.deleted])
+        .flatten()
+        .filter(|file| stashed_files.contains(file))
+        .collect();
+    if !dirty.is_empty() {
+        eprintln!(
+            "error: Your local changes to the following files would be overwritten by merge:"
+        );
+        for file in dirty {
+            eprintln!("\t{}", file.display());
+        }
+        eprintln!("Please commit your changes or stash them before you merge.");
+        eprintln!("Aborting");
+        return false;
+    }
+
+    let conflicts = match crate::command::merge::merge_trees(
+        Some(&base_tree),
+        &head_tree,
+        &stash_commit.tree_id,
+        "Updated upstream",
+        "Stashed changes",
+    )
+    .await
+    {
+        Ok(conflicts) => conflicts,
+        Err(e) => {
+            eprintln!("{}", e);
+            return false;
+        }
+    };
+
+    // unstage the changes of files which exist in HEAD
+    let head_items = Tree::load(&head_tree).get_plain_items();
+    let unstage: Vec<PathBuf> = stashed_files
+        .iter()
+        .filter(|file| !conflicts.contains(file) && head_items.iter().any(|(f, _)| f == *file))
+        .map(util::workdir_to_absolute)
+        .collect();
+    if !unstage.is_empty() {
+        restore::restore_index(&unstage, &head_items).await;
+    }
+    for (file, hash) in untracked_items.iter() {
+        restore::restore_to_file(hash, file).await.unwrap();
+    }
+
+    for file in conflicts.iter() {
+        println!("CONFLICT (content): Merge conflict in {}", file.display());
+    }
+    status::execute().await;
+    conflicts.is_empty()
+}
+
+/// Stash local changes of tracked files before `pull`, return the entry if there are any
+pub(crate) async fn autostash() -> Option<Stash> {
+    if !status::has_uncommitted_changes().await {
+        return None;
+    }
+    match push(false, Some("autostash".to_string()), vec![]).await {
+        true => Stash::find(0).await,
+        false => None,
+    }
+}
+
+/// Reapply & drop the entry created by [autostash], keep it if it can't be applied cleanly
+pub(crate) async fn pop_autostash(stash: &Stash) {
+    if apply(stash).await {
+        stash.delete().await;
+        println!("Applied autostash.");
+    } else {
+        println!("Applying autostash resulted in conflicts.");
+        println!("Your changes are safe in the stash.");
+        println!("You can run \"libra stash pop\" or \"libra stash drop\" at any time.");
+    }
+}
+
+async fn show(stash: &Stash, patch: bool) {
+    let stash_commit = Commit::load(&stash.commit);
+    let base_tree = Commit::load(&stash_commit.parent_commit_ids[0]).tree_id;
+    if patch {
+        let old_blobs = Tree::load(&base_tree).get_plain_items();
+        let new_blobs = Tree::load(&stash_commit.tree_id).get_plain_items();
+        diff::diff(old_blobs, new_blobs, vec![], &Default::default(), &mut io::stdout()).await;
+        return;
+    }
+    for (file, old, new) in diff_trees(&base_tree, &stash_commit.tree_id) {
+        let status = match (old, new) {
+            (None, _) => "A",
+            (_, None) => "D",
+            _ => "M",
+        };
+        println!("{}\t{}", status, file.display());
+    }
+}
+
+async fn drop_stash(n: usize, stash: &Stash) {
+    stash.delete().await;
+    println!("Dropped stash@{{{}}} ({})", n, stash.commit);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::test;
+
+    #[test]
+    fn test_parse_args() {
+        let args = StashArgs::try_parse_from(["stash"]).unwrap();
+        assert!(args.command.is_none());
+        let args = StashArgs::try_parse_from(["stash", "push", "-u", "-m", "wip", "src"]).unwrap();
+        assert!(matches!(
+            args.command,
+            Some(StashCmds::Push { include_untracked: true, message: Some(_), ref pathspec }) if pathspec.len() == 1
+        ));
+        assert!(StashArgs::try_parse_from(["stash", "pop", "stash@{1}"]).is_ok());
+        assert!(StashArgs::try_parse_from(["stash", "show", "-p"]).is_ok());
+    }
+
+    #[test]
+    fn test_parse_stash_ref() {
+        assert_eq!(parse_stash_ref("stash@{0}"), Some(0));
+        assert_eq!(parse_stash_ref("stash@{12}"), Some(12));
+        assert_eq!(parse_stash_ref("3"), Some(3));
+        assert_eq!(parse_stash_ref("stash@{x}"), None);
+        assert_eq!(parse_stash_ref("stash@{1"), None);
+    }
+
+    #[tokio::test]
+    async fn test_stash_push_pop() {
+        test::setup_with_new_libra().await;
+        test::ensure_file("stash_a.txt", Some("a\n"));
+        crate::exec_async(vec!["add", "stash_a.txt"]).await.unwrap();
+        crate::exec_async(vec!["commit", "-m", "init"])
+            .await
+            .unwrap();
+
+        test::ensure_file("stash_a.txt", Some("changed\n"));
+        test::ensure_file("stash_new.txt", Some("new\n"));
+        crate::exec_async(vec!["stash", "push", "-u"])
+            .await
+            .unwrap();
+        assert_eq!(fs::read_to_string("stash_a.txt").unwrap(), "a\n");
+        assert!(!PathBuf::from("stash_new.txt").exists());
+        assert_eq!(Stash::list().await.len(), 1);
+
+        crate::exec_async(vec!["stash", "pop"]).await.unwrap();
+        assert_eq!(fs::read_to_string("stash_a.txt").unwrap(), "changed\n");
+        assert_eq!(fs::read_to_string("stash_new.txt").unwrap(), "new\n");
+        assert!(Stash::list().await.is_empty());
+        // changes are not staged after pop
+        assert!(status::changes_to_be_committed().await.is_empty());
+    }
+}
@@ -1,16 +1,18 @@
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
 use std::path::PathBuf;
 
 use colored::Colorize;
 
+use mercury::hash::SHA1;
+use mercury::internal::object::blob::Blob;
 use mercury::internal::object::commit::Commit;
 use mercury::internal::object::tree::Tree;
 
 use crate::internal::head::Head;
 use mercury::internal::index::Index;
-use crate::command::calc_file_blob_hash;
+use crate::command::{calc_file_blob_hash, cherry_pick, fetch, load_object};
 use crate::utils::object_ext::{CommitExt, TreeExt};
-use crate::utils::{path, util};
+use crate::utils::{path, rename, util};
 
 /// path: to workdir
 #[derive(Debug, Default, Clone)]
@@ -45,7 +47,6 @@ pub async fn execute() {
     if !util::check_repo_exist() {
         return;
     }
-    // TODO .gitignore
     match Head::current().await {
         Head::Detached(commit) => {
             println!("HEAD detached at {}", String::from_utf8_lossy(&commit.0[0..7]));
@@ -55,19 +56,72 @@ pub async fn execute() {
         }
     }
 
+    let rebasing = path::rebase_merge().exists();
+    if rebasing {
+        println!("You are currently rebasing.");
+        println!("  (use \"libra rebase --abort\" to check out the original branch)");
+    }
+    let sequencing = cherry_pick::in_progress().map(|op| op.name());
+    if let Some(op) = sequencing {
+        println!("You are currently in a {}.", op);
+        println!(
+            "  (use \"libra {} --abort\" to cancel the {} operation)",
+            op, op
+        );
+    }
+
     if Head::current_commit().await.is_none() {
         println!("\nNo commits yet\n");
     }
 
+    let mut staged = changes_to_be_committed().await;
+    let renames = staged_renames(&staged).await;
+    // renamed files are not shown as deleted & new
+    let renamed: HashSet<&PathBuf> = renames.iter().flat_map(|r| [&r.from, &r.to]).collect();
+    staged.deleted.retain(|f| !renamed.contains(f));
+    staged.new.retain(|f| !renamed.contains(f));
     // to cur_dir relative path
-    let staged = changes_to_be_committed().await.to_relative();
+    let staged = staged.to_relative();
+    let renames: Vec<(PathBuf, PathBuf)> = renames
+        .iter()
+        .map(|r| {
+            (
+                util::workdir_to_current(&r.from),
+                util::workdir_to_current(&r.to),
+            )
+        })
+        .collect();
     let unstaged = changes_to_be_staged().to_relative();
-    if staged.is_empty() && unstaged.is_empty() {
+    let index = Index::load(path::index()).unwrap();
+    let unmerged: Vec<(PathBuf, &str)> = index
+        .unmerged_files()
+        .iter()
+        .map(|f| (util::workdir_to_current(f), conflict_type(&index, f)))
+        .collect();
+    if staged.is_empty() && renames.is_empty() && unstaged.is_empty() && unmerged.is_empty() {
         println!("nothing to commit, working tree clean");
         return;
     }
 
-    if !staged.is_empty() {
+    if !unmerged.is_empty() {
+        println!("You have unmerged paths.");
+        if rebasing {
+            println!("  (fix conflicts and then run \"libra rebase --continue\")");
+        } else if let Some(op) = sequencing {
+            println!("  (fix conflicts and run \"libra {} --continue\")", op);
+        } else {
+            println!("  (fix conflicts and run \"libra commit\")");
+            println!("  (use \"libra merge --abort\" to abort the merge)");
+        }
+        println!("Unmerged paths:");
+        println!("  use \"libra add <file>...\" to mark resolution");
+        unmerged.iter().for_each(|(f, conflict)| {
+            let str = format!("\t{}: {}", conflict, f.display());
+            println!("{}", str.bright_red());
+        });
+    }
+
+    if !staged.is_empty() || !renames.is_empty() {
         println!("Changes to be committed:");
         println!("  use \"libra restore --staged <file>...\" to unstage");
         staged.deleted.iter().for_each(|f| {
@@ -78,6 +132,10 @@ pub async fn execute() {
             let str = format!("\tmodified: {}", f.display());
             println!("{}", str.bright_green());
         });
+        renames.iter().for_each(|(from, to)| {
+            let str = format!("\trenamed: {} -> {}", from.display(), to.display());
+            println!("{}", str.bright_green());
+        });
         staged.new.iter().for_each(|f| {
             let str = format!("\tnew file: {}", f.display());
             println!("{}", str.bright_green());
@@ -114,6 +172,13 @@ pub async fn is_clean() -> bool {
     staged.is_empty() && unstaged.is_empty()
 }
 
+/// Check if there are changes to tracked files (staged or not), untracked files are ignored
+pub async fn has_uncommitted_changes() -> bool {
+    let staged = changes_to_be_committed().await;
+    let unstaged = changes_to_be_staged();
+    !staged.is_empty() || !unstaged.modified.is_empty() || !unstaged.deleted.is_empty()
+}
+
 /**
  * Compare the difference between `index` and the last `Commit Tree`
  */
@@ -133,8 +198,12 @@ pub async fn changes_to_be_committed() -> Changes {
     let tree = Tree::load(&commit.tree_id);
     let tree_files = tree.get_plain_items();
 
+    let unmerged_files = index.unmerged_files();
     for (item_path, item_hash) in tree_files.iter() {
         let item_str = item_path.to_str().unwrap();
+        if unmerged_files.iter().any(|f| f == item_str) {
+            continue; // conflicts are reported separately
+        }
         if index.tracked(item_str, 0) {
             if !index.verify_hash(item_str, 0, item_hash) {
                 changes.modified.push(item_path.clone());
@@ -153,6 +222,38 @@ pub async fn changes_to_be_committed() -> Changes {
     changes
 }
 
+/// Detect the renames from the deleted files to the new files in the changes to be committed
+pub async fn staged_renames(staged: &Changes) -> Vec<rename::Rename> {
+    if staged.deleted.is_empty() || staged.new.is_empty() {
+        return Vec::new();
+    }
+    let index = Index::load(path::index()).unwrap();
+    // there must be a commit if some files are deleted
+    let commit = Commit::load(&Head::current_commit().await.unwrap());
+    let deleted: HashSet<&PathBuf> = staged.deleted.iter().collect();
+    let old: HashMap<PathBuf, SHA1> = Tree::load(&commit.tree_id)
+        .get_plain_items()
+        .into_iter()
+        .filter(|(path, _)| deleted.contains(path))
+        .collect();
+    let new: HashMap<PathBuf, SHA1> = staged
+        .new
+        .iter()
+        .map(|path| {
+            (
+                path.clone(),
+                index.get_hash(path.to_str().unwrap(), 0).unwrap(),
+            )
+        })
+        .collect();
+    fetch::prefetch_blobs(old.values().chain(new.values()).copied()).await; // partial clone
+    rename::detect(&old, &new, rename::Options::default(), |_, hash| {
+        load_object::<Blob>(hash)
+            .map(|blob| blob.data)
+            .unwrap_or_default()
+    })
+}
+
 /// Compare the difference between `index` and the `workdir`
 pub fn changes_to_be_staged() -> Changes {
     let mut changes = Changes::default();
@@ -161,6 +262,9 @@ pub fn changes_to_be_staged() -> Changes {
     let tracked_files = index.tracked_files();
     for file in tracked_files.iter() {
         let file_str = file.to_str().unwrap();
+        if index.is_skip_worktree(file_str) {
+            continue; // not checked out (sparse checkout)
+        }
         let file_abs = util::workdir_to_absolute(file);
         if !file_abs.exists() {
             changes.deleted.push(file.clone());
@@ -172,12 +276,89 @@ pub fn changes_to_be_staged() -> Changes {
             }
         }
     }
+    let unmerged_files = index.unmerged_files();
     let files = util::list_workdir_files().unwrap(); // to workdir
     for file in files.iter() {
-        if !index.tracked(file.to_str().unwrap(), 0) {
+        let file_str = file.to_str().unwrap();
+        if unmerged_files.iter().any(|f| f == file_str) {
+            continue; // conflicts are reported separately
+        }
+        if !index.tracked(file_str, 0) {
             // file not tracked in `index`
             changes.new.push(file.clone());
         }
     }
     changes
-}
\ No newline at end of file
+}
+
+/// Describe the conflict of the unmerged file by its stages: 1 (base), 2 (ours), 3 (theirs)
+fn conflict_type(index: &Index, file: &str) -> &'static str {
+    let stages = [1, 2, 3].map(|stage| index.get(file, stage).is_some());
+    match stages {
+        [true, true, true] => "both modified",
+        [false, true, true] => "both added",
+        [true, true, false] => "deleted by them",
+        [true, false, true] => "deleted by us",
+        [false, true, false] => "added by us",
+        [false, false, true] => "added by them",
+        _ => "both deleted",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use mercury::internal::index::IndexEntry;
+
+    use super::*;
+    use crate::utils::test;
+
+    #[test]
+    fn test_conflict_type() {
+        let mut index = Index::new();
+        for (name, stages) in [
+            ("m", vec![1, 2, 3]),
+            ("us", vec![1, 3]),
+            ("them", vec![1, 2]),
+        ] {
+            for stage in stages {
+                let mut entry = IndexEntry::new_from_blob(name.to_string(), SHA1::default(), 0);
+                entry.flags.stage = stage;
+                index.add(entry);
+            }
+        }
+        assert_eq!(conflict_type(&index, "m"), "both modified");
+        assert_eq!(conflict_type(&index, "us"), "deleted by us");
+        assert_eq!(conflict_type(&index, "them"), "deleted by them");
+    }
+
+    #[tokio::test]
+    async fn test_staged_renames() {
+        test::setup_with_new_libra().await;
+        // the files of the last run are left in the test repo
+        for file in ["rename_c.txt", "rename_d.txt"] {
+            let _ = fs::remove_file(file);
+        }
+        test::ensure_file("rename_a.txt", Some("line 1\nline 2\nline 3\nline 4\n"));
+        test::ensure_file("rename_b.txt", Some("other\n"));
+        crate::exec_async(vec!["add", "-A"]).await.unwrap();
+        crate::exec_async(vec!["commit", "-m", "init"])
+            .await
+            .unwrap();
+
+        fs::rename("rename_a.txt", "rename_c.txt").unwrap();
+        fs::write("rename_c.txt", "line 1\nline 2\nline 3\nline 4\nline 5\n").unwrap();
+        fs::remove_file("rename_b.txt").unwrap();
+        test::ensure_file("rename_d.txt", Some("new\n"));
+        crate::exec_async(vec!["add", "-A"]).await.unwrap();
+
+        let staged = changes_to_be_committed().await;
+        let renames = staged_renames(&staged).await;
+        assert_eq!(renames.len(), 1);
+        assert_eq!(renames[0].from, PathBuf::from("rename_a.txt"));
+        assert_eq!(renames[0].to, PathBuf::from("rename_c.txt"));
+        assert_eq!(renames[0].similarity, 80);
+        assert!(!renames[0].copy);
+    }
+}
@@ -3,8 +3,11 @@ use mercury::hash::SHA1;
 
 use crate::{
     command::branch,
-    internal::{branch::Branch, head::Head},
-    utils::util::{self, get_commit_base},
+    internal::{branch::Branch, head::Head, worktree},
+    utils::{
+        hooks,
+        util::{self, get_commit_base},
+    },
 };
 
 use super::{
@@ -63,10 +66,16 @@ pub async fn execute(args: SwitchArgs) {
 
 /// change the working directory to the version of commit_hash
 async fn switch_to_commit(commit_hash: SHA1) {
+    let old = Head::current_commit().await;
+    if !pre_checkout(old, commit_hash).await {
+        return;
+    }
     restore_to_commit(commit_hash).await;
     // update HEAD
+    let reason = moving_from(&commit_hash.to_string()).await;
     let head = Head::Detached(commit_hash);
-    Head::update(head, None).await;
+    Head::update(head, None, &reason).await;
+    post_checkout(old, commit_hash).await;
 }
 
 async fn switch_to_branch(branch_name: String) {
@@ -82,15 +91,61 @@ async fn switch_to_branch(branch_name: String) {
         }
         return;
     }
+    if let Some(path) = worktree::find_checkout(&branch_name).await {
+        eprintln!(
+            "fatal: '{}' is already checked out at '{}'",
+            branch_name,
+            path.display()
+        );
+        return;
+    }
+    let old = Head::current_commit().await;
     let commit_id = target_branch.unwrap().commit;
+    if !pre_checkout(old, commit_id).await {
+        return;
+    }
     restore_to_commit(commit_id).await;
     // update HEAD
     // let mut head: ActiveModel = reference::Model::current_head(db).await.unwrap().into();
+    let reason = moving_from(&branch_name).await;
     let head = Head::Branch(branch_name);
-    Head::update(head, None).await;
+    Head::update(head, None, &reason).await;
+    post_checkout(old, commit_id).await;
+}
+
+/// Run the `pre-checkout` hook before switching from `old` HEAD, return `false` if the switch should be aborted
+async fn pre_checkout(old: Option<SHA1>, new: SHA1) -> bool {
+    let old = old.unwrap_or_default().to_string();
+    let new = new.to_string();
+    match hooks::run(hooks::PRE_CHECKOUT, &[&old, &new, "1"], None).await {
+        Ok(()) => true,
+        Err(e) => {
+            eprintln!("fatal: {}", e);
+            false
+        }
+    }
+}
+
+/// Run the `post-checkout` hook after switching from `old` HEAD, its failure can't undo the switch
+async fn post_checkout(old: Option<SHA1>, new: SHA1) {
+    let old = old.unwrap_or_default().to_string();
+    let new = new.to_string();
+    if let Err(e) = hooks::run(hooks::POST_CHECKOUT, &[&old, &new, "1"], None).await {
+        eprintln!("error: {}", e);
+    }
+}
+
+/// The reflog message of switching from current HEAD to `target`
+async fn moving_from(target: &str) -> String {
+    let from = match Head::current().await {
+        Head::Branch(name) => name,
+        Head::Detached(commit) => commit.to_string(),
+    };
+    format!("switch: moving from {} to {}", from, target)
 }
 
-async fn restore_to_commit(commit_id: SHA1) {
+/// restore index & worktree to the version of `commit_id`, HEAD is not changed
+pub(crate) async fn restore_to_commit(commit_id: SHA1) {
     let restore_args = RestoreArgs {
         worktree: true,
         staged: true,
@@ -0,0 +1,322 @@
+use std::fs;
+
+use clap::Parser;
+use common::utils::format_commit_msg;
+use mercury::hash::SHA1;
+use mercury::internal::object::signature::{Signature, SignatureType};
+use mercury::internal::object::tag::Tag as TagObject;
+use mercury::internal::object::types::ObjectType;
+use mercury::internal::object::ObjectTrait;
+
+use crate::internal::tag::Tag;
+use crate::utils::{ignore, signing, util};
+
+use super::{get_target_commit, load_object, save_object};
+
+#[derive(Parser, Debug)]
+pub struct TagArgs {
+    /// Name of the tag to create or delete, or the pattern to list
+    pub name: Option<String>,
+
+    /// The commit that the new tag will refer to, HEAD by default
+    #[clap(requires("name"))]
+    pub commit: Option<String>,
+
+    /// List tags, only the tags matching the pattern (`name`) if given
+    #[clap(short, long, conflicts_with_all(["annotate", "sign", "message", "force", "delete", "commit"]))]
+    pub list: bool,
+
+    /// Make an annotated tag object, the message is edited in the editor if `-m` is not given
+    #[clap(short, long)]
+    pub annotate: bool,
+
+    /// Make a signed tag with `user.signingkey`, implies `-a`
+    #[clap(short, long)]
+    pub sign: bool,
+
+    /// Use the given tag message (instead of prompting), implies `-a`
+    #[clap(short, long)]
+    pub message: Option<String>,
+
+    /// Replace an existing tag with the given name (instead of failing)
+    #[clap(short, long)]
+    pub force: bool,
+
+    /// Delete the tag with the given name
+    #[clap(short, long, requires("name"), conflicts_with_all(["annotate", "sign", "message", "force", "commit"]))]
+    pub delete: bool,
+
+    /// Verify the signature of the tag with the given name
+    #[clap(short, long, requires("name"), conflicts_with_all(["list", "annotate", "sign", "message", "force", "delete", "commit"]))]
+    pub verify: bool,
+}
+
+pub async fn execute(args: TagArgs) {
+    if !util::check_repo_exist() {
+        return;
+    }
+    if args.delete {
+        delete_tag(&args.name.unwrap()).await;
+        return;
+    }
+    if args.verify {
+        verify_tag(&args.name.unwrap()).await;
+        return;
+    }
+    match args.name {
+        Some(name) if !args.list => {
+            let annotate = args.annotate || args.sign || args.message.is_some();
+            create_tag(
+                &name,
+                args.commit,
+                annotate,
+                args.sign,
+                args.message,
+                args.force,
+            )
+            .await;
+        }
+        pattern => list_tags(pattern.as_deref()).await,
+    }
+}
+
+async fn list_tags(pattern: Option<&str>) {
+    for tag in Tag::list_tags().await {
+        if pattern.is_none_or(|pattern| ignore::glob_match(pattern, &tag.name)) {
+            println!("{}", tag.name);
+        }
+    }
+}
+
+async fn create_tag(
+    name: &str,
+    commit: Option<String>,
+    annotate: bool,
+    sign: bool,
+    message: Option<String>,
+    force: bool,
+) {
+    if !is_valid_tag_name(name) {
+        eprintln!("fatal: '{}' is not a valid tag name.", name);
+        return;
+    }
+    let old = Tag::find_tag(name).await;
+    if old.is_some() && !force {
+        eprintln!("fatal: tag '{}' already exists", name);
+        return;
+    }
+    let commit = commit.unwrap_or("HEAD".to_string());
+    let target = match get_target_commit(&commit).await {
+        Ok(target) => target,
+        Err(e) => {
+            eprintln!(
+                "fatal: Failed to resolve '{}' as a valid ref: {}",
+                commit, e
+            );
+            return;
+        }
+    };
+
+    let object = if annotate {
+        let message = match message {
+            Some(message) => message,
+            None => match edit_tag_message(name) {
+                Some(message) => message,
+                None => return,
+            },
+        };
+        let tagger = Signature::new(
+            SignatureType::Tagger,
+            "mega".to_string(),
+            "admin@mega.org".to_string(),
+        );
+        let mut message = format_commit_msg(&message, None);
+        if sign {
+            // the signature is appended to the message, which must end with a newline
+            if !message.ends_with('\n') {
+                message.push('\n');
+            }
+            let unsigned = TagObject::new(
+                target,
+                ObjectType::Commit,
+                name.to_string(),
+                tagger.clone(),
+                &message,
+            );
+            match signing::sign(&unsigned.to_data().unwrap()).await {
+                Ok(signature) => message.push_str(&signature),
+                Err(e) => {
+                    eprintln!("fatal: failed to sign the tag: {}", e);
+                    return;
+                }
+            }
+        }
+        let tag = TagObject::new(
+            target,
+            ObjectType::Commit,
+            name.to_string(),
+            tagger,
+            &message,
+        );
+        save_object(&tag, &tag.id).unwrap();
+        tag.id
+    } else {
+        target
+    };
+
+    Tag::update_tag(name, &object).await;
+    if let Some(old) = old {
+        if old.object != object {
+            println!(
+                "Updated tag '{}' (was {})",
+                name,
+                &old.object.to_string()[..7]
+            );
+        }
+    }
+}
+
+/// Verify the signature of the annotated tag, like `git tag -v`
+async fn verify_tag(name: &str) {
+    let Some(tag) = Tag::find_tag(name).await else {
+        eprintln!("error: tag '{}' not found.", name);
+        return;
+    };
+    if !util::objects_storage().is_object_type(&tag.object, ObjectType::Tag) {
+        eprintln!(
+            "error: '{}' is a lightweight tag, which can't be signed",
+            name
+        );
+        return;
+    }
+    match signing::verify_object(&tag.object).await {
+        Ok(Some(verified)) if verified.is_trusted() => eprintln!("{}", verified),
+        Ok(Some(verified)) => eprintln!("warning: {}", verified),
+        Ok(None) => eprintln!("error: no signature found in tag '{}'", name),
+        Err(e) => eprintln!("error: {}", e),
+    }
+}
+
+/// Edit the tag message in the editor, lines starting with `#` are ignored
+/// - return `None` if the message is empty
+fn edit_tag_message(name: &str) -> Option<String> {
+    let file = util::worktree_storage_path().join("TAG_EDITMSG");
+    let template = format!(
+        "\n#\n# Write a message for tag:\n#   {}\n# Lines starting with '#' will be ignored.\n",
+        name
+    );
+    fs::write(&file, template).unwrap();
+    if let Err(e) = util::edit_file(&file) {
+        eprintln!("error: {}", e);
+        return None;
+    }
+    let content = fs::read_to_string(&file).unwrap();
+    let message: Vec<&str> = content
+        .lines()
+        .filter(|line| !line.starts_with('#'))
+        .collect();
+    let message = message.join("\n").trim().to_string();
+    if message.is_empty() {
+        eprintln!("fatal: no tag message?");
+        return None;
+    }
+    Some(message + "\n")
+}
+
+async fn delete_tag(name: &str) {
+    match Tag::find_tag(name).await {
+        Some(tag) => {
+            Tag::delete_tag(name).await;
+            println!(
+                "Deleted tag '{}' (was {})",
+                name,
+                &tag.object.to_string()[..7]
+            );
+        }
+        None => eprintln!("error: tag '{}' not found.", name),
+    }
+}
+
+/// Peel the tag object to the commit it refers to, other objects are returned as they are
+pub fn peel_to_commit(object: &SHA1) -> SHA1 {
+    let storage = util::objects_storage();
+    let mut object = *object;
+    while storage.is_object_type(&object, ObjectType::Tag) {
+        let tag: TagObject = load_object(&object).unwrap();
+        object = tag.object_hash;
+    }
+    object
+}
+
+/// Simplified rules of `git check-ref-format`
+fn is_valid_tag_name(name: &str) -> bool {
+    !name.is_empty()
+        && !name.starts_with(['-', '/', '.'])
+        && !name.ends_with(['/', '.'])
+        && !name.ends_with(".lock")
+        && !name.contains("..")
+        && !name.contains("//")
+        && !name.contains("@{")
+        && name != "@"
+        && !name
+            .chars()
+            .any(|c| c.is_ascii_control() || " ~^:?*[\\".contains(c))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::test;
+
+    #[test]
+    fn test_parse_args() {
+        assert!(TagArgs::try_parse_from(["tag"]).is_ok());
+        assert!(TagArgs::try_parse_from(["tag", "v1.0", "HEAD", "-m", "release"]).is_ok());
+        assert!(TagArgs::try_parse_from(["tag", "-l", "v1.*"]).is_ok());
+        assert!(TagArgs::try_parse_from(["tag", "-d"]).is_err());
+        assert!(TagArgs::try_parse_from(["tag", "-d", "v1.0", "-m", "msg"]).is_err());
+        assert!(TagArgs::try_parse_from(["tag", "-l", "-a", "v1.0"]).is_err());
+        assert!(TagArgs::try_parse_from(["tag", "-s", "v1.0", "-m", "signed"]).is_ok());
+        assert!(TagArgs::try_parse_from(["tag", "-v", "v1.0"]).is_ok());
+        assert!(TagArgs::try_parse_from(["tag", "-v", "v1.0", "-s"]).is_err());
+    }
+
+    #[test]
+    fn test_is_valid_tag_name() {
+        assert!(is_valid_tag_name("v1.0"));
+        assert!(is_valid_tag_name("release/2024-01"));
+        assert!(!is_valid_tag_name("-v1"));
+        assert!(!is_valid_tag_name("v1..2"));
+        assert!(!is_valid_tag_name("v1 2"));
+        assert!(!is_valid_tag_name("v1.lock"));
+        assert!(!is_valid_tag_name("v1^"));
+    }
+
+    #[tokio::test]
+    async fn test_create_tags() {
+        test::setup_with_new_libra().await;
+        test::ensure_file("tag_a.txt", Some("a"));
+        crate::exec_async(vec!["add", "tag_a.txt"]).await.unwrap();
+        crate::exec_async(vec!["commit", "-m", "init"])
+            .await
+            .unwrap();
+        let head = get_target_commit("HEAD").await.unwrap();
+
+        crate::exec_async(vec!["tag", "v1"]).await.unwrap();
+        assert_eq!(Tag::find_tag("v1").await.unwrap().object, head);
+
+        crate::exec_async(vec!["tag", "v2", "-m", "release 2"])
+            .await
+            .unwrap();
+        let v2 = Tag::find_tag("v2").await.unwrap().object;
+        assert_ne!(v2, head);
+        let tag: TagObject = load_object(&v2).unwrap();
+        assert_eq!(tag.tag_name, "v2");
+        assert_eq!(peel_to_commit(&v2), head);
+        // tags can be used as commit
+        assert_eq!(get_target_commit("v2").await.unwrap(), head);
+
+        crate::exec_async(vec!["tag", "-d", "v1"]).await.unwrap();
+        assert!(!Tag::exists("v1").await);
+    }
+}
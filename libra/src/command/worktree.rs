@@ -0,0 +1,484 @@
+use std::path::Path;
+use std::{env, fs};
+
+use clap::{ArgAction, Parser, Subcommand};
+use mercury::internal::object::commit::Commit;
+use path_absolutize::*;
+
+use crate::internal::branch::Branch;
+use crate::internal::head::Head;
+use crate::internal::worktree::{self, Worktree};
+use crate::utils::object_ext::CommitExt;
+use crate::utils::{path, util};
+
+use super::{branch, get_target_commit, status, switch};
+
+#[derive(Parser, Debug)]
+pub struct WorktreeArgs {
+    #[command(subcommand)]
+    pub command: WorktreeCmds,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum WorktreeCmds {
+    /// Create a worktree at <path> and check out <commit-ish> into it
+    Add {
+        /// Create a new branch at <commit-ish> and check it out in the new worktree
+        #[clap(short = 'b', conflicts_with("detach"))]
+        new_branch: Option<String>,
+        /// Detach HEAD at <commit-ish> in the new worktree
+        #[clap(short, long)]
+        detach: bool,
+        /// Check out the branch even if it's already checked out by another worktree
+        #[clap(short, long)]
+        force: bool,
+        /// Working directory of the new worktree, which must not exist or be empty
+        path: String,
+        /// Branch or commit to check out, a branch named after <path> by default (created from HEAD if not exists)
+        commit_ish: Option<String>,
+    },
+    /// List the main worktree and the linked worktrees
+    List,
+    /// Remove a linked worktree, which must be clean unless forced
+    Remove {
+        /// Remove the worktree even if it has modified or untracked files, twice to remove a locked worktree
+        #[clap(short, long, action = ArgAction::Count)]
+        force: u8,
+        /// Name or path of the worktree
+        worktree: String,
+    },
+    /// Lock a linked worktree, so that it's not removed or pruned
+    Lock {
+        /// Why the worktree is locked
+        #[clap(long)]
+        reason: Option<String>,
+        /// Name or path of the worktree
+        worktree: String,
+    },
+    /// Unlock a linked worktree
+    Unlock {
+        /// Name or path of the worktree
+        worktree: String,
+    },
+    /// Clean up the storage of the worktrees whose working directories have been deleted
+    Prune {
+        /// Only report what would be removed
+        #[clap(short = 'n', long)]
+        dry_run: bool,
+        /// Report the removed worktrees
+        #[clap(short, long)]
+        verbose: bool,
+    },
+}
+
+pub async fn execute(args: WorktreeArgs) {
+    if !util::check_repo_exist() {
+        return;
+    }
+    match args.command {
+        WorktreeCmds::Add {
+            new_branch,
+            detach,
+            force,
+            path,
+            commit_ish,
+        } => add(&path, commit_ish, new_branch, detach, force).await,
+        WorktreeCmds::List => list().await,
+        WorktreeCmds::Remove { force, worktree } => remove(&worktree, force).await,
+        WorktreeCmds::Lock { reason, worktree } => lock(&worktree, reason),
+        WorktreeCmds::Unlock { worktree } => unlock(&worktree),
+        WorktreeCmds::Prune { dry_run, verbose } => prune(dry_run, verbose),
+    }
+}
+
+async fn add(
+    path: &str,
+    commit_ish: Option<String>,
+    new_branch: Option<String>,
+    detach: bool,
+    force: bool,
+) {
+    let dir = util::cur_dir()
+        .join(path)
+        .absolutize()
+        .unwrap()
+        .to_path_buf();
+    if dir.exists() && !util::is_empty_dir(&dir) {
+        eprintln!("fatal: '{}' already exists", dir.display());
+        return;
+    }
+    let base_name = match dir.file_name() {
+        Some(name) => name.to_string_lossy().to_string(),
+        None => {
+            eprintln!("fatal: invalid path '{}'", dir.display());
+            return;
+        }
+    };
+    let start_point = match &commit_ish {
+        Some(commit_ish) => get_target_commit(commit_ish).await,
+        None => Head::current_commit()
+            .await
+            .ok_or("invalid reference: HEAD".into()),
+    };
+    let start_point = match start_point {
+        Ok(commit) => commit,
+        Err(e) => {
+            eprintln!("fatal: {}", e);
+            return;
+        }
+    };
+
+    let (head, created) = if let Some(new_branch) = new_branch {
+        (Head::Branch(new_branch), true)
+    } else if detach {
+        (Head::Detached(start_point), false)
+    } else if let Some(commit_ish) = commit_ish {
+        match Branch::find_branch(&commit_ish, None).await {
+            Some(_) => (Head::Branch(commit_ish), false),
+            None => (Head::Detached(start_point), false),
+        }
+    } else {
+        let exists = Branch::find_branch(&base_name, None).await.is_some();
+        (Head::Branch(base_name.clone()), !exists)
+    };
+    if let Head::Branch(name) = &head {
+        if created {
+            if Branch::find_branch(name, None).await.is_some() {
+                eprintln!("fatal: a branch named '{}' already exists", name);
+                return;
+            }
+            branch::create_branch(name.clone(), Some(start_point.to_string())).await;
+            if Branch::find_branch(name, None).await.is_none() {
+                return; // invalid branch name
+            }
+        } else if !force {
+            let checked_out = match Head::current().await {
+                Head::Branch(current) if current == *name => Some(util::working_dir()),
+                _ => worktree::find_checkout(name).await,
+            };
+            if let Some(checked_out) = checked_out {
+                eprintln!(
+                    "fatal: '{}' is already checked out at '{}'",
+                    name,
+                    checked_out.display()
+                );
+                return;
+            }
+        }
+    }
+    match (&head, created) {
+        (Head::Branch(name), true) => println!("Preparing worktree (new branch '{}')", name),
+        (Head::Branch(name), false) => println!("Preparing worktree (checking out '{}')", name),
+        (Head::Detached(commit), _) => println!(
+            "Preparing worktree (detached HEAD {})",
+            &commit.to_string()[..7]
+        ),
+    }
+
+    // `<name>1`, `<name>2`... if the name is taken
+    let mut name = base_name.clone();
+    let mut n = 0;
+    while path::worktrees().join(&name).exists() {
+        n += 1;
+        name = format!("{}{}", base_name, n);
+    }
+    if let Err(e) = Worktree::create(&name, &dir, &head) {
+        eprintln!(
+            "fatal: could not create worktree '{}': {}",
+            dir.display(),
+            e
+        );
+        return;
+    }
+
+    // CAUTION: change [current_dir] to the new worktree to check out the files
+    let cur_dir = util::cur_dir();
+    env::set_current_dir(&dir).unwrap();
+    let commit = Head::current_commit().await.unwrap();
+    switch::restore_to_commit(commit).await;
+    env::set_current_dir(cur_dir).unwrap();
+    println!(
+        "HEAD is now at {} {}",
+        &commit.to_string()[..7],
+        Commit::load(&commit).format_message()
+    );
+}
+
+async fn list() {
+    let mut worktrees = vec![(
+        util::storage_path().parent().unwrap().to_path_buf(),
+        Head::main_worktree().await,
+        Vec::new(),
+    )];
+    for worktree in Worktree::list() {
+        let mut attributes = Vec::new();
+        if worktree.locked().is_some() {
+            attributes.push("locked");
+        }
+        if worktree.is_prunable() {
+            attributes.push("prunable");
+        }
+        worktrees.push((worktree.path(), worktree.head(), attributes));
+    }
+    let width = worktrees
+        .iter()
+        .map(|(path, _, _)| path.display().to_string().len())
+        .max()
+        .unwrap_or(0);
+    for (path, head, attributes) in worktrees {
+        let (commit, head) = match head {
+            Head::Branch(name) => (
+                Branch::find_branch(&name, None)
+                    .await
+                    .map(|branch| branch.commit),
+                format!("[{}]", name),
+            ),
+            Head::Detached(commit) => (Some(commit), "(detached HEAD)".to_string()),
+        };
+        let commit = commit.unwrap_or_default().to_string();
+        let mut line = format!(
+            "{:<width$} {} {}",
+            path.display().to_string(),
+            &commit[..7],
+            head,
+            width = width
+        );
+        for attribute in attributes {
+            line += " ";
+            line += attribute;
+        }
+        println!("{}", line);
+    }
+}
+
+/// - `force`: 1 to remove a dirty worktree, 2 to remove a locked one too
+async fn remove(name: &str, force: u8) {
+    let Some(worktree) = find_linked(name) else {
+        return;
+    };
+    if Worktree::current().as_ref() == Some(&worktree) {
+        eprintln!("fatal: cannot remove the current working tree");
+        return;
+    }
+    if let Some(reason) = worktree.locked() {
+        if force < 2 {
+            match reason.is_empty() {
+                true => eprintln!("fatal: cannot remove a locked working tree"),
+                false => eprintln!(
+                    "fatal: cannot remove a locked working tree, lock reason: {}",
+                    reason
+                ),
+            }
+            eprintln!("use 'remove -f -f' to override or unlock first");
+            return;
+        }
+    }
+    // never delete a directory which is not (or no longer) the worktree
+    if let Err(e) = worktree.validate() {
+        eprintln!(
+            "fatal: validation failed, cannot remove working tree: {}",
+            e
+        );
+        if worktree.is_prunable() {
+            eprintln!("hint: use 'libra worktree prune' to remove the administrative files");
+        }
+        return;
+    }
+    let path = worktree.path();
+    if force == 0 && !is_clean(&path).await {
+        eprintln!(
+            "fatal: '{}' contains modified or untracked files, use --force to delete it",
+            path.display()
+        );
+        return;
+    }
+    fs::remove_dir_all(&path).unwrap();
+    worktree.delete();
+}
+
+fn lock(name: &str, reason: Option<String>) {
+    let Some(worktree) = find_linked(name) else {
+        return;
+    };
+    match worktree.locked() {
+        Some(_) => eprintln!("fatal: '{}' is already locked", name),
+        None => worktree.lock(&reason.unwrap_or_default()),
+    }
+}
+
+fn unlock(name: &str) {
+    let Some(worktree) = find_linked(name) else {
+        return;
+    };
+    match worktree.locked() {
+        Some(_) => worktree.unlock(),
+        None => eprintln!("fatal: '{}' is not locked", name),
+    }
+}
+
+fn find_linked(name: &str) -> Option<Worktree> {
+    let worktree = Worktree::find(name);
+    if worktree.is_none() {
+        eprintln!("fatal: '{}' is not a linked working tree", name);
+    }
+    worktree
+}
+
+/// Check if the worktree has no changes & untracked files
+async fn is_clean(path: &Path) -> bool {
+    let cur_dir = util::cur_dir();
+    env::set_current_dir(path).unwrap();
+    let clean = status::is_clean().await;
+    env::set_current_dir(cur_dir).unwrap();
+    clean
+}
+
+fn prune(dry_run: bool, verbose: bool) {
+    for worktree in Worktree::list()
+        .iter()
+        .filter(|w| w.is_prunable() && w.locked().is_none())
+    {
+        if dry_run || verbose {
+            println!(
+                "Removing worktrees/{}: libradir file points to non-existent location",
+                worktree.name()
+            );
+        }
+        if !dry_run {
+            worktree.delete();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::*;
+    use crate::utils::test;
+
+    /// Paths of all the worktrees, the main one first
+    fn worktree_paths() -> Vec<PathBuf> {
+        let mut paths = vec![util::storage_path().parent().unwrap().to_path_buf()];
+        paths.extend(Worktree::list().iter().map(Worktree::path));
+        paths
+    }
+
+    #[test]
+    fn test_parse_args() {
+        assert!(WorktreeArgs::try_parse_from(["worktree", "add", "../wt"]).is_ok());
+        assert!(
+            WorktreeArgs::try_parse_from(["worktree", "add", "-b", "dev", "../wt", "main"]).is_ok()
+        );
+        assert!(WorktreeArgs::try_parse_from([
+            "worktree", "add", "-b", "dev", "--detach", "../wt"
+        ])
+        .is_err());
+        assert!(WorktreeArgs::try_parse_from(["worktree", "remove", "-f", "wt"]).is_ok());
+        assert!(WorktreeArgs::try_parse_from(["worktree", "prune", "-n"]).is_ok());
+        assert!(WorktreeArgs::try_parse_from(["worktree"]).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_worktree() {
+        test::setup_with_new_libra().await;
+        let main_dir = util::working_dir();
+        for dir in ["worktree_a", "worktree_b"] {
+            let _ = fs::remove_dir_all(main_dir.join(dir));
+        }
+        test::ensure_file("worktree.txt", Some("main\n"));
+        crate::exec_async(vec!["add", "worktree.txt"])
+            .await
+            .unwrap();
+        crate::exec_async(vec!["commit", "-m", "base"])
+            .await
+            .unwrap();
+        let base = Head::current_commit().await.unwrap();
+
+        crate::exec_async(vec!["worktree", "add", "worktree_a"])
+            .await
+            .unwrap();
+        let wt_dir = main_dir.join("worktree_a");
+        assert!(wt_dir.join(util::ROOT_DIR).is_file());
+        assert_eq!(
+            fs::read_to_string(wt_dir.join("worktree.txt")).unwrap(),
+            "main\n"
+        );
+        assert_eq!(worktree_paths(), vec![main_dir.clone(), wt_dir.clone()]);
+        // the branch is locked by the worktree
+        assert_eq!(
+            worktree::find_checkout("worktree_a").await,
+            Some(wt_dir.clone())
+        );
+        crate::exec_async(vec!["worktree", "add", "worktree_b", "master"])
+            .await
+            .unwrap();
+        assert!(!main_dir.join("worktree_b").exists());
+
+        // commit in the linked worktree
+        env::set_current_dir(&wt_dir).unwrap();
+        assert!(matches!(Head::current().await, Head::Branch(name) if name == "worktree_a"));
+        assert_eq!(
+            worktree::find_checkout("master").await,
+            Some(main_dir.clone())
+        );
+        test::ensure_file("worktree.txt", Some("linked\n"));
+        crate::exec_async(vec!["add", "worktree.txt"])
+            .await
+            .unwrap();
+        crate::exec_async(vec!["commit", "-m", "linked"])
+            .await
+            .unwrap();
+        let linked = Head::current_commit().await.unwrap();
+        env::set_current_dir(&main_dir).unwrap();
+
+        assert!(matches!(Head::current().await, Head::Branch(name) if name == "master"));
+        assert_eq!(Head::current_commit().await, Some(base));
+        assert_eq!(
+            Branch::find_branch("worktree_a", None)
+                .await
+                .unwrap()
+                .commit,
+            linked
+        );
+
+        // dirty worktree is kept without `--force`
+        fs::write(wt_dir.join("untracked.txt"), "").unwrap();
+        remove("worktree_a", 0).await;
+        assert!(wt_dir.exists());
+        // locked worktree is kept without `-f -f`
+        crate::exec_async(vec!["worktree", "lock", "--reason", "usb", "worktree_a"])
+            .await
+            .unwrap();
+        assert_eq!(Worktree::list()[0].locked(), Some("usb".to_string()));
+        remove("worktree_a", 1).await;
+        assert!(wt_dir.exists());
+        crate::exec_async(vec!["worktree", "unlock", "worktree_a"])
+            .await
+            .unwrap();
+        assert_eq!(Worktree::list()[0].locked(), None);
+        remove("worktree_a", 1).await;
+        assert!(!wt_dir.exists());
+        assert!(Worktree::list().is_empty());
+
+        // deleted manually
+        crate::exec_async(vec!["worktree", "add", "--detach", "worktree_b"])
+            .await
+            .unwrap();
+        let wt_dir = main_dir.join("worktree_b");
+        fs::remove_dir_all(&wt_dir).unwrap();
+        assert!(Worktree::list()[0].is_prunable());
+        // an unrelated directory at the same path is never removed
+        fs::create_dir(&wt_dir).unwrap();
+        remove("worktree_b", 2).await;
+        assert!(wt_dir.exists());
+        fs::remove_dir(&wt_dir).unwrap();
+        // locked worktree is not pruned
+        Worktree::list()[0].lock("");
+        prune(false, false);
+        assert_eq!(Worktree::list().len(), 1);
+        Worktree::list()[0].unlock();
+        prune(false, false);
+        assert!(Worktree::list().is_empty());
+    }
+}
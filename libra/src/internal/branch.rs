@@ -7,7 +7,9 @@ use sea_orm::{ColumnTrait, EntityTrait, QueryFilter};
 use mercury::hash::SHA1;
 
 use crate::internal::db::get_db_conn_instance;
+use crate::internal::head::Head;
 use crate::internal::model::reference;
+use crate::internal::reflog::{self, Reflog};
 
 #[derive(Debug)]
 pub struct Branch {
@@ -100,10 +102,20 @@ impl Branch {
         branches
     }
 
-    pub async fn update_branch(branch_name: &str, commit_hash: &str, remote: Option<&str>) {
+    /// Create or update the branch, the movement is recorded in reflog with `reason`,
+    /// and in HEAD's reflog too if it's the current branch, no-op updates are not recorded
+    pub async fn update_branch(
+        branch_name: &str,
+        commit_hash: &str,
+        remote: Option<&str>,
+        reason: &str,
+    ) {
         let db_conn = get_db_conn_instance().await;
         // check if branch exists
         let branch = query_reference(branch_name, remote).await;
+        let old = branch
+            .as_ref()
+            .map(|branch| SHA1::from_str(branch.commit.as_ref().unwrap()).unwrap());
 
         match branch {
             Some(branch) => {
@@ -124,6 +136,19 @@ impl Branch {
                 .unwrap();
             }
         }
+
+        let new = SHA1::from_str(commit_hash).unwrap();
+        if old == Some(new) {
+            return;
+        }
+        Reflog::append(&Reflog::branch_ref(branch_name, remote), old, new, reason).await;
+        if remote.is_none() {
+            if let Head::Branch(current) = Head::current().await {
+                if current == branch_name {
+                    Reflog::append(reflog::HEAD, old, new, reason).await;
+                }
+            }
+        }
     }
 
     pub async fn delete_branch(branch_name: &str, remote: Option<&str>) {
@@ -131,6 +156,7 @@ impl Branch {
         let branch: reference::ActiveModel =
             query_reference(branch_name, remote).await.unwrap().into();
         branch.delete(db_conn).await.unwrap();
+        Reflog::expire(&Reflog::branch_ref(branch_name, remote), None).await;
     }
 }
 
@@ -145,10 +171,16 @@ mod tests {
         test::setup_with_new_libra().await;
 
         let commit_hash = SHA1::default().to_string();
-        Branch::update_branch("upstream/origin/master", &commit_hash, None).await; // should match
-        Branch::update_branch("origin/master", &commit_hash, Some("upstream")).await; // should match
-        Branch::update_branch("master", &commit_hash, Some("upstream/origin")).await; // should match
-        Branch::update_branch("feature", &commit_hash, Some("upstream/origin/master")).await; // should not match
+        Branch::update_branch("upstream/origin/master", &commit_hash, None, "test").await; // should match
+        Branch::update_branch("origin/master", &commit_hash, Some("upstream"), "test").await; // should match
+        Branch::update_branch("master", &commit_hash, Some("upstream/origin"), "test").await; // should match
+        Branch::update_branch(
+            "feature",
+            &commit_hash,
+            Some("upstream/origin/master"),
+            "test",
+        )
+        .await; // should not match
 
         let branches = Branch::search_branch("upstream/origin/master").await;
         assert_eq!(branches.len(), 3);
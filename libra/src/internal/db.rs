@@ -8,7 +8,7 @@ use std::io;
 use std::io::Error as IOError;
 use std::io::ErrorKind;
 use std::path::Path;
-use tokio::sync::OnceCell;
+use tokio::sync::Mutex;
 
 /// Establish a connection to the database.
 ///  - `db_path` is the path to the SQLite database file.
@@ -32,19 +32,37 @@ pub async fn establish_connection(db_path: &str) -> Result<DatabaseConnection, I
     })
 }
 
-static DB_CONN: OnceCell<DbConn> = OnceCell::const_new();
+static DB_CONN: Mutex<Option<&'static DbConn>> = Mutex::const_new(None);
 /// Get global database connection instance (singleton)
 pub async fn get_db_conn_instance() -> &'static DbConn {
-    DB_CONN
-        .get_or_init(|| async { get_db_conn().await.unwrap() })
-        .await
+    let mut instance = DB_CONN.lock().await;
+    if let Some(conn) = *instance {
+        return conn;
+    }
+    let conn: &'static DbConn = Box::leak(Box::new(get_db_conn().await.unwrap()));
+    *instance = Some(conn);
+    conn
 }
 
-/// Create a connection to the database of current repo: `.libra/libra.db`
+/// Close the connection instance, the next [get_db_conn_instance] connects to the database again.
+/// - tests re-create the repo, the old connection still points to the removed database
+#[cfg(test)]
+pub async fn reset_db_conn_instance() {
+    if let Some(conn) = DB_CONN.lock().await.take() {
+        let _ = conn.close_by_ref().await;
+    }
+}
+
+/// Create a connection to the database of current repo: `.libra/libra.db`,
+/// the tables added after the repo was created (e.g. `stash`) are created here, once per process
 async fn get_db_conn() -> io::Result<DatabaseConnection> {
     let db_path = path::database(); // for longer lifetime
     let db_path = db_path.to_str().unwrap();
-    establish_connection(db_path).await
+    let conn = establish_connection(db_path).await?;
+    setup_database_sql(&conn)
+        .await
+        .map_err(|err| IOError::other(format!("Failed to upgrade database: {:?}", err)))?;
+    Ok(conn)
 }
 
 /// create table according to the Model
@@ -71,7 +89,7 @@ async fn setup_database_model(conn: &DatabaseConnection) -> Result<(), Transacti
     .await
 }
 
-/// create table using sql in `src/sql/sqlite_20240331_init.sql`
+/// create table using sql in `src/sql/sqlite_20240331_init.sql`, which only creates the missing tables & indexes
 async fn setup_database_sql(conn: &DatabaseConnection) -> Result<(), TransactionError<DbErr>> {
     conn.transaction::<_, _, DbErr>(|txn| {
         Box::pin(async move {
@@ -176,6 +194,25 @@ mod tests {
         // fs::remove_file(db_path).unwrap();
     }
 
+    #[tokio::test]
+    async fn test_upgrade_database() {
+        // the repo created before `stash` was supported
+        let test_db = TestDbPath::new("test_upgrade_database.db").await;
+        let conn = establish_connection(&test_db.0).await.unwrap();
+        let backend = conn.get_database_backend();
+        conn.execute(Statement::from_string(backend, "DROP TABLE `stash`"))
+            .await
+            .unwrap();
+
+        setup_database_sql(&conn).await.unwrap();
+        setup_database_sql(&conn).await.unwrap(); // idempotent
+        let entry = stash::ActiveModel {
+            commit: Set("2019".to_string()),
+            ..Default::default()
+        };
+        assert!(entry.save(&conn).await.is_ok());
+    }
+
     #[tokio::test]
     async fn test_insert_config() {
         // insert into config_entry & config_section, check foreign key constraint
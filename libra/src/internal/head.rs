@@ -8,6 +8,8 @@ use mercury::hash::SHA1;
 use crate::internal::branch::Branch;
 use crate::internal::db::get_db_conn_instance;
 use crate::internal::model::reference;
+use crate::internal::reflog::{self, Reflog};
+use crate::internal::worktree::Worktree;
 
 #[derive(Debug, Clone)]
 pub enum Head {
@@ -37,7 +39,16 @@ impl Head {
             .unwrap()
     }
 
+    /// HEAD of the current worktree
     pub async fn current() -> Head {
+        match Worktree::current() {
+            Some(worktree) => worktree.head(),
+            None => Self::main_worktree().await,
+        }
+    }
+
+    /// HEAD of the main worktree, which is stored in the database
+    pub async fn main_worktree() -> Head {
         let head = Self::query_local_head().await;
         match head.name {
             Some(name) => Head::Branch(name),
@@ -76,51 +87,81 @@ impl Head {
     }
 
     // HEAD is unique, update if exists, insert if not
-    pub async fn update(new_head: Self, remote: Option<&str>) {
+    /// - the movement of local HEAD is recorded in reflog with `reason`
+    pub async fn update(new_head: Self, remote: Option<&str>, reason: &str) {
         let db_conn = get_db_conn_instance().await;
+        let old_commit = match remote {
+            Some(_) => None,
+            None => Self::current_commit().await,
+        };
+        let switched = match (remote, &new_head) {
+            (None, Head::Branch(name)) => {
+                !matches!(Self::current().await, Head::Branch(current) if current == *name)
+            }
+            _ => false,
+        };
 
-        let head = match remote {
-            Some(remote) => Self::query_remote_head(remote).await,
-            None => Some(Self::query_local_head().await),
+        // HEAD of a linked worktree is stored in its own directory
+        let worktree = match remote {
+            Some(_) => None,
+            None => Worktree::current(),
         };
+        if let Some(worktree) = worktree {
+            worktree.write_head(&new_head);
+        } else {
+            let head = match remote {
+                Some(remote) => Self::query_remote_head(remote).await,
+                None => Some(Self::query_local_head().await),
+            };
 
-        match head {
-            Some(head) => {
-                // update
-                let mut head: reference::ActiveModel = head.into();
-                if remote.is_some() {
-                    head.remote = Set(remote.map(|s| s.to_owned()));
-                }
-                match new_head {
-                    Head::Detached(commit_hash) => {
-                        head.commit = Set(Some(commit_hash.to_string()));
-                        head.name = Set(None);
+            match head {
+                Some(head) => {
+                    // update
+                    let mut head: reference::ActiveModel = head.into();
+                    if remote.is_some() {
+                        head.remote = Set(remote.map(|s| s.to_owned()));
                     }
-                    Head::Branch(branch_name) => {
-                        head.name = Set(Some(branch_name));
-                        head.commit = Set(None);
+                    match new_head {
+                        Head::Detached(commit_hash) => {
+                            head.commit = Set(Some(commit_hash.to_string()));
+                            head.name = Set(None);
+                        }
+                        Head::Branch(branch_name) => {
+                            head.name = Set(Some(branch_name));
+                            head.commit = Set(None);
+                        }
                     }
+                    head.update(db_conn).await.unwrap();
                 }
-                head.update(db_conn).await.unwrap();
-            }
-            None => {
-                // // insert
-                let mut head = reference::ActiveModel {
-                    kind: Set(reference::ConfigKind::Head),
-                    ..Default::default()
-                };
-                if remote.is_some() {
-                    head.remote = Set(remote.map(|s| s.to_owned()));
-                }
-                match new_head {
-                    Head::Detached(commit_hash) => {
-                        head.commit = Set(Some(commit_hash.to_string()));
+                None => {
+                    // // insert
+                    let mut head = reference::ActiveModel {
+                        kind: Set(reference::ConfigKind::Head),
+                        ..Default::default()
+                    };
+                    if remote.is_some() {
+                        head.remote = Set(remote.map(|s| s.to_owned()));
                     }
-                    Head::Branch(branch_name) => {
-                        head.name = Set(Some(branch_name));
+                    match new_head {
+                        Head::Detached(commit_hash) => {
+                            head.commit = Set(Some(commit_hash.to_string()));
+                        }
+                        Head::Branch(branch_name) => {
+                            head.name = Set(Some(branch_name));
+                        }
                     }
+                    head.save(db_conn).await.unwrap();
+                }
+            }
+        }
+
+        if remote.is_none() {
+            match Self::current_commit().await {
+                // switching between branches at the same commit is recorded too
+                Some(new_commit) if switched || old_commit != Some(new_commit) => {
+                    Reflog::append(reflog::HEAD, old_commit, new_commit, reason).await;
                 }
-                head.save(db_conn).await.unwrap();
+                _ => {}
             }
         }
     }
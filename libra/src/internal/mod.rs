@@ -4,3 +4,7 @@ pub mod db;
 pub mod head;
 pub mod model;
 pub mod protocol;
+pub mod reflog;
+pub mod stash;
+pub mod tag;
+pub mod worktree;
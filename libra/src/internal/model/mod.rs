@@ -1,2 +1,4 @@
 pub mod config;
-pub mod reference;
\ No newline at end of file
+pub mod reference;
+pub mod reflog;
+pub mod stash;
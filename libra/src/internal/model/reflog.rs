@@ -0,0 +1,20 @@
+use sea_orm::entity::prelude::*;
+
+/// Movements of HEAD & branches, the latest entry of a ref has the largest id
+#[derive(Clone, Debug, PartialEq, DeriveEntityModel, Eq)]
+#[sea_orm(table_name = "reflog")]
+pub struct Model {
+    #[sea_orm(primary_key, auto_increment = true)]
+    pub id: i64,
+    pub ref_name: String, // `HEAD`, `refs/heads/<branch>` or `refs/remotes/<remote>/<branch>`
+    pub old_oid: String,  // all zeros if the ref is created
+    pub new_oid: String,
+    pub committer: String, // `name <email>`
+    pub timestamp: i64,
+    pub message: String,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {}
+
+impl ActiveModelBehavior for ActiveModel {}
@@ -0,0 +1,15 @@
+use sea_orm::entity::prelude::*;
+
+/// Stash entries, like the reflog of `refs/stash`, the latest entry has the largest id
+#[derive(Clone, Debug, PartialEq, DeriveEntityModel, Eq)]
+#[sea_orm(table_name = "stash")]
+pub struct Model {
+    #[sea_orm(primary_key, auto_increment = true)]
+    pub id: i64,
+    pub commit: String, // the WIP commit
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {}
+
+impl ActiveModelBehavior for ActiveModel {}
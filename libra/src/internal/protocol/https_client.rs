@@ -1,8 +1,9 @@
-use super::ProtocolClient;
+use super::{ProtocolClient, UploadPackOptions};
 use bytes::Bytes;
 use ceres::protocol::smart::{add_pkt_line_string, read_pkt_line};
 use ceres::protocol::ServiceType;
 use ceres::protocol::ServiceType::UploadPack;
+use futures::stream::BoxStream;
 use futures_util::{StreamExt, TryStreamExt};
 use mercury::errors::GitError;
 use mercury::hash::SHA1;
@@ -100,10 +101,12 @@ impl HttpsClient {
     /// the first ref named HEAD as default ref.
     /// ## Args
     /// - auth: (username, password)
+    /// ## Returns
+    /// the references & the capabilities of the server
     pub async fn discovery_reference(
         &self,
         service: ServiceType,
-    ) -> Result<Vec<DiscRef>, GitError> {
+    ) -> Result<(Vec<DiscRef>, Vec<String>), GitError> {
         let service: &str = &service.to_string();
         let url = self
             .url
@@ -147,49 +150,7 @@ impl HttpsClient {
             )));
         }
 
-        let mut ref_list = vec![];
-        let mut read_first_line = false;
-        loop {
-            let (bytes_take, pkt_line) = read_pkt_line(&mut response_content);
-            if bytes_take == 0 {
-                if response_content.is_empty() {
-                    break;
-                } else {
-                    continue;
-                }
-            }
-            let pkt_line = String::from_utf8(pkt_line.to_vec()).unwrap();
-            let (hash, mut refs) = pkt_line.split_at(40); // hex SHA1 string is 40 bytes
-            refs = refs.trim();
-            if !read_first_line {
-                if hash == SHA1::default().to_string() {
-                    break; // empty repo, return empty list // TODO: parse capability
-                }
-                let (head, caps) = refs.split_once('\0').unwrap();
-                if service == UploadPack.to_string() {
-                    // for git-upload-pack, the first line is HEAD
-                    assert_eq!(head, "HEAD");
-                }
-                // default ref named HEAD as the first ref. The stream MUST include capability declarations behind a NUL on the first ref.
-                ref_list.push(DiscoveredReference {
-                    _hash: hash.to_string(),
-                    _ref: head.to_string(),
-                });
-                let caps = caps.split(' ').collect::<Vec<&str>>();
-                tracing::debug!("capability declarations: {:?}", caps);
-                // tracing::warn!(
-                //     "temporary ignore capability declarations:[ {:?} ]",
-                //     refs[4..].to_string()
-                // );
-                read_first_line = true;
-            } else {
-                ref_list.push(DiscoveredReference {
-                    _hash: hash.to_string(),
-                    _ref: refs.to_string(),
-                });
-            }
-        }
-        Ok(ref_list)
+        parse_discovered_references(response_content, service)
     }
 
     /// POST $GIT_URL/git-upload-pack HTTP/1.0<br>
@@ -202,10 +163,11 @@ impl HttpsClient {
         &self,
         have: &Vec<String>,
         want: &Vec<String>,
+        options: &UploadPackOptions,
     ) -> Result<impl StreamExt<Item = Result<Bytes, IoError>>, IoError> {
         // POST $GIT_URL/git-upload-pack HTTP/1.0
         let url = self.url.join("git-upload-pack").unwrap();
-        let body = generate_upload_pack_content(have, want).await;
+        let body = generate_upload_pack_content(have, want, options).await;
         tracing::debug!("fetch_objects with body: {:?}", body);
 
         let res = BasicAuth::send(|| async {
@@ -231,25 +193,96 @@ impl HttpsClient {
         Ok(result)
     }
 
-    pub async fn send_pack<T: Into<Body> + Clone>(
+    /// POST the ref update commands & pack data to `git-receive-pack`, the body is streamed.
+    /// A stream can't be sent twice, so it's not retried for authentication like [BasicAuth::send],
+    /// the credentials have been asked by `discovery_reference(ReceivePack)`.
+    pub async fn send_pack(
         &self,
-        data: T,
+        data: BoxStream<'static, Result<Bytes, IoError>>,
     ) -> Result<Response, reqwest::Error> {
-        BasicAuth::send(|| async {
-            self
-                .client
-                .post(self.url.join("git-receive-pack").unwrap())
-                .header(CONTENT_TYPE, "application/x-git-receive-pack-request")
-                .body(data.clone())
-        }).await
+        let mut request = self
+            .client
+            .post(self.url.join("git-receive-pack").unwrap())
+            .header(CONTENT_TYPE, "application/x-git-receive-pack-request")
+            .body(Body::wrap_stream(data));
+        if let Some(auth) = AUTH.lock().unwrap().deref() {
+            request = request.basic_auth(auth.username.clone(), Some(auth.password.clone()));
+        }
+        request.send().await
     }
 }
+
+/// Parse the references & capabilities advertised by `git-upload-pack` or `git-receive-pack`,
+/// after the `# service=` line (only exists in HTTP) has been consumed.
+pub(crate) fn parse_discovered_references(
+    mut response_content: Bytes,
+    service: &str,
+) -> Result<(Vec<DiscRef>, Vec<String>), GitError> {
+    let mut ref_list = vec![];
+    let mut capabilities = vec![];
+    let mut read_first_line = false;
+    loop {
+        let (bytes_take, pkt_line) = read_pkt_line(&mut response_content);
+        if bytes_take == 0 {
+            if response_content.is_empty() {
+                break;
+            } else {
+                continue;
+            }
+        }
+        let pkt_line = String::from_utf8(pkt_line.to_vec()).unwrap();
+        let (hash, mut refs) = pkt_line.split_at(40); // hex SHA1 string is 40 bytes
+        refs = refs.trim();
+        if !read_first_line {
+            // The stream MUST include capability declarations behind a NUL on the first ref.
+            let (head, caps) = refs.split_once('\0').unwrap_or((refs, ""));
+            capabilities = caps.split_whitespace().map(String::from).collect();
+            tracing::debug!("capability declarations: {:?}", capabilities);
+            if hash == SHA1::default().to_string() {
+                break; // empty repo (`capabilities^{}`), return empty list
+            }
+            if service == UploadPack.to_string() {
+                // for git-upload-pack, the first line is HEAD
+                assert_eq!(head, "HEAD");
+            }
+            // default ref named HEAD as the first ref.
+            ref_list.push(DiscoveredReference {
+                _hash: hash.to_string(),
+                _ref: head.to_string(),
+            });
+            read_first_line = true;
+        } else {
+            ref_list.push(DiscoveredReference {
+                _hash: hash.to_string(),
+                _ref: refs.to_string(),
+            });
+        }
+    }
+    Ok((ref_list, capabilities))
+}
+
 /// for fetching
-async fn generate_upload_pack_content(have: &Vec<String>, want: &Vec<String>) -> Bytes {
+/// - `options`: `shallow`, `deepen` & `filter` lines are sent after `want` lines
+pub(crate) async fn generate_upload_pack_content(
+    have: &Vec<String>,
+    want: &Vec<String>,
+    options: &UploadPackOptions,
+) -> Bytes {
     let mut buf = BytesMut::new();
     let mut write_first_line = false;
 
-    let capability = ["side-band-64k", "ofs-delta", "multi_ack_detailed"].join(" ");
+    // `include-tag`: send annotated tags pointing to the objects being sent
+    let mut capability = vec!["side-band-64k", "ofs-delta", "multi_ack_detailed", "include-tag"];
+    if options.is_deepening() || !options.shallow.is_empty() {
+        capability.push("shallow");
+    }
+    if options.deepen_since.is_some() {
+        capability.push("deepen-since");
+    }
+    if options.filter.is_some() {
+        capability.push("filter");
+    }
+    let capability = capability.join(" ");
     for w in want {
         if !write_first_line {
             add_pkt_line_string(
@@ -261,6 +294,18 @@ async fn generate_upload_pack_content(have: &Vec<String>, want: &Vec<String>) ->
             add_pkt_line_string(&mut buf, format!("want {}\n", w).to_string());
         }
     }
+    for s in options.shallow.iter() {
+        add_pkt_line_string(&mut buf, format!("shallow {}\n", s));
+    }
+    if let Some(depth) = options.depth {
+        add_pkt_line_string(&mut buf, format!("deepen {}\n", depth));
+    }
+    if let Some(since) = options.deepen_since {
+        add_pkt_line_string(&mut buf, format!("deepen-since {}\n", since));
+    }
+    if let Some(filter) = &options.filter {
+        add_pkt_line_string(&mut buf, format!("filter {}\n", filter));
+    }
     buf.extend(b"0000"); // split pkt-lines with a flush-pkt
     for h in have {
         add_pkt_line_string(&mut buf, format!("have {}\n", h).to_string());
@@ -293,7 +338,7 @@ mod tests {
             tracing::error!("{:?}", refs.err().unwrap());
             panic!();
         } else {
-            let refs = refs.unwrap();
+            let (refs, _) = refs.unwrap();
             println!("refs count: {:?}", refs.len());
             println!("example: {:?}", refs[1]);
         }
@@ -305,7 +350,7 @@ mod tests {
 
         let test_repo = "https://github.com/web3infra-foundation/mega/";
         let client = HttpsClient::from_url(&Url::parse(test_repo).unwrap());
-        let refs = client.discovery_reference(UploadPack).await.unwrap();
+        let (refs, _) = client.discovery_reference(UploadPack).await.unwrap();
         let refs: Vec<DiscoveredReference> = refs
             .iter()
             .filter(|r| r._ref.starts_with("refs/heads"))
@@ -316,7 +361,10 @@ mod tests {
         let want = refs.iter().map(|r| r._hash.clone()).collect();
 
         let have = vec!["81a162e7b725bbad2adfe01879fd57e0119406b9".to_string()];
-        let mut result_stream = client.fetch_objects(&have, &want).await.unwrap();
+        let mut result_stream = client
+            .fetch_objects(&have, &want, &UploadPackOptions::default())
+            .await
+            .unwrap();
 
         let mut buffer = vec![];
         while let Some(item) = result_stream.next().await {
@@ -340,6 +388,48 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_parse_discovered_capabilities() {
+        let hash = "7ef152d43162e28b3177f6df380112f6412f5b42";
+        let mut buf = BytesMut::new();
+        add_pkt_line_string(&mut buf, format!("{} HEAD\0shallow filter\n", hash));
+        add_pkt_line_string(&mut buf, format!("{} refs/heads/main\n", hash));
+        buf.extend(b"0000");
+        let (refs, caps) = parse_discovered_references(buf.freeze(), "git-upload-pack").unwrap();
+        assert_eq!(refs.len(), 2);
+        assert_eq!(caps, vec!["shallow", "filter"]);
+
+        // empty repository
+        let mut buf = BytesMut::new();
+        let line = format!("{} capabilities^{{}}\0shallow\n", SHA1::default());
+        add_pkt_line_string(&mut buf, line);
+        buf.extend(b"0000");
+        let (refs, caps) = parse_discovered_references(buf.freeze(), "git-upload-pack").unwrap();
+        assert!(refs.is_empty());
+        assert_eq!(caps, vec!["shallow"]);
+    }
+
+    #[tokio::test]
+    async fn test_generate_upload_pack_content_with_options() {
+        let want = vec!["7ef152d43162e28b3177f6df380112f6412f5b42".to_string()];
+        let options = UploadPackOptions {
+            depth: Some(1),
+            filter: Some("blob:none".to_string()),
+            ..Default::default()
+        };
+        let body = generate_upload_pack_content(&vec![], &want, &options).await;
+        let body = String::from_utf8(body.to_vec()).unwrap();
+        assert!(body.contains("include-tag shallow filter agent=libra"));
+        let flush = body.find("0000").unwrap();
+        assert!(body.find("deepen 1\n").unwrap() < flush);
+        assert!(body.find("filter blob:none\n").unwrap() < flush);
+        assert!(body.ends_with("done\n"));
+
+        let body = generate_upload_pack_content(&vec![], &want, &UploadPackOptions::default()).await;
+        let body = String::from_utf8(body.to_vec()).unwrap();
+        assert!(!body.contains("shallow") && !body.contains("deepen"));
+    }
+
     #[tokio::test]
     async fn test_upload_pack_local() {
         // use /usr/bin/git-upload-pack as a test server. if no /usr/bin/git-upload-pack, skip this test
@@ -352,7 +442,8 @@ mod tests {
         let have = vec!["1c05d7f7dd70e38150bfd2d5fb8fb969e2eb9851".to_string()];
         // **want MUST change to one of the refs in the remote repo, such as `refs/heads/main` before running the test**
         let want = vec!["7ef152d43162e28b3177f6df380112f6412f5b42".to_string()];
-        let body = generate_upload_pack_content(&have, &want).await;
+        let body =
+            generate_upload_pack_content(&have, &want, &UploadPackOptions::default()).await;
         tracing::info!("upload-pack content: {:?}", body);
         let mut cmd = tokio::process::Command::new("/usr/bin/git-upload-pack");
         cmd.arg("..");
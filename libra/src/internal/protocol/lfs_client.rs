@@ -1,7 +1,7 @@
 use crate::command;
 use crate::internal::config::Config;
 use crate::internal::protocol::https_client::BasicAuth;
-use crate::internal::protocol::ProtocolClient;
+use crate::internal::protocol::{self, ProtocolClient};
 use crate::utils::{lfs, util};
 use ceres::lfs::lfs_structs::{BatchRequest, ChunkRepresentation, FetchchunkResponse, LockList, LockListQuery, LockRequest, ObjectError, Ref, Representation, RequestVars, UnlockRequest, VerifiableLockList, VerifiableLockRequest};
 use futures_util::StreamExt;
@@ -69,7 +69,7 @@ impl LFSClient {
     pub async fn new() -> Self {
         let url = Config::get_current_remote_url().await;
         match url {
-            Some(url) => LFSClient::from_url(&Url::parse(&url).unwrap()),
+            Some(url) => LFSClient::from_url(&protocol::parse_url(&url).unwrap()),
             None => panic!("fatal: no remote set for current branch, use `libra branch --set-upstream-to <remote>/<branch>`"),
         }
     }
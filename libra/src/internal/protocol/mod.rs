@@ -1,14 +1,216 @@
+use bytes::Bytes;
+use ceres::protocol::ServiceType;
+use futures::stream::BoxStream;
+use futures_util::StreamExt;
+use mercury::errors::GitError;
+use std::io::Error as IoError;
 use url::Url;
 
+use https_client::{DiscoveredReference, HttpsClient};
+use ssh_client::SshClient;
+
 pub mod https_client;
 pub mod lfs_client;
+pub mod ssh_client;
 
-#[allow(dead_code)] // todo: unimplemented
 pub trait ProtocolClient {
     /// create client from url
     fn from_url(url: &Url) -> Self;
 }
 
+/// Stream of the raw data sent or received
+pub type DataStream<'a> = BoxStream<'a, Result<Bytes, IoError>>;
+
+/// Parse remote repository url, support scp-style ssh url like `git@github.com:user/repo.git`,
+/// which will be converted to `ssh://git@github.com/~/user/repo.git`
+/// - the path is sent verbatim as the relative `user/repo.git`, unless it starts with `/` or `~`
+/// - `C:/repo` is not scp-style, single letter before the colon is a drive
+pub fn parse_url(url: &str) -> Result<Url, url::ParseError> {
+    if !url.contains("://") {
+        if let Some((host, path)) = url.split_once(':') {
+            // no slash before the first colon, or it's a local path
+            let is_drive = host.len() == 1 && host.chars().all(|c| c.is_ascii_alphabetic());
+            if !host.contains('/') && !host.is_empty() && !is_drive {
+                let url = if path.starts_with('/') {
+                    format!("ssh://{}{}", host, path)
+                } else if path.starts_with('~') {
+                    format!("ssh://{}/{}", host, path)
+                } else {
+                    format!("ssh://{}/~/{}", host, path)
+                };
+                return Url::parse(&url);
+            }
+        }
+    }
+    Url::parse(url)
+}
+
+/// Extra arguments of the `git-upload-pack` request, for shallow & partial clone
+#[derive(Debug, Clone, Default)]
+pub struct UploadPackOptions {
+    /// `deepen <depth>`: limit the history to `depth` commits from the wanted tips
+    pub depth: Option<usize>,
+    /// `deepen-since <timestamp>`: limit the history to the commits after the time
+    pub deepen_since: Option<i64>,
+    /// `filter <filter-spec>`: omit some objects from the pack, e.g. `blob:none`
+    pub filter: Option<String>,
+    /// The shallow commits the client already has, sent as `shallow <hash>`
+    pub shallow: Vec<String>,
+}
+
+impl UploadPackOptions {
+    /// The server sends `shallow`/`unshallow` lines before the pack only if the client is deepening
+    pub fn is_deepening(&self) -> bool {
+        self.depth.is_some() || self.deepen_since.is_some()
+    }
+
+    /// Check that the server supports the options, by the capabilities it advertised
+    pub fn check_capabilities(&self, capabilities: &[String]) -> Result<(), String> {
+        let required = [
+            (
+                self.depth.is_some() || !self.shallow.is_empty(),
+                "shallow",
+                "--depth",
+            ),
+            (
+                self.deepen_since.is_some(),
+                "deepen-since",
+                "--shallow-since",
+            ),
+            (self.filter.is_some(), "filter", "--filter"),
+        ];
+        for (used, capability, option) in required {
+            if used && !capabilities.iter().any(|c| c == capability) {
+                return Err(format!(
+                    "the server does not support {} (no `{}` capability)",
+                    option, capability
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Client of the remote repository, the transport is selected by url scheme
+pub enum RemoteClient {
+    Https(HttpsClient),
+    Ssh(SshClient),
+}
+
+impl ProtocolClient for RemoteClient {
+    /// `ssh://` for SSH, otherwise HTTP(S)
+    fn from_url(url: &Url) -> Self {
+        match url.scheme() {
+            "ssh" => RemoteClient::Ssh(SshClient::from_url(url)),
+            _ => RemoteClient::Https(HttpsClient::from_url(url)),
+        }
+    }
+}
+
+impl RemoteClient {
+    /// Discover the references & capabilities of the remote repository, the first ref named HEAD as default ref.
+    pub async fn discovery_reference(
+        &self,
+        service: ServiceType,
+    ) -> Result<(Vec<DiscoveredReference>, Vec<String>), GitError> {
+        match self {
+            RemoteClient::Https(client) => client.discovery_reference(service).await,
+            RemoteClient::Ssh(client) => client.discovery_reference(service).await,
+        }
+    }
+
+    /// Fetch the objects specified by `have` and `want`, return the raw response stream of `git-upload-pack`
+    pub async fn fetch_objects(
+        &self,
+        have: &Vec<String>,
+        want: &Vec<String>,
+        options: &UploadPackOptions,
+    ) -> Result<DataStream<'_>, IoError> {
+        match self {
+            RemoteClient::Https(client) => {
+                Ok(client.fetch_objects(have, want, options).await?.boxed())
+            }
+            RemoteClient::Ssh(client) => client
+                .fetch_objects(have, want, options)
+                .await
+                .map_err(|e| IoError::other(e.to_string())),
+        }
+    }
+
+    /// Send ref update commands & pack data (streamed) to `git-receive-pack`, return the report status
+    pub async fn send_pack(&self, data: DataStream<'static>) -> Result<Bytes, GitError> {
+        match self {
+            RemoteClient::Https(client) => {
+                let res = client
+                    .send_pack(data)
+                    .await
+                    .map_err(|e| GitError::NetworkError(e.to_string()))?;
+                if res.status() != 200 {
+                    eprintln!("status code: {}", res.status());
+                }
+                res.bytes()
+                    .await
+                    .map_err(|e| GitError::NetworkError(e.to_string()))
+            }
+            RemoteClient::Ssh(client) => client.send_pack(data).await,
+        }
+    }
+}
+
 #[cfg(test)]
 mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_url() {
+        let url = parse_url("git@github.com:web3infra-foundation/mega.git").unwrap();
+        assert_eq!(url.scheme(), "ssh");
+        assert_eq!(url.username(), "git");
+        assert_eq!(url.host_str(), Some("github.com"));
+        assert_eq!(url.path(), "/~/web3infra-foundation/mega.git");
+
+        let url = parse_url("git@github.com:/srv/mega.git").unwrap();
+        assert_eq!(url.path(), "/srv/mega.git");
+        let url = parse_url("github.com:~user/mega.git").unwrap();
+        assert_eq!(url.path(), "/~user/mega.git");
+        // windows path is not scp-style
+        assert_ne!(parse_url("C:/repo").unwrap().scheme(), "ssh");
+
+        let url = parse_url("ssh://git@github.com:22/web3infra-foundation/mega.git").unwrap();
+        assert_eq!(url.port(), Some(22));
+
+        let url = parse_url("https://github.com/web3infra-foundation/mega.git").unwrap();
+        assert_eq!(url.scheme(), "https");
+    }
+
+    #[test]
+    fn test_check_capabilities() {
+        let capabilities = vec!["shallow".to_string(), "filter".to_string()];
+        let options = UploadPackOptions {
+            depth: Some(1),
+            filter: Some("blob:none".to_string()),
+            ..Default::default()
+        };
+        assert!(options.check_capabilities(&capabilities).is_ok());
+        assert!(options.check_capabilities(&[]).is_err());
+
+        let options = UploadPackOptions {
+            deepen_since: Some(0),
+            ..Default::default()
+        };
+        let err = options.check_capabilities(&capabilities).unwrap_err();
+        assert!(err.contains("deepen-since"));
+        assert!(UploadPackOptions::default().check_capabilities(&[]).is_ok());
+    }
+
+    #[test]
+    fn test_select_client_by_scheme() {
+        let url = parse_url("git@github.com:web3infra-foundation/mega.git").unwrap();
+        assert!(matches!(RemoteClient::from_url(&url), RemoteClient::Ssh(_)));
+        let url = parse_url("https://github.com/web3infra-foundation/mega.git").unwrap();
+        assert!(matches!(
+            RemoteClient::from_url(&url),
+            RemoteClient::Https(_)
+        ));
+    }
 }
@@ -0,0 +1,427 @@
+use super::https_client::{
+    generate_upload_pack_content, parse_discovered_references, DiscoveredReference,
+};
+use super::{DataStream, ProtocolClient, UploadPackOptions};
+use async_trait::async_trait;
+use bytes::{Bytes, BytesMut};
+use ceres::protocol::ServiceType;
+use futures::stream::{self, Stream, StreamExt};
+use mercury::errors::GitError;
+use russh::client::{self, Handle};
+use russh::ChannelMsg;
+#[cfg(unix)]
+use russh_keys::agent::client::AgentClient;
+use russh_keys::key::PrivateKeyWithHashAlg;
+use russh_keys::{HashAlg, PublicKey};
+use std::io::{Error as IoError, IsTerminal};
+use std::path::PathBuf;
+use std::sync::Arc;
+use url::Url;
+
+const DEFAULT_SSH_PORT: u16 = 22;
+const DEFAULT_SSH_USER: &str = "git";
+/// private keys tried in order when the agent can't authenticate, same as OpenSSH
+const DEFAULT_KEY_FILES: [&str; 3] = ["id_ed25519", "id_ecdsa", "id_rsa"];
+
+/// A Git protocol client that communicates with a Git server over SSH.
+/// Run `git-upload-pack` or `git-receive-pack` on the remote, see [pack-protocol](https://git-scm.com/docs/pack-protocol).
+/// - authenticate by ssh-agent (`SSH_AUTH_SOCK`) first, then private keys in `~/.ssh`
+pub struct SshClient {
+    host: String,
+    port: u16,
+    user: String,
+    /// repo path on the server
+    path: String,
+}
+
+impl ProtocolClient for SshClient {
+    /// `url` must be `ssh://[user@]host[:port]/path`, scp-style url should be converted by [`super::parse_url`]
+    fn from_url(url: &Url) -> Self {
+        let user = match url.username() {
+            "" => DEFAULT_SSH_USER.to_owned(),
+            user => user.to_owned(),
+        };
+        Self {
+            host: url.host_str().unwrap_or_default().to_owned(),
+            port: url.port().unwrap_or(DEFAULT_SSH_PORT),
+            user,
+            path: remote_path(url.path()),
+        }
+    }
+}
+
+/// `/~/path` is sent as the relative `path` (scp-style `host:path` is converted to it),
+/// `/~user/path` as `~user/path`, both are resolved against the home directory on the server
+fn remote_path(url_path: &str) -> String {
+    let path = url_path.trim_end_matches('/');
+    if let Some(relative) = path.strip_prefix("/~/") {
+        relative.to_owned()
+    } else if path.starts_with("/~") {
+        path[1..].to_owned()
+    } else {
+        path.to_owned()
+    }
+}
+
+/// Handle server events, only check the host key now
+struct ClientHandler {
+    host: String,
+    port: u16,
+}
+
+#[async_trait]
+impl client::Handler for ClientHandler {
+    type Error = russh::Error;
+
+    /// Check the server key with `~/.ssh/known_hosts`, like `StrictHostKeyChecking=ask` of OpenSSH:
+    /// unknown hosts are added only if the user confirms the fingerprint, changed keys are rejected
+    async fn check_server_key(
+        &mut self,
+        server_public_key: &PublicKey,
+    ) -> Result<bool, Self::Error> {
+        match russh_keys::check_known_hosts(&self.host, self.port, server_public_key) {
+            Ok(true) => Ok(true),
+            Ok(false) => {
+                if !confirm_unknown_host(&self.host, server_public_key) {
+                    eprintln!("fatal: Host key verification failed.");
+                    return Ok(false);
+                }
+                eprintln!(
+                    "Warning: Permanently added '{}' ({}) to the list of known hosts.",
+                    self.host,
+                    server_public_key.algorithm()
+                );
+                if let Err(e) = russh_keys::known_hosts::learn_known_hosts(
+                    &self.host,
+                    self.port,
+                    server_public_key,
+                ) {
+                    tracing::warn!("failed to save known host: {:?}", e);
+                }
+                Ok(true)
+            }
+            Err(e) => {
+                eprintln!(
+                    "fatal: Host key verification failed for '{}': {}",
+                    self.host, e
+                );
+                Ok(false)
+            }
+        }
+    }
+}
+
+/// Ask the user whether to trust the unknown host, always refuse if stdin is not a terminal
+fn confirm_unknown_host(host: &str, key: &PublicKey) -> bool {
+    if !std::io::stdin().is_terminal() {
+        eprintln!("No {} host key is known for '{}'.", key.algorithm(), host);
+        return false;
+    }
+    eprintln!(
+        "The authenticity of host '{}' can't be established.\n{} key fingerprint is {}.",
+        host,
+        key.algorithm(),
+        key.fingerprint(HashAlg::Sha256)
+    );
+    loop {
+        eprint!("Are you sure you want to continue connecting (yes/no)? ");
+        let mut answer = String::new();
+        if std::io::stdin().read_line(&mut answer).unwrap_or(0) == 0 {
+            return false;
+        }
+        match answer.trim() {
+            "yes" => return true,
+            "no" => return false,
+            _ => eprintln!("Please type 'yes' or 'no'."),
+        }
+    }
+}
+
+// Same as HttpsClient, but each request runs the service in a new ssh channel,
+// so the references are advertised again and skipped before the request.
+impl SshClient {
+    /// Discover the references & capabilities of the remote repository, the first ref named HEAD as default ref.
+    pub async fn discovery_reference(
+        &self,
+        service: ServiceType,
+    ) -> Result<(Vec<DiscoveredReference>, Vec<String>), GitError> {
+        // a flush-pkt tells the server that the client doesn't want anything
+        let (advertisement, response) = self
+            .run_service(service, stream::iter([Ok(Bytes::from_static(b"0000"))]))
+            .await?;
+        collect_response(response).await?;
+        parse_discovered_references(advertisement, &service.to_string())
+    }
+
+    /// Fetch the objects from the remote repository, which is specified by `have` and `want`.<br>
+    /// Return the raw response stream of `git-upload-pack` (pkt-lines with side-band data), like `HttpsClient::fetch_objects`
+    pub async fn fetch_objects(
+        &self,
+        have: &Vec<String>,
+        want: &Vec<String>,
+        options: &UploadPackOptions,
+    ) -> Result<DataStream<'static>, GitError> {
+        let body = generate_upload_pack_content(have, want, options).await;
+        tracing::debug!("fetch_objects with body: {:?}", body);
+        let (_, response) = self
+            .run_service(ServiceType::UploadPack, stream::iter([Ok(body)]))
+            .await?;
+        Ok(response)
+    }
+
+    /// Send ref update commands & pack data to `git-receive-pack`, return the report status
+    pub async fn send_pack(&self, data: DataStream<'static>) -> Result<Bytes, GitError> {
+        let (_, response) = self.run_service(ServiceType::ReceivePack, data).await?;
+        collect_response(response).await
+    }
+
+    /// Run `service` on the server, send `request` (in chunks) after the references advertisement.
+    /// - return (advertisement, response stream), the stream ends with an error if the service exits with non-zero status
+    async fn run_service(
+        &self,
+        service: ServiceType,
+        mut request: impl Stream<Item = Result<Bytes, IoError>> + Unpin,
+    ) -> Result<(Bytes, DataStream<'static>), GitError> {
+        let session = self.connect().await?;
+        let mut channel = session
+            .channel_open_session()
+            .await
+            .map_err(network_error)?;
+        let command = format!("{} {}", service, shell_quote(&self.path));
+        tracing::debug!("ssh exec: {}", command);
+        channel.exec(true, command).await.map_err(network_error)?;
+
+        let mut stdout = BytesMut::new();
+        let mut exit_status = None;
+        let advertisement = loop {
+            if let Some(end) = advertisement_end(&stdout) {
+                break stdout.split_to(end).freeze();
+            }
+            match channel.wait().await {
+                Some(ChannelMsg::Data { data }) => stdout.extend_from_slice(&data),
+                Some(ChannelMsg::ExtendedData { data, ext: 1 }) => {
+                    eprint!("remote: {}", String::from_utf8_lossy(&data)); // stderr
+                }
+                Some(ChannelMsg::ExitStatus { exit_status: code }) => exit_status = Some(code),
+                Some(ChannelMsg::Close) | None => {
+                    return Err(GitError::NetworkError(format!(
+                        "'{}' did not advertise references, exit status: {:?}",
+                        service, exit_status
+                    )));
+                }
+                Some(_) => {}
+            }
+        };
+        while let Some(chunk) = request.next().await {
+            let chunk = chunk.map_err(network_error)?;
+            channel.data(&chunk[..]).await.map_err(network_error)?;
+        }
+        channel.eof().await.map_err(network_error)?;
+
+        // the rest of stdout is read lazily, the session is closed at the end
+        let state = Some((session, channel, exit_status));
+        let response = stream::unfold(state, move |state| async move {
+            let (session, mut channel, mut exit_status) = state?;
+            loop {
+                match channel.wait().await {
+                    Some(ChannelMsg::Data { data }) => {
+                        let data = Bytes::copy_from_slice(&data);
+                        return Some((Ok(data), Some((session, channel, exit_status))));
+                    }
+                    Some(ChannelMsg::ExtendedData { data, ext: 1 }) => {
+                        eprint!("remote: {}", String::from_utf8_lossy(&data)); // stderr
+                    }
+                    Some(ChannelMsg::ExitStatus { exit_status: code }) => exit_status = Some(code),
+                    Some(ChannelMsg::Close) | None => break,
+                    Some(_) => {}
+                }
+            }
+            session
+                .disconnect(russh::Disconnect::ByApplication, "", "English")
+                .await
+                .ok();
+            match exit_status {
+                Some(code) if code != 0 => {
+                    let msg = format!("'{}' exited with status {}", service, code);
+                    Some((Err(IoError::other(msg)), None))
+                }
+                _ => None,
+            }
+        });
+        let rest = (!stdout.is_empty()).then(|| Ok(stdout.freeze()));
+        Ok((advertisement, stream::iter(rest).chain(response).boxed()))
+    }
+
+    /// Connect & authenticate
+    async fn connect(&self) -> Result<Handle<ClientHandler>, GitError> {
+        let config = Arc::new(client::Config::default());
+        let handler = ClientHandler {
+            host: self.host.clone(),
+            port: self.port,
+        };
+        let mut session = client::connect(config, (self.host.as_str(), self.port), handler)
+            .await
+            .map_err(network_error)?;
+
+        if self.auth_with_agent(&mut session).await || self.auth_with_key_files(&mut session).await
+        {
+            Ok(session)
+        } else {
+            Err(GitError::UnAuthorized(format!(
+                "{}@{}: Permission denied (publickey)",
+                self.user, self.host
+            )))
+        }
+    }
+
+    /// Try all identities of ssh-agent
+    #[cfg(unix)]
+    async fn auth_with_agent(&self, session: &mut Handle<ClientHandler>) -> bool {
+        let mut agent = match AgentClient::connect_env().await {
+            Ok(agent) => agent,
+            Err(e) => {
+                tracing::debug!("ssh-agent not available: {:?}", e);
+                return false;
+            }
+        };
+        let identities = agent.request_identities().await.unwrap_or_default();
+        for key in identities {
+            match session
+                .authenticate_publickey_with(&self.user, key, &mut agent)
+                .await
+            {
+                Ok(true) => return true,
+                Ok(false) => continue,
+                Err(e) => tracing::debug!("ssh-agent authentication failed: {:?}", e),
+            }
+        }
+        false
+    }
+
+    /// ssh-agent is only supported on unix (`SSH_AUTH_SOCK`) now
+    #[cfg(not(unix))]
+    async fn auth_with_agent(&self, _session: &mut Handle<ClientHandler>) -> bool {
+        false
+    }
+
+    /// Try private keys in `~/.ssh`, ask for passphrase if the key is encrypted
+    async fn auth_with_key_files(&self, session: &mut Handle<ClientHandler>) -> bool {
+        let ssh_dir = match home::home_dir() {
+            Some(home) => home.join(".ssh"),
+            None => return false,
+        };
+        let key_files: Vec<PathBuf> = DEFAULT_KEY_FILES
+            .iter()
+            .map(|name| ssh_dir.join(name))
+            .filter(|file| file.exists())
+            .collect();
+        for file in key_files {
+            let key = match russh_keys::load_secret_key(&file, None) {
+                Ok(key) => key,
+                Err(_) => {
+                    // maybe encrypted
+                    let prompt = format!("Enter passphrase for key '{}': ", file.display());
+                    let passphrase = rpassword::prompt_password(prompt).unwrap_or_default();
+                    match russh_keys::load_secret_key(&file, Some(&passphrase)) {
+                        Ok(key) => key,
+                        Err(e) => {
+                            eprintln!("Load key '{}' failed: {}", file.display(), e);
+                            continue;
+                        }
+                    }
+                }
+            };
+            // `ssh-rsa` (SHA-1) signature is disabled by most servers
+            let hash_alg = key.algorithm().is_rsa().then_some(HashAlg::Sha256);
+            let key = match PrivateKeyWithHashAlg::new(Arc::new(key), hash_alg) {
+                Ok(key) => key,
+                Err(e) => {
+                    tracing::debug!("unsupported key '{}': {:?}", file.display(), e);
+                    continue;
+                }
+            };
+            if let Ok(true) = session.authenticate_publickey(&self.user, key).await {
+                return true;
+            }
+        }
+        false
+    }
+}
+
+/// Read the whole response, fail if the remote service failed
+async fn collect_response(mut response: DataStream<'static>) -> Result<Bytes, GitError> {
+    let mut data = BytesMut::new();
+    while let Some(chunk) = response.next().await {
+        data.extend_from_slice(&chunk.map_err(network_error)?);
+    }
+    Ok(data.freeze())
+}
+
+/// Quote the argument for the remote shell, `'` is escaped as `'\''`
+fn shell_quote(arg: &str) -> String {
+    format!("'{}'", arg.replace('\'', "'\\''"))
+}
+
+/// Find the end of references advertisement (after the first flush-pkt)
+fn advertisement_end(data: &[u8]) -> Option<usize> {
+    let mut pos = 0;
+    while pos + 4 <= data.len() {
+        let len = std::str::from_utf8(&data[pos..pos + 4])
+            .ok()
+            .and_then(|hex| usize::from_str_radix(hex, 16).ok())?;
+        if len == 0 {
+            return Some(pos + 4);
+        }
+        pos += len;
+    }
+    None
+}
+
+fn network_error(e: impl std::fmt::Display) -> GitError {
+    GitError::NetworkError(e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_from_url() {
+        let url = Url::parse("ssh://git@github.com:2222/web3infra-foundation/mega.git/").unwrap();
+        let client = SshClient::from_url(&url);
+        assert_eq!(client.host, "github.com");
+        assert_eq!(client.port, 2222);
+        assert_eq!(client.user, "git");
+        assert_eq!(client.path, "/web3infra-foundation/mega.git");
+
+        let client = SshClient::from_url(&Url::parse("ssh://localhost/repo").unwrap());
+        assert_eq!(client.port, DEFAULT_SSH_PORT);
+        assert_eq!(client.user, DEFAULT_SSH_USER);
+
+        let client = SshClient::from_url(&Url::parse("ssh://localhost/~/repo.git").unwrap());
+        assert_eq!(client.path, "repo.git");
+        let client = SshClient::from_url(&Url::parse("ssh://localhost/~user/repo.git").unwrap());
+        assert_eq!(client.path, "~user/repo.git");
+
+        // scp-style relative path is sent verbatim
+        let url = super::super::parse_url("git@github.com:web3infra-foundation/mega.git").unwrap();
+        assert_eq!(
+            SshClient::from_url(&url).path,
+            "web3infra-foundation/mega.git"
+        );
+    }
+
+    #[test]
+    fn test_shell_quote() {
+        assert_eq!(shell_quote("/srv/repo.git"), "'/srv/repo.git'");
+        assert_eq!(shell_quote("/srv/it's.git"), "'/srv/it'\\''s.git'");
+    }
+
+    #[test]
+    fn test_advertisement_end() {
+        let data = b"003d7ef152d43162e28b3177f6df380112f6412f5b42 refs/heads/main\n0000NAK";
+        assert_eq!(advertisement_end(data), Some(data.len() - 3));
+        assert_eq!(advertisement_end(b"003f7ef152d4"), None);
+        assert_eq!(advertisement_end(b"0000"), Some(4));
+    }
+}
@@ -0,0 +1,235 @@
+use std::str::FromStr;
+
+use sea_orm::ActiveValue::Set;
+use sea_orm::{
+    ActiveModelTrait, ColumnTrait, EntityTrait, ModelTrait, QueryFilter, QueryOrder, QuerySelect,
+};
+
+use mercury::hash::SHA1;
+
+use crate::internal::branch::Branch;
+use crate::internal::config::Config;
+use crate::internal::db::get_db_conn_instance;
+use crate::internal::head::Head;
+use crate::internal::model::reflog;
+use crate::internal::worktree::Worktree;
+use crate::utils::util;
+
+pub const HEAD: &str = "HEAD";
+
+/// A reflog entry, `<ref>@{0}` is the latest one
+#[derive(Debug, Clone)]
+pub struct Reflog {
+    id: i64,
+    pub ref_name: String,
+    /// `None` if the ref is created
+    pub old: Option<SHA1>,
+    pub new: SHA1,
+    pub committer: String,
+    pub timestamp: i64,
+    pub message: String,
+}
+
+impl From<reflog::Model> for Reflog {
+    fn from(entry: reflog::Model) -> Self {
+        let old = SHA1::from_str(&entry.old_oid).unwrap();
+        Reflog {
+            id: entry.id,
+            ref_name: entry.ref_name,
+            old: (old != SHA1::default()).then_some(old),
+            new: SHA1::from_str(&entry.new_oid).unwrap(),
+            committer: entry.committer,
+            timestamp: entry.timestamp,
+            message: entry.message,
+        }
+    }
+}
+
+impl Reflog {
+    /// The full ref name of a branch, e.g. `refs/heads/main` or `refs/remotes/origin/main`
+    pub fn branch_ref(branch_name: &str, remote: Option<&str>) -> String {
+        match remote {
+            Some(remote) => format!("refs/remotes/{}/{}", remote, branch_name),
+            None => format!("refs/heads/{}", branch_name),
+        }
+    }
+
+    /// Resolve the ref name used in `<ref>@{...}` or `libra reflog <ref>` to the full ref name
+    /// - empty name means the current branch (or HEAD if detached)
+    pub async fn full_ref_name(name: &str) -> Result<String, String> {
+        if name.is_empty() {
+            return Ok(match Head::current().await {
+                Head::Branch(branch) => Self::branch_ref(&branch, None),
+                Head::Detached(_) => HEAD.to_string(),
+            });
+        }
+        if name == HEAD || name.starts_with("refs/") {
+            return Ok(name.to_string());
+        }
+        let branches = Branch::search_branch(name).await;
+        match &branches[..] {
+            [] => Err(format!("no such ref: '{}'", name)),
+            [branch] => Ok(Self::branch_ref(&branch.name, branch.remote.as_deref())),
+            _ => Err(format!("ambiguous ref: '{}'", name)),
+        }
+    }
+
+    /// Record a movement of the ref
+    pub async fn append(ref_name: &str, old: Option<SHA1>, new: SHA1, message: &str) {
+        let db_conn = get_db_conn_instance().await;
+        reflog::ActiveModel {
+            ref_name: Set(stored_ref_name(ref_name)),
+            old_oid: Set(old.unwrap_or_default().to_string()),
+            new_oid: Set(new.to_string()),
+            committer: Set(committer().await),
+            timestamp: Set(chrono::Utc::now().timestamp()),
+            message: Set(message.to_owned()),
+            ..Default::default()
+        }
+        .insert(db_conn)
+        .await
+        .unwrap();
+    }
+
+    /// list all entries of the ref, the latest first
+    pub async fn list(ref_name: &str) -> Vec<Self> {
+        let db_conn = get_db_conn_instance().await;
+        reflog::Entity::find()
+            .filter(reflog::Column::RefName.eq(stored_ref_name(ref_name)))
+            .order_by_desc(reflog::Column::Id)
+            .all(db_conn)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(Reflog::from)
+            .collect()
+    }
+
+    /// get `<ref>@{n}`
+    pub async fn find(ref_name: &str, n: usize) -> Option<Self> {
+        Self::list(ref_name).await.into_iter().nth(n)
+    }
+
+    /// All refs which have reflog
+    pub async fn refs() -> Vec<String> {
+        let db_conn = get_db_conn_instance().await;
+        reflog::Entity::find()
+            .select_only()
+            .column(reflog::Column::RefName)
+            .distinct()
+            .into_tuple::<String>()
+            .all(db_conn)
+            .await
+            .unwrap()
+    }
+
+    /// delete the entry, later entries are renumbered
+    pub async fn delete(&self) {
+        let db_conn = get_db_conn_instance().await;
+        if let Some(entry) = reflog::Entity::find_by_id(self.id)
+            .one(db_conn)
+            .await
+            .unwrap()
+        {
+            entry.delete(db_conn).await.unwrap();
+        }
+    }
+
+    /// delete the entries of the ref older than `timestamp` (all entries if `None`), return the number of deleted entries
+    pub async fn expire(ref_name: &str, timestamp: Option<i64>) -> u64 {
+        let db_conn = get_db_conn_instance().await;
+        let mut query = reflog::Entity::delete_many()
+            .filter(reflog::Column::RefName.eq(stored_ref_name(ref_name)));
+        if let Some(timestamp) = timestamp {
+            query = query.filter(reflog::Column::Timestamp.lt(timestamp));
+        }
+        query.exec(db_conn).await.unwrap().rows_affected
+    }
+
+    /// Resolve `<ref>@{<selector>}`, `selector` is `n` (the `n`th prior value) or a date (the value at that time)
+    pub async fn resolve(ref_name: &str, selector: &str) -> Result<SHA1, String> {
+        let entries = Self::list(ref_name).await;
+        let oldest = entries
+            .last()
+            .ok_or(format!("log for '{}' is empty", ref_name))?;
+        if let Ok(n) = selector.parse::<usize>() {
+            return match entries.get(n) {
+                Some(entry) => Ok(entry.new),
+                // the value before the oldest entry
+                None if n == entries.len() && oldest.old.is_some() => Ok(oldest.old.unwrap()),
+                None => Err(format!(
+                    "log for '{}' only has {} entries",
+                    ref_name,
+                    entries.len()
+                )),
+            };
+        }
+        let timestamp = util::parse_date(selector)?;
+        match entries.iter().find(|entry| entry.timestamp <= timestamp) {
+            Some(entry) => Ok(entry.new),
+            None => {
+                eprintln!(
+                    "warning: log for '{}' only goes back to {}",
+                    ref_name,
+                    chrono::DateTime::from_timestamp(oldest.timestamp, 0).unwrap()
+                );
+                Ok(oldest.old.unwrap_or(oldest.new))
+            }
+        }
+    }
+}
+
+/// HEAD of a linked worktree is logged as `worktrees/<name>/HEAD`, like git
+fn stored_ref_name(ref_name: &str) -> String {
+    match Worktree::current() {
+        Some(worktree) if ref_name == HEAD => format!("worktrees/{}/HEAD", worktree.name()),
+        _ => ref_name.to_string(),
+    }
+}
+
+/// `user.name <user.email>` in config, same default as commits
+async fn committer() -> String {
+    let name = Config::get("user", None, "name").await;
+    let email = Config::get("user", None, "email").await;
+    format!(
+        "{} <{}>",
+        name.as_deref().unwrap_or("mega"),
+        email.as_deref().unwrap_or("admin@mega.org")
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::utils::test;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_reflog_resolve() {
+        test::setup_with_new_libra().await;
+        let ref_name = Reflog::branch_ref("reflog_test", None);
+        let (first, second) = (SHA1::new(&[1; 20]), SHA1::new(&[2; 20]));
+        Reflog::append(&ref_name, None, first, "branch: Created").await;
+        Reflog::append(&ref_name, Some(first), second, "commit: second").await;
+
+        let entries = Reflog::list(&ref_name).await;
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].new, second);
+        assert_eq!(entries[1].old, None);
+        assert!(Reflog::refs().await.contains(&ref_name));
+
+        assert_eq!(Reflog::resolve(&ref_name, "0").await.unwrap(), second);
+        assert_eq!(Reflog::resolve(&ref_name, "1").await.unwrap(), first);
+        assert!(Reflog::resolve(&ref_name, "2").await.is_err());
+        assert_eq!(Reflog::resolve(&ref_name, "now").await.unwrap(), second);
+        assert_eq!(
+            Reflog::resolve(&ref_name, "yesterday").await.unwrap(),
+            first
+        );
+
+        Reflog::find(&ref_name, 0).await.unwrap().delete().await;
+        assert_eq!(Reflog::resolve(&ref_name, "0").await.unwrap(), first);
+        assert_eq!(Reflog::expire(&ref_name, None).await, 1);
+        assert!(Reflog::resolve(&ref_name, "0").await.is_err());
+    }
+}
@@ -0,0 +1,86 @@
+use std::str::FromStr;
+
+use sea_orm::ActiveValue::Set;
+use sea_orm::{ActiveModelTrait, EntityTrait, ModelTrait, QueryOrder};
+
+use mercury::hash::SHA1;
+
+use crate::internal::db::get_db_conn_instance;
+use crate::internal::model::stash;
+
+/// A stash entry, `stash@{0}` is the latest one
+#[derive(Debug, Clone)]
+pub struct Stash {
+    id: i64,
+    pub commit: SHA1,
+}
+
+impl Stash {
+    /// list all stash entries, the latest first
+    pub async fn list() -> Vec<Self> {
+        let db_conn = get_db_conn_instance().await;
+        stash::Entity::find()
+            .order_by_desc(stash::Column::Id)
+            .all(db_conn)
+            .await
+            .unwrap()
+            .iter()
+            .map(|entry| Stash {
+                id: entry.id,
+                commit: SHA1::from_str(&entry.commit).unwrap(),
+            })
+            .collect()
+    }
+
+    /// get `stash@{n}`
+    pub async fn find(n: usize) -> Option<Self> {
+        Self::list().await.into_iter().nth(n)
+    }
+
+    /// push a new entry as `stash@{0}`
+    pub async fn push(commit: &SHA1) {
+        let db_conn = get_db_conn_instance().await;
+        stash::ActiveModel {
+            commit: Set(commit.to_string()),
+            ..Default::default()
+        }
+        .insert(db_conn)
+        .await
+        .unwrap();
+    }
+
+    /// delete the entry, later entries are renumbered
+    pub async fn delete(&self) {
+        let db_conn = get_db_conn_instance().await;
+        if let Some(entry) = stash::Entity::find_by_id(self.id)
+            .one(db_conn)
+            .await
+            .unwrap()
+        {
+            entry.delete(db_conn).await.unwrap();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::utils::test;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_stash_order() {
+        test::setup_with_new_libra().await;
+        let (first, second) = (SHA1::new(&[1; 20]), SHA1::new(&[2; 20]));
+        Stash::push(&first).await;
+        Stash::push(&second).await;
+
+        let stashes = Stash::list().await;
+        assert_eq!(stashes.len(), 2);
+        assert_eq!(stashes[0].commit, second);
+
+        Stash::find(0).await.unwrap().delete().await;
+        assert_eq!(Stash::find(0).await.unwrap().commit, first);
+        assert!(Stash::find(1).await.is_none());
+    }
+}
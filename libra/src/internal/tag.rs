@@ -0,0 +1,114 @@
+use std::str::FromStr;
+
+use sea_orm::ActiveModelTrait;
+use sea_orm::ActiveValue::Set;
+use sea_orm::{ColumnTrait, EntityTrait, QueryFilter, QueryOrder};
+
+use mercury::hash::SHA1;
+
+use crate::internal::db::get_db_conn_instance;
+use crate::internal::model::reference;
+
+/// A tag reference (`refs/tags/<name>`), tags are shared by local & remote
+/// - `object` is the tag object for annotated tag, or the commit for lightweight tag
+#[derive(Debug, Clone)]
+pub struct Tag {
+    pub name: String,
+    pub object: SHA1,
+}
+
+async fn query_reference(tag_name: &str) -> Option<reference::Model> {
+    let db_conn = get_db_conn_instance().await;
+    reference::Entity::find()
+        .filter(reference::Column::Name.eq(tag_name))
+        .filter(reference::Column::Kind.eq(reference::ConfigKind::Tag))
+        .one(db_conn)
+        .await
+        .unwrap()
+}
+
+impl Tag {
+    /// list all tags, sorted by name
+    pub async fn list_tags() -> Vec<Self> {
+        let db_conn = get_db_conn_instance().await;
+        reference::Entity::find()
+            .filter(reference::Column::Kind.eq(reference::ConfigKind::Tag))
+            .order_by_asc(reference::Column::Name)
+            .all(db_conn)
+            .await
+            .unwrap()
+            .iter()
+            .map(|tag| Tag {
+                name: tag.name.as_ref().unwrap().clone(),
+                object: SHA1::from_str(tag.commit.as_ref().unwrap()).unwrap(),
+            })
+            .collect()
+    }
+
+    /// get the tag by name
+    pub async fn find_tag(tag_name: &str) -> Option<Self> {
+        query_reference(tag_name).await.map(|tag| Tag {
+            name: tag.name.unwrap(),
+            object: SHA1::from_str(&tag.commit.unwrap()).unwrap(),
+        })
+    }
+
+    pub async fn exists(tag_name: &str) -> bool {
+        query_reference(tag_name).await.is_some()
+    }
+
+    /// create or move the tag
+    pub async fn update_tag(tag_name: &str, object: &SHA1) {
+        let db_conn = get_db_conn_instance().await;
+        match query_reference(tag_name).await {
+            Some(tag) => {
+                let mut tag: reference::ActiveModel = tag.into();
+                tag.commit = Set(Some(object.to_string()));
+                tag.update(db_conn).await.unwrap();
+            }
+            None => {
+                reference::ActiveModel {
+                    name: Set(Some(tag_name.to_owned())),
+                    kind: Set(reference::ConfigKind::Tag),
+                    commit: Set(Some(object.to_string())),
+                    remote: Set(None),
+                    ..Default::default()
+                }
+                .insert(db_conn)
+                .await
+                .unwrap();
+            }
+        }
+    }
+
+    pub async fn delete_tag(tag_name: &str) {
+        let db_conn = get_db_conn_instance().await;
+        let tag: reference::ActiveModel = query_reference(tag_name).await.unwrap().into();
+        tag.delete(db_conn).await.unwrap();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::utils::test;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_update_delete_tag() {
+        test::setup_with_new_libra().await;
+        let (first, second) = (SHA1::new(&[1; 20]), SHA1::new(&[2; 20]));
+        Tag::update_tag("v2", &first).await;
+        Tag::update_tag("v1", &first).await;
+        Tag::update_tag("v1", &second).await;
+
+        let tags = Tag::list_tags().await;
+        assert_eq!(tags.len(), 2);
+        assert_eq!(tags[0].name, "v1");
+        assert_eq!(tags[0].object, second);
+
+        Tag::delete_tag("v2").await;
+        assert!(!Tag::exists("v2").await);
+        assert_eq!(Tag::find_tag("v1").await.unwrap().object, second);
+    }
+}
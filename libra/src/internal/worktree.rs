@@ -0,0 +1,160 @@
+//! Linked worktrees share the objects & database with the main worktree,
+//! but each one has its own HEAD, index and in-progress states, stored in `.libra/worktrees/<name>`:
+//! - `libradir`: the `.libra` file in the linked worktree, which points back to this directory
+//! - `HEAD`: `ref: refs/heads/<branch>` or the commit hash of detached HEAD
+//! - `locked`: the reason of `libra worktree lock`, exists only if locked
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use mercury::hash::SHA1;
+use path_absolutize::*;
+
+use crate::internal::head::Head;
+use crate::utils::{path, util};
+
+const LIBRADIR: &str = "libradir";
+const HEAD: &str = "HEAD";
+const LOCKED: &str = "locked";
+const BRANCH_PREFIX: &str = "ref: refs/heads/";
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Worktree {
+    /// `.libra/worktrees/<name>` of the main worktree
+    pub dir: PathBuf,
+}
+
+impl Worktree {
+    /// All linked worktrees, sorted by name
+    pub fn list() -> Vec<Self> {
+        let mut worktrees: Vec<Self> = fs::read_dir(path::worktrees())
+            .into_iter()
+            .flatten()
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|dir| dir.join(HEAD).is_file())
+            .map(|dir| Worktree { dir })
+            .collect();
+        worktrees.sort_by(|a, b| a.dir.cmp(&b.dir));
+        worktrees
+    }
+
+    /// The linked worktree of the current directory, `None` in the main worktree
+    pub fn current() -> Option<Self> {
+        let dir = util::worktree_storage_path();
+        (dir != util::storage_path()).then_some(Worktree { dir })
+    }
+
+    /// Find the linked worktree by its name or working directory
+    pub fn find(name_or_path: &str) -> Option<Self> {
+        let path = util::cur_dir().join(name_or_path);
+        let path = path.absolutize().map(|p| p.to_path_buf()).unwrap_or(path);
+        Self::list()
+            .into_iter()
+            .find(|worktree| worktree.name() == name_or_path || worktree.path() == path)
+    }
+
+    /// Create the storage of a new worktree at `path`, and the `.libra` file pointing to it
+    /// - `path` must be absolute, the directory is created if not exists
+    pub fn create(name: &str, path: &Path, head: &Head) -> std::io::Result<Self> {
+        let dir = path::worktrees().join(name);
+        fs::create_dir_all(&dir)?;
+        fs::create_dir_all(path)?;
+        let libra_file = path.join(util::ROOT_DIR);
+        fs::write(
+            &libra_file,
+            format!("{}{}\n", util::LIBRA_FILE_PREFIX, dir.display()),
+        )?;
+        fs::write(dir.join(LIBRADIR), format!("{}\n", libra_file.display()))?;
+        let worktree = Worktree { dir };
+        worktree.write_head(head);
+        Ok(worktree)
+    }
+
+    pub fn name(&self) -> String {
+        self.dir.file_name().unwrap().to_string_lossy().to_string()
+    }
+
+    /// The working directory of the worktree
+    pub fn path(&self) -> PathBuf {
+        let libra_file = fs::read_to_string(self.dir.join(LIBRADIR)).unwrap_or_default();
+        let mut path = PathBuf::from(libra_file.trim_end());
+        path.pop();
+        path
+    }
+
+    /// The working directory has been deleted without `libra worktree remove`
+    pub fn is_prunable(&self) -> bool {
+        !self.path().join(util::ROOT_DIR).is_file()
+    }
+
+    /// The `.libra` file in the working directory exists and points back to this worktree,
+    /// otherwise the directory may have been moved, deleted or reused by something else
+    pub fn validate(&self) -> Result<(), String> {
+        let libra_file = self.path().join(util::ROOT_DIR);
+        match util::read_libra_file(&libra_file) {
+            Ok(dir) if dir == self.dir => Ok(()),
+            Ok(_) => Err(format!(
+                "'{}' does not point back to '{}'",
+                libra_file.display(),
+                self.dir.display()
+            )),
+            Err(_) => Err(format!("'{}' does not exist", libra_file.display())),
+        }
+    }
+
+    /// The reason of the lock (may be empty), `None` if not locked
+    pub fn locked(&self) -> Option<String> {
+        fs::read_to_string(self.dir.join(LOCKED))
+            .ok()
+            .map(|reason| reason.trim_end().to_string())
+    }
+
+    /// A locked worktree is not removed or pruned, e.g. on a portable device which is not always mounted
+    pub fn lock(&self, reason: &str) {
+        fs::write(self.dir.join(LOCKED), reason).unwrap();
+    }
+
+    pub fn unlock(&self) {
+        let _ = fs::remove_file(self.dir.join(LOCKED));
+    }
+
+    pub fn head(&self) -> Head {
+        let head = fs::read_to_string(self.dir.join(HEAD)).unwrap();
+        let head = head.trim_end();
+        match head.strip_prefix(BRANCH_PREFIX) {
+            Some(branch) => Head::Branch(branch.to_string()),
+            None => Head::Detached(
+                SHA1::from_str(head).expect("fatal: storage broken, invalid worktree HEAD"),
+            ),
+        }
+    }
+
+    pub fn write_head(&self, head: &Head) {
+        let content = match head {
+            Head::Branch(name) => format!("{}{}\n", BRANCH_PREFIX, name),
+            Head::Detached(commit) => format!("{}\n", commit),
+        };
+        fs::write(self.dir.join(HEAD), content).unwrap();
+    }
+
+    /// Remove the storage of the worktree, the working directory is not touched
+    pub fn delete(&self) {
+        fs::remove_dir_all(&self.dir).unwrap();
+        let _ = fs::remove_dir(path::worktrees()); // only if empty
+    }
+}
+
+/// Find the worktree (other than the current one) which has checked out the branch, return its working directory.
+/// A branch can't be checked out by two worktrees, otherwise a commit in one of them would make the other dirty.
+pub async fn find_checkout(branch: &str) -> Option<PathBuf> {
+    let current = Worktree::current();
+    let is_on = |head: Head| matches!(head, Head::Branch(name) if name == branch);
+    if current.is_some() && is_on(Head::main_worktree().await) {
+        return util::storage_path().parent().map(PathBuf::from);
+    }
+    Worktree::list()
+        .into_iter()
+        .filter(|worktree| Some(worktree) != current.as_ref())
+        .find(|worktree| is_on(worktree.head()))
+        .map(|worktree| worktree.path())
+}
@@ -18,9 +18,11 @@ fn main() {
     match res {
         Ok(_) => {}
         Err(e) => {
-            if !matches!(e, GitError::RepoNotFound) {
+            // already reported by the command, or the silent `cat-file -e`
+            if !matches!(e, GitError::RepoNotFound | GitError::ObjectNotFound(_)) {
                 eprintln!("Error: {:?}", e);
             }
+            std::process::exit(1);
         }
     }
 }
\ No newline at end of file
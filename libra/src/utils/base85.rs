@@ -0,0 +1,64 @@
+//! The base85 encoding of git binary patches (not Ascii85): every 4 bytes are encoded to 5 characters.
+
+const ALPHABET: &[u8; 85] =
+    b"0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz!#$%&()*+-;<=>?@^_`{|}~";
+
+/// Encode the data, the last group is padded with zeros
+pub fn encode(data: &[u8]) -> String {
+    let mut encoded = String::with_capacity(data.len().div_ceil(4) * 5);
+    for group in data.chunks(4) {
+        let mut bytes = [0u8; 4];
+        bytes[..group.len()].copy_from_slice(group);
+        let mut value = u32::from_be_bytes(bytes);
+        let mut chars = [0u8; 5];
+        for c in chars.iter_mut().rev() {
+            *c = ALPHABET[(value % 85) as usize];
+            value /= 85;
+        }
+        encoded.extend(chars.iter().map(|&c| c as char));
+    }
+    encoded
+}
+
+/// Decode `len` bytes from the encoded text
+pub fn decode(text: &str, len: usize) -> Result<Vec<u8>, String> {
+    let text = text.as_bytes();
+    if !text.len().is_multiple_of(5) || text.len() / 5 * 4 < len {
+        return Err(format!("invalid base85 length {}", text.len()));
+    }
+    let mut data = Vec::with_capacity(text.len() / 5 * 4);
+    for group in text.chunks(5) {
+        let mut value: u32 = 0;
+        for &c in group {
+            let digit = ALPHABET
+                .iter()
+                .position(|&a| a == c)
+                .ok_or_else(|| format!("invalid base85 character '{}'", c as char))?;
+            value = value
+                .checked_mul(85)
+                .and_then(|v| v.checked_add(digit as u32))
+                .ok_or("invalid base85 data")?;
+        }
+        data.extend(value.to_be_bytes());
+    }
+    data.truncate(len);
+    Ok(data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_base85() {
+        let data = b"libra binary patch\x00\xff";
+        let encoded = encode(data);
+        assert_eq!(encoded.len(), 25);
+        assert_eq!(decode(&encoded, data.len()).unwrap(), data);
+        // 0 bytes
+        assert_eq!(encode(&[0, 0, 0, 0]), "00000");
+        assert_eq!(encode(&[0xff; 4]), "|NsC0");
+        assert!(decode("abc", 2).is_err());
+        assert!(decode("~~~~~", 4).is_err());
+    }
+}
@@ -1,9 +1,11 @@
 use std::{fs, io};
+use std::cmp::Ordering;
 use std::collections::HashSet;
 use std::io::prelude::*;
 use std::path::{Path, PathBuf};
 use std::str::FromStr;
 use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
 
 use byteorder::{BigEndian, ReadBytesExt};
 use flate2::Compression;
@@ -19,6 +21,7 @@ use mercury::internal::object::types::ObjectType;
 use mercury::utils::read_sha1;
 
 use crate::command;
+use crate::command::index_pack::IDX_V2_MAGIC;
 static PACK_OBJ_CACHE: Lazy<Mutex<LruCache<String, CacheObject>>> = Lazy::new(|| {
     // `lazy_static!` may affect IDE's code completion
     Mutex::new(LruCache::new(1024 * 1024 * 200))
@@ -53,16 +56,7 @@ impl ClientStorage {
     }
 
     pub fn get_object_type(&self, obj_id: &SHA1) -> Result<ObjectType, GitError> {
-        if self.exist_loosely(obj_id) {
-            let raw_data = self.read_raw_data(obj_id)?;
-            let data = Self::decompress_zlib(&raw_data)?;
-            let (obj_type, _, _) = Self::parse_header(&data);
-            ObjectType::from_string(&obj_type)
-        } else {
-            self.get_from_pack(obj_id)?
-                .map(|x| x.1)
-                .ok_or(GitError::ObjectNotFound(obj_id.to_string()))
-        }
+        self.get_with_type(obj_id).map(|x| x.1)
     }
 
     /// Check if the object with `obj_id` is of type `obj_type`
@@ -84,7 +78,7 @@ impl ClientStorage {
     }
 
     /// list all objects' hash in `objects`
-    fn list_objects_loose(&self) -> Vec<SHA1> {
+    pub fn list_objects_loose(&self) -> Vec<SHA1> {
         let mut objects = Vec::new();
         let paths = fs::read_dir(&self.base_path).unwrap();
         for path in paths {
@@ -106,7 +100,7 @@ impl ClientStorage {
     }
 
     /// List all objects' hash in PACKs
-    fn list_objects_pack(&self) -> HashSet<SHA1> {
+    pub fn list_objects_pack(&self) -> HashSet<SHA1> {
         let idxes = self.list_all_idx();
         let mut objs = HashSet::new();
         for idx in idxes {
@@ -135,19 +129,22 @@ impl ClientStorage {
         Ok(decompressed_data)
     }
 
-    fn parse_header(data: &[u8]) -> (String, usize, usize) {
+    fn parse_header(data: &[u8]) -> Result<(String, usize, usize), GitError> {
+        let invalid = |msg: &str| GitError::InvalidObjectInfo(msg.to_string());
         let end_of_header = data.iter()
             .position(|&b| b == b'\0')
-            .expect("Invalid object: no header terminator");
+            .ok_or(invalid("no header terminator"))?;
         let header_str = std::str::from_utf8(&data[..end_of_header])
-            .expect("Invalid UTF-8 in header");
+            .map_err(|_| invalid("invalid UTF-8 in header"))?;
 
         let mut parts = header_str.splitn(2, ' ');
-        let obj_type = parts.next().expect("No object type in header").to_string();
-        let size_str = parts.next().expect("No size in header");
-        let size = size_str.parse::<usize>().expect("Invalid size in header");
-        assert_eq!(size, data.len() - 1 - end_of_header, "Invalid object size");
-        (obj_type, size, end_of_header)
+        let obj_type = parts.next().ok_or(invalid("no object type in header"))?.to_string();
+        let size_str = parts.next().ok_or(invalid("no size in header"))?;
+        let size = size_str.parse::<usize>().map_err(|_| invalid("invalid size in header"))?;
+        if size != data.len() - 1 - end_of_header {
+            return Err(invalid("invalid object size"));
+        }
+        Ok((obj_type, size, end_of_header))
     }
 
     fn read_raw_data(&self, obj_id: &SHA1) -> Result<Vec<u8>, io::Error> {
@@ -159,19 +156,46 @@ impl ClientStorage {
     }
 
     pub fn get(&self, object_id: &SHA1) -> Result<Vec<u8>, GitError> {
-        if self.exist_loosely(object_id) {
-            let raw_data = self.read_raw_data(object_id)?;
-            let data = Self::decompress_zlib(&raw_data)?;
+        self.get_with_type(object_id).map(|x| x.0)
+    }
 
-            // skip & check header
-            let (_, _, end_of_header) = Self::parse_header(&data);
-            Ok(data[end_of_header + 1..].to_vec())
-        } else {
-            // Ok(self.get_from_pack(object_id)?.unwrap().0)
-            self.get_from_pack(object_id)?
-                .map(|x| x.0)
-                .ok_or(GitError::ObjectNotFound(object_id.to_string()))
+    /// Get object data & type, PACKs are preferred, then loose objects
+    /// - the blobs omitted by a partial clone must be fetched by `fetch::prefetch_blobs` before
+    pub fn get_with_type(&self, obj_id: &SHA1) -> Result<(Vec<u8>, ObjectType), GitError> {
+        if let Some(obj) = self.get_from_pack(obj_id)? {
+            return Ok(obj);
+        }
+        if self.exist_loosely(obj_id) {
+            return self.read_loose(obj_id);
         }
+        Err(GitError::ObjectNotFound(obj_id.to_string()))
+    }
+
+    /// Read the loose object, the header is checked
+    pub fn read_loose(&self, obj_id: &SHA1) -> Result<(Vec<u8>, ObjectType), GitError> {
+        let raw_data = self.read_raw_data(obj_id)?;
+        let data = Self::decompress_zlib(&raw_data)?;
+
+        // skip & check header
+        let (obj_type, _, end_of_header) = Self::parse_header(&data)?;
+        Ok((data[end_of_header + 1..].to_vec(), ObjectType::from_string(&obj_type)?))
+    }
+
+    /// Get the object type & size, only the header is decompressed for loose objects
+    pub fn get_type_and_size(&self, obj_id: &SHA1) -> Result<(ObjectType, usize), GitError> {
+        if self.exist_loosely(obj_id) && !self.exist_in_pack(obj_id) {
+            let file = fs::File::open(self.get_obj_path(obj_id))?;
+            let mut header = Vec::new();
+            // "<type> <size>\0", only the first block is decompressed
+            io::BufReader::new(ZlibDecoder::new(file)).read_until(b'\0', &mut header)?;
+            header.pop();
+            let invalid = || GitError::InvalidObjectInfo(format!("invalid header of {}", obj_id));
+            let header = String::from_utf8(header).map_err(|_| invalid())?;
+            let (obj_type, size) = header.split_once(' ').ok_or_else(invalid)?;
+            let size = size.parse().map_err(|_| invalid())?;
+            return Ok((ObjectType::from_string(obj_type)?, size));
+        }
+        self.get_with_type(obj_id).map(|(data, obj_type)| (obj_type, data.len()))
     }
 
     /// Save content to `objects`
@@ -188,10 +212,55 @@ impl ClientStorage {
         Ok(path.to_str().unwrap().to_string())
     }
 
-    /// Check if the object with `obj_id` exists in `objects` or PACKs
+    /// Check if the object with `obj_id` exists in PACKs or `objects`
     pub fn exist(&self, obj_id: &SHA1) -> bool {
+        self.exist_in_pack(obj_id) || self.exist_loosely(obj_id)
+    }
+
+    /// Check if the object with `obj_id` exists in PACKs, only .idx files are read
+    pub fn exist_in_pack(&self, obj_id: &SHA1) -> bool {
+        self.list_all_idx()
+            .iter()
+            .any(|idx| matches!(Self::read_idx(idx, obj_id), Ok(Some(_))))
+    }
+
+    /// Remove the loose object, the empty fan-out directory is removed too
+    pub fn remove_loose(&self, obj_id: &SHA1) -> Result<(), io::Error> {
         let path = self.get_obj_path(obj_id);
-        Path::exists(&path) || self.get_from_pack(obj_id).unwrap().is_some()
+        fs::remove_file(&path)?;
+        let _ = fs::remove_dir(path.parent().unwrap()); // fails if not empty
+        Ok(())
+    }
+
+    /// Last modified time of the loose object
+    pub fn loose_mtime(&self, obj_id: &SHA1) -> Result<SystemTime, io::Error> {
+        fs::metadata(self.get_obj_path(obj_id))?.modified()
+    }
+
+    /// Set the last modified time of the loose object, e.g. to keep the age of an object exploded from a PACK
+    pub fn set_loose_mtime(&self, obj_id: &SHA1, time: SystemTime) -> Result<(), io::Error> {
+        fs::File::options()
+            .write(true)
+            .open(self.get_obj_path(obj_id))?
+            .set_modified(time)
+    }
+
+    /// Set the remote to fetch the missing objects from, for partial clone (`--filter`)
+    pub fn set_promisor_remote(&self, url: &str) -> Result<(), io::Error> {
+        let path = self.promisor_path();
+        fs::create_dir_all(path.parent().unwrap())?;
+        fs::write(path, url)
+    }
+
+    /// The url of the promisor remote, `None` if the repository is not a partial clone
+    pub fn promisor_remote(&self) -> Option<String> {
+        fs::read_to_string(self.promisor_path())
+            .ok()
+            .map(|url| url.trim().to_owned())
+    }
+
+    fn promisor_path(&self) -> PathBuf {
+        self.base_path.join("info").join("promisor")
     }
 
     /// Check if the object with `obj_id` exists in `objects`
@@ -203,10 +272,24 @@ impl ClientStorage {
 const FANOUT: u64 = 256 * 4;
 // TODO refactor to `PackReader`
 impl ClientStorage {
+    /// List all objects' hash in the PACK, by its .idx file
+    pub fn list_pack_objects(&self, pack: &Path) -> Vec<SHA1> {
+        Self::list_idx_objects(&pack.with_extension("idx")).unwrap_or_default()
+    }
+
+    /// Read the object from the specified PACK, `None` if it's not in the PACK
+    pub fn read_packed(&self, pack: &Path, obj_id: &SHA1) -> Result<Option<(Vec<u8>, ObjectType)>, GitError> {
+        Ok(Self::read_pack_by_idx(&pack.with_extension("idx"), obj_id)?
+            .map(|obj| (obj.data_decompressed.clone(), obj.object_type())))
+    }
+
     /// List all .pack files in `pack` directory
-    fn list_all_packs(&self) -> Vec<PathBuf> {
+    pub fn list_all_packs(&self) -> Vec<PathBuf> {
         let pack_dir = self.base_path.join("pack");
         let mut packs = Vec::new();
+        if !pack_dir.exists() {
+            return packs;
+        }
         for entry in fs::read_dir(pack_dir).unwrap() {
             let path = entry.unwrap().path();
             if path.is_file() && path.extension().unwrap() == "pack" {
@@ -224,7 +307,7 @@ impl ClientStorage {
         for pack in packs {
             let idx = pack.with_extension("idx");
             if !idx.exists() {
-                command::index_pack::build_index_v1(pack.to_str().unwrap(), idx.to_str().unwrap()).unwrap();
+                command::index_pack::build_index_v2(pack.to_str().unwrap(), idx.to_str().unwrap()).unwrap();
             }
             idxs.push(idx);
         }
@@ -244,54 +327,99 @@ impl ClientStorage {
         Ok(None)
     }
 
-    fn read_idx_fanout(idx_file: &Path) -> Result<[u32; 256], io::Error> {
-        let mut idx_file = fs::File::open(idx_file)?;
-        // const FANOUT: usize = 256 * 4;
+    /// Read the version & fan-out table of .idx file, and seek to the end of the fan-out table
+    /// - version 2 starts with a magic number & version, version 1 has no header
+    fn read_idx_header(idx_file: &mut fs::File) -> Result<(u32, [u32; 256]), io::Error> {
+        let mut magic = [0; 4];
+        idx_file.read_exact(&mut magic)?;
+        let version = if magic == IDX_V2_MAGIC {
+            idx_file.read_u32::<BigEndian>()?
+        } else {
+            idx_file.seek(io::SeekFrom::Start(0))?;
+            1
+        };
+        if version != 1 && version != 2 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("unsupported index version {}", version),
+            ));
+        }
         let mut fanout: [u32; 256] = [0; 256]; // 256 * 4 bytes
-        let mut buf = [0; 4];
-        fanout.iter_mut().for_each(|x| {
-            idx_file.read_exact(&mut buf).unwrap();
-            *x = u32::from_be_bytes(buf);
-        });
-        Ok(fanout)
+        for x in fanout.iter_mut() {
+            *x = idx_file.read_u32::<BigEndian>()?;
+        }
+        Ok((version, fanout))
+    }
+
+    /// Position of the `i`th hash in .idx file
+    /// - v1: (4-byte offset, hash) entries after the fan-out table
+    /// - v2: hash table after the header & fan-out table
+    fn idx_hash_pos(version: u32, i: u64) -> u64 {
+        match version {
+            1 => FANOUT + 24 * i + 4,
+            _ => 8 + FANOUT + 20 * i,
+        }
+    }
+
+    /// Read the offset of the `i`th object in .idx file, `count` is the number of objects
+    /// - v2: offset table is after the hash table & CRC32 table, if the MSB is set,
+    ///   the rest bits are the index into the 8-byte large offset table
+    fn read_idx_offset(idx_file: &mut fs::File, version: u32, count: u64, i: u64) -> Result<u64, io::Error> {
+        if version == 1 {
+            idx_file.seek(io::SeekFrom::Start(FANOUT + 24 * i))?;
+            return Ok(idx_file.read_u32::<BigEndian>()? as u64);
+        }
+        let offset_table = 8 + FANOUT + 24 * count;
+        idx_file.seek(io::SeekFrom::Start(offset_table + 4 * i))?;
+        let offset = idx_file.read_u32::<BigEndian>()?;
+        if offset & 0x8000_0000 == 0 {
+            return Ok(offset as u64);
+        }
+        let large_offset_table = offset_table + 4 * count;
+        idx_file.seek(io::SeekFrom::Start(large_offset_table + 8 * (offset & 0x7fff_ffff) as u64))?;
+        idx_file.read_u64::<BigEndian>()
     }
 
     /// List all objects hash in .idx file
     fn list_idx_objects(idx_file: &Path) -> Result<Vec<SHA1>, io::Error> {
-        let fanout: [u32; 256] = Self::read_idx_fanout(idx_file)?; // TODO param change to `&mut File`, to auto seek
         let mut idx_file = fs::File::open(idx_file)?;
-        idx_file.seek(io::SeekFrom::Start(FANOUT))?; // important!
+        let (version, fanout) = Self::read_idx_header(&mut idx_file)?;
 
         let mut objs = Vec::new();
-        for _ in 0..fanout[255] {
-            let _offset = idx_file.read_u32::<BigEndian>()?;
-            let hash = read_sha1(&mut idx_file)?;
-
-            objs.push(hash);
+        for i in 0..fanout[255] as u64 {
+            // hashes of v2 are contiguous
+            if version == 1 || i == 0 {
+                idx_file.seek(io::SeekFrom::Start(Self::idx_hash_pos(version, i)))?;
+            }
+            objs.push(read_sha1(&mut idx_file)?);
         }
         Ok(objs)
     }
 
-    /// Read object `offset` from .idx file by `hash`
+    /// Read object `offset` from .idx file by `hash`, binary search in the range of the fan-out table
     fn read_idx(idx_file: &Path, obj_id: &SHA1) -> Result<Option<u64>, io::Error> {
-        let fanout: [u32; 256] = Self::read_idx_fanout(idx_file)?;
         let mut idx_file = fs::File::open(idx_file)?;
+        let (version, fanout) = Self::read_idx_header(&mut idx_file)?;
 
         let first_byte = obj_id.0[0];
-        let start = if first_byte == 0 {
+        let mut start = if first_byte == 0 {
             0
         } else {
-            fanout[first_byte as usize - 1] as usize
+            fanout[first_byte as usize - 1] as u64
         };
-        let end = fanout[first_byte as usize] as usize;
+        let mut end = fanout[first_byte as usize] as u64;
 
-        idx_file.seek(io::SeekFrom::Start(FANOUT + 24 * start as u64))?;
-        for _ in start..end {
-            let offset = idx_file.read_u32::<BigEndian>()?;
+        while start < end {
+            let mid = start + (end - start) / 2;
+            idx_file.seek(io::SeekFrom::Start(Self::idx_hash_pos(version, mid)))?;
             let hash = read_sha1(&mut idx_file)?;
-
-            if &hash == obj_id {
-                return Ok(Some(offset as u64));
+            match hash.0.cmp(&obj_id.0) {
+                Ordering::Equal => {
+                    let count = fanout[255] as u64;
+                    return Ok(Some(Self::read_idx_offset(&mut idx_file, version, count, mid)?));
+                }
+                Ordering::Less => start = mid + 1,
+                Ordering::Greater => end = mid,
             }
         }
 
@@ -354,13 +482,15 @@ impl ClientStorage {
 
 #[cfg(test)]
 mod tests {
-    use std::{env, fs};
+    use std::fs;
     use std::path::PathBuf;
 
+    use mercury::hash::SHA1;
     use mercury::internal::object::blob::Blob;
     use mercury::internal::object::ObjectTrait;
     use mercury::internal::object::types::ObjectType;
 
+    use crate::command;
     use crate::utils::{test, util};
 
     use super::ClientStorage;
@@ -370,10 +500,8 @@ mod tests {
         let content = "Hello, world!";
         let blob = Blob::from_content(content);
 
-        let mut source = PathBuf::from(env::current_dir().unwrap().parent().unwrap());
-        source.push("tests/objects");
-
-        let client_storage = ClientStorage::init(source.clone());
+        let source = tempfile::tempdir().unwrap();
+        let client_storage = ClientStorage::init(source.path().to_path_buf());
         assert!(client_storage.put(&blob.id, &blob.data, blob.get_type()).is_ok());
         assert!(client_storage.exist(&blob.id));
 
@@ -386,10 +514,8 @@ mod tests {
     fn test_search() {
         let blob = Blob::from_content("Hello, world!");
 
-        let mut source = PathBuf::from(env::current_dir().unwrap().parent().unwrap());
-        source.push("tests/objects");
-
-        let client_storage = ClientStorage::init(source.clone());
+        let source = tempfile::tempdir().unwrap();
+        let client_storage = ClientStorage::init(source.path().to_path_buf());
         assert!(client_storage.put(&blob.id, &blob.data, blob.get_type()).is_ok());
 
         let objs = client_storage.search("5dd01c177");
@@ -414,10 +540,8 @@ mod tests {
     fn test_get_obj_type() {
         let blob = Blob::from_content("Hello, world!");
 
-        let mut source = PathBuf::from(env::current_dir().unwrap().parent().unwrap());
-        source.push("tests/objects");
-
-        let client_storage = ClientStorage::init(source.clone());
+        let source = tempfile::tempdir().unwrap();
+        let client_storage = ClientStorage::init(source.path().to_path_buf());
         assert!(client_storage.put(&blob.id, &blob.data, blob.get_type()).is_ok());
 
         let obj_type = client_storage.get_object_type(&blob.id).unwrap();
@@ -440,6 +564,50 @@ mod tests {
         println!("{:?}", String::from_utf8(decompressed_data).unwrap());
     }
 
+    #[tokio::test]
+    async fn test_read_idx_v1_v2() {
+        test::setup_with_new_libra().await;
+        test::ensure_file("idx_a.txt", Some("a\n"));
+        test::ensure_file("idx_b.txt", Some("b\n"));
+        crate::exec_async(vec!["add", "."]).await.unwrap();
+        crate::exec_async(vec!["commit", "-m", "idx"]).await.unwrap();
+        let storage = util::objects_storage();
+        let loose = storage.list_objects_loose();
+        let pack = command::repack::write_pack(&loose, 10).await.unwrap();
+
+        let idx_v2 = pack.with_extension("idx");
+        let idx_v1 = pack.with_extension("idx1");
+        command::index_pack::build_index_v1(pack.to_str().unwrap(), idx_v1.to_str().unwrap()).unwrap();
+        let objs = ClientStorage::list_idx_objects(&idx_v2).unwrap();
+        assert_eq!(objs.len(), loose.len());
+        assert_eq!(objs, ClientStorage::list_idx_objects(&idx_v1).unwrap());
+        for obj in objs.iter() {
+            let offset = ClientStorage::read_idx(&idx_v2, obj).unwrap();
+            assert!(offset.is_some());
+            assert_eq!(offset, ClientStorage::read_idx(&idx_v1, obj).unwrap());
+        }
+        assert_eq!(ClientStorage::read_idx(&idx_v2, &SHA1::default()).unwrap(), None);
+        fs::remove_file(idx_v1).unwrap();
+    }
+
+    #[test]
+    fn test_read_idx_v2_large_offset() {
+        let mut entries: Vec<(SHA1, u64, u32)> = (0u8..10)
+            .map(|i| (SHA1::new(&[i]), 12 + (i as u64) * (1 << 30), i as u32))
+            .collect();
+        entries.sort_by(|a, b| a.0.cmp(&b.0));
+        let dir = tempfile::tempdir().unwrap();
+        let idx = dir.path().join("large.idx");
+        let mut file = fs::File::create(&idx).unwrap();
+        command::index_pack::write_index_v2(&entries, &SHA1::default(), &mut file).unwrap();
+
+        let objs = ClientStorage::list_idx_objects(&idx).unwrap();
+        assert_eq!(objs, entries.iter().map(|e| e.0).collect::<Vec<_>>());
+        for (hash, offset, _) in entries.iter() {
+            assert_eq!(ClientStorage::read_idx(&idx, hash).unwrap(), Some(*offset));
+        }
+    }
+
     #[test]
     fn test_get_from_pack() {
         unimplemented!();
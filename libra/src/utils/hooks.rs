@@ -0,0 +1,177 @@
+//! Client-side hooks: executables named after the hook in `.libra/hooks` (or `core.hooksPath`),
+//! run in the root of the working tree with the same arguments & stdin as git.
+//! Missing hooks (or not executable on Unix) are skipped.
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+use crate::internal::config::Config;
+use crate::utils::{path, util};
+
+/// Before creating the commit, no arguments
+pub const PRE_COMMIT: &str = "pre-commit";
+/// Check (or edit) the commit message, the argument is the file of the message
+pub const COMMIT_MSG: &str = "commit-msg";
+/// Before creating the merge commit of an automatic merge, no arguments
+pub const PRE_MERGE_COMMIT: &str = "pre-merge-commit";
+/// Before pushing, the arguments are the remote name & URL,
+/// stdin has `<local ref> <local hash> <remote ref> <remote hash>` per line
+pub const PRE_PUSH: &str = "pre-push";
+/// Before switching, the same arguments as `post-checkout`; non-zero exit aborts the switch.
+/// It's libra-only: git has no hook that can block a checkout
+pub const PRE_CHECKOUT: &str = "pre-checkout";
+/// After switching, the arguments are the previous & new HEAD commit and `1` (branch checkout),
+/// it can't abort the switch, which has been done
+pub const POST_CHECKOUT: &str = "post-checkout";
+
+/// The directory of hooks: `core.hooksPath` (relative to the working tree) or `.libra/hooks`
+pub async fn hooks_dir() -> PathBuf {
+    match Config::get("core", None, "hooksPath").await {
+        Some(dir) => match dir.strip_prefix("~/") {
+            Some(relative) => home::home_dir().unwrap_or_default().join(relative),
+            None => util::working_dir().join(dir),
+        },
+        None => path::hooks(),
+    }
+}
+
+/// Find the executable of the hook, `None` if it doesn't exist
+async fn find_hook(name: &str) -> Option<PathBuf> {
+    let hook = hooks_dir().await.join(name);
+    if !hook.is_file() {
+        return None;
+    }
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        if hook.metadata().ok()?.permissions().mode() & 0o111 == 0 {
+            eprintln!(
+                "hint: The '{}' hook was ignored because it's not set as executable.",
+                name
+            );
+            return None;
+        }
+    }
+    Some(hook)
+}
+
+/// Run the hook with the arguments & stdin
+/// - `Ok` if the hook doesn't exist or exits with 0, otherwise the caller should abort
+pub async fn run(name: &str, args: &[&str], stdin: Option<&[u8]>) -> Result<(), String> {
+    match find_hook(name).await {
+        Some(hook) => execute(name, &hook, args, stdin),
+        None => Ok(()),
+    }
+}
+
+/// Run the `commit-msg` hook with the message in `.libra/COMMIT_EDITMSG`, return the message which may be edited by the hook
+pub async fn commit_msg(message: &str) -> Result<String, String> {
+    let Some(hook) = find_hook(COMMIT_MSG).await else {
+        return Ok(message.to_string());
+    };
+    let file = util::worktree_storage_path().join("COMMIT_EDITMSG");
+    fs::write(&file, format!("{}\n", message.trim_end())).unwrap();
+    execute(COMMIT_MSG, &hook, &[file.to_str().unwrap()], None)?;
+    let message = fs::read_to_string(&file).unwrap();
+    let message = message.trim_end();
+    if message.is_empty() {
+        return Err("Aborting commit due to empty commit message.".to_string());
+    }
+    Ok(message.to_string())
+}
+
+fn execute(name: &str, hook: &Path, args: &[&str], stdin: Option<&[u8]>) -> Result<(), String> {
+    // there is no shebang support on Windows, run hooks by `sh` like Git for Windows
+    let mut command = if cfg!(windows) {
+        let mut command = Command::new("sh");
+        command.arg(hook);
+        command
+    } else {
+        Command::new(hook)
+    };
+    command
+        .args(args)
+        .current_dir(util::working_dir())
+        .stdin(match stdin {
+            Some(_) => Stdio::piped(),
+            None => Stdio::null(),
+        });
+    let mut child = command
+        .spawn()
+        .map_err(|e| format!("cannot run the {} hook '{}': {}", name, hook.display(), e))?;
+    if let Some(input) = stdin {
+        // the hook may exit without reading all of stdin
+        let _ = child.stdin.take().unwrap().write_all(input);
+    }
+    let status = child
+        .wait()
+        .map_err(|e| format!("the {} hook failed: {}", name, e))?;
+    if status.success() {
+        Ok(())
+    } else {
+        Err(format!("the {} hook declined ({})", name, status))
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use std::os::unix::fs::PermissionsExt;
+
+    use super::*;
+    use crate::utils::test;
+
+    fn install_hook(name: &str, script: &str) {
+        fs::create_dir_all(path::hooks()).unwrap();
+        let hook = path::hooks().join(name);
+        fs::write(&hook, script).unwrap();
+        fs::set_permissions(&hook, fs::Permissions::from_mode(0o755)).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_run_hooks() {
+        test::setup_with_new_libra().await;
+        // missing hooks are skipped
+        assert!(run(PRE_COMMIT, &[], None).await.is_ok());
+
+        install_hook(PRE_COMMIT, "#!/bin/sh\nexit 1\n");
+        assert!(run(PRE_COMMIT, &[], None).await.is_err());
+        // not executable
+        fs::set_permissions(
+            path::hooks().join(PRE_COMMIT),
+            fs::Permissions::from_mode(0o644),
+        )
+        .unwrap();
+        assert!(run(PRE_COMMIT, &[], None).await.is_ok());
+
+        // arguments & stdin
+        install_hook(
+            PRE_PUSH,
+            "#!/bin/sh\ntest \"$1\" = origin || exit 1\nread line\ntest \"$line\" = \"refs/heads/main 1 refs/heads/main 0\"\n",
+        );
+        let input = b"refs/heads/main 1 refs/heads/main 0\n";
+        assert!(run(PRE_PUSH, &["origin", "url"], Some(input)).await.is_ok());
+        assert!(run(PRE_PUSH, &["upstream", "url"], Some(input))
+            .await
+            .is_err());
+
+        install_hook(
+            COMMIT_MSG,
+            "#!/bin/sh\necho 'Signed-off-by: mega' >> \"$1\"\n",
+        );
+        assert_eq!(
+            commit_msg("fix: typo").await.unwrap(),
+            "fix: typo\nSigned-off-by: mega"
+        );
+
+        // hooks in `core.hooksPath`
+        Config::insert("core", None, "hooksPath", "custom_hooks").await;
+        assert!(run(PRE_PUSH, &["upstream", "url"], Some(input))
+            .await
+            .is_ok());
+
+        // don't leave hooks for the following tests in the repo
+        Config::remove_config("core", None, "hooksPath", None, true).await;
+        fs::remove_dir_all(path::hooks()).unwrap();
+    }
+}
@@ -0,0 +1,407 @@
+//! Ignore rules of the working tree, compatible with `.gitignore`.
+//!
+//! Patterns are read from (lowest to highest priority):
+//! 1. global excludes: `$XDG_CONFIG_HOME/git/ignore` or `~/.config/git/ignore`
+//! 2. `.libra/info/exclude`
+//! 3. `.gitignore` & `.libraignore` of each directory, deeper ones take precedence
+//!
+//! The last matching pattern decides, and a file can't be re-included if its parent directory is excluded.
+use std::env;
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+
+use crate::utils::util;
+
+pub const IGNORE_FILES: [&str; 2] = [".gitignore", ".libraignore"];
+
+/// One line of ignore file, also used for `.libra_attributes` patterns
+#[derive(Debug, Clone)]
+pub struct Pattern {
+    glob: Vec<char>,
+    negated: bool,
+    dir_only: bool,
+    /// contains `/` (except the trailing one): match the path relative to `base`, otherwise the file name
+    anchored: bool,
+    /// the directory of the ignore file, to workdir
+    base: PathBuf,
+}
+
+impl Pattern {
+    /// Parse a line of ignore file, return `None` for blank lines & comments
+    /// - `base`: the directory which the pattern is relative to (to workdir)
+    pub fn parse(line: &str, base: impl AsRef<Path>) -> Option<Pattern> {
+        let mut line = line.trim_end_matches(['\n', '\r']);
+        // trailing spaces are ignored unless they are quoted with backslash
+        while line.ends_with(' ') && !line.ends_with("\\ ") {
+            line = &line[..line.len() - 1];
+        }
+        if line.is_empty() || line.starts_with('#') {
+            return None;
+        }
+
+        let negated = line.starts_with('!');
+        if negated {
+            line = &line[1..];
+        }
+        let dir_only = line.ends_with('/');
+        if dir_only {
+            line = line.trim_end_matches('/');
+        }
+        let anchored = line.contains('/');
+        let line = line.strip_prefix('/').unwrap_or(line);
+        if line.is_empty() {
+            return None;
+        }
+
+        Some(Pattern {
+            glob: line.chars().collect(),
+            negated,
+            dir_only,
+            anchored,
+            base: base.as_ref().to_path_buf(),
+        })
+    }
+
+    /// Check if `path` (to workdir) matches the pattern, ignoring negation
+    pub fn matches(&self, path: impl AsRef<Path>, is_dir: bool) -> bool {
+        if self.dir_only && !is_dir {
+            return false;
+        }
+        let rel = match path.as_ref().strip_prefix(&self.base) {
+            Ok(rel) => rel,
+            Err(_) => return false, // not under the directory of the ignore file
+        };
+        let text: Vec<char> = if self.anchored {
+            to_slash_path(rel).chars().collect()
+        } else {
+            match rel.file_name() {
+                Some(name) => name.to_string_lossy().chars().collect(),
+                None => return false,
+            }
+        };
+        wildmatch(&self.glob, 0, &text, 0)
+    }
+}
+
+/// Remove `.` components, e.g. `.` (workdir itself) -> ``
+fn normalize(path: &Path) -> PathBuf {
+    path.components()
+        .filter(|c| *c != Component::CurDir)
+        .collect()
+}
+
+/// Join path components with `/`, even on windows
+fn to_slash_path(path: &Path) -> String {
+    path.components()
+        .map(|c| c.as_os_str().to_string_lossy())
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+/// Match `text` against glob `pattern`, e.g. `v1.*`
+pub(crate) fn glob_match(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+    wildmatch(&pattern, 0, &text, 0)
+}
+
+/// Match `text[ti..]` against glob `pattern[pi..]` like git's `wildmatch` with `WM_PATHNAME`
+/// - `*`, `?` and `[...]` don't match `/`
+/// - `**` between slashes (or at the start/end) matches any number of directories
+fn wildmatch(pattern: &[char], pi: usize, text: &[char], ti: usize) -> bool {
+    if pi == pattern.len() {
+        return ti == text.len();
+    }
+    match pattern[pi] {
+        '*' if pattern.get(pi + 1) == Some(&'*')
+            && (pi == 0 || pattern[pi - 1] == '/')
+            && matches!(pattern.get(pi + 2), None | Some('/')) =>
+        {
+            if pi + 2 == pattern.len() {
+                return true; // trailing `**` matches everything inside
+            }
+            // `**/` matches zero or more directories
+            let rest = pi + 3;
+            (ti..=text.len())
+                .filter(|&i| i == ti || text[i - 1] == '/')
+                .any(|i| wildmatch(pattern, rest, text, i))
+        }
+        '*' => {
+            let mut rest = pi + 1;
+            while pattern.get(rest) == Some(&'*') {
+                rest += 1; // `**` not between slashes acts like `*`
+            }
+            for i in ti..=text.len() {
+                if wildmatch(pattern, rest, text, i) {
+                    return true;
+                }
+                if i < text.len() && text[i] == '/' {
+                    break;
+                }
+            }
+            false
+        }
+        '?' => ti < text.len() && text[ti] != '/' && wildmatch(pattern, pi + 1, text, ti + 1),
+        '[' => match (
+            text.get(ti),
+            match_class(pattern, pi, text.get(ti).copied()),
+        ) {
+            (Some(_), Some((true, next))) => wildmatch(pattern, next, text, ti + 1),
+            (_, Some((false, _))) | (None, _) => false,
+            (Some(&c), None) => c == '[' && wildmatch(pattern, pi + 1, text, ti + 1), // no closing `]`
+        },
+        '\\' if pi + 1 < pattern.len() => {
+            ti < text.len()
+                && text[ti] == pattern[pi + 1]
+                && wildmatch(pattern, pi + 2, text, ti + 1)
+        }
+        c => ti < text.len() && text[ti] == c && wildmatch(pattern, pi + 1, text, ti + 1),
+    }
+}
+
+/// Match `c` against the bracket expression starting at `pattern[pi]` (`[`)
+/// - return (matched, index after `]`), `None` if the bracket is not closed
+fn match_class(pattern: &[char], pi: usize, c: Option<char>) -> Option<(bool, usize)> {
+    let mut i = pi + 1;
+    let negated = matches!(pattern.get(i), Some('!') | Some('^'));
+    if negated {
+        i += 1;
+    }
+    let mut matched = false;
+    let mut first = true;
+    loop {
+        let mut ch = *pattern.get(i)?;
+        if ch == ']' && !first {
+            break;
+        }
+        first = false;
+        if ch == '\\' {
+            i += 1;
+            ch = *pattern.get(i)?;
+        }
+        if pattern.get(i + 1) == Some(&'-') && pattern.get(i + 2).is_some_and(|&e| e != ']') {
+            let mut end = pattern[i + 2];
+            i += 2;
+            if end == '\\' {
+                i += 1;
+                end = *pattern.get(i)?;
+            }
+            if c.is_some_and(|c| ch <= c && c <= end) {
+                matched = true;
+            }
+        } else if c == Some(ch) {
+            matched = true;
+        }
+        i += 1;
+    }
+    let matched = matched != negated && c != Some('/');
+    Some((matched, i + 1))
+}
+
+/// Read patterns from an ignore file, empty if not exists
+/// - `base`: the directory which the patterns are relative to (to workdir)
+pub fn read_patterns(file: impl AsRef<Path>, base: impl AsRef<Path>) -> Vec<Pattern> {
+    match fs::read_to_string(file) {
+        Ok(content) => content
+            .lines()
+            .filter_map(|line| Pattern::parse(line, base.as_ref()))
+            .collect(),
+        Err(_) => Vec::new(),
+    }
+}
+
+/// Path of the global excludes file, like `core.excludesFile` of Git
+fn global_excludes_file() -> Option<PathBuf> {
+    match env::var_os("XDG_CONFIG_HOME").filter(|dir| !dir.is_empty()) {
+        Some(dir) => Some(PathBuf::from(dir).join("git").join("ignore")),
+        None => env::var_os("HOME").map(|home| PathBuf::from(home).join(".config/git/ignore")),
+    }
+}
+
+/// Stack of ignore patterns, grows when walking into sub-directories
+#[derive(Debug, Default, Clone)]
+pub struct IgnoreRules {
+    patterns: Vec<Pattern>,
+}
+
+impl IgnoreRules {
+    /// Load global excludes, `info/exclude` and ignore files in the root of working dir
+    pub fn load() -> IgnoreRules {
+        let mut rules = IgnoreRules::default();
+        if let Some(file) = global_excludes_file() {
+            rules.patterns.extend(read_patterns(file, ""));
+        }
+        let exclude = util::storage_path().join("info").join("exclude");
+        rules.patterns.extend(read_patterns(exclude, ""));
+        rules.load_dir("");
+        rules
+    }
+
+    /// Load ignore rules for files in `dir` (to workdir), include all the parent directories
+    /// - `None` if `dir` or any of its parents is ignored
+    pub fn load_for_dir(dir: impl AsRef<Path>) -> Option<IgnoreRules> {
+        let mut rules = IgnoreRules::load();
+        let mut cur = PathBuf::new();
+        for component in normalize(dir.as_ref()).components() {
+            cur.push(component);
+            if rules.is_ignored(&cur, true) {
+                return None;
+            }
+            rules.load_dir(&cur);
+        }
+        Some(rules)
+    }
+
+    /// Add patterns from ignore files in `dir` (to workdir)
+    pub fn load_dir(&mut self, dir: impl AsRef<Path>) {
+        let dir = normalize(dir.as_ref());
+        for name in IGNORE_FILES {
+            let file = util::workdir_to_absolute(&dir).join(name);
+            self.patterns.extend(read_patterns(file, &dir));
+        }
+    }
+
+    /// Number of loaded patterns, to restore the rules by `truncate` later
+    pub fn pattern_count(&self) -> usize {
+        self.patterns.len()
+    }
+
+    /// Drop patterns loaded after the first `len`, used when leaving a directory
+    pub fn truncate(&mut self, len: usize) {
+        self.patterns.truncate(len);
+    }
+
+    /// Check if `path` (to workdir) is ignored by the loaded patterns, the last matching one decides
+    /// - parent directories are not checked
+    pub fn is_ignored(&self, path: impl AsRef<Path>, is_dir: bool) -> bool {
+        let path = path.as_ref();
+        self.patterns
+            .iter()
+            .rev()
+            .find(|pattern| pattern.matches(path, is_dir))
+            .is_some_and(|pattern| !pattern.negated)
+    }
+}
+
+/// Check if the `path` (absolute or relative to current dir) is ignored, include its parent directories
+pub fn is_path_ignored(path: impl AsRef<Path>) -> bool {
+    let path_wd = normalize(&util::to_workdir_path(path));
+    let parent = path_wd.parent().unwrap_or(Path::new(""));
+    match IgnoreRules::load_for_dir(parent) {
+        Some(rules) => rules.is_ignored(&path_wd, util::workdir_to_absolute(&path_wd).is_dir()),
+        None => true,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::utils::test;
+
+    fn is_match(pattern: &str, path: &str, is_dir: bool) -> bool {
+        Pattern::parse(pattern, "").unwrap().matches(path, is_dir)
+    }
+
+    #[test]
+    fn test_parse() {
+        assert!(Pattern::parse("", "").is_none());
+        assert!(Pattern::parse("# comment", "").is_none());
+        assert!(Pattern::parse("   ", "").is_none());
+        let pattern = Pattern::parse("!/build/ ", "").unwrap();
+        assert!(pattern.negated && pattern.dir_only && pattern.anchored);
+        assert_eq!(pattern.glob, "build".chars().collect::<Vec<_>>());
+        assert!(Pattern::parse("\\#file", "").is_some());
+    }
+
+    #[test]
+    fn test_basename_pattern() {
+        assert!(is_match("*.o", "a.o", false));
+        assert!(is_match("*.o", "src/lib/a.o", false));
+        assert!(!is_match("*.o", "a.oo", false));
+        assert!(is_match("target", "sub/target", true));
+        assert!(is_match("fo?.[ch]", "foo.c", false));
+        assert!(!is_match("fo?.[!ch]", "foo.c", false));
+        assert!(is_match("\\#file", "#file", false));
+    }
+
+    #[test]
+    fn test_anchored_pattern() {
+        assert!(is_match("/target", "target", true));
+        assert!(!is_match("/target", "sub/target", true));
+        assert!(is_match("doc/*.txt", "doc/a.txt", false));
+        assert!(!is_match("doc/*.txt", "doc/sub/a.txt", false));
+        assert!(!is_match("doc/*.txt", "a/doc/a.txt", false));
+    }
+
+    #[test]
+    fn test_dir_only_pattern() {
+        assert!(is_match("build/", "build", true));
+        assert!(is_match("build/", "src/build", true));
+        assert!(!is_match("build/", "build", false));
+    }
+
+    #[test]
+    fn test_double_star() {
+        assert!(is_match("**/foo", "foo", false));
+        assert!(is_match("**/foo", "a/b/foo", false));
+        assert!(is_match("a/**/b", "a/b", false));
+        assert!(is_match("a/**/b", "a/x/y/b", false));
+        assert!(is_match("abc/**", "abc/x/y", false));
+        assert!(!is_match("abc/**", "abc", true));
+        assert!(!is_match("a**b/c", "ax/yb/c", false));
+    }
+
+    #[test]
+    fn test_nested_base() {
+        let pattern = Pattern::parse("*.log", "sub").unwrap();
+        assert!(pattern.matches("sub/a.log", false));
+        assert!(pattern.matches("sub/x/a.log", false));
+        assert!(!pattern.matches("a.log", false));
+    }
+
+    #[test]
+    fn test_negation() {
+        let rules = IgnoreRules {
+            patterns: ["*.log", "!keep.log"]
+                .iter()
+                .filter_map(|line| Pattern::parse(line, ""))
+                .collect(),
+        };
+        assert!(rules.is_ignored("a.log", false));
+        assert!(!rules.is_ignored("keep.log", false));
+        assert!(!rules.is_ignored("a.txt", false));
+    }
+
+    #[tokio::test]
+    async fn test_list_workdir_files() {
+        test::setup_with_new_libra().await;
+        test::ensure_file(".gitignore", Some("*.log\n!keep.log\nbuild/\n"));
+        test::ensure_file("ignore_test/.libraignore", Some("*.tmp\n"));
+        for file in [
+            "a.log",
+            "keep.log",
+            "build/x.txt",
+            "ignore_test/b.tmp",
+            "ignore_test/c.txt",
+            "b.tmp",
+        ] {
+            test::ensure_file(file, None);
+        }
+
+        let files = util::list_workdir_files().unwrap();
+        let listed = |file: &str| files.contains(&PathBuf::from(file));
+        assert!(!listed("a.log"));
+        assert!(listed("keep.log"));
+        assert!(!listed("build/x.txt"));
+        assert!(!listed("ignore_test/b.tmp"));
+        assert!(listed("ignore_test/c.txt"));
+        assert!(listed("b.tmp")); // `.libraignore` in sub-dir doesn't affect parent dir
+
+        assert!(is_path_ignored("build/x.txt"));
+        assert!(is_path_ignored("ignore_test/b.tmp"));
+        assert!(!is_path_ignored("ignore_test/c.txt"));
+
+        // don't affect other tests
+        fs::remove_file(util::working_dir().join(".gitignore")).unwrap();
+    }
+}
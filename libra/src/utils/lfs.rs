@@ -1,5 +1,5 @@
 use crate::utils::path_ext::PathExt;
-use crate::utils::{path, util};
+use crate::utils::{ignore, path, util};
 use lazy_static::lazy_static;
 use mercury::internal::index::Index;
 use regex::Regex;
@@ -10,7 +10,6 @@ use std::io::{BufRead, BufReader, Read};
 use std::path::{Path, PathBuf};
 use std::{fs, io};
 use url::Url;
-use wax::Pattern;
 
 lazy_static! {
     static ref LFS_PATTERNS: Vec<String> = { // cache
@@ -27,7 +26,7 @@ lazy_static! {
 }
 
 /// Check if a file is LFS tracked
-/// - support Glob pattern matching (TODO: support .gitignore patterns)
+/// - patterns follow the `.gitignore` rules (except negation), e.g. `*.bin` matches files in all sub-dirs
 /// - only check root attributes file now, should check all attributes files in sub-dirs
 /// - absolute path
 pub fn is_lfs_tracked<P>(path: P) -> bool
@@ -39,8 +38,10 @@ where
     }
 
     let path = util::to_workdir_path(path);
-    let glob = wax::any(LFS_PATTERNS.iter().map(|s| s.as_str()).collect::<Vec<_>>()).unwrap();
-    glob.is_match(path.to_str().unwrap())
+    LFS_PATTERNS
+        .iter()
+        .filter_map(|pattern| ignore::Pattern::parse(pattern, ""))
+        .any(|pattern| pattern.matches(&path, false))
 }
 
 const LFS_VERSION: &str = "https://git-lfs.github.com/spec/v1";
@@ -48,7 +49,7 @@ const LFS_VERSION: &str = "https://git-lfs.github.com/spec/v1";
 pub const LFS_TRANSFER_API: &str = "basic";
 pub const LFS_HASH_ALGO: &str = "sha256";
 const LFS_OID_LEN: usize = 64;
-const LFS_POINTER_MAX_SIZE: usize = 300; // bytes
+pub const LFS_POINTER_MAX_SIZE: usize = 300; // bytes
 
 /// Generate lfs pointer file string
 /// - return (pointer content, lfs oid)
@@ -87,8 +88,11 @@ fn generate_git_lfs_server_url(mut url: String) -> String {
         // git@git-server.com:foo/bar.git
         url = "https://".to_string() + &url[4..].replace(":", "/");
     } else if url.starts_with("ssh://") {
-        // ssh://git-server.com/foo/bar.git
-        url = "https://".to_string() + &url[6..];
+        // ssh://[user@]git-server.com[:port]/foo/bar.git, user & ssh port are useless for https
+        let (host, path) = url[6..].split_once('/').unwrap_or((&url[6..], ""));
+        let host = host.rsplit_once('@').map_or(host, |(_, host)| host);
+        let host = host.split_once(':').map_or(host, |(host, _)| host);
+        url = format!("https://{}/{}", host, path);
     }
 
     url
@@ -294,6 +298,9 @@ mod tests {
 
         let url = "ssh://github.com/web3infra-foundation/mega.git".to_owned();
         assert_eq!(generate_lfs_server_url(url), LFS_SERVER_URL);
+
+        let url = "ssh://git@github.com:22/web3infra-foundation/mega.git".to_owned();
+        assert_eq!(generate_lfs_server_url(url), LFS_SERVER_URL);
     }
 
     #[test]
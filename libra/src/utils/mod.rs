@@ -4,4 +4,11 @@ pub(crate) mod path;
 pub(crate) mod object_ext;
 pub(crate) mod path_ext;
 pub(crate) mod client_storage;
+pub(crate) mod ignore;
+pub(crate) mod hooks;
+pub(crate) mod shallow;
+pub(crate) mod sparse;
+pub(crate) mod signing;
+pub(crate) mod rename;
+pub(crate) mod base85;
 pub mod lfs;
\ No newline at end of file
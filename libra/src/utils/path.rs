@@ -1,8 +1,9 @@
 use std::path::PathBuf;
 use crate::utils::util;
 
+/// The index of the current worktree
 pub fn index() -> PathBuf {
-    util::storage_path().join("index")
+    util::worktree_storage_path().join("index")
 }
 
 pub fn objects() -> PathBuf {
@@ -15,4 +16,48 @@ pub fn database() -> PathBuf {
 
 pub fn attributes() -> PathBuf {
     util::working_dir().join(util::ATTRIBUTES)
-}
\ No newline at end of file
+}
+/// Records the commit being merged while conflicts are not resolved
+pub fn merge_head() -> PathBuf {
+    util::worktree_storage_path().join("MERGE_HEAD")
+}
+
+/// State of the in-progress `rebase`, aka `.libra/rebase-merge`
+pub fn rebase_merge() -> PathBuf {
+    util::worktree_storage_path().join("rebase-merge")
+}
+
+/// State of the in-progress `cherry-pick` or `revert`, aka `.libra/sequencer`
+pub fn sequencer() -> PathBuf {
+    util::worktree_storage_path().join("sequencer")
+}
+
+/// Records the commit being cherry-picked while conflicts are not resolved
+pub fn cherry_pick_head() -> PathBuf {
+    util::worktree_storage_path().join("CHERRY_PICK_HEAD")
+}
+
+/// Records the commit being reverted while conflicts are not resolved
+pub fn revert_head() -> PathBuf {
+    util::worktree_storage_path().join("REVERT_HEAD")
+}
+
+/// Default directory of client-side hooks, see `core.hooksPath`
+pub fn hooks() -> PathBuf {
+    util::storage_path().join("hooks")
+}
+
+/// Commits whose parents are not fetched (shallow clone), one hash per line
+pub fn shallow() -> PathBuf {
+    util::storage_path().join("shallow")
+}
+
+/// Storages of the linked worktrees, aka `.libra/worktrees`
+pub fn worktrees() -> PathBuf {
+    util::storage_path().join("worktrees")
+}
+
+/// Patterns of sparse checkout (per worktree), exists only if it's enabled
+pub fn sparse_checkout() -> PathBuf {
+    util::worktree_storage_path().join("info").join("sparse-checkout")
+}
@@ -0,0 +1,373 @@
+//! Rename & copy detection between two snapshots (`path -> blob`), like `git diff -M -C`.
+//!
+//! Files with the same blob are paired first, then the rest are paired by content similarity:
+//! the bytes of the common lines (line hashing) divided by the size of the larger file.
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{HashMap, HashSet};
+use std::hash::{Hash, Hasher};
+use std::path::{Path, PathBuf};
+
+use mercury::hash::SHA1;
+use mercury::internal::object::types::ObjectType;
+
+/// The default minimum similarity (percent) of `-M` & `-C`
+pub const DEFAULT_THRESHOLD: u8 = 50;
+/// Inexact detection is skipped if there are more than `RENAME_LIMIT * RENAME_LIMIT` pairs to compare
+const RENAME_LIMIT: usize = 1000;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Options {
+    /// The minimum similarity (percent) to be a rename or copy
+    pub threshold: u8,
+    /// Detect copies from the modified files too (`-C`)
+    pub copies: bool,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Options {
+            threshold: DEFAULT_THRESHOLD,
+            copies: false,
+        }
+    }
+}
+
+/// A new file which is renamed or copied from an old file
+#[derive(Debug, Clone, PartialEq)]
+pub struct Rename {
+    pub from: PathBuf,
+    pub to: PathBuf,
+    /// Similarity percent, `100` if the content is the same
+    pub similarity: u8,
+    /// The source is kept (copy), otherwise it's deleted (rename)
+    pub copy: bool,
+}
+
+/// Parse the similarity of `-M<n>` & `-C<n>` like git: `90%` is percent,
+/// digits without `%` are the fraction after the decimal point (`5` is 50%, `75` is 75%)
+pub fn parse_similarity(value: &str) -> Result<u8, String> {
+    let invalid = || format!("invalid similarity '{}'", value);
+    match value.strip_suffix('%') {
+        Some(percent) => match percent.parse::<u8>() {
+            Ok(percent) if percent <= 100 => Ok(percent),
+            _ => Err(invalid()),
+        },
+        None => {
+            if value.is_empty() || !value.chars().all(|c| c.is_ascii_digit()) {
+                return Err(invalid());
+            }
+            // `0.<value>` in percent, only the first 2 digits matter
+            let digits: String = value.chars().chain("00".chars()).take(2).collect();
+            Ok(digits.parse().unwrap())
+        }
+    }
+}
+
+/// Find renames (files deleted in `new`) & copies (with `options.copies`, files modified in `new`)
+/// of the files added in `new`, `read` loads the content of a blob for similarity.
+/// The result is sorted by the new path.
+pub fn detect(
+    old: &HashMap<PathBuf, SHA1>,
+    new: &HashMap<PathBuf, SHA1>,
+    options: Options,
+    read: impl Fn(&Path, &SHA1) -> Vec<u8>,
+) -> Vec<Rename> {
+    let mut added: Vec<&PathBuf> = new.keys().filter(|f| !old.contains_key(*f)).collect();
+    let mut sources: Vec<&PathBuf> = old
+        .iter()
+        .filter(|(file, hash)| match new.get(*file) {
+            None => true, // deleted
+            Some(new_hash) => options.copies && new_hash != *hash,
+        })
+        .map(|(file, _)| file)
+        .collect();
+    if added.is_empty() || sources.is_empty() {
+        return Vec::new();
+    }
+    added.sort();
+    sources.sort();
+
+    // deleted sources which have been renamed, later pairs with them are copies
+    let mut renamed: HashSet<PathBuf> = HashSet::new();
+    let mut result = Vec::new();
+
+    // exact renames: the same blob, empty files are not paired
+    let empty = SHA1::from_type_and_data(ObjectType::Blob, &[]);
+    let mut by_hash: HashMap<&SHA1, Vec<&PathBuf>> = HashMap::new();
+    for file in sources.iter() {
+        by_hash.entry(&old[*file]).or_default().push(file);
+    }
+    added.retain(|to| {
+        let hash = &new[*to];
+        let candidates = match by_hash.get(hash) {
+            Some(candidates) if *hash != empty => candidates,
+            _ => return true,
+        };
+        // prefer the source which can be renamed
+        let from = candidates
+            .iter()
+            .find(|from| !new.contains_key(**from) && !renamed.contains(**from))
+            .unwrap_or(&candidates[0]);
+        let paired = pair(
+            &mut result,
+            &mut renamed,
+            new,
+            from,
+            to,
+            100,
+            options.copies,
+        );
+        !paired
+    });
+    if added.is_empty() {
+        return result;
+    }
+    if added.len() * sources.len() > RENAME_LIMIT * RENAME_LIMIT {
+        eprintln!("warning: inexact rename detection was skipped due to too many files.");
+        return result;
+    }
+
+    // inexact renames: similar content
+    let fingerprints: Vec<Fingerprint> = sources
+        .iter()
+        .map(|from| Fingerprint::new(&read(from, &old[*from])))
+        .collect();
+    let mut candidates = Vec::new();
+    for (to_idx, to) in added.iter().enumerate() {
+        let fingerprint = Fingerprint::new(&read(to, &new[*to]));
+        for (from_idx, from) in fingerprints.iter().enumerate() {
+            let similarity = fingerprint.similarity(from);
+            if similarity > 0 && similarity >= options.threshold {
+                candidates.push((similarity, to_idx, from_idx));
+            }
+        }
+    }
+    // the most similar pairs first, then by paths
+    candidates.sort_by(|a, b| b.0.cmp(&a.0).then((a.1, a.2).cmp(&(b.1, b.2))));
+    let mut paired = vec![false; added.len()];
+    // renames take precedence over copies
+    let passes: &[bool] = if options.copies {
+        &[false, true]
+    } else {
+        &[false]
+    };
+    for allow_copy in passes {
+        for (similarity, to_idx, from_idx) in candidates.iter() {
+            if !paired[*to_idx] {
+                paired[*to_idx] = pair(
+                    &mut result,
+                    &mut renamed,
+                    new,
+                    sources[*from_idx],
+                    added[*to_idx],
+                    *similarity,
+                    *allow_copy,
+                );
+            }
+        }
+    }
+
+    result.sort_by(|a, b| a.to.cmp(&b.to));
+    result
+}
+
+/// Pair `from` to `to`, it's a copy if `from` is kept or has been renamed
+fn pair(
+    result: &mut Vec<Rename>,
+    renamed: &mut HashSet<PathBuf>,
+    new: &HashMap<PathBuf, SHA1>,
+    from: &Path,
+    to: &Path,
+    similarity: u8,
+    allow_copy: bool,
+) -> bool {
+    let copy = new.contains_key(from) || renamed.contains(from);
+    if copy && !allow_copy {
+        return false;
+    }
+    if !copy {
+        renamed.insert(from.to_path_buf());
+    }
+    result.push(Rename {
+        from: from.to_path_buf(),
+        to: to.to_path_buf(),
+        similarity,
+        copy,
+    });
+    true
+}
+
+/// The bytes of each line (by hash) of a file
+struct Fingerprint {
+    size: usize,
+    lines: HashMap<u64, usize>,
+}
+
+impl Fingerprint {
+    fn new(data: &[u8]) -> Self {
+        let mut lines = HashMap::new();
+        for line in data.split_inclusive(|&b| b == b'\n') {
+            let mut hasher = DefaultHasher::new();
+            line.hash(&mut hasher);
+            *lines.entry(hasher.finish()).or_insert(0) += line.len();
+        }
+        Fingerprint {
+            size: data.len(),
+            lines,
+        }
+    }
+
+    /// The percent of the common bytes in the larger file, `0` for empty files
+    fn similarity(&self, other: &Fingerprint) -> u8 {
+        let max_size = self.size.max(other.size);
+        if self.size == 0 || other.size == 0 {
+            return 0;
+        }
+        let common: usize = self
+            .lines
+            .iter()
+            .filter_map(|(hash, bytes)| other.lines.get(hash).map(|other| *bytes.min(other)))
+            .sum();
+        (common * 100 / max_size) as u8
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn snapshot(files: &[(&str, &str)]) -> HashMap<PathBuf, SHA1> {
+        files
+            .iter()
+            .map(|(file, content)| {
+                (
+                    PathBuf::from(file),
+                    SHA1::from_type_and_data(ObjectType::Blob, content.as_bytes()),
+                )
+            })
+            .collect()
+    }
+
+    fn detect_in(
+        old: &[(&str, &str)],
+        new: &[(&str, &str)],
+        options: Options,
+    ) -> Vec<(String, String, u8, bool)> {
+        let contents: HashMap<SHA1, String> = old
+            .iter()
+            .chain(new.iter())
+            .map(|(_, content)| {
+                (
+                    SHA1::from_type_and_data(ObjectType::Blob, content.as_bytes()),
+                    content.to_string(),
+                )
+            })
+            .collect();
+        detect(&snapshot(old), &snapshot(new), options, |_, hash| {
+            contents[hash].as_bytes().to_vec()
+        })
+        .into_iter()
+        .map(|r| {
+            (
+                r.from.display().to_string(),
+                r.to.display().to_string(),
+                r.similarity,
+                r.copy,
+            )
+        })
+        .collect()
+    }
+
+    #[test]
+    fn test_parse_similarity() {
+        assert_eq!(parse_similarity("90%"), Ok(90));
+        assert_eq!(parse_similarity("5"), Ok(50));
+        assert_eq!(parse_similarity("75"), Ok(75));
+        assert_eq!(parse_similarity("100%"), Ok(100));
+        assert_eq!(parse_similarity("0"), Ok(0));
+        assert!(parse_similarity("101%").is_err());
+        assert!(parse_similarity("abc").is_err());
+        assert!(parse_similarity("").is_err());
+    }
+
+    #[test]
+    fn test_detect_renames() {
+        let content = "line 1\nline 2\nline 3\nline 4\nline 5\nline 6\nline 7\nline 8\n";
+        let edited = "line 1\nline 2\nline 3\nline 4\nline 5\nline 6\nline 7\nline 8\nline 9\n";
+        let old = [
+            ("a.txt", content),
+            ("b.txt", edited),
+            ("c.txt", "other\n"),
+            ("e.txt", ""),
+        ];
+        let renamed_edited =
+            "line 1\nline 2\nline 3\nline 4\nline 5\nline 6\nline 7\nline 8\nline nine\n";
+        let new = [
+            ("x/a.txt", content),
+            ("y/b.txt", renamed_edited),
+            ("z.txt", "nothing in common\n"),
+            ("f.txt", ""),
+        ];
+
+        let renames = detect_in(&old, &new, Options::default());
+        assert_eq!(
+            renames,
+            vec![
+                ("a.txt".to_string(), "x/a.txt".to_string(), 100, false),
+                ("b.txt".to_string(), "y/b.txt".to_string(), 84, false),
+            ]
+        );
+
+        let strict = Options {
+            threshold: 90,
+            copies: false,
+        };
+        assert_eq!(detect_in(&old, &new, strict).len(), 1);
+    }
+
+    #[test]
+    fn test_detect_copies() {
+        let content = "fn main() {\n    println!(\"hello\");\n}\n";
+        let old = [("main.rs", content), ("lib.rs", "pub mod a;\n")];
+        let new = [
+            ("main.rs", content),
+            ("lib.rs", "pub mod a;\npub mod b;\n"),
+            ("bin.rs", content),
+            ("lib2.rs", "pub mod a;\n"),
+        ];
+        // unmodified files are not the sources of copies
+        let copies = detect_in(
+            &old,
+            &new,
+            Options {
+                threshold: DEFAULT_THRESHOLD,
+                copies: true,
+            },
+        );
+        assert_eq!(
+            copies,
+            vec![("lib.rs".to_string(), "lib2.rs".to_string(), 100, true)]
+        );
+        // without `copies`, there is no rename as nothing is deleted
+        assert!(detect_in(&old, &new, Options::default()).is_empty());
+
+        // the second pair of a deleted file is a copy
+        let old = [("a.txt", content)];
+        let new = [("b.txt", content), ("c.txt", content)];
+        let copies = detect_in(
+            &old,
+            &new,
+            Options {
+                threshold: DEFAULT_THRESHOLD,
+                copies: true,
+            },
+        );
+        assert_eq!(
+            copies,
+            vec![
+                ("a.txt".to_string(), "b.txt".to_string(), 100, false),
+                ("a.txt".to_string(), "c.txt".to_string(), 100, true),
+            ]
+        );
+    }
+}
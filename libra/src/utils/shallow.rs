@@ -0,0 +1,58 @@
+//! Shallow repository support: the commits whose parents are not fetched are recorded in `.libra/shallow`,
+//! history walking must stop at them.
+use std::collections::HashSet;
+use std::fs;
+use std::str::FromStr;
+
+use mercury::hash::SHA1;
+
+use crate::utils::path;
+
+/// Load the shallow commits, empty if the repository is complete
+pub fn load() -> HashSet<SHA1> {
+    match fs::read_to_string(path::shallow()) {
+        Ok(content) => content
+            .lines()
+            .filter_map(|line| SHA1::from_str(line.trim()).ok())
+            .collect(),
+        Err(_) => HashSet::new(),
+    }
+}
+
+/// Add the `shallow` commits and remove the `unshallow` commits sent by the server,
+/// the file is removed if there are no shallow commits anymore
+pub fn update(shallow: &[SHA1], unshallow: &[SHA1]) {
+    let mut commits = load();
+    commits.extend(shallow.iter().copied());
+    for commit in unshallow {
+        commits.remove(commit);
+    }
+    if commits.is_empty() {
+        let _ = fs::remove_file(path::shallow());
+        return;
+    }
+    let mut lines: Vec<String> = commits.iter().map(|c| c.to_string()).collect();
+    lines.sort();
+    fs::write(path::shallow(), lines.join("\n") + "\n").unwrap();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::test;
+
+    #[tokio::test]
+    async fn test_update_shallow() {
+        test::setup_with_new_libra().await;
+        assert!(!path::shallow().exists());
+        let (a, b) = (SHA1::new(&[1; 20]), SHA1::new(&[2; 20]));
+        update(&[a, b], &[]);
+        assert!(path::shallow().exists());
+        assert_eq!(load(), HashSet::from([a, b]));
+
+        update(&[], &[a]);
+        assert_eq!(load(), HashSet::from([b]));
+        update(&[], &[b]);
+        assert!(!path::shallow().exists());
+    }
+}
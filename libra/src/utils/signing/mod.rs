@@ -0,0 +1,297 @@
+//! Sign and verify commits & tags without external `gpg` or `ssh-keygen`.
+//!
+//! `gpg.format` selects the format of new signatures:
+//! - `openpgp` (default): `user.signingkey` is the file of an armored OpenPGP secret key
+//!   (`gpg --export-secret-keys --armor`), signatures are verified with the keys in `gpg.keyring`
+//!   (armored public keys, can be concatenated) and the signing key itself
+//! - `ssh`: `user.signingkey` is an OpenSSH private key file (or its `.pub` file),
+//!   signatures are trusted if the key is listed in `gpg.ssh.allowedSignersFile` like git
+//!
+//! Only unencrypted Ed25519 keys can sign, while RSA (and ECDSA P-256 for ssh) signatures can also be verified.
+use std::fmt::Display;
+use std::fs;
+use std::path::PathBuf;
+
+use mercury::hash::SHA1;
+use mercury::internal::object::types::ObjectType;
+
+use crate::internal::config::Config;
+use crate::utils::util;
+
+mod openpgp;
+mod ssh;
+
+/// The result of a cryptographically valid signature
+#[derive(Debug, Clone)]
+pub struct Verified {
+    /// `OpenPGP` or `SSH`
+    pub format: &'static str,
+    /// The fingerprint of the signing key
+    pub key: String,
+    /// The user ID (OpenPGP) or principals (SSH) of the key, `None` if the key is not trusted
+    pub signer: Option<String>,
+}
+
+impl Verified {
+    pub fn is_trusted(&self) -> bool {
+        self.signer.is_some()
+    }
+}
+
+impl Display for Verified {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match &self.signer {
+            Some(signer) => write!(
+                f,
+                "Good signature from \"{}\" with {} key {}",
+                signer, self.format, self.key
+            ),
+            None => write!(
+                f,
+                "Good signature with {} key {}, but the key is not trusted",
+                self.format, self.key
+            ),
+        }
+    }
+}
+
+/// Sign the payload with `user.signingkey`, return the armored signature
+pub async fn sign(payload: &[u8]) -> Result<String, String> {
+    let key_file = Config::get("user", None, "signingkey")
+        .await
+        .ok_or("user.signingkey is not set")?;
+    match Config::get("gpg", None, "format").await.as_deref() {
+        None | Some("openpgp") => {
+            let key = openpgp::SigningKey::from_armored(&read_file(&key_file)?)?;
+            Ok(key.sign(payload))
+        }
+        Some("ssh") => {
+            let key_file = key_file.strip_suffix(".pub").unwrap_or(&key_file);
+            let key = ssh::SigningKey::from_openssh(&read_file(key_file)?)?;
+            Ok(key.sign(payload))
+        }
+        Some(format) => Err(format!("unsupported gpg.format: '{}'", format)),
+    }
+}
+
+/// Verify the armored signature of the payload, the format is detected from the signature
+pub async fn verify(payload: &[u8], signature: &str) -> Result<Verified, String> {
+    if signature.contains(ssh::BEGIN) {
+        let public_key = ssh::verify(payload, signature)?;
+        let signer = match Config::get("gpg", Some("ssh"), "allowedSignersFile").await {
+            Some(file) => {
+                ssh::find_principals(&read_file(&file)?, &public_key, signing_time(payload))
+            }
+            None => None,
+        };
+        return Ok(Verified {
+            format: "SSH",
+            key: ssh::fingerprint(&public_key),
+            signer,
+        });
+    }
+
+    let mut keys = Vec::new();
+    if let Some(file) = Config::get("gpg", None, "keyring").await {
+        keys.extend(openpgp::load_keys(&read_file(&file)?)?);
+    }
+    // the own signing key is trusted too (skipped if it's an ssh key)
+    if let Some(file) = Config::get("user", None, "signingkey").await {
+        if let Ok(own_keys) = read_file(&file).and_then(|content| openpgp::load_keys(&content)) {
+            keys.extend(own_keys);
+        }
+    }
+    let key = openpgp::verify(payload, signature, &keys)?;
+    Ok(Verified {
+        format: "OpenPGP",
+        key: key.fingerprint(),
+        signer: Some(key.user_id.clone().unwrap_or_else(|| key.fingerprint())),
+    })
+}
+
+/// The time of the committer (or tagger) in the payload, like `-Overify-time` passed by git, or now if not found
+fn signing_time(payload: &[u8]) -> i64 {
+    String::from_utf8_lossy(payload)
+        .lines()
+        .take_while(|line| !line.is_empty()) // headers only
+        .filter(|line| line.starts_with("committer ") || line.starts_with("tagger "))
+        .find_map(|line| line.rsplit(' ').nth(1)?.parse().ok())
+        .unwrap_or_else(|| chrono::Utc::now().timestamp())
+}
+
+/// Verify the signature of the commit or tag object
+/// - `Ok(None)` if the object is not signed
+pub async fn verify_object(id: &SHA1) -> Result<Option<Verified>, String> {
+    let (data, object_type) = util::objects_storage()
+        .get_with_type(id)
+        .map_err(|e| e.to_string())?;
+    let split = match object_type {
+        ObjectType::Commit => split_commit(&data),
+        ObjectType::Tag => split_tag(&data),
+        _ => return Err(format!("{} is a {}, not a commit or tag", id, object_type)),
+    };
+    match split {
+        Some((payload, signature)) => verify(&payload, &signature).await.map(Some),
+        None => Ok(None),
+    }
+}
+
+/// Make the `gpgsig` header of the commit from the armored signature,
+/// use it as the signature of [common::utils::format_commit_msg]
+pub fn commit_header(signature: &str) -> String {
+    format!("gpgsig {}", signature.trim_end().replace('\n', "\n "))
+}
+
+/// Split the raw commit data into the signed payload (without the `gpgsig` header) and the armored signature
+pub fn split_commit(data: &[u8]) -> Option<(Vec<u8>, String)> {
+    let mut payload = Vec::with_capacity(data.len());
+    let mut signature: Option<Vec<u8>> = None;
+    let mut in_signature = false;
+    let mut rest = data;
+    while let Some(end) = rest.iter().position(|&b| b == b'\n') {
+        let (line, tail) = rest.split_at(end + 1);
+        if line == b"\n" {
+            break; // the end of headers, the rest is the message
+        }
+        if let Some(first_line) = line.strip_prefix(b"gpgsig ") {
+            signature = Some(first_line.to_vec());
+            in_signature = true;
+        } else if in_signature && line.starts_with(b" ") {
+            // continuation lines are indented by a space
+            signature.as_mut().unwrap().extend(&line[1..]);
+        } else {
+            in_signature = false;
+            payload.extend(line);
+        }
+        rest = tail;
+    }
+    payload.extend(rest);
+    Some((payload, String::from_utf8(signature?).ok()?))
+}
+
+/// Split the raw tag data into the signed payload and the armored signature appended to the message
+pub fn split_tag(data: &[u8]) -> Option<(Vec<u8>, String)> {
+    let text = std::str::from_utf8(data).ok()?;
+    let start = [openpgp::BEGIN, ssh::BEGIN]
+        .iter()
+        .filter_map(|begin| text.rfind(&format!("\n{}", begin)))
+        .max()?
+        + 1;
+    Some((data[..start].to_vec(), text[start..].to_string()))
+}
+
+/// Read the key file, `~` is expanded to the home directory
+fn read_file(file: &str) -> Result<String, String> {
+    let path = match file.strip_prefix("~/") {
+        Some(relative) => home::home_dir().unwrap_or_default().join(relative),
+        None => PathBuf::from(file),
+    };
+    fs::read_to_string(&path).map_err(|e| format!("failed to read '{}': {}", path.display(), e))
+}
+
+/// Big-endian binary reader for the key & signature formats
+struct Reader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Reader { data }
+    }
+
+    fn remaining(&self) -> &'a [u8] {
+        self.data
+    }
+
+    fn bytes(&mut self, n: usize) -> Result<&'a [u8], String> {
+        if self.data.len() < n {
+            return Err("unexpected end of data".to_string());
+        }
+        let (head, tail) = self.data.split_at(n);
+        self.data = tail;
+        Ok(head)
+    }
+
+    fn u8(&mut self) -> Result<u8, String> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, String> {
+        Ok(u16::from_be_bytes(self.bytes(2)?.try_into().unwrap()))
+    }
+
+    fn u32(&mut self) -> Result<u32, String> {
+        Ok(u32::from_be_bytes(self.bytes(4)?.try_into().unwrap()))
+    }
+
+    /// SSH `string`: `u32` length & data
+    fn string(&mut self) -> Result<&'a [u8], String> {
+        let len = self.u32()? as usize;
+        self.bytes(len)
+    }
+
+    /// OpenPGP multiprecision integer: `u16` bit count & big-endian number
+    fn mpi(&mut self) -> Result<&'a [u8], String> {
+        let bits = self.u16()? as usize;
+        self.bytes(bits.div_ceil(8))
+    }
+}
+
+/// Left pad the big-endian number (leading zeros are trimmed first) to `len` bytes
+fn left_pad(number: &[u8], len: usize) -> Result<Vec<u8>, String> {
+    let number = trim_zeros(number);
+    if number.len() > len {
+        return Err("invalid signature length".to_string());
+    }
+    let mut padded = vec![0; len - number.len()];
+    padded.extend(number);
+    Ok(padded)
+}
+
+fn trim_zeros(number: &[u8]) -> &[u8] {
+    let start = number.iter().position(|&b| b != 0).unwrap_or(number.len());
+    &number[start..]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_split_commit() {
+        let unsigned = "tree 1\nauthor a\ncommitter c\n\nmessage\n";
+        let signed = "tree 1\nauthor a\ncommitter c\ngpgsig -----BEGIN SSH SIGNATURE-----\n \n abc\n -----END SSH SIGNATURE-----\n\nmessage\n";
+        let (payload, signature) = split_commit(signed.as_bytes()).unwrap();
+        assert_eq!(payload, unsigned.as_bytes());
+        assert_eq!(
+            signature,
+            "-----BEGIN SSH SIGNATURE-----\n\nabc\n-----END SSH SIGNATURE-----\n"
+        );
+        assert_eq!(
+            commit_header(&signature),
+            "gpgsig -----BEGIN SSH SIGNATURE-----\n \n abc\n -----END SSH SIGNATURE-----"
+        );
+        assert!(split_commit(unsigned.as_bytes()).is_none());
+    }
+
+    #[test]
+    fn test_signing_time() {
+        let payload =
+            b"tree 1\nauthor a <a> 1 +0000\ncommitter c <c> 1717200000 +0800\n\nmessage\n";
+        assert_eq!(signing_time(payload), 1717200000);
+        let payload = b"object 1\ntype commit\ntag v1\ntagger t <t> 1717200001 +0000\n\nrelease\n";
+        assert_eq!(signing_time(payload), 1717200001);
+    }
+
+    #[test]
+    fn test_split_tag() {
+        let signed = "object 1\ntype commit\ntag v1\ntagger t\n\nrelease\n-----BEGIN PGP SIGNATURE-----\n\nabc\n-----END PGP SIGNATURE-----\n";
+        let (payload, signature) = split_tag(signed.as_bytes()).unwrap();
+        assert_eq!(
+            payload,
+            b"object 1\ntype commit\ntag v1\ntagger t\n\nrelease\n"
+        );
+        assert!(signature.starts_with(openpgp::BEGIN));
+        assert!(split_tag(&payload).is_none());
+    }
+}
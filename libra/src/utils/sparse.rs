@@ -0,0 +1,160 @@
+//! Sparse checkout (cone mode): only the files in the given directories (recursively), and the files directly
+//! in their parent directories & the root, are checked out. Others are marked as `skip_worktree` in the index.
+//!
+//! The patterns are stored in `.libra/info/sparse-checkout` (per worktree) in the cone format of git, e.g. for `a/b`:
+//! ```text
+//! /*
+//! !/*/
+//! /a/
+//! !/a/*/
+//! /a/b/
+//! ```
+//! The file exists only when sparse checkout is enabled.
+use std::collections::BTreeSet;
+use std::fs;
+use std::path::Path;
+
+use mercury::internal::index::Index;
+
+use crate::utils::path;
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Sparse {
+    /// the directories checked out recursively, to workdir, without leading & trailing `/`
+    dirs: BTreeSet<String>,
+}
+
+impl Sparse {
+    /// Load the patterns, `None` if sparse checkout is disabled
+    pub fn load() -> Option<Self> {
+        let content = fs::read_to_string(path::sparse_checkout()).ok()?;
+        Some(Self::from_patterns(&content))
+    }
+
+    pub fn save(&self) {
+        let file = path::sparse_checkout();
+        fs::create_dir_all(file.parent().unwrap()).unwrap();
+        fs::write(file, self.to_patterns()).unwrap();
+    }
+
+    /// Disable sparse checkout
+    pub fn remove() {
+        let _ = fs::remove_file(path::sparse_checkout());
+    }
+
+    /// Normalize the input directories, e.g. `./a/b/` -> `a/b`, return `None` if some of them is invalid
+    pub fn new<S: AsRef<str>>(dirs: &[S]) -> Option<Self> {
+        let mut sparse = Sparse::default();
+        sparse.add(dirs).then_some(sparse)
+    }
+
+    /// Add the directories, return `false` if some of them is invalid (e.g. `..` or wildcards)
+    pub fn add<S: AsRef<str>>(&mut self, dirs: &[S]) -> bool {
+        for dir in dirs {
+            let parts: Vec<&str> = dir
+                .as_ref()
+                .split('/')
+                .filter(|part| !part.is_empty() && *part != ".")
+                .collect();
+            if parts
+                .iter()
+                .any(|part| *part == ".." || part.contains(['*', '?', '[', '\\']))
+            {
+                return false;
+            }
+            if !parts.is_empty() {
+                self.dirs.insert(parts.join("/"));
+            }
+        }
+        true
+    }
+
+    /// The directories checked out recursively
+    pub fn dirs(&self) -> impl Iterator<Item = &String> {
+        self.dirs.iter()
+    }
+
+    /// Whether the file (to workdir) should be checked out
+    pub fn contains(&self, file: impl AsRef<Path>) -> bool {
+        let parent = match file.as_ref().parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => parent,
+            _ => return true, // files in the root
+        };
+        self.dirs.iter().any(|dir| {
+            let dir = Path::new(dir);
+            // files in the directory (recursively) & directly in its parents
+            parent.starts_with(dir) || dir.starts_with(parent)
+        })
+    }
+
+    /// Set the `skip_worktree` flags of the index (stage 0) by the patterns
+    pub fn mark_index(&self, index: &mut Index) {
+        for file in index.tracked_files() {
+            index.set_skip_worktree(file.to_str().unwrap(), !self.contains(&file));
+        }
+    }
+
+    fn to_patterns(&self) -> String {
+        let mut patterns = String::from("/*\n!/*/\n");
+        let mut parents = BTreeSet::new();
+        for dir in &self.dirs {
+            let mut parent = Path::new(dir).parent();
+            while let Some(dir) = parent.filter(|p| !p.as_os_str().is_empty()) {
+                parents.insert(dir.to_str().unwrap().to_string());
+                parent = dir.parent();
+            }
+        }
+        // sorted, parents are in front of their children
+        let all: BTreeSet<&String> = parents.iter().chain(self.dirs.iter()).collect();
+        for dir in all {
+            patterns += &format!("/{}/\n", dir);
+            if !self.dirs.contains(dir) {
+                patterns += &format!("!/{}/*/\n", dir);
+            }
+        }
+        patterns
+    }
+
+    /// The recursive directories are those positive patterns without `!/<dir>/*/`
+    fn from_patterns(patterns: &str) -> Self {
+        let lines: Vec<&str> = patterns.lines().map(str::trim).collect();
+        let dirs = lines
+            .iter()
+            .filter(|line| !line.starts_with('!') && !line.starts_with('#') && line.len() > 2)
+            .filter_map(|line| line.strip_prefix('/')?.strip_suffix('/'))
+            .filter(|dir| !lines.contains(&format!("!/{}/*/", dir).as_str()))
+            .map(String::from)
+            .collect();
+        Sparse { dirs }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sparse_patterns() {
+        let sparse = Sparse::new(&["a/b/", "./c", "a/b/d"]).unwrap();
+        assert_eq!(sparse.dirs().collect::<Vec<_>>(), vec!["a/b", "a/b/d", "c"]);
+        let patterns = sparse.to_patterns();
+        assert_eq!(patterns, "/*\n!/*/\n/a/\n!/a/*/\n/a/b/\n/a/b/d/\n/c/\n");
+        assert_eq!(Sparse::from_patterns(&patterns), sparse);
+        assert!(Sparse::new(&["a/../b"]).is_none());
+        assert!(Sparse::new(&["src/*"]).is_none());
+    }
+
+    #[test]
+    fn test_sparse_contains() {
+        let sparse = Sparse::new(&["a/b"]).unwrap();
+        assert!(sparse.contains("root.txt"));
+        assert!(sparse.contains("a/file.txt"));
+        assert!(sparse.contains("a/b/file.txt"));
+        assert!(sparse.contains("a/b/c/d/file.txt"));
+        assert!(!sparse.contains("a/c/file.txt"));
+        assert!(!sparse.contains("ab/file.txt"));
+        assert!(!sparse.contains("c/file.txt"));
+        assert!(Sparse::default().contains("root.txt"));
+        assert!(!Sparse::default().contains("a/file.txt"));
+    }
+}
@@ -8,8 +8,9 @@ use std::io::Write;
 use std::path::Path;
 use std::{env, fs, path::PathBuf};
 
-use crate::utils::util;
 use crate::command;
+use crate::internal::db;
+use crate::utils::util;
 
 pub const TEST_DIR: &str = "libra_test_repo";
 
@@ -73,9 +74,11 @@ fn setup_env() {
 /// This function first calls `setup_env()` to switch the current directory to the test directory.
 /// Then, it checks if the Libra root directory (`.libra`) exists in the current directory.
 /// If it does, the function removes the entire `.libra` directory.
-pub fn setup_clean_testing_env() {
+/// The database connection of the removed repo is closed, so the next one connects to the new repo.
+pub async fn setup_clean_testing_env() {
     // Switch the current directory to the test directory
     setup_env();
+    db::reset_db_conn_instance().await;
 
     // Get the current directory
     let mut path = util::cur_dir();
@@ -91,7 +94,7 @@ pub fn setup_clean_testing_env() {
 
 /// switch to test dir and create a new .libra
 pub async fn setup_with_new_libra() {
-    setup_clean_testing_env();
+    setup_clean_testing_env().await;
     command::init::init().await.unwrap();
 }
 
@@ -5,15 +5,20 @@ use path_absolutize::*;
 use std::{env, fs, io};
 use indicatif::{ProgressBar, ProgressStyle};
 use mercury::hash::SHA1;
+use mercury::internal::object::commit::Commit;
 use mercury::internal::object::types::ObjectType;
+use mercury::internal::object::ObjectTrait;
 
 use crate::utils::client_storage::ClientStorage;
+use crate::utils::ignore::IgnoreRules;
 use crate::utils::path;
 use crate::utils::path_ext::PathExt;
 
 pub const ROOT_DIR: &str = ".libra";
 pub const DATABASE: &str = "libra.db";
 pub const ATTRIBUTES: &str = ".libra_attributes";
+/// The `.libra` file of a linked worktree: `libradir: <path of .libra/worktrees/<name>>`
+pub const LIBRA_FILE_PREFIX: &str = "libradir: ";
 
 /// Returns the current working directory as a `PathBuf`.
 ///
@@ -29,9 +34,9 @@ pub fn cur_dir() -> PathBuf {
     env::current_dir().unwrap()
 }
 
-/// Try to get the storage path of the repository, which is the path of the `.libra` directory
-/// - if the current directory is not a repository, return an error
-pub fn try_get_storage_path() -> Result<PathBuf, io::Error> {
+/// Find `.libra` in the current directory or its parents, which is a directory in the main worktree,
+/// or a file pointing to `.libra/worktrees/<name>` of the main worktree in a linked worktree
+fn find_libra_entry() -> Result<PathBuf, io::Error> {
     let mut cur_dir = env::current_dir()?;
     loop {
         let mut libra = cur_dir.clone();
@@ -48,11 +53,57 @@ pub fn try_get_storage_path() -> Result<PathBuf, io::Error> {
     }
 }
 
+/// Read `libradir: <path>` in the `.libra` file of a linked worktree
+pub(crate) fn read_libra_file(file: &Path) -> Result<PathBuf, io::Error> {
+    fs::read_to_string(file)?
+        .trim_end()
+        .strip_prefix(LIBRA_FILE_PREFIX)
+        .map(PathBuf::from)
+        .ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("invalid libra file: {}", file.display()),
+            )
+        })
+}
+
+/// Try to get the storage path of the repository, which is the path of the `.libra` directory
+/// - in a linked worktree, it's the `.libra` of the main worktree, which is shared
+/// - if the current directory is not a repository, return an error
+pub fn try_get_storage_path() -> Result<PathBuf, io::Error> {
+    let libra = find_libra_entry()?;
+    if libra.is_dir() {
+        return Ok(libra);
+    }
+    // `.libra/worktrees/<name>` -> `.libra`
+    let worktree_storage = read_libra_file(&libra)?;
+    match worktree_storage.parent().and_then(Path::parent) {
+        Some(storage) => Ok(storage.to_path_buf()),
+        None => Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("invalid libra file: {}", libra.display()),
+        )),
+    }
+}
+
 /// Get the storage path of the repository, aka `.libra`
 /// - panics if the current directory is not a repository
 pub fn storage_path() -> PathBuf {
     try_get_storage_path().unwrap()
 }
+
+/// Get the storage of the current worktree (index, in-progress states, etc.),
+/// aka `.libra` in the main worktree, or `.libra/worktrees/<name>` in a linked worktree
+/// - panics if the current directory is not a repository
+pub fn worktree_storage_path() -> PathBuf {
+    let libra = find_libra_entry().unwrap();
+    if libra.is_dir() {
+        libra
+    } else {
+        read_libra_file(&libra).unwrap()
+    }
+}
+
 /// Check if libra repo exists
 pub fn check_repo_exist() -> bool {
     if try_get_storage_path().is_err() {
@@ -70,9 +121,9 @@ pub fn objects_storage() -> ClientStorage {
 /// Get the working directory of the repository
 /// - panics if the current directory is not a repository
 pub fn working_dir() -> PathBuf {
-    let mut storage_path = storage_path();
-    storage_path.pop();
-    storage_path
+    let mut libra = find_libra_entry().unwrap();
+    libra.pop();
+    libra
 }
 
 /// Get the working directory of the repository as a string, panics if the path is not valid utf-8
@@ -211,7 +262,13 @@ pub fn list_files(path: &Path) -> io::Result<Vec<PathBuf>> {
         for entry in fs::read_dir(path)? {
             let entry = entry?;
             let path = entry.path();
+            if path.file_name().unwrap_or_default() == ROOT_DIR {
+                continue; // `.libra` file of a linked worktree
+            }
             if path.is_dir() {
+                if path.join(ROOT_DIR).exists() {
+                    continue; // another repository or worktree
+                }
                 files.extend(list_files(&path)?);
             } else {
                 files.push(to_workdir_path(&path));
@@ -221,10 +278,55 @@ pub fn list_files(path: &Path) -> io::Result<Vec<PathBuf>> {
     Ok(files)
 }
 
-/// list all files in the working dir(include sub_dir)
+/// List all files in the given dir and its sub_dir, except `.libra` and ignored files (`.gitignore` etc.)
+/// - input `path`: absolute path or relative path to the current dir
+/// - output: to workdir path
+/// - empty if `path` itself is ignored
+pub fn list_files_not_ignored(path: &Path) -> io::Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    if path.is_dir() {
+        if let Some(mut rules) = IgnoreRules::load_for_dir(to_workdir_path(path)) {
+            list_files_with_rules(path, &mut rules, &mut files)?;
+        }
+    }
+    Ok(files)
+}
+
+/// walk `dir`, `rules` must contain the ignore files of `dir` and its parents
+fn list_files_with_rules(
+    dir: &Path,
+    rules: &mut IgnoreRules,
+    files: &mut Vec<PathBuf>,
+) -> io::Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.file_name().unwrap_or_default() == ROOT_DIR {
+            continue; // ignore `.libra`
+        }
+        let path_wd = to_workdir_path(&path);
+        let is_dir = path.is_dir();
+        if rules.is_ignored(&path_wd, is_dir) {
+            continue;
+        }
+        if is_dir && path.join(ROOT_DIR).exists() {
+            continue; // another repository or worktree
+        }
+        if is_dir {
+            let count = rules.pattern_count();
+            rules.load_dir(&path_wd);
+            list_files_with_rules(&path, rules, files)?;
+            rules.truncate(count);
+        } else {
+            files.push(path_wd);
+        }
+    }
+    Ok(())
+}
+
+/// list all files in the working dir(include sub_dir), except ignored files
 /// - output: to workdir path
 pub fn list_workdir_files() -> io::Result<Vec<PathBuf>> {
-    list_files(&working_dir())
+    list_files_not_ignored(&working_dir())
 }
 
 /// Integrate the input paths (relative, absolute, file, dir) to workdir paths
@@ -260,7 +362,7 @@ pub fn clear_empty_dir(dir: &Path) {
         dir.parent().unwrap().to_path_buf()
     };
 
-    let repo = storage_path();
+    let repo = working_dir().join(ROOT_DIR);
     // CAN NOT remove .libra & current dir
     while !is_sub_path(&repo, &dir) && !is_cur_dir(&dir) {
         if is_empty_dir(&dir) {
@@ -291,8 +393,10 @@ pub fn path_to_string(path: &Path) -> String {
 }
 
 /// extend hash, panic if not valid or ambiguous
+/// - support ancestry suffixes, e.g. `1a2b3c~2`, `1a2b3c^2`, see [navigate_rev]
 pub fn get_commit_base(commit_base: &str) -> Result<SHA1, String> {
     let storage = objects_storage();
+    let (commit_base, suffix) = split_rev_suffix(commit_base);
 
     let commits = storage.search(commit_base);
     if commits.is_empty() {
@@ -307,8 +411,60 @@ pub fn get_commit_base(commit_base: &str) -> Result<SHA1, String> {
             storage.get_object_type(&commits[0]).unwrap()
         ))
     } else {
-        Ok(commits[0])
+        navigate_rev(commits[0], suffix).map_err(|e| format!("fatal: {}", e))
+    }
+}
+
+/// Split the revision into the base name and the ancestry suffix (starts with `~` or `^`)
+/// - e.g. `HEAD~2^2` -> (`HEAD`, `~2^2`)
+pub fn split_rev_suffix(rev: &str) -> (&str, &str) {
+    match rev.find(['~', '^']) {
+        Some(pos) => rev.split_at(pos),
+        None => (rev, ""),
+    }
+}
+
+/// Walk the ancestry suffix from `commit`
+/// - `~<n>`: the `n`th generation ancestor following the first parents, `~` means `~1`
+/// - `^<n>`: the `n`th parent, `^` means `^1`, `^0` is the commit itself
+pub fn navigate_rev(commit: SHA1, suffix: &str) -> Result<SHA1, String> {
+    let storage = objects_storage();
+    let mut commit = commit;
+    let mut chars = suffix.chars().peekable();
+    while let Some(op) = chars.next() {
+        let mut digits = String::new();
+        while let Some(c) = chars.next_if(|c| c.is_ascii_digit()) {
+            digits.push(c);
+        }
+        let n: usize = match digits.as_str() {
+            "" => 1,
+            digits => digits
+                .parse()
+                .map_err(|_| format!("invalid revision suffix: {}", suffix))?,
+        };
+        let parents_of = |commit: &SHA1| -> Result<Vec<SHA1>, String> {
+            let data = storage.get(commit).map_err(|e| e.to_string())?;
+            let commit = Commit::from_bytes(&data, *commit).map_err(|e| e.to_string())?;
+            Ok(commit.parent_commit_ids)
+        };
+        match op {
+            '~' => {
+                for _ in 0..n {
+                    commit = *parents_of(&commit)?
+                        .first()
+                        .ok_or(format!("{} has no parent", commit))?;
+                }
+            }
+            '^' if n > 0 => {
+                commit = *parents_of(&commit)?
+                    .get(n - 1)
+                    .ok_or(format!("{} has no parent #{}", commit, n))?;
+            }
+            '^' => {}
+            _ => return Err(format!("invalid revision suffix: {}", suffix)),
+        }
     }
+    Ok(commit)
 }
 
 /// Get the repository name from the url
@@ -323,6 +479,65 @@ pub fn get_repo_name_from_url(mut url: &str) -> Option<&str> {
     Some(&url[repo_start..repo_end])
 }
 
+/// Parse the date to unix timestamp, supported formats:
+/// - `now`, `yesterday`, relative date like `2.weeks.ago` or `3 days ago`
+/// - unix timestamp, RFC 3339 or `YYYY-MM-DD` (UTC)
+pub fn parse_date(date: &str) -> Result<i64, String> {
+    let date = date.trim();
+    let now = chrono::Utc::now().timestamp();
+    match date {
+        "now" => return Ok(now),
+        "yesterday" => return Ok(now - 24 * 3600),
+        _ => {}
+    }
+    let words: Vec<&str> = date.split(['.', ' ']).filter(|w| !w.is_empty()).collect();
+    if let [n, unit, "ago"] = words[..] {
+        let n: i64 = n.parse().map_err(|_| format!("invalid date '{}'", date))?;
+        let seconds = match unit.trim_end_matches('s') {
+            "second" => 1,
+            "minute" => 60,
+            "hour" => 3600,
+            "day" => 24 * 3600,
+            "week" => 7 * 24 * 3600,
+            "month" => 30 * 24 * 3600,
+            "year" => 365 * 24 * 3600,
+            _ => return Err(format!("invalid date '{}'", date)),
+        };
+        return Ok(now - n * seconds);
+    }
+    if let Ok(timestamp) = date.parse::<i64>() {
+        return Ok(timestamp);
+    }
+    if let Ok(date) = chrono::DateTime::parse_from_rfc3339(date) {
+        return Ok(date.timestamp());
+    }
+    chrono::NaiveDate::parse_from_str(date, "%Y-%m-%d")
+        .map(|date| date.and_hms_opt(0, 0, 0).unwrap().and_utc().timestamp())
+        .map_err(|_| format!("invalid date '{}'", date))
+}
+
+/// Open `file` with the editor and wait for it to exit
+/// - editor is taken from `GIT_EDITOR`, `VISUAL` or `EDITOR`, `vi` by default
+/// - editor may contain arguments, e.g. `code --wait`
+pub fn edit_file(file: &Path) -> io::Result<()> {
+    let editor = ["GIT_EDITOR", "VISUAL", "EDITOR"]
+        .iter()
+        .find_map(|var| env::var(var).ok().filter(|v| !v.trim().is_empty()))
+        .unwrap_or_else(|| "vi".to_string());
+    let mut args = editor.split_whitespace();
+    let status = std::process::Command::new(args.next().unwrap())
+        .args(args)
+        .arg(file)
+        .status()?;
+    if !status.success() {
+        return Err(io::Error::other(format!(
+            "there was a problem with the editor '{}'",
+            editor
+        )));
+    }
+    Ok(())
+}
+
 /// Find the appropriate unit and value for Bytes.
 /// ### Examples
 /// - 1024 bytes -> 1 KiB
@@ -379,4 +594,54 @@ mod test {
         assert_eq!(to_workdir_path("./"), PathBuf::from("."));
         assert_eq!(to_workdir_path(""), PathBuf::from("."));
     }
+
+    #[test]
+    fn test_parse_date() {
+        assert_eq!(parse_date("1700000000"), Ok(1700000000));
+        assert_eq!(parse_date("2024-01-01"), Ok(1704067200));
+        assert_eq!(parse_date("2024-01-01T08:00:00+08:00"), Ok(1704067200));
+        let now = chrono::Utc::now().timestamp();
+        let two_weeks_ago = parse_date("2.weeks.ago").unwrap();
+        assert!((now - 14 * 24 * 3600 - two_weeks_ago).abs() <= 1);
+        assert_eq!(parse_date("1 day ago"), parse_date("1.days.ago"));
+        assert!(parse_date("tomorrow").is_err());
+        assert!(parse_date("2.fortnights.ago").is_err());
+    }
+
+    #[test]
+    fn test_split_rev_suffix() {
+        assert_eq!(split_rev_suffix("HEAD~2^2"), ("HEAD", "~2^2"));
+        assert_eq!(split_rev_suffix("main^"), ("main", "^"));
+        assert_eq!(split_rev_suffix("1a2b3c"), ("1a2b3c", ""));
+    }
+
+    #[tokio::test]
+    async fn test_navigate_rev() {
+        test::setup_with_new_libra().await;
+        let storage = objects_storage();
+        let save = |commit: &Commit| {
+            storage
+                .put(&commit.id, &commit.to_data().unwrap(), commit.get_type())
+                .unwrap();
+        };
+        let tree = SHA1::new(&[1; 20]);
+        let root = Commit::from_tree_id(tree, vec![], "root");
+        let side = Commit::from_tree_id(tree, vec![root.id], "side");
+        let main = Commit::from_tree_id(tree, vec![root.id], "main");
+        let merge = Commit::from_tree_id(tree, vec![main.id, side.id], "merge");
+        for commit in [&root, &side, &main, &merge] {
+            save(commit);
+        }
+
+        assert_eq!(navigate_rev(merge.id, ""), Ok(merge.id));
+        assert_eq!(navigate_rev(merge.id, "^0"), Ok(merge.id));
+        assert_eq!(navigate_rev(merge.id, "^"), Ok(main.id));
+        assert_eq!(navigate_rev(merge.id, "^2"), Ok(side.id));
+        assert_eq!(navigate_rev(merge.id, "~2"), Ok(root.id));
+        assert_eq!(navigate_rev(merge.id, "^2~"), Ok(root.id));
+        assert!(navigate_rev(merge.id, "^3").is_err());
+        assert!(navigate_rev(root.id, "~").is_err());
+        let abbrev = format!("{}^2", &merge.id.to_string()[..10]);
+        assert_eq!(get_commit_base(&abbrev), Ok(side.id));
+    }
 }
@@ -0,0 +1,243 @@
+//! Fast delta encoding like `diff-delta.c` of git: the hashes of the base blocks are chained in a table,
+//! the target is scanned with a rolling hash to find the blocks, then the matches are extended byte by byte.
+//! It's linear to the data size, unlike the diff of [crate::encode], but the delta may be a bit larger.
+
+/// The size of the indexed blocks, shorter matches are inserted as data
+pub const BLOCK_SIZE: usize = 16;
+/// Offsets kept in a bucket, to limit the time of repetitive data (e.g. zeros)
+const MAX_CHAIN: u32 = 64;
+/// A copy instruction can copy `0x10000` bytes at most (encoded as size 0)
+const MAX_COPY: usize = 0x10000;
+const MAX_INSERT: usize = 0x7f;
+const HASH_BASE: u32 = 0x0100_0193;
+
+/// The index of a base object, which can be reused to encode several targets.
+/// The base data is not kept, it must be passed to [DeltaIndex::encode].
+///
+/// The offsets of the blocks are grouped by bucket in one array (about 4 bytes per block),
+/// only the first `4 GiB` of the base is indexed.
+pub struct DeltaIndex {
+    base_len: usize,
+    /// `log2` of the bucket number
+    bucket_bits: u32,
+    /// bucket -> the start of its offsets in `offsets`, with the end at the last
+    buckets: Vec<u32>,
+    /// offsets of the blocks (aligned to [BLOCK_SIZE]), at most [MAX_CHAIN] for a bucket
+    offsets: Vec<u32>,
+}
+
+impl DeltaIndex {
+    pub fn new(base: &[u8]) -> Self {
+        let indexed = &base[..base.len().min(u32::MAX as usize)];
+        let blocks = indexed.len() / BLOCK_SIZE;
+        // about 4 blocks a bucket, like git
+        let bucket_bits = (blocks / 4).max(1).next_power_of_two().trailing_zeros();
+        let bucket_of = |block: &[u8]| bucket(block_hash(block), bucket_bits);
+
+        // count the offsets of each bucket first, so they fit in a single array
+        let mut buckets = vec![0u32; (1 << bucket_bits) + 1];
+        for block in indexed.chunks_exact(BLOCK_SIZE) {
+            let count = &mut buckets[bucket_of(block) + 1];
+            *count = (*count + 1).min(MAX_CHAIN);
+        }
+        for i in 1..buckets.len() {
+            buckets[i] += buckets[i - 1];
+        }
+        let mut offsets = vec![0u32; buckets[buckets.len() - 1] as usize];
+        let mut next = buckets.clone();
+        for (i, block) in indexed.chunks_exact(BLOCK_SIZE).enumerate() {
+            let b = bucket_of(block);
+            if next[b] < buckets[b + 1] {
+                offsets[next[b] as usize] = (i * BLOCK_SIZE) as u32;
+                next[b] += 1;
+            }
+        }
+        DeltaIndex {
+            base_len: base.len(),
+            bucket_bits,
+            buckets,
+            offsets,
+        }
+    }
+
+    /// The memory used by the index in bytes
+    pub fn memory_size(&self) -> usize {
+        (self.buckets.len() + self.offsets.len()) * size_of::<u32>()
+    }
+
+    /// Offsets of the blocks which may have the hash
+    fn candidates(&self, hash: u32) -> &[u32] {
+        let b = bucket(hash, self.bucket_bits);
+        &self.offsets[self.buckets[b] as usize..self.buckets[b + 1] as usize]
+    }
+
+    /// Encode `target` as a git delta of `base`, which must be the data of the index
+    pub fn encode(&self, base: &[u8], target: &[u8]) -> Vec<u8> {
+        assert_eq!(
+            base.len(),
+            self.base_len,
+            "the base doesn't match the index"
+        );
+        let mut delta = Vec::with_capacity(target.len() / 4 + 16);
+        write_size(&mut delta, base.len());
+        write_size(&mut delta, target.len());
+
+        // the start of the data which hasn't been copied
+        let mut insert_from = 0;
+        let mut i = 0;
+        let mut hash = match target.len() >= BLOCK_SIZE {
+            true => block_hash(&target[..BLOCK_SIZE]),
+            false => 0,
+        };
+        let high = HASH_BASE.wrapping_pow(BLOCK_SIZE as u32 - 1);
+        while i + BLOCK_SIZE <= target.len() {
+            let (mut offset, mut len) = (0, 0);
+            for &candidate in self.candidates(hash) {
+                let candidate = candidate as usize;
+                let matched = common_prefix(&base[candidate..], &target[i..]);
+                if matched > len {
+                    (offset, len) = (candidate, matched);
+                }
+            }
+            if len < BLOCK_SIZE {
+                // roll the hash to the next byte
+                if i + BLOCK_SIZE < target.len() {
+                    hash = hash
+                        .wrapping_sub((target[i] as u32).wrapping_mul(high))
+                        .wrapping_mul(HASH_BASE)
+                        .wrapping_add(target[i + BLOCK_SIZE] as u32);
+                }
+                i += 1;
+                continue;
+            }
+            // extend the match backwards into the pending data
+            let mut start = i;
+            while start > insert_from && offset > 0 && base[offset - 1] == target[start - 1] {
+                (start, offset, len) = (start - 1, offset - 1, len + 1);
+            }
+            write_insert(&mut delta, &target[insert_from..start]);
+            write_copy(&mut delta, offset, len);
+            i = start + len;
+            insert_from = i;
+            if i + BLOCK_SIZE <= target.len() {
+                hash = block_hash(&target[i..i + BLOCK_SIZE]);
+            }
+        }
+        write_insert(&mut delta, &target[insert_from..]);
+        delta
+    }
+}
+
+/// Polynomial hash, which can be rolled by removing the first byte & appending a byte
+fn block_hash(block: &[u8]) -> u32 {
+    block.iter().fold(0u32, |hash, &b| {
+        hash.wrapping_mul(HASH_BASE).wrapping_add(b as u32)
+    })
+}
+
+/// The bucket of a block hash, by the high bits of the mixed hash (the low bits of the
+/// polynomial hash only depend on the low bits of the bytes)
+fn bucket(hash: u32, bits: u32) -> usize {
+    match bits {
+        0 => 0,
+        _ => (hash.wrapping_mul(0x9e37_79b1) >> (32 - bits)) as usize,
+    }
+}
+
+fn common_prefix(a: &[u8], b: &[u8]) -> usize {
+    a.iter().zip(b).take_while(|(x, y)| x == y).count()
+}
+
+/// Variable-length size, 7 bits per byte, little-endian
+fn write_size(delta: &mut Vec<u8>, mut size: usize) {
+    loop {
+        let byte = (size & 0x7f) as u8;
+        size >>= 7;
+        if size == 0 {
+            delta.push(byte);
+            break;
+        }
+        delta.push(byte | 0x80);
+    }
+}
+
+fn write_insert(delta: &mut Vec<u8>, data: &[u8]) {
+    for chunk in data.chunks(MAX_INSERT) {
+        delta.push(chunk.len() as u8);
+        delta.extend_from_slice(chunk);
+    }
+}
+
+/// Copy instructions: the flags of the non-zero bytes of offset (4 bytes) & size (3 bytes), then the bytes
+fn write_copy(delta: &mut Vec<u8>, mut offset: usize, mut len: usize) {
+    while len > 0 {
+        let size = len.min(MAX_COPY);
+        let mut instruction = 0x80u8;
+        let mut bytes = Vec::with_capacity(7);
+        for i in 0..4 {
+            let byte = (offset >> (8 * i)) as u8;
+            if byte != 0 {
+                instruction |= 1 << i;
+                bytes.push(byte);
+            }
+        }
+        // `0x10000` is encoded as 0
+        for i in 0..3 {
+            let byte = ((size & 0xffff) >> (8 * i)) as u8;
+            if byte != 0 {
+                instruction |= 0x10 << i;
+                bytes.push(byte);
+            }
+        }
+        delta.push(instruction);
+        delta.extend(bytes);
+        offset += size;
+        len -= size;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+    use crate::decode::delta_decode;
+
+    fn roundtrip(base: &[u8], target: &[u8]) -> usize {
+        let delta = DeltaIndex::new(base).encode(base, target);
+        let decoded = delta_decode(&mut Cursor::new(&delta), base).unwrap();
+        assert_eq!(decoded, target);
+        delta.len()
+    }
+
+    #[test]
+    fn test_delta_index() {
+        // pseudo-random data (xorshift)
+        let mut x = 0x2545_f491u32;
+        let base: Vec<u8> = (0..400_000)
+            .map(|_| {
+                x ^= x << 13;
+                x ^= x >> 17;
+                x ^= x << 5;
+                x as u8
+            })
+            .collect();
+        let mut target = base.clone();
+        target[5000] ^= 0xff; // modified
+        target.splice(20_000..20_000, b"inserted data".iter().copied());
+        target.drain(300_000..300_100); // removed
+        let size = roundtrip(&base, &target);
+        assert!(size < 200, "delta size: {}", size);
+        // about 5 bytes for a block of 16 bytes
+        let memory = DeltaIndex::new(&base).memory_size();
+        assert!(memory < base.len() / 3, "index memory: {}", memory);
+        // repeated blocks are capped
+        assert!(DeltaIndex::new(&[0; 300_000]).offsets.len() <= MAX_CHAIN as usize);
+
+        // nothing in common, or too short
+        roundtrip(b"abcdefghijklmnopqrstuvwxyz", b"0123456789");
+        roundtrip(b"", b"0123456789abcdefghijklmnopqrstuvwxyz");
+        roundtrip(&[0; 1000], &[0; 300_000]);
+        roundtrip(&base, b"");
+    }
+}
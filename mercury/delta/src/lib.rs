@@ -3,11 +3,13 @@ use encode::DeltaDiff;
 mod decode;
 mod encode;
 mod errors;
+mod index;
 mod utils;
 
 
 
 pub use decode::delta_decode as decode;
+pub use index::DeltaIndex;
 pub fn encode_rate(old_data: & [u8], new_data: & [u8]) -> f64{
     let differ = DeltaDiff::new(old_data, new_data);
     differ.get_ssam_rate()
@@ -52,15 +52,21 @@ impl Display for Time {
     }
 }
 
-/// 16 bits
+/// 16 bits, and 16 bits extended flags (v3) if `extended`
 #[derive(Debug)]
 pub struct Flags {
     pub assume_valid: bool,
     pub extended: bool,   // must be 0 in v2
     pub stage: u8,        // 2-bit during merge
     pub name_length: u16, // 12-bit
+    /// (v3) the extended flags as read, e.g. `skip-worktree` & `intent-to-add`, written back unchanged
+    pub extended_flags: u16,
 }
 
+/// the bit of `skip-worktree` in extended flags:
+/// the file is not checked out in the worktree, e.g. excluded by sparse checkout
+const SKIP_WORKTREE: u16 = 0x4000;
+
 impl From<u16> for Flags {
     fn from(flags: u16) -> Self {
         Flags {
@@ -68,6 +74,7 @@ impl From<u16> for Flags {
             extended: flags & 0x4000 != 0,
             stage: ((flags & 0x3000) >> 12) as u8,
             name_length: flags & 0xFFF,
+            extended_flags: 0,
         }
     }
 }
@@ -79,7 +86,7 @@ impl TryInto<u16> for &Flags {
         if self.assume_valid {
             flags |= 0x8000; // 16
         }
-        if self.extended {
+        if self.extended || self.extended_flags != 0 {
             flags |= 0x4000; // 15
         }
         flags |= (self.stage as u16) << 12; // 13-14
@@ -98,6 +105,20 @@ impl Flags {
             extended: false,
             stage: 0,
             name_length: name_len,
+            extended_flags: 0,
+        }
+    }
+
+    pub fn skip_worktree(&self) -> bool {
+        self.extended_flags & SKIP_WORKTREE != 0
+    }
+
+    /// Toggle the `skip-worktree` bit only, other extended flags are kept
+    pub fn set_skip_worktree(&mut self, skip: bool) {
+        if skip {
+            self.extended_flags |= SKIP_WORKTREE;
+        } else {
+            self.extended_flags &= !SKIP_WORKTREE;
         }
     }
 }
@@ -209,8 +230,8 @@ impl Index {
         }
 
         let version = file.read_u32::<BigEndian>()?;
-        // only support v2 now
-        if version != 2 {
+        // only support v2 & v3 (extended flags) now
+        if version != 2 && version != 3 {
             return Err(GitError::InvalidIndexHeader(version.to_string()));
         }
 
@@ -250,6 +271,12 @@ impl Index {
                 flags: Flags::from(file.read_u16::<BigEndian>()?),
                 name: String::new(),
             };
+            let mut flags_len = 2;
+            if entry.flags.extended {
+                entry.flags.extended_flags = file.read_u16::<BigEndian>()?;
+                entry.flags.extended = false; // computed when writing
+                flags_len += 2;
+            }
             let name_len = entry.flags.name_length as usize;
             let mut name = vec![0; name_len];
             file.read_exact(&mut name)?;
@@ -259,7 +286,7 @@ impl Index {
 
             // 1-8 nul bytes as necessary to pad the entry to a multiple of eight bytes
             // while keeping the name NUL-terminated. // so at least 1 byte nul
-            let padding = 8 - ((22 + name_len) % 8); // 22 = sha1 + flags, others are 40 % 8 == 0
+            let padding = 8 - ((20 + flags_len + name_len) % 8); // sha1 + flags, others are 40 % 8 == 0
             utils::read_bytes(file, padding)?;
         }
 
@@ -296,8 +323,13 @@ impl Index {
         let mut hash = Sha1::new();
 
         let mut header = Vec::new();
+        // v3 is only needed for extended flags
+        let extended = self
+            .entries
+            .values()
+            .any(|entry| entry.flags.extended_flags != 0);
         header.write_all(b"DIRC")?;
-        header.write_u32::<BigEndian>(2u32)?; // version 2
+        header.write_u32::<BigEndian>(if extended { 3 } else { 2 })?;
         header.write_u32::<BigEndian>(self.entries.len() as u32)?;
         file.write_all(&header)?;
         hash.update(&header);
@@ -316,8 +348,13 @@ impl Index {
             entry_bytes.write_u32::<BigEndian>(entry.size)?;
             entry_bytes.write_all(&entry.hash.0)?;
             entry_bytes.write_u16::<BigEndian>((&entry.flags).try_into().unwrap())?;
+            let mut flags_len = 2;
+            if entry.flags.extended_flags != 0 {
+                entry_bytes.write_u16::<BigEndian>(entry.flags.extended_flags)?;
+                flags_len += 2;
+            }
             entry_bytes.write_all(entry.name.as_bytes())?;
-            let padding = 8 - ((22 + entry.name.len()) % 8);
+            let padding = 8 - ((20 + flags_len + entry.name.len()) % 8);
             entry_bytes.write_all(&vec![0; padding])?;
 
             file.write_all(&entry_bytes)?;
@@ -392,6 +429,19 @@ impl Index {
         }
     }
 
+    /// Is the file (stage 0) not checked out in the worktree, see [Flags::skip_worktree]
+    pub fn is_skip_worktree(&self, name: &str) -> bool {
+        self.get(name, 0)
+            .is_some_and(|entry| entry.flags.skip_worktree())
+    }
+
+    /// Set or clear the `skip_worktree` flag of the file (stage 0), do nothing if it's not tracked
+    pub fn set_skip_worktree(&mut self, name: &str, skip: bool) {
+        if let Some(entry) = self.entries.get_mut(&(name.to_string(), 0)) {
+            entry.flags.set_skip_worktree(skip);
+        }
+    }
+
     /// Get all entries with the same stage
     pub fn tracked_entries(&self, stage: u8) -> Vec<&IndexEntry> {
         // ? should use stage or not
@@ -407,6 +457,25 @@ impl Index {
         self.tracked_entries(0).iter().map(|entry| PathBuf::from(&entry.name)).collect()
     }
 
+    /// Get all unmerged files (stage 1-3, conflicts left by merge), deduplicated & sorted
+    pub fn unmerged_files(&self) -> Vec<String> {
+        let mut files: Vec<String> = self
+            .entries
+            .keys()
+            .filter(|(_, stage)| *stage != 0)
+            .map(|(name, _)| name.clone())
+            .collect();
+        files.dedup(); // BTreeMap is sorted by (name, stage), so same names are adjacent
+        files
+    }
+
+    /// Remove the conflict entries (stage 1-3) of `name`, aka mark it as resolved
+    pub fn remove_unmerged(&mut self, name: &str) {
+        for stage in 1..=3 {
+            self.remove(name, stage);
+        }
+    }
+
     /// Judge if the file(s) of `dir` is in the index
     /// - false if `dir` is a file
     pub fn contains_dir_file(&self, dir: &str) -> bool {
@@ -479,6 +548,50 @@ mod tests {
         assert_eq!(index.size(), new_index.size());
     }
 
+    #[test]
+    fn test_skip_worktree() {
+        let mut index = Index::new();
+        for name in ["a.txt", "dir/b.txt", "dir/sub/c"] {
+            index.add(IndexEntry::new_from_blob(name.to_string(), SHA1::default(), 0));
+        }
+        index.set_skip_worktree("dir/b.txt", true);
+        let file = tempfile::NamedTempFile::new().unwrap();
+        index.to_file(file.path()).unwrap();
+        let mut index = Index::from_file(file.path()).unwrap();
+        assert_eq!(index.size(), 3);
+        assert!(index.is_skip_worktree("dir/b.txt"));
+        assert!(!index.is_skip_worktree("a.txt") && !index.is_skip_worktree("dir/sub/c"));
+
+        // back to v2
+        index.set_skip_worktree("dir/b.txt", false);
+        index.to_file(file.path()).unwrap();
+        let index = Index::from_file(file.path()).unwrap();
+        assert!(!index.is_skip_worktree("dir/b.txt"));
+    }
+
+    #[test]
+    fn test_keep_extended_flags() {
+        const INTENT_TO_ADD: u16 = 0x2000;
+        let mut index = Index::new();
+        let mut entry = IndexEntry::new_from_blob("a.txt".to_string(), SHA1::default(), 0);
+        entry.flags.extended_flags = INTENT_TO_ADD;
+        index.add(entry);
+        let file = tempfile::NamedTempFile::new().unwrap();
+        index.to_file(file.path()).unwrap();
+
+        let mut index = Index::from_file(file.path()).unwrap();
+        index.set_skip_worktree("a.txt", true);
+        index.to_file(file.path()).unwrap();
+        let mut index = Index::from_file(file.path()).unwrap();
+        assert!(index.is_skip_worktree("a.txt"));
+        index.set_skip_worktree("a.txt", false);
+        index.to_file(file.path()).unwrap();
+        let index = Index::from_file(file.path()).unwrap();
+        let flags = &index.get("a.txt", 0).unwrap().flags;
+        assert!(!flags.skip_worktree());
+        assert_eq!(flags.extended_flags, INTENT_TO_ADD);
+    }
+
     #[test]
     fn test_index_entry_create() {
         let file = Path::new("Cargo.toml"); // use as a normal file
@@ -487,4 +600,20 @@ mod tests {
         let entry = IndexEntry::new_from_file(file, hash, workdir).unwrap();
         println!("{}", entry);
     }
+
+    #[test]
+    fn test_unmerged_files() {
+        let mut index = Index::new();
+        index.add(IndexEntry::new_from_blob("a.txt".to_string(), SHA1::default(), 0));
+        for stage in 1..=3 {
+            let mut entry = IndexEntry::new_from_blob("b.txt".to_string(), SHA1::default(), 0);
+            entry.flags.stage = stage;
+            index.add(entry);
+        }
+        assert_eq!(index.unmerged_files(), vec!["b.txt".to_string()]);
+
+        index.remove_unmerged("b.txt");
+        assert!(index.unmerged_files().is_empty());
+        assert_eq!(index.size(), 1);
+    }
 }
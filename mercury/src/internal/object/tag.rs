@@ -75,6 +75,26 @@ impl Display for Tag {
 }
 
 impl Tag {
+    /// Create an annotated tag object, `message` should start with a blank line like commit message
+    pub fn new(
+        object_hash: SHA1,
+        object_type: ObjectType,
+        tag_name: String,
+        tagger: Signature,
+        message: &str,
+    ) -> Tag {
+        let mut tag = Tag {
+            id: SHA1::default(),
+            object_hash,
+            object_type,
+            tag_name,
+            tagger,
+            message: message.to_string(),
+        };
+        tag.id = SHA1::from_type_and_data(ObjectType::Tag, &tag.to_data().unwrap());
+        tag
+    }
+
     // pub fn new_from_meta(meta: Meta) -> Result<Tag, GitError> {
     //     Ok(Tag::new_from_data(meta.data))
     // }
@@ -181,3 +201,34 @@ impl ObjectTrait for Tag {
         Ok(data)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tag_new_and_parse() {
+        let tagger = Signature::from_data(
+            "tagger mega <admin@mega.org> 1700000000 +0800"
+                .as_bytes()
+                .to_vec(),
+        )
+        .unwrap();
+        let object_hash = SHA1::from_str("4b00093bee9b3ef5afc5f8e3645dc39cfa2f49aa").unwrap();
+        let tag = Tag::new(
+            object_hash,
+            ObjectType::Commit,
+            "v1.0".to_string(),
+            tagger,
+            "\nrelease 1.0\n",
+        );
+
+        let data = tag.to_data().unwrap();
+        assert_eq!(tag.id, SHA1::from_type_and_data(ObjectType::Tag, &data));
+        let parsed = Tag::from_bytes(&data, tag.id).unwrap();
+        assert_eq!(parsed.object_hash, object_hash);
+        assert_eq!(parsed.object_type, ObjectType::Commit);
+        assert_eq!(parsed.tag_name, "v1.0");
+        assert_eq!(parsed.message, "\nrelease 1.0\n");
+    }
+}
@@ -7,18 +7,27 @@ use sha1::{Digest, Sha1};
 use tokio::sync::mpsc;
 use tokio::task::JoinHandle;
 
+use delta::DeltaIndex;
+
 use crate::internal::object::types::ObjectType;
 use crate::time_it;
 use crate::{errors::GitError, hash::SHA1, internal::pack::entry::Entry};
 
 const MIN_DELTA_RATE: f64 = 0.5; // minimum delta rate can accept
+/// Objects smaller than this are diffed exactly, larger objects are encoded by [DeltaIndex] (hash chains)
+const EXACT_DELTA_LIMIT: usize = 1024;
+/// Larger objects are not deltified, the index of each object in the window would take too much memory
+const MAX_DELTA_SIZE: usize = 16 * 1024 * 1024;
+/// Old objects are dropped from the window if the data & indexes take more memory, like `pack.windowMemory`
+const MAX_WINDOW_MEMORY: usize = 256 * 1024 * 1024;
 
 /// A encoder for generating pack files with delta objects.
 pub struct PackEncoder {
     object_number: usize,
     process_index: usize,
     window_size: usize,
-    window: VecDeque<(Entry, usize)>, // entry and offset
+    window: VecDeque<(Entry, usize, Option<DeltaIndex>)>, // entry, offset and index for fast delta
+    window_memory: usize, // memory of the data & indexes in the window
     sender: Option<mpsc::Sender<Vec<u8>>>,
     inner_offset: usize, // offset of current entry
     inner_hash: Sha1,    // Not SHA1 because need update trait
@@ -108,6 +117,11 @@ fn encode_one_object(entry: &Entry, offset: Option<usize>) -> Result<Vec<u8>, Gi
     Ok(encoded_data)
 }
 
+/// The memory taken by an object in the window
+fn window_memory(entry: &Entry, index: &Option<DeltaIndex>) -> usize {
+    entry.data.len() + index.as_ref().map_or(0, DeltaIndex::memory_size)
+}
+
 impl PackEncoder {
     pub fn new(object_number: usize, window_size: usize, sender: mpsc::Sender<Vec<u8>>) -> Self {
         PackEncoder {
@@ -115,6 +129,7 @@ impl PackEncoder {
             window_size,
             process_index: 0,
             window: VecDeque::with_capacity(window_size),
+            window_memory: 0,
             sender: Some(sender),
             inner_offset: 12, // 12 bytes header
             inner_hash: Sha1::new(),
@@ -160,6 +175,12 @@ impl PackEncoder {
             match entry_rx.recv().await {
                 Some(entry) => {
                     self.process_index += 1;
+                    if self.window_size == 0 {
+                        // no delta, neither the window nor the index is needed
+                        let obj_data = encode_one_object(&entry, None)?;
+                        self.write_all_and_update(&obj_data).await;
+                        continue;
+                    }
                     // push window after encode to void diff by self
                     let offset = self.inner_offset;
                     let mut try_delta_entry = entry.clone();
@@ -167,9 +188,16 @@ impl PackEncoder {
                     let obj_data = encode_one_object(&try_delta_entry, try_delfa_offset)?;
 
                     self.write_all_and_update(&obj_data).await;
-                    self.window.push_back((entry, offset));
-                    if self.window.len() > self.window_size {
-                        self.window.pop_front();
+                    let index =
+                        (entry.data.len() <= MAX_DELTA_SIZE).then(|| DeltaIndex::new(&entry.data));
+                    self.window_memory += window_memory(&entry, &index);
+                    self.window.push_back((entry, offset, index));
+                    // keep the newest object even if it's too large
+                    while self.window.len() > self.window_size
+                        || (self.window.len() > 1 && self.window_memory > MAX_WINDOW_MEMORY)
+                    {
+                        let (entry, _, index) = self.window.pop_front().unwrap();
+                        self.window_memory -= window_memory(&entry, &index);
                     }
                 }
                 None => {
@@ -264,34 +292,46 @@ impl PackEncoder {
         Ok(())
     }
 
-    /// Try to encode as delta using objects in window
+    /// Try to encode as delta using objects in window, the smallest delta is chosen
     /// # Returns
     /// - Return (offset) if success make delta
     /// - Return (None) if didn't delta,
     fn try_as_offset_delta(&mut self, entry: &mut Entry) -> Option<usize> {
-        let mut best_base: Option<&(Entry, usize)> = None;
-        let mut best_rate: f64 = 0.0;
-        for try_base in self.window.iter() {
-            if try_base.0.obj_type != entry.obj_type {
+        if entry.data.len() > MAX_DELTA_SIZE {
+            return None;
+        }
+        let mut best: Option<(Vec<u8>, usize)> = None; // delta and offset of the base
+        for (base, base_offset, index) in self.window.iter() {
+            if base.obj_type != entry.obj_type {
                 continue;
             }
-            let rate = delta::encode_rate(&try_base.0.data, &entry.data);
-            if rate > MIN_DELTA_RATE && rate > best_rate {
-                best_rate = rate;
-                best_base = Some(try_base);
+            let Some(index) = index else {
+                continue;
+            };
+            let delta =
+                if base.data.len() < EXACT_DELTA_LIMIT && entry.data.len() < EXACT_DELTA_LIMIT {
+                    if delta::encode_rate(&base.data, &entry.data) <= MIN_DELTA_RATE {
+                        continue;
+                    }
+                    delta::encode(&base.data, &entry.data)
+                } else {
+                    let delta = index.encode(&base.data, &entry.data);
+                    if delta.len() as f64 > entry.data.len() as f64 * (1.0 - MIN_DELTA_RATE) {
+                        continue;
+                    }
+                    delta
+                };
+            if best
+                .as_ref()
+                .is_none_or(|(best, _)| best.len() > delta.len())
+            {
+                best = Some((delta, *base_offset));
             }
         }
-        if best_rate > 0.0 {
-            let best_base = best_base.unwrap(); // must some if best rate > 0
-            let delta = delta::encode(&best_base.0.data, &entry.data);
-            let offset = self.inner_offset - best_base.1;
-            entry.obj_type = ObjectType::OffsetDelta;
-            entry.data = delta;
-
-            Some(offset)
-        } else {
-            None
-        }
+        let (delta, base_offset) = best?;
+        entry.obj_type = ObjectType::OffsetDelta;
+        entry.data = delta;
+        Some(self.inner_offset - base_offset)
     }
 
     /// Write data to writer and update hash & offset
@@ -381,6 +421,61 @@ mod tests {
         check_format(&pack_with_delta);
     }
 
+    #[tokio::test]
+    async fn test_encode_without_window() {
+        let (tx, mut rx) = mpsc::channel(100);
+        let (entry_tx, entry_rx) = mpsc::channel::<Entry>(10);
+        let contents = ["hello, code,", "hello, world."];
+        for content in contents {
+            entry_tx
+                .send(Blob::from_content(content).into())
+                .await
+                .unwrap();
+        }
+        drop(entry_tx);
+        let mut encoder = PackEncoder::new(contents.len(), 0, tx);
+        encoder.encode(entry_rx).await.unwrap();
+        assert!(encoder.window.is_empty());
+        let mut result = Vec::new();
+        while let Some(chunk) = rx.recv().await {
+            result.extend(chunk);
+        }
+        check_format(&result);
+    }
+
+    #[tokio::test]
+    async fn test_pack_encoder_fast_delta() {
+        async fn encode_large(window_size: usize) -> Vec<u8> {
+            let (tx, mut rx) = mpsc::channel(100);
+            let (entry_tx, entry_rx) = mpsc::channel::<Entry>(1);
+
+            // large & similar objects, which are encoded by `DeltaIndex`
+            let base: String = (0..1000).map(|i| format!("line {}\n", i)).collect();
+            let contents = vec![
+                base.clone(),
+                base.replace("line 500\n", "changed\n"),
+                base + "end\n",
+            ];
+            let encoder = PackEncoder::new(contents.len(), window_size, tx);
+            encoder.encode_async(entry_rx).await.unwrap();
+            for content in contents {
+                let entry: Entry = Blob::from_content(&content).into();
+                entry_tx.send(entry).await.unwrap();
+            }
+            drop(entry_tx);
+            let mut result = Vec::new();
+            while let Some(chunk) = rx.recv().await {
+                result.extend(chunk);
+            }
+            result
+        }
+
+        let pack_without_delta = encode_large(0).await;
+        let pack_with_delta = encode_large(10).await;
+        check_format(&pack_with_delta);
+        assert!(pack_with_delta.len() * 2 < pack_without_delta.len());
+    }
+
     async fn get_entries_for_test() -> Arc<Mutex<Vec<Entry>>> {
         let mut source = PathBuf::from(env::current_dir().unwrap().parent().unwrap());
         source.push("tests/data/packs/git-2d187177923cd618a75da6c6db45bb89d92bd504.pack");